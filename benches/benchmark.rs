@@ -3,15 +3,17 @@
 use core_simd::simd::f32x4;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use std::sync::Arc;
-use va_filter::filter::svf::SvfCoreFast;
+use va_filter::filter::svf::{Svf, SvfCoreFast};
+use va_filter::filter::tanh_levien;
+use va_filter::utils::tanh_pade;
+use va_filter::VaFilter;
 // use va_filter::utils::AtomicOps;
 use va_filter::{
     filter::sallen_key::SallenKeyCore, filter::sallen_key::SallenKeyCoreFast, filter::svf::SvfCore,
     filter::LadderFilter, filter_params::FilterParams,
 };
 pub fn criterion_benchmark(c: &mut Criterion) {
-    let should_update_filter = Arc::new(std::sync::atomic::AtomicBool::new(false));
-    let params = Arc::new(FilterParams::new(should_update_filter.clone()));
+    let params = Arc::new(FilterParams::new());
 
     let mut ladder = LadderFilter::new(params.clone());
 
@@ -59,6 +61,52 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("run moog:", |b| {
         b.iter(|| black_box(ladder.run_filter_newton(f32x4::splat(0.0))))
     });
+
+    // run with and without `--features precise-tanh` to compare the tanh approximation against std's
+    let mut buffer = [f32x4::splat(0.); 512];
+    for (i, frame) in buffer.iter_mut().enumerate() {
+        *frame = f32x4::splat(2. * (i as f32 * 0.05).sin());
+    }
+    c.bench_function("run moog 512 samples:", |b| {
+        b.iter(|| {
+            for frame in buffer.iter() {
+                black_box(ladder.tick_newton(*frame));
+            }
+        })
+    });
+    c.bench_function("tanh pade:", |b| {
+        b.iter(|| black_box(tanh_pade(black_box(f32x4::splat(0.7)))))
+    });
+    c.bench_function("tanh levien:", |b| {
+        b.iter(|| black_box(tanh_levien(black_box(f32x4::splat(0.7)))))
+    });
+
+    // what a coefficient update costs. While sweeping this used to happen every sample, now it's once per sub-block
+    let mut svf_stereo = Svf::new(params.clone());
+    let mut cutoff = 1000.;
+    c.bench_function("update svf coefficients:", |b| {
+        b.iter(|| {
+            cutoff = if cutoff > 10000. {
+                1000.
+            } else {
+                cutoff * 1.01
+            };
+            params.update_g(black_box(cutoff));
+            svf_stereo.update();
+        })
+    });
+
+    // the whole plugin on a sub-block without any smoothing going on
+    let mut plugin = VaFilter::default();
+    let mut frames = [f32x4::splat(0.); 16];
+    c.bench_function("run plugin sub-block:", |b| {
+        b.iter(|| {
+            for (i, frame) in frames.iter_mut().enumerate() {
+                *frame = f32x4::splat(0.1 * i as f32 - 0.8);
+            }
+            plugin.process_sub_block(black_box(&mut frames));
+        })
+    });
 }
 criterion_group!(benches, criterion_benchmark);
 criterion_main!(benches);
@@ -0,0 +1,176 @@
+#![feature(portable_simd)]
+// per-circuit benchmarks over a realistic buffer, at a few settings each
+use core_simd::simd::f32x4;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nih_plug::prelude::{EnumParam, FloatParam, FloatRange};
+use std::sync::Arc;
+use va_filter::filter::sallen_key::SallenKey;
+use va_filter::filter::svf::{tick_dk_lanes, Svf, SvfCore, SvfCoreFast};
+use va_filter::filter::LadderFilter;
+use va_filter::filter_params::{ControlRate, FilterParams, DIRTY_ALL, DIRTY_RES, DIRTY_TOPOLOGY};
+use va_filter::smoothing::CoefficientSmoother;
+use va_filter::utils::AtomicOps;
+
+const BUFFER_LEN: usize = 512;
+
+/// (name, cutoff, q, drive). The last one is the worst case, where newton needs the most iterations
+const SETTINGS: [(&str, f32, f32, f32); 3] = [
+    ("moderate", 1000., 1.5, 1.),
+    ("resonant", 200., 5., 1.),
+    ("hot", 5000., 8., 15.849),
+];
+
+// builds the params directly, since the drive can't be changed once it's behind the `Arc`
+fn make_params(cutoff: f32, q: f32, drive: f32) -> Arc<FilterParams> {
+    let mut params = FilterParams::new();
+    params.sample_rate.set(88200.);
+    params.drive.amount = FloatParam::new(
+        "Drive",
+        drive,
+        FloatRange::Linear {
+            min: 1.,
+            max: 15.849,
+        },
+    );
+    params.update_g(cutoff);
+    params.set_resonances(q);
+    Arc::new(params)
+}
+
+// a saw at 110 Hz, rich enough to keep the nonlinearities busy
+fn make_input() -> Vec<f32x4> {
+    (0..BUFFER_LEN)
+        .map(|i| {
+            let phase = (i as f32 * 110. / 88200.).fract();
+            f32x4::from_array([2. * phase - 1., 1. - 2. * phase, 0., 0.])
+        })
+        .collect()
+}
+
+pub fn filter_benchmark(c: &mut Criterion) {
+    let input = make_input();
+    for (name, cutoff, q, drive) in SETTINGS {
+        let params = make_params(cutoff, q, drive);
+
+        let mut ladder = LadderFilter::new(params.clone());
+        c.bench_function(&format!("ladder tick_newton {}", name), |b| {
+            b.iter(|| {
+                for frame in &input {
+                    black_box(ladder.tick_newton(*frame));
+                }
+            })
+        });
+        c.bench_function(&format!("ladder tick_linear {}", name), |b| {
+            b.iter(|| {
+                for frame in &input {
+                    black_box(ladder.tick_linear(*frame));
+                }
+            })
+        });
+
+        let mut svf = Svf::new(params.clone());
+        svf.update();
+        c.bench_function(&format!("svf process {}", name), |b| {
+            b.iter(|| {
+                for frame in &input {
+                    black_box(svf.process(*frame));
+                }
+            })
+        });
+
+        let mut svf_core = SvfCore::new(params.clone());
+        svf_core.update_matrices();
+        c.bench_function(&format!("svf core tick_dk {}", name), |b| {
+            b.iter(|| {
+                for frame in &input {
+                    black_box(svf_core.tick_dk(frame[0]));
+                }
+            })
+        });
+
+        // both channels one after the other, against both at once in the lanes
+        let channels = || {
+            [0, 1].map(|channel| {
+                let mut core = SvfCoreFast::new(params.clone());
+                core.channel = channel;
+                core.update_matrices();
+                core
+            })
+        };
+        let [mut left, mut right] = channels();
+        c.bench_function(&format!("svf tick_dk per channel {}", name), |b| {
+            b.iter(|| {
+                for frame in &input {
+                    black_box((left.tick_dk(frame[0]), right.tick_dk(frame[1])));
+                }
+            })
+        });
+        let [mut left, mut right] = channels();
+        c.bench_function(&format!("svf tick_dk_lanes {}", name), |b| {
+            b.iter(|| {
+                for frame in &input {
+                    black_box(tick_dk_lanes([&mut left, &mut right], *frame));
+                }
+            })
+        });
+
+        let mut sallen_key = SallenKey::new(params.clone());
+        sallen_key.update();
+        c.bench_function(&format!("sallen key process {}", name), |b| {
+            b.iter(|| {
+                for frame in &input {
+                    black_box(sallen_key.process(*frame));
+                }
+            })
+        });
+    }
+
+    // what a slope change costs at the next sub-block, with only its own flags against redoing everything
+    let params = make_params(1000., 1.5, 1.);
+    let coefficients = CoefficientSmoother::new(params.clone());
+    let mut svf = Svf::new(params);
+    for (name, dirty) in [
+        ("slope", DIRTY_TOPOLOGY | DIRTY_RES),
+        ("everything", DIRTY_ALL),
+    ] {
+        c.bench_function(&format!("svf apply_dirty {}", name), |b| {
+            b.iter(|| {
+                if coefficients.apply_dirty(black_box(dirty)) {
+                    svf.update();
+                }
+            })
+        });
+    }
+
+    // a cutoff sweep that never settles, where the control rate decides how often the coefficients are redone
+    let mut output = vec![f32x4::splat(0.); BUFFER_LEN];
+    for rate in [
+        ControlRate::EverySample,
+        ControlRate::Samples16,
+        ControlRate::Samples64,
+    ] {
+        let mut params = FilterParams::new();
+        params.sample_rate.set(88200.);
+        params.filter.control_rate = EnumParam::new("Control Rate", rate);
+        let params = Arc::new(params);
+        let mut coefficients = CoefficientSmoother::new(params.clone());
+        coefficients.apply();
+        let mut svf = Svf::new(params);
+        svf.update();
+        let mut up = false;
+        c.bench_function(
+            &format!("svf sweep every {} samples", rate.sub_block_len()),
+            |b| {
+                b.iter(|| {
+                    // turned around before it gets there, the default smoothing is longer than the buffer
+                    up = !up;
+                    coefficients.set_targets(88200., if up { 5000. } else { 200. }, 1.5);
+                    svf.process_block(&mut coefficients, black_box(&input), &mut output);
+                })
+            },
+        );
+    }
+}
+
+criterion_group!(benches, filter_benchmark);
+criterion_main!(benches);
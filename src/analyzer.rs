@@ -0,0 +1,374 @@
+// spectrum analyzer shown behind the bode plot
+use crate::utils::{AtomicF32, AtomicOps, AtomicUsize};
+use std::f32::consts::PI;
+use std::sync::atomic::{self, AtomicBool, Ordering};
+
+/// How many samples each spectrum is computed from, about 43 ms at 48 kHz
+pub const WINDOW_LEN: usize = 2048;
+// a bit of room so the audio thread is rarely writing the part the gui is reading
+const RING_LEN: usize = 2 * WINDOW_LEN;
+/// How much of the previous spectrum is kept on every update, in dB
+const SMOOTHING: f32 = 0.7;
+/// The bottom of the spectrum view
+pub const FLOOR_DB: f32 = -100.;
+/// How far up and down from 0 dB the channel delta views go
+pub const DELTA_RANGE_DB: f32 = 24.;
+
+/// What the analyzer shows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnalyzerView {
+    /// the spectrum of both channels mixed to mono
+    Spectrum,
+    /// how much louder the left channel is than the right, per band
+    LeftRight,
+    /// how much louder the side is than the mid, per band. All the way down when both channels are the same
+    MidSide,
+}
+
+impl AnalyzerView {
+    pub const ALL: [AnalyzerView; 3] = [
+        AnalyzerView::Spectrum,
+        AnalyzerView::LeftRight,
+        AnalyzerView::MidSide,
+    ];
+    /// Out of range indices, like from a newer version's state, are the plain spectrum
+    pub fn from_index(index: usize) -> Self {
+        Self::ALL
+            .get(index)
+            .copied()
+            .unwrap_or(AnalyzerView::Spectrum)
+    }
+    pub fn to_index(self) -> usize {
+        Self::ALL.iter().position(|view| *view == self).unwrap_or(0)
+    }
+    /// The view after this one, wrapping around
+    pub fn next(self) -> Self {
+        Self::from_index((self.to_index() + 1) % Self::ALL.len())
+    }
+    pub fn is_delta(self) -> bool {
+        self != AnalyzerView::Spectrum
+    }
+    /// Where a value of the view sits between the bottom of the plot at 0 and the top at 1
+    pub fn height(self, db: f32) -> f32 {
+        if self.is_delta() {
+            (0.5 + 0.5 * db / DELTA_RANGE_DB).clamp(0., 1.)
+        } else {
+            (1. - db / FLOOR_DB).clamp(0., 1.)
+        }
+    }
+}
+
+/// Ring buffer the audio thread writes into and the gui reads the latest window from.
+/// There's only ever one writer, and the reader just copies, so neither of them locks or allocates.
+/// A read can catch a few samples that are newer than the rest, which doesn't matter for a display
+pub struct SpectrumTap {
+    // the left and right channel, both the same for mono
+    samples: [Vec<AtomicF32>; 2],
+    write_pos: AtomicUsize,
+    // how many editors are showing the spectrum. While there are none, the audio thread skips the tap entirely
+    viewers: atomic::AtomicUsize,
+    /// analyze the input instead of the output
+    pub pre_filter: AtomicBool,
+    /// the sample rate the tap is fed at
+    pub sample_rate: AtomicF32,
+    /// the index of the `AnalyzerView` the editor shows
+    pub view: AtomicUsize,
+}
+
+impl Default for SpectrumTap {
+    fn default() -> Self {
+        Self {
+            samples: [(); 2].map(|_| (0..RING_LEN).map(|_| AtomicF32::new(0.)).collect()),
+            write_pos: AtomicUsize::new(0),
+            viewers: atomic::AtomicUsize::new(0),
+            pre_filter: AtomicBool::new(false),
+            sample_rate: AtomicF32::new(48000.),
+            view: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl SpectrumTap {
+    pub fn is_active(&self) -> bool {
+        self.viewers.load(Ordering::Relaxed) > 0
+    }
+    // counted instead of a flag, so an editor closing can't switch off the one that replaced it
+    pub fn add_viewer(&self) {
+        self.viewers.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn remove_viewer(&self) {
+        self.viewers.fetch_sub(1, Ordering::Relaxed);
+    }
+    pub fn push(&self, left: f32, right: f32) {
+        let pos = self.write_pos.get();
+        self.samples[0][pos].set(left);
+        self.samples[1][pos].set(right);
+        self.write_pos.set((pos + 1) % RING_LEN);
+    }
+    /// Copies the last `WINDOW_LEN` samples of each channel into `windows`, oldest first
+    pub fn read_window(&self, windows: &mut [[f32; WINDOW_LEN]; 2]) {
+        let start = self.write_pos.get() + RING_LEN - WINDOW_LEN;
+        for (window, samples) in windows.iter_mut().zip(&self.samples) {
+            for (i, sample) in window.iter_mut().enumerate() {
+                *sample = samples[(start + i) % RING_LEN].get();
+            }
+        }
+    }
+    pub fn view(&self) -> AnalyzerView {
+        AnalyzerView::from_index(self.view.get())
+    }
+}
+
+/// Turns the latest window of the tap into a smoothed spectrum over a log frequency axis from 20 Hz to 20 kHz,
+/// the same axis as the bode plot. Lives on the gui side
+pub struct Spectrum {
+    windows: Box<[[f32; WINDOW_LEN]; 2]>,
+    // one fft per channel, or per mid and side. The plain spectrum only uses the first
+    re: [Vec<f32>; 2],
+    im: [Vec<f32>; 2],
+    /// what `values` are of, the tap's view as of the last update
+    pub view: AnalyzerView,
+    /// dB per horizontal pixel
+    pub values: Vec<f32>,
+}
+
+impl Spectrum {
+    pub fn new(width: usize) -> Self {
+        Self {
+            windows: Box::new([[0.; WINDOW_LEN]; 2]),
+            re: [vec![0.; WINDOW_LEN], vec![0.; WINDOW_LEN]],
+            im: [vec![0.; WINDOW_LEN], vec![0.; WINDOW_LEN]],
+            view: AnalyzerView::Spectrum,
+            values: vec![FLOOR_DB; width],
+        }
+    }
+
+    pub fn update(&mut self, tap: &SpectrumTap) {
+        let view = tap.view();
+        if view != self.view {
+            // start the new view from its middle instead of smoothing over from the old one
+            self.view = view;
+            let start = if view.is_delta() { 0. } else { FLOOR_DB };
+            self.values.iter_mut().for_each(|value| *value = start);
+        }
+        tap.read_window(&mut self.windows);
+        let [left, right] = &*self.windows;
+        // the delta views compare the first to the second: left to right, side to mid
+        let channel = |i: usize, c: usize| match (view, c) {
+            (AnalyzerView::Spectrum, _) => 0.5 * (left[i] + right[i]),
+            (AnalyzerView::LeftRight, 0) => left[i],
+            (AnalyzerView::LeftRight, _) => right[i],
+            (AnalyzerView::MidSide, 0) => 0.5 * (left[i] - right[i]),
+            (AnalyzerView::MidSide, _) => 0.5 * (left[i] + right[i]),
+        };
+        let channels = if view.is_delta() { 2 } else { 1 };
+        for c in 0..channels {
+            // hann window, scaled so a full scale sine reads 0 dB
+            for (i, (re, im)) in self.re[c].iter_mut().zip(self.im[c].iter_mut()).enumerate() {
+                let hann = 0.5 - 0.5 * (2. * PI * i as f32 / WINDOW_LEN as f32).cos();
+                *re = channel(i, c) * hann * 4. / WINDOW_LEN as f32;
+                *im = 0.;
+            }
+            fft(&mut self.re[c], &mut self.im[c]);
+        }
+
+        let sample_rate = tap.sample_rate.get();
+        let width = self.values.len();
+        let (min, max) = (20f32.log2(), 20000f32.log2());
+        let (re, im) = (&self.re, &self.im);
+        for (x, value) in self.values.iter_mut().enumerate() {
+            let freq = |x: f32| (min + (max - min) * x / width as f32).exp2();
+            let bin = |freq: f32| freq / sample_rate * WINDOW_LEN as f32;
+            let first = (bin(freq(x as f32)).round() as usize).min(WINDOW_LEN / 2);
+            let last = (bin(freq(x as f32 + 1.)).round() as usize).clamp(first, WINDOW_LEN / 2);
+            let power = |c: usize, i: usize| re[c][i] * re[c][i] + im[c][i] * im[c][i];
+            let db = if view.is_delta() {
+                // the power over the whole pixel, so one bin that happens to cancel doesn't swing it around.
+                // Anything below the spectrum's floor counts as the floor, so silence on both sides reads 0 dB
+                let floor = 10f32.powf(FLOOR_DB / 10.);
+                let a = (first..=last).map(|i| power(0, i)).sum::<f32>();
+                let b = (first..=last).map(|i| power(1, i)).sum::<f32>();
+                (10. * (a.max(floor) / b.max(floor)).log10()).clamp(-DELTA_RANGE_DB, DELTA_RANGE_DB)
+            } else {
+                // the loudest bin between this pixel and the next, so narrow peaks don't fall between pixels
+                let peak = (first..=last).map(|i| power(0, i)).fold(0., f32::max);
+                (10. * peak.log10()).max(FLOOR_DB)
+            };
+            *value = SMOOTHING * *value + (1. - SMOOTHING) * db;
+        }
+    }
+}
+
+/// in-place radix-2 fft
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2. * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[test]
+fn test_tap_keeps_latest_window() {
+    let tap = SpectrumTap::default();
+    // wraps around the ring a few times
+    for i in 0..3 * RING_LEN + 100 {
+        tap.push(i as f32, -(i as f32));
+    }
+    let mut windows = [[0.; WINDOW_LEN]; 2];
+    tap.read_window(&mut windows);
+    let last = (3 * RING_LEN + 99) as f32;
+    assert_eq!(windows[0][WINDOW_LEN - 1], last);
+    assert_eq!(windows[0][0], last - (WINDOW_LEN - 1) as f32);
+    assert_eq!(windows[1][WINDOW_LEN - 1], -last);
+}
+
+#[test]
+fn test_sine_peak() {
+    let tap = SpectrumTap::default();
+    let width = 360;
+    let mut spectrum = Spectrum::new(width);
+    let freq = 1000.;
+    for i in 0..WINDOW_LEN {
+        let x = (2. * PI * freq * i as f32 / 48000.).sin();
+        tap.push(x, x);
+    }
+    // let the smoothing settle
+    for _ in 0..50 {
+        spectrum.update(&tap);
+    }
+    let (peak_x, peak_db) =
+        spectrum
+            .values
+            .iter()
+            .enumerate()
+            .fold(
+                (0, FLOOR_DB),
+                |max, (x, db)| if *db > max.1 { (x, *db) } else { max },
+            );
+    let expected_x = (freq / 20.).log2() / 1000f32.log2() * width as f32;
+    assert!(
+        (peak_x as f32 - expected_x).abs() < 3.,
+        "peak at pixel {}, expected {}",
+        peak_x,
+        expected_x
+    );
+    assert!(
+        peak_db.abs() < 1.5,
+        "a full scale sine reads {} dB",
+        peak_db
+    );
+}
+
+/// The delta view of `left` and `right` played into the tap, after the smoothing settles
+#[cfg(test)]
+fn settled_delta(view: AnalyzerView, left: &[f32], right: &[f32]) -> Vec<f32> {
+    let tap = SpectrumTap::default();
+    tap.view.set(view.to_index());
+    let mut spectrum = Spectrum::new(360);
+    for (left, right) in left.iter().zip(right) {
+        tap.push(*left, *right);
+    }
+    for _ in 0..50 {
+        spectrum.update(&tap);
+    }
+    assert_eq!(spectrum.view, view);
+    spectrum.values
+}
+
+#[cfg(test)]
+fn tone(freq: f32) -> Vec<f32> {
+    (0..WINDOW_LEN)
+        .map(|i| 0.5 * (2. * PI * freq * i as f32 / 48000.).sin())
+        .collect()
+}
+
+#[test]
+fn test_identical_channels() {
+    // noise, so every pixel has something in it
+    let mut state = 1;
+    let noise: Vec<f32> = (0..WINDOW_LEN)
+        .map(|_| crate::utils::random_bipolar(&mut state))
+        .collect();
+    let values = settled_delta(AnalyzerView::LeftRight, &noise, &noise);
+    assert!(values.iter().all(|db| db.abs() < 0.01), "{:?}", values);
+    // no side at all
+    let values = settled_delta(AnalyzerView::MidSide, &noise, &noise);
+    assert!(
+        values.iter().all(|db| *db < -DELTA_RANGE_DB + 0.01),
+        "{:?}",
+        values
+    );
+    // and silence is no different on either side
+    let silence = [0.; WINDOW_LEN];
+    let values = settled_delta(AnalyzerView::LeftRight, &silence, &silence);
+    assert!(values.iter().all(|db| db.abs() < 0.01));
+}
+
+#[test]
+fn test_band_limited_difference() {
+    let width = 360;
+    let x_of = |freq: f32| ((freq / 20.).log2() / 1000f32.log2() * width as f32).round() as usize;
+    // the same low tone on both sides, and a higher one only on the left
+    let low = tone(200.);
+    let both: Vec<f32> = low.iter().zip(tone(4000.)).map(|(a, b)| a + b).collect();
+    let values = settled_delta(AnalyzerView::LeftRight, &both, &low);
+    assert!(
+        values[x_of(200.)].abs() < 1.,
+        "{} dB at 200 Hz",
+        values[x_of(200.)]
+    );
+    assert!(
+        values[x_of(4000.)] > DELTA_RANGE_DB - 1.,
+        "{} dB at 4 kHz",
+        values[x_of(4000.)]
+    );
+    // swapped round, it goes the other way
+    let values = settled_delta(AnalyzerView::LeftRight, &low, &both);
+    assert!(values[x_of(4000.)] < -DELTA_RANGE_DB + 1.);
+    // in mid and side, half the high tone is side and half is mid, and the low one is all mid
+    let values = settled_delta(AnalyzerView::MidSide, &both, &low);
+    assert!(
+        values[x_of(4000.)].abs() < 1.,
+        "{} dB at 4 kHz",
+        values[x_of(4000.)]
+    );
+    assert!(values[x_of(200.)] < -DELTA_RANGE_DB + 1.);
+}
+
+#[test]
+fn test_view_height() {
+    assert_eq!(AnalyzerView::LeftRight.height(0.), 0.5);
+    assert_eq!(AnalyzerView::MidSide.height(DELTA_RANGE_DB), 1.);
+    assert_eq!(AnalyzerView::LeftRight.height(-2. * DELTA_RANGE_DB), 0.);
+    assert_eq!(AnalyzerView::Spectrum.height(0.), 1.);
+    assert_eq!(AnalyzerView::Spectrum.height(FLOOR_DB), 0.);
+    assert_eq!(AnalyzerView::MidSide.next(), AnalyzerView::Spectrum);
+    assert_eq!(AnalyzerView::from_index(7), AnalyzerView::Spectrum);
+}
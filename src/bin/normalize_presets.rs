@@ -0,0 +1,32 @@
+// sets the output gain of every preset in `presets/` so it comes out at `loudness::TARGET_DB`, and rewrites the files.
+// Run it with `cargo run --bin normalize_presets` after changing the presets or the dsp
+use std::path::Path;
+use va_filter::loudness;
+use va_filter::presets::Preset;
+
+fn main() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("presets");
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .expect("the presets folder should be readable")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "vapreset"))
+        .collect();
+    paths.sort();
+    let fixture = loudness::program_fixture();
+    for path in paths {
+        let text = std::fs::read_to_string(&path).expect("the preset should be readable");
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let preset = match Preset::parse(&name, &text) {
+            Ok(preset) => preset,
+            Err(e) => {
+                eprintln!("skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let level = loudness::relative_level(&preset, &fixture);
+        let gain = loudness::output_gain(&preset, level);
+        std::fs::write(&path, loudness::set_gain_line(&text, gain))
+            .expect("the preset should be writable");
+        println!("{}: {:.1} dB, gain = {}", name, level, gain);
+    }
+}
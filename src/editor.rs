@@ -10,19 +10,26 @@ pub use vizia::*;
 
 const STYLE: &str = include_str!("style.css");
 
-pub const WINDOW_WIDTH: u32 = 512;
-pub const WINDOW_HEIGHT: u32 = 512;
+// the size at 100 %, everything in the gui is laid out for this
+pub const WINDOW_WIDTH: u32 = 1100;
+pub const WINDOW_HEIGHT: u32 = 800;
+/// The sizes the editor can be set to
+pub const SCALES: [f32; 3] = [1., 1.5, 2.];
 
-pub fn create_vizia_editor<U>(update: U) -> Option<Box<dyn Editor>>
+/// `scale` gives the user's scale factor, which the window gets opened and reported to the host at
+pub fn create_vizia_editor<S, U>(scale: S, update: U) -> Option<Box<dyn Editor>>
 where
+    S: Fn() -> f32 + 'static + Send + Sync,
     U: Fn(&mut prelude::Context, Arc<dyn GuiContext>) + 'static + Send + Sync,
 {
     Some(Box::new(ViziaEditor {
+        scale: Arc::new(scale),
         update: Arc::new(update),
     }))
 }
 
 pub struct ViziaEditor {
+    scale: Arc<dyn Fn() -> f32 + 'static + Send + Sync>,
     update: Arc<dyn Fn(&mut prelude::Context, Arc<dyn GuiContext>) + 'static + Send + Sync>,
 }
 
@@ -39,7 +46,9 @@ impl Editor for ViziaEditor {
 
             (update)(cx, context.clone());
         })
+        // vizia scales every pixel size, from the stylesheet and the layout alike
         .inner_size((WINDOW_WIDTH, WINDOW_HEIGHT))
+        .user_scale_factor((self.scale)() as f64)
         .title("Hello Plugin")
         .open_parented(&parent);
 
@@ -47,7 +56,11 @@ impl Editor for ViziaEditor {
     }
 
     fn size(&self) -> (u32, u32) {
-        (WINDOW_WIDTH, WINDOW_HEIGHT)
+        let scale = (self.scale)();
+        (
+            (WINDOW_WIDTH as f32 * scale).round() as u32,
+            (WINDOW_HEIGHT as f32 * scale).round() as u32,
+        )
     }
 
     fn set_scale_factor(&self, _factor: f32) -> bool {
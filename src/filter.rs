@@ -1,18 +1,32 @@
 // use crate::filter_parameters::FilterParameters;
 // use crate::filter_params_nih::{FilterParams, SvfMode};
-use crate::{filter_params::FilterParams, utils::AtomicOps};
+use crate::{
+    filter_params::{Character, FilterParams, LadderResponse, LadderSolver, Solver, CUTOFF_MIN},
+    smoothing::CoefficientSmoother,
+    utils::AtomicOps,
+};
 // use packed_simd::f32x4;
 use core_simd::simd::*;
+use std::f32::consts::PI;
 use std::sync::Arc;
 use std_float::*;
 
 mod solver;
 use solver::DKSolver;
 
+pub mod nonlinearity;
 pub mod preprocess;
 pub mod sallen_key;
 pub mod svf;
 
+/// How long `fade_reset()` crossfades from the old state to the one starting over from silence
+const RESET_FADE_MS: f32 = 1.;
+
+/// `RESET_FADE_MS` in samples at the circuits' rate
+fn reset_fade_len(params: &FilterParams) -> usize {
+    ((RESET_FADE_MS / 1000. * params.sample_rate.get()).round() as usize).max(1)
+}
+
 /// Cheap tanh to make the filter faster.
 ///
 /// From a quick look it looks extremely good, max error of ~0.0002 or .02%  
@@ -27,6 +41,21 @@ pub fn tanh_levien(x: f32x4) -> f32x4 {
     a / (f32x4::splat(1.0) + (a * a)).sqrt()
 }
 
+impl LadderResponse {
+    /// The Oberheim Xpander's pole mixing. Weights for what goes into the first stage after the feedback,
+    /// and the outputs of the four stages. The lowpass takes a single stage, picked by the slope
+    pub fn pole_mix(self) -> Option<[f32; 5]> {
+        match self {
+            LadderResponse::LP => None,
+            LadderResponse::BP12 => Some([0., 2., -2., 0., 0.]),
+            LadderResponse::BP24 => Some([0., 0., 4., -8., 4.]),
+            LadderResponse::HP12 => Some([1., -2., 1., 0., 0.]),
+            LadderResponse::HP24 => Some([1., -4., 6., -4., 1.]),
+            LadderResponse::Notch => Some([1., -2., 2., 0., 0.]),
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(PartialEq, Clone, Copy)]
 enum EstimateSource {
@@ -40,7 +69,55 @@ pub struct LadderFilter {
 
     vout: [f32x4; 4],
     pub s: [f32x4; 4],
+    /// what came out of the input pair and the four stages last sample, the dk solver's first guess
+    i_est: [f32x4; 5],
+    /// what went into the saturation last sample: the dry input, the input pair and the four stages.
+    /// The hq saturation averages the function from these to the new inputs
+    adaa_prev: [f32x4; 6],
+    /// the largest level that went into the saturation since `take_saturation_peak()`
+    sat_peak: f32x4,
+    /// the state from before `fade_reset()`, still running while it fades out
+    fading: LadderState,
+    /// how many samples of the fade are left, and how long it is
+    fade_left: usize,
+    fade_len: usize,
+}
+
+/// What the ladder remembers from one sample to the next
+#[derive(Clone, Copy)]
+struct LadderState {
+    vout: [f32x4; 4],
+    s: [f32x4; 4],
+    i_est: [f32x4; 5],
+    adaa_prev: [f32x4; 6],
+}
+
+impl LadderState {
+    const ZERO: Self = Self {
+        vout: [f32x4::from_array([0.; 4]); 4],
+        s: [f32x4::from_array([0.; 4]); 4],
+        i_est: [f32x4::from_array([0.; 4]); 5],
+        adaa_prev: [f32x4::from_array([0.; 4]); 6],
+    };
 }
+
+/// The saturation of input `n` of `adaa_prev`, anti-aliased when hq saturation is on
+fn saturator(params: &FilterParams, prev: [f32x4; 6]) -> impl Fn(usize, f32x4) -> (f32x4, f32x4) {
+    let sat = params.saturation();
+    let hq = params.drive.hq_saturation.value();
+    move |n, x| {
+        if hq {
+            sat.eval_adaa(x, prev[n])
+        } else {
+            sat.eval(x)
+        }
+    }
+}
+
+/// The dk solver gives up after this many iterations, so extreme drive can't stall the audio thread
+const DK_MAX_ITERATIONS: usize = 32;
+/// Same for the newton solver, which can keep jumping between the sides of a kink like the hard clip's
+const NEWTON_MAX_ITERATIONS: usize = 32;
 #[allow(dead_code)]
 impl LadderFilter {
     pub fn new(params: Arc<FilterParams>) -> Self {
@@ -48,8 +125,58 @@ impl LadderFilter {
             params,
             vout: [f32x4::splat(0.); 4],
             s: [f32x4::splat(0.); 4],
+            i_est: [f32x4::splat(0.); 5],
+            adaa_prev: [f32x4::splat(0.); 6],
+            sat_peak: f32x4::splat(0.),
+            fading: LadderState::ZERO,
+            fade_left: 0,
+            fade_len: 1,
+        }
+    }
+    pub fn reset(&mut self) {
+        self.vout = [f32x4::splat(0.); 4];
+        self.s = [f32x4::splat(0.); 4];
+        self.i_est = [f32x4::splat(0.); 5];
+        self.adaa_prev = [f32x4::splat(0.); 6];
+        self.sat_peak = f32x4::splat(0.);
+        self.fade_left = 0;
+    }
+    /// Starts over from silence like `reset()`, but crossfades from where it was over `RESET_FADE_MS` so it
+    /// doesn't click. Once the fade is done, what comes out only depends on the input since
+    pub fn fade_reset(&mut self) {
+        self.fading = LadderState {
+            vout: self.vout,
+            s: self.s,
+            i_est: self.i_est,
+            adaa_prev: self.adaa_prev,
+        };
+        self.reset();
+        self.fade_len = reset_fade_len(&self.params);
+        self.fade_left = self.fade_len;
+    }
+    /// Trades the state for the one fading out
+    fn swap_fading(&mut self) {
+        std::mem::swap(&mut self.vout, &mut self.fading.vout);
+        std::mem::swap(&mut self.s, &mut self.fading.s);
+        std::mem::swap(&mut self.i_est, &mut self.fading.i_est);
+        std::mem::swap(&mut self.adaa_prev, &mut self.fading.adaa_prev);
+    }
+    fn track_peak(&mut self, inputs: &[f32x4]) {
+        for x in inputs {
+            self.sat_peak = self.sat_peak.simd_max(x.abs());
         }
     }
+    /// The largest level that went into the saturation since the last call. The linear solver leaves it at 0
+    pub fn take_saturation_peak(&mut self) -> f32 {
+        let peak = std::mem::replace(&mut self.sat_peak, f32x4::splat(0.));
+        peak.to_array().iter().fold(0., |peak, x| x.max(peak))
+    }
+    /// Cheap check for NaN/inf in the state. Any non-finite value propagates through the sum.
+    pub fn is_finite(&self) -> bool {
+        (self.s[0] + self.s[1] + self.s[2] + self.s[3])
+            .is_finite()
+            .all()
+    }
     fn get_estimate(&mut self, n: usize, estimate: EstimateSource, input: f32x4) -> f32x4 {
         // if we ask for an estimate based on the linear filter, we have to run it
         if estimate == EstimateSource::LinearStateEstimate
@@ -74,10 +201,11 @@ impl LadderFilter {
     }
     // nonlinear ladder filter function with distortion, solved with Mystran's fixed-pivot method.
     fn run_filter_pivotal(&mut self, input: f32x4) -> f32x4 {
+        let input = self.fat_input(input);
         let mut a: [f32x4; 5] = [f32x4::splat(1.); 5];
         // let base = [input, self.s[0], self.s[1], self.s[2], self.s[3]];
-        let g = f32x4::splat(self.params.g.get());
-        let k = f32x4::splat(self.params.k_ladder.get());
+        let (g, k) = self.params.stereo_tuning();
+        let sat = self.params.saturation();
         let base = [
             input - k * self.s[3],
             self.s[0],
@@ -85,12 +213,13 @@ impl LadderFilter {
             self.s[2],
             self.s[3],
         ];
+        self.track_peak(&base);
         // a[n] is the fixed-pivot approximation for tanh()
         for n in 0..base.len() {
             // hopefully this should cook down to the original when not 0,
             // and 1 when 0
             let mask = base[n].simd_ne(f32x4::splat(0.));
-            a[n] = tanh_levien(base[n]) / base[n];
+            a[n] = sat.eval(base[n]).0 / base[n];
             // since the line above can become NaN or other stuff when a value in base[n] is 0,
             // replace values where a[n] is 0.
             a[n] = mask.select(a[n], f32x4::splat(1.));
@@ -117,15 +246,25 @@ impl LadderFilter {
         self.vout[0] = g0 * (g * a[1] * (input * a[0] - k * a[3] * self.vout[3]) + self.s[0]);
         self.vout[1] = g1 * (g * a[2] * self.vout[0] + self.s[1]);
         self.vout[2] = g2 * (g * a[3] * self.vout[1] + self.s[2]);
+        // where the newton and dk solvers would have left the saturation, so switching to them with hq saturation
+        // on averages from here
+        self.adaa_prev = [
+            input,
+            input - k * self.vout[3],
+            self.vout[0],
+            self.vout[1],
+            self.vout[2],
+            self.vout[3],
+        ];
 
-        self.vout[self.params.slope.value() as usize]
+        self.output(input * a[0] - k * a[3] * self.vout[3])
     }
 
     /// Linear version without distortion.
     pub fn run_filter_linear(&mut self, input: f32x4) -> f32x4 {
+        let input = self.fat_input(input);
         // denominators of solutions of individual stages. Simplifies the math a bit
-        let g = f32x4::splat(self.params.g.get());
-        let k = f32x4::splat(self.params.k_ladder.get());
+        let (g, k) = self.params.stereo_tuning();
         let one = f32x4::splat(1.);
         let g0 = one / (one + g);
         let g1 = g * g0 * g0;
@@ -139,7 +278,50 @@ impl LadderFilter {
         self.vout[0] = g0 * (g * (input - k * self.vout[3]) + self.s[0]);
         self.vout[1] = g0 * (g * self.vout[0] + self.s[1]);
         self.vout[2] = g0 * (g * self.vout[1] + self.s[2]);
-        self.vout[self.params.slope.value() as usize]
+        self.output(input - k * self.vout[3])
+    }
+
+    /// The stage the slope picks for the lowpass, or the pole mix of the other responses.
+    /// `input` is what drives the first stage, after the feedback has been taken off
+    #[inline(always)]
+    fn output(&self, input: f32x4) -> f32x4 {
+        match self.params.filter.ladder_response.value().pole_mix() {
+            None => self.vout[self.params.filter.slope.value() as usize],
+            Some(mix) => {
+                let mut out = f32x4::splat(mix[0]) * input;
+                for (weight, stage) in mix[1..].iter().zip(self.vout) {
+                    out += f32x4::splat(*weight) * stage;
+                }
+                out
+            }
+        }
+    }
+
+    /// How much more of the input drives the first stage with fat on. The other responses have their passbands above
+    /// dc, where the feedback doesn't reach, so they're left without it
+    fn fat(&self) -> f32 {
+        if self.params.filter.ladder_response.value() == LadderResponse::LP {
+            self.params.ladder_fat.get()
+        } else {
+            0.
+        }
+    }
+
+    /// The input with the fat mixed back in
+    #[inline(always)]
+    fn fat_input(&self, input: f32x4) -> f32x4 {
+        input * f32x4::splat(1. + self.fat())
+    }
+
+    /// The gain that brings the lowpass back up to unity at dc, where the feedback takes it down to
+    /// (1 + fat) / (1 + k). With fat on that's already 1, so the drive goes up with the resonance instead.
+    /// The other responses have their passbands above dc, where the feedback doesn't reach
+    fn makeup_gain(&self) -> f32 {
+        if self.params.filter.ladder_response.value() == LadderResponse::LP {
+            (1. + self.params.k_ladder()) / (1. + self.fat())
+        } else {
+            1.
+        }
     }
 
     /// Newton-raphson method version.
@@ -147,8 +329,42 @@ impl LadderFilter {
         // dbg!(input);
         // ---------- setup ----------
         // load in g and k from parameters
-        let g = f32x4::splat(self.params.g.get());
-        let k = f32x4::splat(self.params.k_ladder.get());
+        // each stage has its own g, with the analog mismatch
+        let (g, k) = self.params.ladder_stage_tuning();
+        // the fat only adds to the input, which none of the estimates depend on, so the jacobian stays the same
+        let input = self.fat_input(input);
+        let saturate = saturator(&self.params, self.adaa_prev);
+        // below full res character the saturation in the loop is blended with a straight line, so the resonance
+        // compresses less with level. The input still gets saturated on its own, so the drive keeps its color
+        let squash = f32x4::splat(self.params.res_squash.get());
+        let one = f32x4::splat(1.);
+        let stage = |n: usize, x: f32x4| {
+            let (wet, d_wet) = saturate(n + 2, x);
+            (
+                squash * wet + (one - squash) * x,
+                squash * d_wet + one - squash,
+            )
+        };
+        // the feedback's own saturation, with the gain into it taken back off after, so the feedback drive only
+        // changes how the resonance compresses and not how much of it there is
+        let feedback_drive = self.params.feedback_drive.get();
+        let sat = self.params.saturation();
+        let feedback = |y4: f32x4| {
+            if feedback_drive == 0. {
+                return (y4, one);
+            }
+            let (wet, d_wet) = sat.eval(f32x4::splat(feedback_drive) * y4);
+            (wet / f32x4::splat(feedback_drive), d_wet)
+        };
+        let (sat_dry, _) = saturate(0, input);
+        let input_stage = |y4: f32x4| {
+            let (fb, d_fb) = feedback(y4);
+            let (wet, d_wet) = saturate(1, input - k * fb);
+            (
+                squash * wet + (one - squash) * (sat_dry - k * fb),
+                (squash * d_wet + one - squash) * d_fb,
+            )
+        };
         // a[n] is the fixed-pivot approximation for whatever is being processed nonlinearly
         let mut v_est: [f32x4; 4];
         let mut temp: [f32x4; 4] = [f32x4::splat(0.); 4];
@@ -156,37 +372,40 @@ impl LadderFilter {
         // use state as estimate
         v_est = [self.s[0], self.s[1], self.s[2], self.s[3]];
 
-        let mut tanh_input = tanh_levien(input - k * v_est[3]);
-        let mut tanh_y1_est = tanh_levien(v_est[0]);
-        let mut tanh_y2_est = tanh_levien(v_est[1]);
-        let mut tanh_y3_est = tanh_levien(v_est[2]);
-        let mut tanh_y4_est = tanh_levien(v_est[3]);
+        // the saturated values and their derivatives
+        let (mut sat_input, mut d_input) = input_stage(v_est[3]);
+        let (mut sat_y1_est, mut d_y1_est) = stage(0, v_est[0]);
+        let (mut sat_y2_est, mut d_y2_est) = stage(1, v_est[1]);
+        let (mut sat_y3_est, mut d_y3_est) = stage(2, v_est[2]);
+        let (mut sat_y4_est, mut d_y4_est) = stage(3, v_est[3]);
         let mut residue = [
-            g * (tanh_input - tanh_y1_est) + self.s[0] - v_est[0],
-            g * (tanh_y1_est - tanh_y2_est) + self.s[1] - v_est[1],
-            g * (tanh_y2_est - tanh_y3_est) + self.s[2] - v_est[2],
-            g * (tanh_y3_est - tanh_y4_est) + self.s[3] - v_est[3],
+            g[0] * (sat_input - sat_y1_est) + self.s[0] - v_est[0],
+            g[1] * (sat_y1_est - sat_y2_est) + self.s[1] - v_est[1],
+            g[2] * (sat_y2_est - sat_y3_est) + self.s[2] - v_est[2],
+            g[3] * (sat_y3_est - sat_y4_est) + self.s[3] - v_est[3],
         ];
         // let max_error = 0.00001;
         let max_error = f32x4::splat(0.00001);
+        let mut n_iterations = 0;
 
         // f32x4.lt(max_error) returns a mask.
-        while residue[0].abs().simd_gt(max_error).any()
+        while (residue[0].abs().simd_gt(max_error).any()
             || residue[1].abs().simd_gt(max_error).any()
             || residue[2].abs().simd_gt(max_error).any()
-            || residue[3].abs().simd_gt(max_error).any()
-        // && n_iterations < 9
+            || residue[3].abs().simd_gt(max_error).any())
+            && n_iterations < NEWTON_MAX_ITERATIONS
         {
-            let one = f32x4::splat(1.);
+            n_iterations += 1;
             // jacobian matrix
-            let j10 = g * (one - tanh_y1_est * tanh_y1_est);
-            let j00 = -j10 - one;
-            let j03 = -g * k * (one - tanh_input * tanh_input);
-            let j21 = g * (one - tanh_y2_est * tanh_y2_est);
-            let j11 = -j21 - one;
-            let j32 = g * (one - tanh_y3_est * tanh_y3_est);
-            let j22 = -j32 - one;
-            let j33 = -g * (one - tanh_y4_est * tanh_y4_est) - one;
+            let j10 = g[1] * d_y1_est;
+            let j00 = -(g[0] * d_y1_est) - one;
+            // the feedback's saturation is in `d_input` along with the input pair's
+            let j03 = -g[0] * k * d_input;
+            let j21 = g[2] * d_y2_est;
+            let j11 = -(g[1] * d_y2_est) - one;
+            let j32 = g[3] * d_y3_est;
+            let j22 = -(g[2] * d_y3_est) - one;
+            let j33 = -g[3] * d_y4_est - one;
 
             temp[0] = (((j22 * residue[3] - j32 * residue[2]) * j11
                 + j21 * j32 * (-j10 * v_est[0] + residue[1]))
@@ -199,45 +418,191 @@ impl LadderFilter {
             temp[3] = (j32 * v_est[2] - j32 * temp[2] + j33 * v_est[3] - residue[3]) / (j33);
 
             v_est = temp;
-            tanh_input = tanh_levien(input - k * v_est[3]);
-            tanh_y1_est = tanh_levien(v_est[0]);
-            tanh_y2_est = tanh_levien(v_est[1]);
-            tanh_y3_est = tanh_levien(v_est[2]);
-            tanh_y4_est = tanh_levien(v_est[3]);
+            (sat_input, d_input) = input_stage(v_est[3]);
+            (sat_y1_est, d_y1_est) = stage(0, v_est[0]);
+            (sat_y2_est, d_y2_est) = stage(1, v_est[1]);
+            (sat_y3_est, d_y3_est) = stage(2, v_est[2]);
+            (sat_y4_est, d_y4_est) = stage(3, v_est[3]);
 
             residue = [
-                g * (tanh_input - tanh_y1_est) + self.s[0] - v_est[0],
-                g * (tanh_y1_est - tanh_y2_est) + self.s[1] - v_est[1],
-                g * (tanh_y2_est - tanh_y3_est) + self.s[2] - v_est[2],
-                g * (tanh_y3_est - tanh_y4_est) + self.s[3] - v_est[3],
+                g[0] * (sat_input - sat_y1_est) + self.s[0] - v_est[0],
+                g[1] * (sat_y1_est - sat_y2_est) + self.s[1] - v_est[1],
+                g[2] * (sat_y2_est - sat_y3_est) + self.s[2] - v_est[2],
+                g[3] * (sat_y3_est - sat_y4_est) + self.s[3] - v_est[3],
             ];
         }
+        self.adaa_prev = [
+            input,
+            input - k * feedback(v_est[3]).0,
+            v_est[0],
+            v_est[1],
+            v_est[2],
+            v_est[3],
+        ];
+        let inputs = self.adaa_prev;
+        self.track_peak(&inputs[1..]);
         self.vout = v_est;
-        self.vout[self.params.slope.value() as usize]
+        self.output(sat_input)
+    }
+
+    /// DK-method version. The unknowns are the currents out of the five saturating parts, the input pair
+    /// and the four stages, and the voltages driving them are linear in those:
+    /// `v = p + K i`, with `p` from the input and state. Newton-raphson then solves `f(v) - i = 0`.
+    /// The currents are bounded where the stages saturate, so this keeps converging at drives where
+    /// the voltages run off
+    pub fn run_filter_dk(&mut self, input: f32x4) -> f32x4 {
+        let (g, k) = self.params.ladder_stage_tuning();
+        let input = self.fat_input(input);
+        let saturate = saturator(&self.params, self.adaa_prev);
+        // the same blend of the saturation with a straight line as `run_filter_newton()`
+        let squash = f32x4::splat(self.params.res_squash.get());
+        let one = f32x4::splat(1.);
+        let (sat_dry, _) = saturate(0, input);
+        let f = |n: usize, v: f32x4| {
+            let (wet, d_wet) = saturate(n + 1, v);
+            // the input pair's straight line is the dry input's saturation minus the feedback
+            let line = if n == 0 { v + sat_dry - input } else { v };
+            (
+                squash * wet + (one - squash) * line,
+                squash * d_wet + one - squash,
+            )
+        };
+        // p, what the voltages would be with no current
+        let p = [
+            input - k * self.s[3],
+            self.s[0],
+            self.s[1],
+            self.s[2],
+            self.s[3],
+        ];
+        // v = p + K i. The input pair sees the input minus the last stage's output,
+        // each stage its state plus what its integrator gets from the stage before it
+        let voltages = |i: &[f32x4; 5]| {
+            let y4 = g[3] * (i[3] - i[4]);
+            [
+                p[0] - k * y4,
+                p[1] + g[0] * (i[0] - i[1]),
+                p[2] + g[1] * (i[1] - i[2]),
+                p[3] + g[2] * (i[2] - i[3]),
+                p[4] + y4,
+            ]
+        };
+        let max_error = f32x4::splat(0.00001);
+
+        let mut i = self.i_est;
+        let mut v = voltages(&i);
+        for _ in 0..DK_MAX_ITERATIONS {
+            let mut residue = [f32x4::splat(0.); 5];
+            let mut d = [f32x4::splat(0.); 5];
+            for n in 0..5 {
+                let (f_v, d_v) = f(n, v[n]);
+                residue[n] = f_v - i[n];
+                d[n] = d_v;
+            }
+            if residue.iter().all(|r| r.abs().simd_le(max_error).all()) {
+                break;
+            }
+            // the jacobian diag(d) K - I has the stages on a lower bidiagonal, and the feedback in the first row.
+            // Write every stage's step as alpha + beta * step[0], then the first row gives step[0]
+            let mut alpha = [f32x4::splat(0.); 5];
+            let mut beta = [one; 5];
+            for n in 1..5 {
+                let a = d[n] * g[n - 1];
+                let b = -a - one;
+                alpha[n] = (residue[n] - a * alpha[n - 1]) / b;
+                beta[n] = -a * beta[n - 1] / b;
+            }
+            let c = d[0] * k * g[3];
+            let step0 = (residue[0] - c * (alpha[4] - alpha[3])) / (c * (beta[4] - beta[3]) - one);
+            for n in 0..5 {
+                i[n] -= alpha[n] + beta[n] * step0;
+            }
+            v = voltages(&i);
+        }
+        self.i_est = i;
+        self.adaa_prev = [input, v[0], v[1], v[2], v[3], v[4]];
+        self.track_peak(&v);
+        self.vout = [v[1], v[2], v[3], v[4]];
+        self.output(i[0])
     }
 
     /// Performs a complete filter process (newton-raphson method).
     pub fn tick_newton(&mut self, input: f32x4) -> f32x4 {
         // perform filter process
-        let out = self.run_filter_newton(input * f32x4::splat(self.params.drive.value()));
+        let out = self.run_filter_newton(input * f32x4::splat(self.params.drive_gain()));
         // update ic1eq and ic2eq for next sample
         self.update_state();
-        out * f32x4::splat((1. + self.params.k_ladder.get()) / (self.params.drive.value() * 0.5))
+        out * f32x4::splat(self.makeup_gain() / (self.params.drive_gain() * 0.5))
     }
 
-    /// Performs a complete filter process (newton-raphson method).
+    /// Performs a complete filter process (dk-method).
+    pub fn tick_dk(&mut self, input: f32x4) -> f32x4 {
+        let out = self.run_filter_dk(input * f32x4::splat(self.params.drive_gain()));
+        self.update_state();
+        out * f32x4::splat(self.makeup_gain() / (self.params.drive_gain() * 0.5))
+    }
+
+    /// One sample with the solver the character and solver params pick. They all share the state, so switching doesn't jump.
+    /// Linear leaves out the drive, since it would only be a gain, and matches the newton version's level at low levels
+    pub fn tick(&mut self, input: f32x4) -> f32x4 {
+        let mut output = self.tick_solver(input);
+        if self.fade_left > 0 {
+            let fade = f32x4::splat(self.fade_left as f32 / self.fade_len as f32);
+            self.swap_fading();
+            let faded = self.tick_solver(input);
+            self.swap_fading();
+            output += fade * (faded - output);
+            self.fade_left -= 1;
+        }
+        output
+    }
+    fn tick_solver(&mut self, input: f32x4) -> f32x4 {
+        let solver = match self.params.drive.character.value() {
+            Character::Clean => Solver::Linear,
+            Character::Driven => self.params.drive.solver.value(),
+        };
+        match solver {
+            Solver::Linear => self.tick_linear(input) * f32x4::splat(self.makeup_gain() * 2.),
+            Solver::FixedPivot => self.tick_pivotal(input),
+            Solver::Newton => match self.params.filter.ladder_solver.value() {
+                LadderSolver::Newton => self.tick_newton(input),
+                LadderSolver::Dk => self.tick_dk(input),
+            },
+        }
+    }
+
+    /// Performs a complete filter process (fixed-pivot method).
     pub fn tick_pivotal(&mut self, input: f32x4) -> f32x4 {
         // perform filter process
-        let out = self.run_filter_pivotal(input * f32x4::splat(self.params.drive.value()));
+        let out = self.run_filter_pivotal(input * f32x4::splat(self.params.drive_gain()));
         // update ic1eq and ic2eq for next sample
         self.update_state();
-        out
+        out * f32x4::splat(self.makeup_gain() / (self.params.drive_gain() * 0.5))
+    }
+
+    /// Renders `input` offline, with the cutoff and res following `coefficients` the same way they do in the plugin.
+    /// `input` and `output` need to be the same length
+    pub fn process_block(
+        &mut self,
+        coefficients: &mut CoefficientSmoother,
+        input: &[f32x4],
+        output: &mut [f32x4],
+    ) {
+        assert_eq!(input.len(), output.len());
+        let len = self.params.sub_block_len();
+        for (input, output) in input.chunks(len).zip(output.chunks_mut(len)) {
+            // the ladder reads its coefficients straight from the params, so there's no matrices to update
+            coefficients.advance(input.len(), 0., 0.);
+            for (x, y) in input.iter().zip(output.iter_mut()) {
+                *y = self.tick(*x);
+            }
+        }
     }
 
     /// Performs a complete filter process (newton-raphson method).
     pub fn tick_linear(&mut self, input: f32x4) -> f32x4 {
         // perform filter process
-        // let out = self.run_filter_linear(input * f32x4::splat(self.params.drive.value));
+        // let out = self.run_filter_linear(input * f32x4::splat(self.params.drive.amount.value));
         let out = self.run_filter_linear(input);
         // update ic1eq and ic2eq for next sample
         self.update_state();
@@ -245,20 +610,206 @@ impl LadderFilter {
     }
 }
 
+/// A feedback comb, tuned to the cutoff as a pitch so key tracking makes it playable.
+/// The resonance sets the feedback, and a tanh in the loop keeps it from running away
+pub struct Comb {
+    pub params: Arc<FilterParams>,
+    line: CombLine,
+    /// the line from before `fade_reset()`, still ringing while it fades out
+    fading: CombLine,
+    /// how many samples of the fade are left, and how long it is
+    fade_left: usize,
+    fade_len: usize,
+    /// the delay of each channel in samples and the feedback, as of the last `update()`
+    delay: [f32; 2],
+    feedback: f32x4,
+    /// the largest level that went into the tanh since `take_saturation_peak()`
+    sat_peak: f32x4,
+}
+
+/// A delay line with both channels in the lanes
+struct CombLine {
+    buffer: Vec<f32x4>,
+    /// where the next sample goes
+    write: usize,
+    /// how much of the buffer has been written since it was last cleared
+    filled: usize,
+}
+
+impl CombLine {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![f32x4::splat(0.); len],
+            write: 0,
+            filled: 0,
+        }
+    }
+    /// Zeroes what's been written, which is a lot less than the whole line at any normal pitch
+    fn clear(&mut self) {
+        self.buffer[..self.filled].fill(f32x4::splat(0.));
+        self.write = 0;
+        self.filled = 0;
+    }
+    /// Returns the output, and the level that went into the tanh
+    fn tick(
+        &mut self,
+        input: f32x4,
+        delay: [f32; 2],
+        feedback: f32x4,
+        drive: f32,
+    ) -> (f32x4, f32x4) {
+        let len = self.buffer.len();
+        let mut delayed = [0.; 4];
+        for (lane, delay) in delay.iter().enumerate() {
+            // linear interpolation between the two samples either side of the delay
+            let whole = *delay as usize;
+            let frac = delay - whole as f32;
+            let newer = self.buffer[(self.write + len - whole) % len][lane];
+            let older = self.buffer[(self.write + len - whole - 1) % len][lane];
+            delayed[lane] = newer + frac * (older - newer);
+        }
+        let drive = f32x4::splat(drive);
+        let delayed = f32x4::from_array(delayed) * drive;
+        let out = input + feedback * tanh_levien(delayed) / drive;
+        self.buffer[self.write] = out;
+        self.filled = self.filled.max(self.write + 1);
+        self.write = (self.write + 1) % len;
+        (out, delayed.abs())
+    }
+    /// Writes what's in the line into `into`, stretched by `ratio` for the circuits' rate changing by that much
+    fn stretch_into(&self, into: &mut CombLine, ratio: f32) {
+        let len = self.buffer.len();
+        let n = ((self.filled as f32 * ratio) as usize).min(into.buffer.len());
+        into.clear();
+        for i in 0..n {
+            // `back` samples before the newest at the old rate
+            let back = i as f32 / ratio;
+            let whole = back as usize;
+            let frac = back - whole as f32;
+            let newer = self.buffer[(self.write + len - 1 - whole) % len];
+            let older = if whole + 1 < self.filled {
+                self.buffer[(self.write + len - 2 - whole) % len]
+            } else {
+                f32x4::splat(0.)
+            };
+            into.buffer[n - 1 - i] = newer + f32x4::splat(frac) * (older - newer);
+        }
+        into.write = n % into.buffer.len();
+        into.filled = n;
+    }
+}
+
+/// Long enough for a period of `CUTOFF_MIN` at `sample_rate`, plus the sample the interpolation reads past it
+fn comb_len(sample_rate: f32) -> usize {
+    (sample_rate / CUTOFF_MIN).ceil() as usize + 2
+}
+
+impl Comb {
+    pub fn new(params: Arc<FilterParams>) -> Self {
+        let len = comb_len(params.sample_rate.get());
+        let mut comb = Self {
+            params,
+            line: CombLine::new(len),
+            fading: CombLine::new(len),
+            fade_left: 0,
+            fade_len: 1,
+            delay: [1.; 2],
+            feedback: f32x4::splat(0.),
+            sat_peak: f32x4::splat(0.),
+        };
+        comb.update();
+        comb
+    }
+    /// Sizes the delay lines for rates up to `max_sample_rate`. They're only ever allocated here and in `new()`,
+    /// so this belongs in `initialize()` and not on the audio thread
+    pub fn allocate(&mut self, max_sample_rate: f32) {
+        let len = comb_len(max_sample_rate);
+        if len != self.line.buffer.len() {
+            self.line = CombLine::new(len);
+            self.fading = CombLine::new(len);
+            self.fade_left = 0;
+        }
+    }
+    /// Carries on at `ratio` times the rate, with what's in the line stretched to match. The lines need to have
+    /// been allocated for the higher rate. A fade from `fade_reset()` gets cut short
+    pub fn resample(&mut self, ratio: f32) {
+        self.line.stretch_into(&mut self.fading, ratio);
+        std::mem::swap(&mut self.line, &mut self.fading);
+        self.fade_left = 0;
+    }
+    /// Picks up the cutoff, res and polarity
+    pub fn update(&mut self) {
+        self.update_delay();
+        let negative = self.params.filter.comb_negative.value();
+        let feedback = self.params.comb_feedback.get();
+        self.feedback = f32x4::splat(if negative { -feedback } else { feedback });
+    }
+    /// Only the delay, for the audio rate fm that moves the cutoff every sample
+    pub fn update_delay(&mut self) {
+        let fs = self.params.sample_rate.get();
+        let negative = self.params.filter.comb_negative.value();
+        let max_delay = (self.line.buffer.len() - 2) as f32;
+        for (channel, delay) in self.delay.iter_mut().enumerate() {
+            // back from the prewarped g, which has the spread in it
+            let hz = self.params.g_channel(channel).atan() * fs / PI;
+            // flipping the feedback doubles the period, so half the delay keeps the pitch
+            let period = if negative { 0.5 * fs / hz } else { fs / hz };
+            *delay = period.clamp(1., max_delay);
+        }
+    }
+    pub fn process(&mut self, input: f32x4) -> f32x4 {
+        let drive = self.params.drive_gain();
+        let (mut output, level) = self.line.tick(input, self.delay, self.feedback, drive);
+        self.sat_peak = self.sat_peak.simd_max(level);
+        if self.fade_left > 0 {
+            let fade = f32x4::splat(self.fade_left as f32 / self.fade_len as f32);
+            let (faded, _) = self.fading.tick(input, self.delay, self.feedback, drive);
+            output += fade * (faded - output);
+            self.fade_left -= 1;
+        }
+        output
+    }
+    pub fn reset(&mut self) {
+        self.line.clear();
+        self.sat_peak = f32x4::splat(0.);
+        self.fade_left = 0;
+    }
+    /// Starts over from silence like `reset()`, but crossfades from where it was over `RESET_FADE_MS` so it
+    /// doesn't click
+    pub fn fade_reset(&mut self) {
+        std::mem::swap(&mut self.line, &mut self.fading);
+        self.reset();
+        self.fade_len = reset_fade_len(&self.params);
+        self.fade_left = self.fade_len;
+    }
+    /// Anything that isn't finite comes back around the loop, so the newest sample finds it within a period
+    pub fn is_finite(&self) -> bool {
+        let len = self.line.buffer.len();
+        self.line.buffer[(self.line.write + len - 1) % len]
+            .is_finite()
+            .all()
+    }
+    /// The largest level into the tanh since the last call
+    pub fn take_saturation_peak(&mut self) -> f32 {
+        let peak = std::mem::replace(&mut self.sat_peak, f32x4::splat(0.));
+        peak.to_array().iter().fold(0., |peak, x| x.max(peak))
+    }
+}
+
 #[test]
 fn break_ladder() {
     use rand::Rng;
-    let mut params = FilterParams::new(Arc::new(std::sync::atomic::AtomicBool::new(false)));
+    let mut params = FilterParams::new();
 
     params.sample_rate.set(44100.);
     params.update_g(20000.);
-    params.drive = nih_plug::prelude::FloatParam::new(
+    params.drive.amount = nih_plug::prelude::FloatParam::new(
         "drive",
         24.,
         nih_plug::prelude::FloatRange::Linear { min: 0., max: 24. },
     );
-    params.k_ladder.set(0.);
-    dbg!(params.g.get());
+    params.set_k_ladder(0.);
+    dbg!(params.g());
     let mut filt = LadderFilter::new(Arc::new(params));
 
     let mut rng = rand::thread_rng();
@@ -268,3 +819,613 @@ fn break_ladder() {
     }
     // filt.run_filter_newton(f32x4::splat(1.));
 }
+
+// measures the frequency of a signal from its rising zero crossings
+#[cfg(test)]
+fn zero_crossing_freq(signal: &[f32], fs: f32) -> f32 {
+    let mut first = None;
+    let mut last = 0.;
+    let mut count = 0;
+    for i in 1..signal.len() {
+        if signal[i - 1] < 0. && signal[i] >= 0. {
+            // interpolate where between the samples the crossing is
+            let t = (i - 1) as f32 + signal[i - 1] / (signal[i - 1] - signal[i]);
+            if first.is_none() {
+                first = Some(t);
+            } else {
+                count += 1;
+            }
+            last = t;
+        }
+    }
+    count as f32 * fs / (last - first.unwrap_or(last))
+}
+
+#[test]
+fn test_self_oscillation_pitch() {
+    // the ladder runs at the oversampled rate
+    for fs in [88200., 96000.] {
+        for cutoff in [1000., 5000., 12000.] {
+            for k in [4.05, 4.3, 4.6] {
+                let params = Arc::new(FilterParams::new());
+                params.sample_rate.set(fs);
+                params.set_k_ladder(k);
+                params.update_g(cutoff);
+                let mut filt = LadderFilter::new(params);
+
+                let len = 2 * fs as usize;
+                let mut out = vec![0.; len];
+                for i in 0..len {
+                    let input = if i == 0 { 1. } else { 0. };
+                    out[i] = filt.tick_newton(f32x4::splat(input))[0];
+                }
+                // measure over the last second, where the oscillation has settled
+                let freq = zero_crossing_freq(&out[len / 2..], fs);
+                let cents = 1200. * (freq / cutoff).log2();
+                assert!(
+                    cents.abs() < 5.,
+                    "fs {}, cutoff {}, k {}: oscillates at {} Hz, {} cents off",
+                    fs,
+                    cutoff,
+                    k,
+                    freq,
+                    cents
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_oscillation_onset_per_slope() {
+    use crate::filter_params::LadderSlope;
+    let fs = 88200.;
+    let mut onsets = vec![];
+    for slope in [
+        LadderSlope::LP6,
+        LadderSlope::LP12,
+        LadderSlope::LP18,
+        LadderSlope::LP24,
+    ] {
+        let mut onset = None;
+        for step in 0..20 {
+            let k = 3.9 + step as f32 * 0.01;
+            let mut params = FilterParams::new();
+            params.filter.slope = nih_plug::prelude::EnumParam::new("slope", slope);
+            params.sample_rate.set(fs);
+            params.set_k_ladder(k);
+            params.update_g(1000.);
+            let mut filt = LadderFilter::new(Arc::new(params));
+
+            // kick the filter once, then see if the ringing grows or dies out on silent input.
+            // Comparing the filter to itself makes this independent of how loud the selected stage is
+            let len = fs as usize;
+            let mut early = 0.;
+            let mut late = 0.;
+            for i in 0..len {
+                let input = if i == 0 { 1. } else { 0. };
+                let out = filt.tick_newton(f32x4::splat(input))[0];
+                if i >= len / 4 && i < len / 2 {
+                    early += out * out;
+                } else if i >= 3 * len / 4 {
+                    late += out * out;
+                }
+            }
+            if late > early {
+                onset = Some(k);
+                break;
+            }
+        }
+        onsets.push(onset.expect("the ladder should self-oscillate above k = 4"));
+    }
+    for onset in &onsets {
+        assert!((onset - onsets[0]).abs() < 0.015, "onsets: {:?}", onsets);
+    }
+}
+
+#[test]
+fn test_stable_at_cutoff_extremes() {
+    use crate::filter_params::{Circuits, CUTOFF_MAX, CUTOFF_MIN, Q_MAX};
+    use rand::Rng;
+    // the circuits' rates at 44.1 kHz, which gets oversampled, and at 192 kHz
+    for fs in [88200., 192000.] {
+        for circuit in [
+            Circuits::Ladder,
+            Circuits::SVF,
+            Circuits::SallenKey,
+            Circuits::Comb,
+        ] {
+            // far past the top of the range, like stacked modulation would ask for
+            for cutoff in [CUTOFF_MIN, CUTOFF_MAX, 4. * CUTOFF_MAX] {
+                let mut params = FilterParams::new();
+                params.filter.filter_type = nih_plug::prelude::EnumParam::new("circuit", circuit);
+                params.sample_rate.set(fs);
+                params.set_resonances(Q_MAX);
+                params.update_g(cutoff);
+                let params = Arc::new(params);
+                let mut ladder = LadderFilter::new(params.clone());
+                let mut svf = svf::Svf::new(params.clone());
+                svf.update();
+                let mut sallen_key = sallen_key::SallenKey::new(params.clone());
+                sallen_key.update();
+                let mut comb = Comb::new(params);
+
+                let mut rng = rand::thread_rng();
+                for _ in 0..4800 {
+                    let input = f32x4::splat(4. * rng.gen::<f32>() - 2.);
+                    let out = match circuit {
+                        Circuits::Ladder => ladder.tick_newton(input),
+                        Circuits::SVF => svf.process(input),
+                        Circuits::SallenKey => sallen_key.process(input),
+                        Circuits::Comb => comb.process(input),
+                    };
+                    assert!(
+                        out[0].is_finite() && out[0].abs() < 100.,
+                        "{:?} at {} Hz, fs {}: {}",
+                        circuit,
+                        cutoff,
+                        fs,
+                        out[0]
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_spread() {
+    use crate::filter_params::Circuits;
+    let fs = 88200.;
+    let run = |circuit, cutoff, spread| {
+        let mut params = FilterParams::new();
+        params.filter.filter_type = nih_plug::prelude::EnumParam::new("circuit", circuit);
+        params.sample_rate.set(fs);
+        params.set_resonances(5.);
+        params.set_spread(spread);
+        params.update_g(cutoff);
+        let params = Arc::new(params);
+        let mut ladder = LadderFilter::new(params.clone());
+        let mut svf = svf::Svf::new(params.clone());
+        svf.update();
+        let mut sallen_key = sallen_key::SallenKey::new(params.clone());
+        sallen_key.update();
+        let mut comb = Comb::new(params);
+        (0..2000)
+            .map(|i| {
+                let input = f32x4::splat(if i == 0 { 0.5 } else { 0. });
+                match circuit {
+                    Circuits::Ladder => ladder.tick_newton(input),
+                    Circuits::SVF => svf.process(input),
+                    Circuits::SallenKey => sallen_key.process(input),
+                    Circuits::Comb => comb.process(input),
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    for circuit in [
+        Circuits::Ladder,
+        Circuits::SVF,
+        Circuits::SallenKey,
+        Circuits::Comb,
+    ] {
+        // no spread leaves both sides identical
+        for out in run(circuit, 1000., 0.) {
+            assert_eq!(out[0].to_bits(), out[1].to_bits(), "{:?}", circuit);
+        }
+        // an octave of spread puts each side where an unspread filter an octave away would be.
+        // The ladder's lanes share their newton iterations, so they're only about the same
+        let spread = run(circuit, 1000., 1.);
+        let (low, high) = (run(circuit, 500., 0.), run(circuit, 2000., 0.));
+        let peak = low
+            .iter()
+            .chain(&high)
+            .map(|out| out[0].abs().max(out[1].abs()));
+        let tolerance = 0.02 * peak.fold(0., f32::max);
+        for ((out, low), high) in spread.iter().zip(&low).zip(&high) {
+            assert!((out[0] - low[0]).abs() < tolerance, "{:?}", circuit);
+            assert!((out[1] - high[1]).abs() < tolerance, "{:?}", circuit);
+        }
+        assert!(spread.iter().any(|out| (out[0] - out[1]).abs() > 1e-3));
+    }
+}
+
+#[test]
+fn test_saturation_derivatives() {
+    // compare the analytic derivatives to finite differences, away from the kinks
+    for sat in nonlinearity::Saturation::ALL {
+        for x in [-3., -0.9, -0.2, 0.3, 0.95, 2.5] {
+            let h = 1e-6;
+            let (_, d) = sat.eval_f64(x);
+            let numeric = (sat.eval_f64(x + h).0 - sat.eval_f64(x - h).0) / (2. * h);
+            assert!(
+                (d - numeric).abs() < 1e-3,
+                "{:?} at {x}: {d} vs {numeric}",
+                sat
+            );
+
+            let (f_simd, d_simd) = sat.eval(f32x4::splat(x as f32));
+            assert!((f_simd[0] as f64 - sat.eval_f64(x).0).abs() < 1e-4);
+            assert!((d_simd[0] as f64 - d).abs() < 1e-3);
+        }
+    }
+}
+
+#[test]
+fn test_clean_ladder() {
+    let make_params = |character| {
+        let mut params = FilterParams::new();
+        params.drive.character = nih_plug::prelude::EnumParam::new("character", character);
+        params.sample_rate.set(48000.);
+        params.update_g(1000.);
+        params.set_resonances(3.);
+        Arc::new(params)
+    };
+    let mut clean = LadderFilter::new(make_params(Character::Clean));
+    let mut driven = LadderFilter::new(make_params(Character::Driven));
+    // quiet enough that the saturation stays linear, so switching between them doesn't change the level
+    let mut peak: f32 = 0.;
+    let mut max_error: f32 = 0.;
+    for i in 0..4000 {
+        let x = f32x4::splat(1e-3 * ((i as f32 * 0.07).sin() + (i as f32 * 0.011).sin()));
+        let (a, b) = (clean.tick(x)[0], driven.tick(x)[0]);
+        peak = peak.max(b.abs());
+        max_error = max_error.max((a - b).abs());
+    }
+    assert!(max_error < 0.01 * peak, "{} off, peak {}", max_error, peak);
+}
+
+#[test]
+fn test_dk_ladder() {
+    let make_params = |drive: f32, q: f32| {
+        let mut params = FilterParams::new();
+        params.drive.amount = nih_plug::prelude::FloatParam::new(
+            "drive",
+            drive,
+            nih_plug::prelude::FloatRange::Linear { min: 0., max: 24. },
+        );
+        params.sample_rate.set(96000.);
+        params.update_g(2000.);
+        params.set_resonances(q);
+        Arc::new(params)
+    };
+    let signal = |i: usize| (i as f32 * 0.013).sin() + 0.5 * (i as f32 * 0.0031).sin();
+    // at moderate settings both solve the same equations, so they only differ by their tolerance
+    let params = make_params(2., 3.);
+    let mut newton = LadderFilter::new(params.clone());
+    let mut dk = LadderFilter::new(params);
+    let mut peak: f32 = 0.;
+    let mut max_error: f32 = 0.;
+    for i in 0..8000 {
+        let x = f32x4::splat(0.5 * signal(i));
+        let (a, b) = (newton.tick_newton(x)[0], dk.tick_dk(x)[0]);
+        peak = peak.max(a.abs());
+        max_error = max_error.max((a - b).abs());
+    }
+    assert!(max_error < 1e-3 * peak, "{} off, peak {}", max_error, peak);
+
+    // far past anything the drive knob goes to, into self oscillation
+    let mut dk = LadderFilter::new(make_params(24., 40.));
+    for i in 0..8000 {
+        let out = dk.tick_dk(f32x4::splat(10. * signal(i)));
+        assert!(out.is_finite().all());
+    }
+    assert!(dk.is_finite());
+}
+
+#[test]
+fn test_newton_hard_clip() {
+    use nonlinearity::Saturation;
+    // the hard clip's derivative is 0 past the knee, which newton doesn't always find its way out of
+    let mut params = FilterParams::new();
+    params.drive.saturation = nih_plug::prelude::EnumParam::new("Saturation", Saturation::HardClip);
+    params.drive.amount = nih_plug::prelude::FloatParam::new(
+        "drive",
+        24.,
+        nih_plug::prelude::FloatRange::Linear { min: 0., max: 24. },
+    );
+    params.restore_saturation();
+    params.sample_rate.set(48000.);
+    params.update_g(2000.);
+    params.set_resonances(crate::filter_params::Q_SELF_OSC);
+    let mut ladder = LadderFilter::new(Arc::new(params));
+    for i in 0..8000 {
+        let out = ladder.tick_newton(f32x4::splat(4. * (i as f32 * 0.013).sin()));
+        assert!(out.is_finite().all(), "{}", i);
+    }
+}
+
+#[test]
+fn test_ladder_solvers() {
+    let make_params = |solver| {
+        let mut params = FilterParams::new();
+        params.drive.solver = nih_plug::prelude::EnumParam::new("solver", solver);
+        params.sample_rate.set(48000.);
+        params.update_g(1000.);
+        params.set_resonances(3.);
+        Arc::new(params)
+    };
+    let input = |i: usize| (i as f32 * 0.07).sin() + (i as f32 * 0.011).sin();
+    let [mut newton, mut pivotal, mut linear] =
+        [Solver::Newton, Solver::FixedPivot, Solver::Linear]
+            .map(|solver| LadderFilter::new(make_params(solver)));
+    // quiet enough that the saturation stays linear, so they all agree
+    let mut peak: f32 = 0.;
+    let (mut pivotal_error, mut linear_error): (f32, f32) = (0., 0.);
+    for i in 0..4000 {
+        let x = f32x4::splat(1e-3 * input(i));
+        let reference = newton.tick(x)[0];
+        peak = peak.max(reference.abs());
+        pivotal_error = pivotal_error.max((pivotal.tick(x)[0] - reference).abs());
+        linear_error = linear_error.max((linear.tick(x)[0] - reference).abs());
+    }
+    assert!(
+        pivotal_error < 0.01 * peak,
+        "{} off, peak {}",
+        pivotal_error,
+        peak
+    );
+    assert!(
+        linear_error < 0.01 * peak,
+        "{} off, peak {}",
+        linear_error,
+        peak
+    );
+
+    // switching to the fixed pivot halfway through a loud signal carries on from the same state
+    let params = make_params(Solver::Newton);
+    let mut newton = LadderFilter::new(params.clone());
+    let mut switched = LadderFilter::new(params);
+    let mut peak: f32 = 0.;
+    let mut max_error: f32 = 0.;
+    for i in 0..4000 {
+        let x = f32x4::splat(0.5 * input(i));
+        let reference = newton.tick_newton(x)[0];
+        let out = if i < 2000 {
+            switched.tick_newton(x)[0]
+        } else {
+            switched.tick_pivotal(x)[0]
+        };
+        peak = peak.max(reference.abs());
+        max_error = max_error.max((out - reference).abs());
+    }
+    assert!(max_error < 0.1 * peak, "{} off, peak {}", max_error, peak);
+}
+
+#[test]
+fn test_hq_saturation_aliasing() {
+    let make_params = |hq| {
+        let mut params = FilterParams::new();
+        params.drive.hq_saturation = nih_plug::prelude::BoolParam::new("hq", hq);
+        params.drive.amount = nih_plug::prelude::FloatParam::new(
+            "drive",
+            10.,
+            nih_plug::prelude::FloatRange::Linear { min: 0., max: 24. },
+        );
+        params.sample_rate.set(48000.);
+        params.update_g(16000.);
+        params.set_resonances(0.7);
+        Arc::new(params)
+    };
+    // 4410 Hz lands on bin 441, and so do its harmonics and wherever they fold back to
+    let (fs, len, freq_bin) = (48000., 4800, 441);
+    let freq = freq_bin as f32 * fs / len as f32;
+    // energy at everything that isn't a harmonic, relative to everything
+    let aliasing = |hq| {
+        let mut filt = LadderFilter::new(make_params(hq));
+        let signal: Vec<f32> = (0..2 * len)
+            .map(|i| {
+                let x = (std::f32::consts::TAU * freq * i as f32 / fs).sin();
+                filt.tick_newton(f32x4::splat(x))[0]
+            })
+            .skip(len)
+            .collect();
+        let (mut alias, mut total) = (0., 0.);
+        for bin in 1..len / 2 {
+            let w = std::f32::consts::TAU * bin as f32 / len as f32;
+            let (mut re, mut im) = (0., 0.);
+            for (i, x) in signal.iter().enumerate() {
+                re += x * (w * i as f32).cos();
+                im += x * (w * i as f32).sin();
+            }
+            let power = re * re + im * im;
+            total += power;
+            if bin % freq_bin != 0 {
+                alias += power;
+            }
+        }
+        alias / total
+    };
+    let (plain, hq) = (aliasing(false), aliasing(true));
+    // at least 10 dB less
+    assert!(hq < 0.1 * plain, "{} vs {}", hq, plain);
+}
+
+#[test]
+fn test_fade_reset() {
+    use crate::filter_params::Circuits;
+    let fs = 88200.;
+    let make_params = || {
+        let mut params = FilterParams::new();
+        params.sample_rate.set(fs);
+        params.set_resonances(5.);
+        params.update_g(1000.);
+        Arc::new(params)
+    };
+    // plays `before`, starts over with a fade, without one or not at all, and then plays the same note whatever came
+    // before. Returns everything after the reset
+    let run = |circuit, before: &dyn Fn(usize) -> f32, reset: Option<bool>| {
+        let params = make_params();
+        let mut svf = svf::Svf::new(params.clone());
+        svf.update();
+        let mut sallen_key = sallen_key::SallenKey::new(params.clone());
+        sallen_key.update();
+        let comb = Comb::new(params.clone());
+        let mut circuits = (LadderFilter::new(params), svf, sallen_key, comb);
+        let tick = |(ladder, svf, sallen_key, comb): &mut (
+            LadderFilter,
+            svf::Svf,
+            sallen_key::SallenKey,
+            Comb,
+        ),
+                    input: f32| {
+            let input = f32x4::splat(input);
+            match circuit {
+                Circuits::Ladder => ladder.tick(input),
+                Circuits::SVF => svf.process(input),
+                Circuits::SallenKey => sallen_key.process(input),
+                Circuits::Comb => comb.process(input),
+            }
+        };
+        for i in 0..1000 {
+            tick(&mut circuits, before(i));
+        }
+        let (ladder, svf, sallen_key, comb) = &mut circuits;
+        match (circuit, reset) {
+            (_, None) => (),
+            (Circuits::Ladder, Some(true)) => ladder.fade_reset(),
+            (Circuits::Ladder, Some(false)) => ladder.reset(),
+            (Circuits::SVF, Some(true)) => svf.fade_reset(),
+            (Circuits::SVF, Some(false)) => svf.reset(),
+            (Circuits::SallenKey, Some(true)) => sallen_key.fade_reset(),
+            (Circuits::SallenKey, Some(false)) => sallen_key.reset(),
+            (Circuits::Comb, Some(true)) => comb.fade_reset(),
+            (Circuits::Comb, Some(false)) => comb.reset(),
+        }
+        (0..2000)
+            .map(|i| tick(&mut circuits, 0.5 * (i as f32 * 0.03).sin()))
+            .collect::<Vec<_>>()
+    };
+    let fade_len = (RESET_FADE_MS / 1000. * fs).round() as usize;
+    let sine = |i: usize| (i as f32 * 0.07).sin();
+    let saw = |i: usize| 0.3 * ((i as f32 * 0.013).fract() * 2. - 1.);
+    for circuit in [
+        Circuits::Ladder,
+        Circuits::SVF,
+        Circuits::SallenKey,
+        Circuits::Comb,
+    ] {
+        let after_sine = run(circuit, &sine, Some(true));
+        let after_saw = run(circuit, &saw, Some(true));
+        // once the fade is done, only the note matters
+        for (a, b) in after_sine[fade_len..].iter().zip(&after_saw[fade_len..]) {
+            assert_eq!(a[0].to_bits(), b[0].to_bits(), "{:?}", circuit);
+        }
+        assert!(after_sine[..fade_len] != after_saw[..fade_len]);
+        // and the fade starts out where it would have carried on, where a plain reset jumps
+        let kept = run(circuit, &sine, None);
+        let hard = run(circuit, &sine, Some(false));
+        let (jump, hard_jump) = (
+            (after_sine[0] - kept[0])[0].abs(),
+            (hard[0] - kept[0])[0].abs(),
+        );
+        assert!(hard_jump > 0.05, "{:?} {}", circuit, hard_jump);
+        assert!(jump < 1e-5, "{:?} {}", circuit, jump);
+    }
+}
+
+#[test]
+fn test_comb_tuning() {
+    let fs = 88200.;
+    for negative in [false, true] {
+        for cutoff in [110., 440., 1234.5] {
+            let mut params = FilterParams::new();
+            params.filter.comb_negative = nih_plug::prelude::BoolParam::new("negative", negative);
+            params.sample_rate.set(fs);
+            params.set_resonances(5.);
+            params.update_g(cutoff);
+            let mut comb = Comb::new(Arc::new(params));
+            // small enough to stay out of the tanh
+            let out: Vec<f32> = (0..fs as usize / 50)
+                .map(|i| comb.process(f32x4::splat(if i == 0 { 0.01 } else { 0. })))
+                .map(|out| out[0])
+                .collect();
+            // the first echo, shared between the samples either side of the delay, is centered on it
+            let period = fs / cutoff;
+            let delay = if negative { 0.5 * period } else { period };
+            let echo = &out[1..(1.5 * delay) as usize];
+            let weight: f32 = echo.iter().sum();
+            let center = 1.
+                + echo
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| i as f32 * x)
+                    .sum::<f32>()
+                    / weight;
+            assert!((center - delay).abs() < 1e-2, "{} vs {}", center, delay);
+            // flipped when it's negative, which the next echo flips back
+            assert_eq!(weight < 0., negative);
+        }
+    }
+}
+
+#[test]
+fn test_comb_resample() {
+    // ringing on its own at 200 Hz, then carrying on at twice the rate
+    let params = Arc::new(FilterParams::new());
+    params.sample_rate.set(48000.);
+    params.update_g(200.);
+    params.set_resonances(20.);
+    let mut comb = Comb::new(params.clone());
+    comb.allocate(96000.);
+    comb.update();
+    let mut last = 0.;
+    for i in 0..4800 {
+        let x = if i < 240 {
+            (2. * PI * 200. / 48000. * i as f32).sin()
+        } else {
+            0.
+        };
+        last = comb.process(f32x4::splat(0.1 * x))[0];
+    }
+    params.sample_rate.set(96000.);
+    params.update_g(200.);
+    comb.resample(2.);
+    comb.update();
+    let out: Vec<f32> = (0..960)
+        .map(|_| comb.process(f32x4::splat(0.))[0])
+        .collect();
+    // no jump, the sample at twice the rate is about halfway to the next one
+    let step = 2. * PI * 200. / 96000. * 0.1;
+    assert!((out[0] - last).abs() < 1.5 * step, "{} {}", last, out[0]);
+    // and it keeps ringing at the same pitch, so a period later it's back where it was
+    for i in 0..480 {
+        assert!(
+            (out[i + 480] - out[i]).abs() < 0.2 * step.max(out[i].abs()),
+            "{}",
+            i
+        );
+    }
+}
+
+#[test]
+fn test_feedback_drive() {
+    let make_params = |feedback: f32| {
+        let params = FilterParams::new();
+        params.sample_rate.set(48000.);
+        params.update_g(1000.);
+        params.set_resonances(10.);
+        params.feedback_drive.set(feedback);
+        Arc::new(params)
+    };
+    // the peak of a sine at the cutoff, once the resonance has built up
+    let peak = |feedback: f32, level: f32| {
+        let mut ladder = LadderFilter::new(make_params(feedback));
+        let mut peak: f32 = 0.;
+        for i in 0..9600 {
+            let x = level * (2. * PI * 1000. / 48000. * i as f32).sin();
+            let out = ladder.tick(f32x4::splat(x))[0];
+            if i >= 4800 {
+                peak = peak.max(out.abs());
+            }
+        }
+        peak
+    };
+    let max = crate::filter_params::FEEDBACK_DRIVE_MAX;
+    // quiet, the feedback's saturation stays linear and leaves the resonance alone
+    let (clean, driven) = (peak(0., 1e-3), peak(max, 1e-3));
+    assert!((driven / clean - 1.).abs() < 0.02, "{} {}", clean, driven);
+    // loud, it squashes the resonance
+    let (clean, driven) = (peak(0., 0.1), peak(max, 0.1));
+    assert!(driven < 0.8 * clean, "{} {}", clean, driven);
+}
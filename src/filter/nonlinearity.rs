@@ -0,0 +1,359 @@
+// the saturating functions the solvers can use, and the registry that turns them into the saturation parameter
+use core_simd::simd::*;
+use nih_plug::prelude::Enum;
+
+use crate::utils::{solver_tanh, solver_tanh_f64};
+
+/// A saturating function for the solvers.
+///
+/// The newton solvers need the derivative along with the value. The antiderivative is optional,
+/// it's only there for antiderivative anti-aliasing
+pub trait Nonlinearity {
+    /// Stable name that gets saved with the plugin state. Never change it once released
+    fn id(&self) -> &'static str;
+    /// The value and the derivative
+    fn eval(&self, x: f32x4) -> (f32x4, f32x4);
+    /// Double precision version of `eval()` for the DK-method solvers
+    fn eval_f64(&self, x: f64) -> (f64, f64);
+    fn antiderivative(&self, _x: f64) -> Option<f64> {
+        None
+    }
+}
+
+pub struct Tanh;
+impl Nonlinearity for Tanh {
+    fn id(&self) -> &'static str {
+        "tanh"
+    }
+    #[inline(always)]
+    fn eval(&self, x: f32x4) -> (f32x4, f32x4) {
+        let t = solver_tanh(x);
+        (t, f32x4::splat(1.) - t * t)
+    }
+    #[inline(always)]
+    fn eval_f64(&self, x: f64) -> (f64, f64) {
+        let t = solver_tanh_f64(x);
+        (t, 1. - t * t)
+    }
+    fn antiderivative(&self, x: f64) -> Option<f64> {
+        Some(log_cosh(x))
+    }
+}
+
+/// ln(cosh(x)), the antiderivative of tanh, in a form that doesn't overflow where cosh does, past |x| of about 710
+fn log_cosh(x: f64) -> f64 {
+    let x = x.abs();
+    x + (-2. * x).exp().ln_1p() - std::f64::consts::LN_2
+}
+
+pub struct Asinh;
+impl Nonlinearity for Asinh {
+    fn id(&self) -> &'static str {
+        "asinh"
+    }
+    #[inline(always)]
+    fn eval(&self, x: f32x4) -> (f32x4, f32x4) {
+        let one = f32x4::splat(1.);
+        (
+            f32x4::from_array(x.to_array().map(f32::asinh)),
+            one / (x * x + one).sqrt(),
+        )
+    }
+    #[inline(always)]
+    fn eval_f64(&self, x: f64) -> (f64, f64) {
+        (x.asinh(), 1. / (x * x + 1.).sqrt())
+    }
+    fn antiderivative(&self, x: f64) -> Option<f64> {
+        Some(x * x.asinh() - (x * x + 1.).sqrt())
+    }
+}
+
+/// Clips the negative half twice as hard as the positive one, so it adds a dc offset to the output.
+/// The dc filter in front of the circuits doesn't remove that, since it's created inside the circuit
+pub struct DiodePair;
+impl Nonlinearity for DiodePair {
+    fn id(&self) -> &'static str {
+        "diode_pair"
+    }
+    #[inline(always)]
+    fn eval(&self, x: f32x4) -> (f32x4, f32x4) {
+        // 0.5 * tanh(2x) for the negative half, which has the same slope at 0
+        let neg = x.simd_lt(f32x4::splat(0.));
+        let t = solver_tanh(neg.select(f32x4::splat(2.) * x, x));
+        (
+            neg.select(f32x4::splat(0.5) * t, t),
+            f32x4::splat(1.) - t * t,
+        )
+    }
+    #[inline(always)]
+    fn eval_f64(&self, x: f64) -> (f64, f64) {
+        if x < 0. {
+            let t = solver_tanh_f64(2. * x);
+            (0.5 * t, 1. - t * t)
+        } else {
+            let t = solver_tanh_f64(x);
+            (t, 1. - t * t)
+        }
+    }
+    fn antiderivative(&self, x: f64) -> Option<f64> {
+        Some(if x < 0. {
+            0.25 * log_cosh(2. * x)
+        } else {
+            log_cosh(x)
+        })
+    }
+}
+
+pub struct HardClip;
+impl Nonlinearity for HardClip {
+    fn id(&self) -> &'static str {
+        "hard_clip"
+    }
+    #[inline(always)]
+    fn eval(&self, x: f32x4) -> (f32x4, f32x4) {
+        let one = f32x4::splat(1.);
+        let clipped = x.abs().simd_gt(one);
+        (
+            clipped.select(x.simd_lt(f32x4::splat(0.)).select(-one, one), x),
+            clipped.select(f32x4::splat(0.), one),
+        )
+    }
+    #[inline(always)]
+    fn eval_f64(&self, x: f64) -> (f64, f64) {
+        if x.abs() > 1. {
+            (x.signum(), 0.)
+        } else {
+            (x, 1.)
+        }
+    }
+    fn antiderivative(&self, x: f64) -> Option<f64> {
+        Some(if x.abs() > 1. {
+            x.abs() - 0.5
+        } else {
+            0.5 * x * x
+        })
+    }
+}
+
+/// Builds the `Saturation` enum from the list of nonlinearities, so every entry shows up in the parameter.
+/// The solvers match on the enum, so each call goes straight to the implementation without dynamic dispatch
+macro_rules! nonlinearities {
+    ($($(#[$meta:meta])* $variant:ident: $name:literal => $imp:expr,)*) => {
+        #[derive(Enum, Debug, PartialEq, Clone, Copy)]
+        pub enum Saturation {
+            $($(#[$meta])* #[name = $name] $variant,)*
+        }
+
+        impl Saturation {
+            /// Every registered nonlinearity, in the order of the parameter
+            pub const ALL: &'static [Saturation] = &[$($(#[$meta])* Saturation::$variant,)*];
+
+            pub fn nonlinearity(self) -> &'static dyn Nonlinearity {
+                match self {
+                    $($(#[$meta])* Saturation::$variant => &$imp,)*
+                }
+            }
+            /// The saturating function and its derivative, as needed by the newton solvers.
+            #[inline(always)]
+            pub fn eval(&self, x: f32x4) -> (f32x4, f32x4) {
+                match self {
+                    $($(#[$meta])* Saturation::$variant => $imp.eval(x),)*
+                }
+            }
+            /// Double precision version of `eval()` for the DK-method solvers
+            #[inline(always)]
+            pub fn eval_f64(&self, x: f64) -> (f64, f64) {
+                match self {
+                    $($(#[$meta])* Saturation::$variant => $imp.eval_f64(x),)*
+                }
+            }
+        }
+    };
+}
+
+// To add a nonlinearity, implement `Nonlinearity` for it and add it here. Entries can be behind a feature,
+// like `#[cfg(feature = "my-saturator")] MySaturator: "My Saturator" => MySaturator,`.
+// The state stores the `id()`, so reordering the list or building without an entry doesn't break saved projects.
+// Tanh has to stay, it's what ids missing from the build fall back to
+nonlinearities! {
+    Tanh: "Tanh" => Tanh,
+    Asinh: "Asinh" => Asinh,
+    DiodePair: "Diode Pair" => DiodePair,
+    HardClip: "Hard Clip" => HardClip,
+}
+
+/// Below this difference between successive inputs the antiderivative's difference quotient loses its precision
+const ADAA_EPS: f64 = 1e-6;
+
+impl Saturation {
+    /// First order antiderivative anti-aliasing. The average of the function between `prev`, the input from
+    /// the sample before, and `x`, along with its derivative by `x`. Gets evaluated at the midpoint when they're
+    /// too close for the difference quotient, and falls back to `eval()` without an antiderivative
+    pub fn eval_adaa(&self, x: f32x4, prev: f32x4) -> (f32x4, f32x4) {
+        let nonlinearity = self.nonlinearity();
+        let mut value = [0.; 4];
+        let mut derivative = [0.; 4];
+        for lane in 0..4 {
+            let (x, prev) = (x[lane] as f64, prev[lane] as f64);
+            let diff = x - prev;
+            let (f, d) = match (
+                nonlinearity.antiderivative(x),
+                nonlinearity.antiderivative(prev),
+            ) {
+                (Some(a), Some(b)) if diff.abs() > ADAA_EPS => {
+                    let average = (a - b) / diff;
+                    (average, (nonlinearity.eval_f64(x).0 - average) / diff)
+                }
+                (Some(_), Some(_)) => {
+                    let (f, d) = nonlinearity.eval_f64(0.5 * (x + prev));
+                    (f, 0.5 * d)
+                }
+                _ => nonlinearity.eval_f64(x),
+            };
+            value[lane] = f as f32;
+            derivative[lane] = d as f32;
+        }
+        (f32x4::from_array(value), f32x4::from_array(derivative))
+    }
+    pub fn id(self) -> &'static str {
+        self.nonlinearity().id()
+    }
+    /// Looks up a saved id, which might be from a build with a different set of nonlinearities
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|sat| sat.id() == id)
+    }
+}
+
+#[test]
+fn test_ids_round_trip() {
+    for (i, sat) in Saturation::ALL.iter().enumerate() {
+        assert_eq!(Saturation::from_id(sat.id()), Some(*sat));
+        // the ids have to be unique for the lookup to work
+        assert!(Saturation::ALL[..i]
+            .iter()
+            .all(|other| other.id() != sat.id()));
+    }
+    assert_eq!(Saturation::from_id("not_in_this_build"), None);
+}
+
+#[test]
+fn test_antiderivatives() {
+    for sat in Saturation::ALL {
+        let nonlinearity = sat.nonlinearity();
+        for x in [-3., -0.9, -0.2, 0.3, 0.95, 2.5] {
+            let h = 1e-5;
+            if let (Some(a), Some(b)) = (
+                nonlinearity.antiderivative(x + h),
+                nonlinearity.antiderivative(x - h),
+            ) {
+                let numeric = (a - b) / (2. * h);
+                let (f, _) = nonlinearity.eval_f64(x);
+                assert!((numeric - f).abs() < 1e-3, "{:?} at {}", sat, x);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn params_with(
+    saturation: Saturation,
+    id: &str,
+) -> std::sync::Arc<crate::filter_params::FilterParams> {
+    use crate::filter_params::FilterParams;
+    let mut params = FilterParams::new();
+    params.drive.saturation = nih_plug::prelude::EnumParam::new("Saturation", saturation);
+    *params.saturation_id.write().unwrap() = id.to_string();
+    params.restore_saturation();
+    std::sync::Arc::new(params)
+}
+
+#[test]
+fn test_restore_saturation_state() {
+    // the id wins over the index the parameter was saved with
+    let params = params_with(Saturation::Tanh, "hard_clip");
+    assert_eq!(params.saturation(), Saturation::HardClip);
+    // a nonlinearity this build doesn't have falls back to tanh, and gets saved as tanh from then on
+    let params = params_with(Saturation::Asinh, "only_in_some_fork");
+    assert_eq!(params.saturation(), Saturation::Tanh);
+    assert_eq!(*params.saturation_id.read().unwrap(), "tanh");
+    // state from before the id existed keeps the parameter
+    let params = params_with(Saturation::DiodePair, "");
+    assert_eq!(params.saturation(), Saturation::DiodePair);
+    params.sync_saturation_id();
+    assert_eq!(*params.saturation_id.read().unwrap(), "diode_pair");
+}
+
+#[test]
+fn test_solvers_converge() {
+    use crate::filter::{sallen_key::SallenKey, svf::Svf, LadderFilter};
+    use crate::utils::AtomicOps;
+
+    for sat in Saturation::ALL {
+        let params = params_with(*sat, sat.id());
+        params.sample_rate.set(96000.);
+        params.update_g(1000.);
+        params.set_resonances(5.);
+        let mut ladder = LadderFilter::new(params.clone());
+        let mut svf = Svf::new(params.clone());
+        svf.update();
+        let mut sallen_key = SallenKey::new(params.clone());
+        sallen_key.update();
+        // loud enough to drive every nonlinearity far into saturation
+        for i in 0..9600 {
+            let input = f32x4::splat(4. * (i as f32 * 0.02).sin());
+            for out in [
+                ladder.tick_newton(input),
+                svf.process(input),
+                sallen_key.process(input),
+            ] {
+                assert!(
+                    out[0].is_finite() && out[0].abs() < 100.,
+                    "{:?}: {} at {}",
+                    sat,
+                    out[0],
+                    i
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_adaa() {
+    for sat in Saturation::ALL {
+        for (x, prev) in [(0.5, 0.5), (2., 2. + 1e-8), (-1.5, 0.7), (3., -3.)] {
+            let (value, derivative) = sat.eval_adaa(f32x4::splat(x), f32x4::splat(prev));
+            // the average over the range between the two
+            let n = 1000;
+            let mut average = 0.;
+            for i in 0..n {
+                let t = prev as f64 + (x - prev) as f64 * (i as f64 + 0.5) / n as f64;
+                average += sat.eval_f64(t).0 / n as f64;
+            }
+            assert!(
+                (value[0] as f64 - average).abs() < 1e-3,
+                "{:?} at {}",
+                sat,
+                x
+            );
+            // and the derivative matches the value's slope
+            let h = 1e-2;
+            let (above, _) = sat.eval_adaa(f32x4::splat(x + h), f32x4::splat(prev));
+            let (below, _) = sat.eval_adaa(f32x4::splat(x - h), f32x4::splat(prev));
+            let numeric = (above[0] - below[0]) / (2. * h);
+            assert!((numeric - derivative[0]).abs() < 1e-2, "{:?} at {}", sat, x);
+        }
+        // far past where cosh overflows, which a ladder stage can get to at full drive
+        for (x, prev) in [(800., 799.), (-1000., 1000.)] {
+            let (value, derivative) = sat.eval_adaa(f32x4::splat(x), f32x4::splat(prev));
+            assert!(
+                value[0].is_finite() && derivative[0].is_finite(),
+                "{:?} at {}",
+                sat,
+                x
+            );
+        }
+    }
+    assert!((log_cosh(3.) - 3f64.cosh().ln()).abs() < 1e-12);
+    assert!((log_cosh(-1000.) - (1000. - std::f64::consts::LN_2)).abs() < 1e-9);
+}
@@ -1,4 +1,5 @@
-use core_simd::simd::f32x4;
+use crate::utils::tanh_pade;
+use core_simd::simd::*;
 
 // basic DC-filter from Understanding Digital Signal Processing by Richard Lyons
 pub struct DcFilter {
@@ -19,7 +20,19 @@ impl Default for DcFilter {
     }
 }
 
+/// where the dc blocker on the output sits
+pub const DC_BLOCKER_HZ: f32 = 5.;
+
 impl DcFilter {
+    /// A filter with its corner at `fc`, for running at `fs`
+    pub fn new(fc: f32, fs: f32) -> Self {
+        let mut filter = Self::default();
+        filter.set_cutoff(fc, fs);
+        filter
+    }
+    pub fn set_cutoff(&mut self, fc: f32, fs: f32) {
+        self.alpha = f32x4::splat((-2. * std::f32::consts::PI * fc / fs).exp());
+    }
     pub fn process(&mut self, input: f32x4) -> f32x4 {
         let y_new = input - self.x0 + self.alpha * self.y0;
         self.x0 = input;
@@ -27,4 +40,179 @@ impl DcFilter {
 
         y_new
     }
+    pub fn reset(&mut self) {
+        self.y0 = f32x4::splat(0.);
+        self.x0 = f32x4::splat(0.);
+    }
+    pub fn is_finite(&self) -> bool {
+        (self.y0 + self.x0).is_finite().all()
+    }
+}
+
+/// The output limiter leaves everything below this alone, about -3 dBFS
+pub const LIMITER_KNEE: f32 = 0.7;
+
+/// Soft clips `input` so it can't go past 0 dBFS. Straight up to the knee, and a tanh curve from there to full scale,
+/// with the slope matching where they meet. There's no state, so no latency and nothing to release
+pub fn soft_limit(input: f32x4) -> f32x4 {
+    let knee = f32x4::splat(LIMITER_KNEE);
+    let headroom = f32x4::splat(1. - LIMITER_KNEE);
+    let level = input.abs();
+    let over = (level - knee) / headroom;
+    let limited = knee + headroom * tanh_pade(over);
+    let limited = input.simd_lt(f32x4::splat(0.)).select(-limited, limited);
+    level.simd_gt(knee).select(limited, input)
+}
+
+/// level of the dc offset that counts as a problem, about -26 dBFS
+pub const DC_THRESHOLD: f32 = 0.05;
+/// rms level of the content below ~5 Hz that counts as a problem
+pub const SUBSONIC_THRESHOLD: f32 = 0.1;
+
+// Watches the input for dc offset and subsonic content, which make the circuits clip one polarity earlier than the other.
+// Only measures, the signal is left alone
+pub struct DcDetector {
+    // very slow running mean, i.e. the dc offset
+    mean: f32x4,
+    // 2-pole lowpass at 5 Hz of the input minus the offset
+    lp1: f32x4,
+    lp2: f32x4,
+    // smoothed square of the lowpass
+    energy: f32x4,
+
+    a_mean: f32x4,
+    a_lp: f32x4,
+    a_energy: f32x4,
+}
+
+impl Default for DcDetector {
+    fn default() -> Self {
+        let mut detector = Self {
+            mean: f32x4::splat(0.),
+            lp1: f32x4::splat(0.),
+            lp2: f32x4::splat(0.),
+            energy: f32x4::splat(0.),
+            a_mean: f32x4::splat(0.),
+            a_lp: f32x4::splat(0.),
+            a_energy: f32x4::splat(0.),
+        };
+        detector.set_sample_rate(48000.);
+        detector
+    }
+}
+
+impl DcDetector {
+    pub fn set_sample_rate(&mut self, fs: f32) {
+        let one_pole = |fc: f32| f32x4::splat((-2. * std::f32::consts::PI * fc / fs).exp());
+        self.a_mean = one_pole(0.2);
+        self.a_lp = one_pole(5.);
+        self.a_energy = one_pole(1.);
+    }
+    pub fn process(&mut self, input: f32x4) {
+        let one = f32x4::splat(1.);
+        self.mean = input + self.a_mean * (self.mean - input);
+        let x = input - self.mean;
+        self.lp1 = x + self.a_lp * (self.lp1 - x);
+        self.lp2 = self.lp1 + self.a_lp * (self.lp2 - self.lp1);
+        self.energy = self.a_energy * self.energy + (one - self.a_energy) * self.lp2 * self.lp2;
+    }
+    /// whether any channel currently has too much dc offset or subsonic content
+    pub fn detected(&self) -> bool {
+        self.mean.abs().simd_gt(f32x4::splat(DC_THRESHOLD)).any()
+            || self
+                .energy
+                .simd_gt(f32x4::splat(SUBSONIC_THRESHOLD * SUBSONIC_THRESHOLD))
+                .any()
+    }
+    pub fn reset(&mut self) {
+        self.mean = f32x4::splat(0.);
+        self.lp1 = f32x4::splat(0.);
+        self.lp2 = f32x4::splat(0.);
+        self.energy = f32x4::splat(0.);
+    }
+    pub fn is_finite(&self) -> bool {
+        (self.mean + self.energy).is_finite().all()
+    }
+}
+
+#[cfg(test)]
+fn run_detector(signal: impl Fn(f32) -> f32) -> bool {
+    let fs = 48000.;
+    let mut detector = DcDetector::default();
+    detector.set_sample_rate(fs);
+    // long enough for the slowest smoother to settle
+    for i in 0..(10. * fs) as usize {
+        let x = signal(i as f32 / fs);
+        detector.process(f32x4::from_array([x, x, 0., 0.]));
+    }
+    detector.detected()
+}
+
+#[test]
+fn test_dc_detector_thresholds() {
+    use std::f32::consts::TAU;
+    // plain offsets
+    assert!(run_detector(|_| 1.5 * DC_THRESHOLD));
+    assert!(run_detector(|_| -1.5 * DC_THRESHOLD));
+    assert!(!run_detector(|_| 0.5 * DC_THRESHOLD));
+    // an offset under program material
+    assert!(run_detector(|t| 0.1 + 0.8 * (TAU * 220. * t).sin()));
+    // subsonic content, like a slow lfo leaking into the audio
+    assert!(run_detector(|t| 0.5 * (TAU * 2. * t).sin()));
+    assert!(!run_detector(|t| 0.05 * (TAU * 2. * t).sin()));
+}
+
+#[test]
+fn test_dc_detector_ignores_clean_signals() {
+    use std::f32::consts::TAU;
+    assert!(!run_detector(|_| 0.));
+    // full scale sines down to the bottom of the audible range
+    for freq in [40., 100., 1000., 10000.] {
+        assert!(!run_detector(|t| (TAU * freq * t).sin()), "{} Hz", freq);
+    }
+    // full scale square, which is dc-free but very much not sinusoidal
+    assert!(!run_detector(|t| if (50. * t).fract() < 0.5 {
+        1.
+    } else {
+        -1.
+    }));
+}
+
+#[test]
+fn test_dc_blocker() {
+    use std::f32::consts::TAU;
+    let fs = 48000.;
+    let mut blocker = DcFilter::new(DC_BLOCKER_HZ, fs);
+    let mut out = f32x4::splat(1.);
+    for _ in 0..fs as usize {
+        out = blocker.process(f32x4::splat(1.));
+    }
+    assert!(out[0].abs() < 1e-3, "{}", out[0]);
+
+    // a 30 Hz sine, measured after the blocker has settled
+    blocker.reset();
+    let mut peak = 0f32;
+    for i in 0..2 * fs as usize {
+        let x = (TAU * 30. * i as f32 / fs).sin();
+        let y = blocker.process(f32x4::splat(x))[0];
+        if i as f32 >= fs {
+            peak = peak.max(y.abs());
+        }
+    }
+    assert!(peak > 0.97, "{}", peak);
+}
+
+#[test]
+fn test_soft_limit() {
+    for x in [0., 0.3, LIMITER_KNEE, 0.9, 1., 4., 1000.] {
+        let y = soft_limit(f32x4::from_array([x, -x, 0., 0.]));
+        assert!(y[0] <= 1. && y[0] == -y[1], "{}", x);
+        if x <= LIMITER_KNEE {
+            assert_eq!(y[0], x);
+        }
+    }
+    // no corner where the curve takes over
+    let h = 1e-3;
+    let y = soft_limit(f32x4::splat(LIMITER_KNEE + h))[0];
+    assert!((y - LIMITER_KNEE - h).abs() < 1e-5);
 }
@@ -1,6 +1,11 @@
 use core_simd::simd::f32x4;
 
-use crate::{filter::DKSolver, filter_params::FilterParams, utils::AtomicOps};
+use crate::{
+    filter::DKSolver,
+    filter_params::{FilterParams, Saturation},
+    smoothing::CoefficientSmoother,
+    utils::AtomicOps,
+};
 // use packed_simd::f32x4;
 // use core_simd::*;
 // use std_float::*;
@@ -14,33 +19,98 @@ const N_STATES: usize = 2;
 const TOL: f64 = 1e-5;
 pub struct SallenKey {
     filters: [SallenKeyCoreFast; 2],
+    /// the filters from before `fade_reset()`, still running while they fade out
+    fading: [SallenKeyCoreFast; 2],
+    /// how many samples of the fade are left, and how long it is
+    fade_left: usize,
+    fade_len: usize,
 }
 
 impl SallenKey {
     pub fn new(params: Arc<FilterParams>) -> Self {
+        let filters = [
+            SallenKeyCoreFast::new(params.clone()),
+            SallenKeyCoreFast {
+                channel: 1,
+                ..SallenKeyCoreFast::new(params)
+            },
+        ];
         Self {
-            filters: [
-                SallenKeyCoreFast::new(params.clone()),
-                SallenKeyCoreFast::new(params),
-            ],
+            fading: filters.clone(),
+            filters,
+            fade_left: 0,
+            fade_len: 1,
         }
     }
     pub fn process(&mut self, input: f32x4) -> f32x4 {
-        f32x4::from_array([
-            self.filters[0].tick_dk(input[0]),
-            self.filters[1].tick_dk(input[1]),
-            0.,
-            0.,
-        ])
+        let mut output = tick_channels(&mut self.filters, input);
+        if self.fade_left > 0 {
+            let fade = f32x4::splat(self.fade_left as f32 / self.fade_len as f32);
+            let faded = tick_channels(&mut self.fading, input);
+            output += fade * (faded - output);
+            self.fade_left -= 1;
+        }
+        output
     }
     pub fn update(&mut self) {
         self.filters[0].update_matrices();
         self.filters[1].update_matrices();
     }
+    /// Only what depends on the cutoff, for the audio rate fm that moves it every sample
+    pub fn update_g(&mut self) {
+        self.filters[0].update_g();
+        self.filters[1].update_g();
+    }
+    /// Renders `input` offline, with the cutoff and res following `coefficients` the same way they do in the plugin.
+    /// `input` and `output` need to be the same length
+    pub fn process_block(
+        &mut self,
+        coefficients: &mut CoefficientSmoother,
+        input: &[f32x4],
+        output: &mut [f32x4],
+    ) {
+        assert_eq!(input.len(), output.len());
+        let len = self.filters[0].params.sub_block_len();
+        for (input, output) in input.chunks(len).zip(output.chunks_mut(len)) {
+            if coefficients.advance(input.len(), 0., 0.) {
+                self.update();
+            }
+            for (x, y) in input.iter().zip(output.iter_mut()) {
+                *y = self.process(*x);
+            }
+        }
+    }
     pub fn reset(&mut self) {
         self.filters[0].reset();
         self.filters[1].reset();
+        self.fade_left = 0;
+    }
+    /// Starts over from silence like `reset()`, but crossfades from where it was over `RESET_FADE_MS` so it
+    /// doesn't click
+    pub fn fade_reset(&mut self) {
+        self.fading.clone_from(&self.filters);
+        self.reset();
+        self.fade_len = super::reset_fade_len(&self.filters[0].params);
+        self.fade_left = self.fade_len;
     }
+    pub fn is_finite(&self) -> bool {
+        self.filters[0].is_finite() && self.filters[1].is_finite()
+    }
+    /// The largest level into the opamps since the last call
+    pub fn take_saturation_peak(&mut self) -> f32 {
+        self.filters[0]
+            .take_saturation_peak()
+            .max(self.filters[1].take_saturation_peak())
+    }
+}
+/// Ticks a pair of channels, left in lane 0 and right in lane 1
+fn tick_channels(filters: &mut [SallenKeyCoreFast; 2], input: f32x4) -> f32x4 {
+    f32x4::from_array([
+        filters[0].tick_dk(input[0]),
+        filters[1].tick_dk(input[1]),
+        0.,
+        0.,
+    ])
 }
 pub struct SallenKeyCore {
     pub params: Arc<FilterParams>,
@@ -65,6 +135,7 @@ pub struct SallenKeyCore {
     ey: [f32; N_OUTS],
     fy: [[f32; N_N]; N_OUTS],
 
+    saturation: Saturation,
     pub solver: DKSolver<N_N, N_P, P_LEN>,
 }
 // here we flatten a bunch of stuff to hopefully make it faster
@@ -123,6 +194,7 @@ impl SallenKeyCore {
             ey: [0.],
             fy: [[(res - 1.) / (4. * res) - 0.25, 0., 0., 0.]],
 
+            saturation: Saturation::Tanh,
             solver: DKSolver::new(),
         };
         a.reset();
@@ -130,9 +202,10 @@ impl SallenKeyCore {
         a
     }
     pub fn update_matrices(&mut self) {
-        let g = self.params.g.get();
+        self.saturation = self.params.saturation();
+        let g = self.params.g();
         // the model starts to self-oscillate at 0.8
-        let res = (self.params.res.value() * 0.79).clamp(0.01, 0.99);
+        let res = self.params.sk_feedback.get();
         let g_f64 = g as f64;
         let res_f64 = res as f64;
 
@@ -164,7 +237,7 @@ impl SallenKeyCore {
     }
 
     pub fn tick_dk(&mut self, input: f32) -> f32 {
-        let input = input * (self.params.drive.value());
+        let input = input * (self.params.drive_gain());
 
         let mut p = [0f64; 2];
 
@@ -301,8 +374,8 @@ impl SallenKeyCore {
             q[i] += dot_p[i];
         }
 
-        let (res1, jq1) = self.solver.eval_opamp(q[0], q[1]);
-        let (res2, jq2) = self.solver.eval_opamp(q[2], q[3]);
+        let (res1, jq1) = self.solver.eval_opamp(q[0], q[1], self.saturation);
+        let (res2, jq2) = self.solver.eval_opamp(q[2], q[3], self.saturation);
 
         let (res3, jq3) = self.solver.eval_diode(&q[4..6], 1e-15, 1.5);
         let (res4, jq4) = self.solver.eval_diode(&q[6..8], 1e-15, 1.5);
@@ -343,6 +416,7 @@ const N_N2: usize = 3;
 const P_LEN2: usize = 6;
 
 /// This does the same as `SallenKeyCore`, but with most equations simplified to make it faster.
+#[derive(Clone)]
 pub struct SallenKeyCoreFast {
     pub params: Arc<FilterParams>,
     pub vout: [f32; N_OUTS],
@@ -368,7 +442,13 @@ pub struct SallenKeyCoreFast {
 
     jq: [f64; P_LEN2],
 
+    saturation: Saturation,
     solver: DKSolver<N_N2, N_P2, P_LEN2>,
+    /// which side's cutoff it follows, they differ with the spread
+    pub channel: usize,
+    /// the largest level into the opamps at the last evaluation, and since `take_saturation_peak()`
+    sat_level: f64,
+    sat_peak: f32,
 }
 // here we flatten a bunch of stuff to hopefully make it faster
 impl SallenKeyCoreFast {
@@ -400,19 +480,30 @@ impl SallenKeyCoreFast {
 
             jq: [0., -1., 0., -1., 0., 1.],
 
+            saturation: Saturation::Tanh,
             solver: DKSolver::new(),
+            channel: 0,
+            sat_level: 0.,
+            sat_peak: 0.,
         };
         a.reset();
 
         a
     }
     pub fn update_matrices(&mut self) {
-        let g = self.params.g.get();
-        let res = (self.params.res.value() * 0.79).clamp(0.01, 0.99);
+        self.saturation = self.params.saturation();
+        let res = self.params.sk_feedback.get();
+        self.fq30 = -0.25 / res as f64;
+        self.fy[0][0] = -0.25 / res;
+        self.update_g();
+    }
+    /// The coefficients that depend on the cutoff, the rest stay as `update_matrices()` left them
+    pub fn update_g(&mut self) {
+        let g = self.params.g_channel(self.channel);
+        let res = self.params.sk_feedback.get();
         let g_f64 = g as f64;
         let res_f64 = res as f64;
 
-        self.fq30 = -0.25 / res_f64;
         self.fq22 = 2. * g_f64;
         self.fq20 = (0.25 + 0.5 * g_f64) / res_f64;
         self.fq42 = 2. * g_f64 + 1.;
@@ -425,12 +516,10 @@ impl SallenKeyCoreFast {
         self.c[1][2] = 4. * g;
 
         self.eq[1] = 2. * g;
-
-        self.fy[0][0] = -0.25 / res;
     }
 
     pub fn tick_dk(&mut self, input: f32) -> f32 {
-        let input = input * (self.params.drive.value());
+        let input = input * (self.params.drive_gain());
 
         let mut p = [0f64; 2];
         p[0] = self.s[1] as f64;
@@ -439,6 +528,7 @@ impl SallenKeyCoreFast {
         // self.nonlinear_contribs(p);
         // find nonlinear contributions (values for solver.z that falls in the null-space described by fq), applying homotopy if it fails to converge
         self.homotopy_solver(p);
+        self.sat_peak = self.sat_peak.max(self.sat_level as f32);
         // find output voltage(s)
         self.vout[0] = self.fy[0][0] * self.solver.z[0] as f32;
         // update states
@@ -564,8 +654,9 @@ impl SallenKeyCoreFast {
             self.solver.p_full[5] + z[0] * -1.25 - z[1] + z[2] * self.fq52,
         ];
 
-        let (res1, jq1) = self.solver.eval_opamp(q[0], q[1]);
-        let (res2, jq2) = self.solver.eval_opamp(q[2], q[3]);
+        self.sat_level = q[0].abs().max(q[2].abs());
+        let (res1, jq1) = self.solver.eval_opamp(q[0], q[1], self.saturation);
+        let (res2, jq2) = self.solver.eval_opamp(q[2], q[3], self.saturation);
         let (res3, jq3) = self.solver.eval_diodepair(q[4], q[5], 1e-15, 1.7);
 
         self.solver.residue = [res1, res2, res3];
@@ -580,4 +671,11 @@ impl SallenKeyCoreFast {
         self.evaluate_nonlinearities([0.; N_N2]);
         self.solver.set_extrapolation_origin([0.; N_P2], [0.; N_N2]);
     }
+    /// The largest level that went into the opamps since the last call
+    pub fn take_saturation_peak(&mut self) -> f32 {
+        std::mem::take(&mut self.sat_peak)
+    }
+    pub fn is_finite(&self) -> bool {
+        (self.s[0] + self.s[1]).is_finite() && self.solver.last_z.iter().sum::<f64>().is_finite()
+    }
 }
@@ -1,3 +1,5 @@
+use crate::filter_params::Saturation;
+
 #[inline(always)]
 pub fn tanh_levien(x: f64) -> f64 {
     let x2 = x * x;
@@ -159,16 +161,34 @@ impl<const N_N: usize, const N_P: usize, const P_LEN: usize> DKSolver<N_N, N_P,
     }
 
     #[inline(always)]
-    pub fn eval_opamp(&self, v_in: f64, v_out: f64) -> (f64, [f64; 2]) {
-        let tanh_vin = tanh_levien(v_in);
-        let residue = tanh_vin - v_out;
-        let mut jacobian = [(1. - tanh_vin * tanh_vin), -1.0];
+    pub fn eval_opamp(&self, v_in: f64, v_out: f64, sat: Saturation) -> (f64, [f64; 2]) {
+        let (sat_vin, d_vin) = sat.eval_f64(v_in);
+        let residue = sat_vin - v_out;
+        let mut jacobian = [d_vin, -1.0];
         // this if-statement sort of helps the solver converge, since 0-valued entries in the jacobian can't be used for iteration
         if jacobian[0] == 0.0 {
             jacobian[0] = v_in.signum() * 1e-9;
         }
         (residue, jacobian)
     }
+    /// `eval_opamp()` blended with an opamp that never saturates. 1 is the plain opamp
+    #[inline(always)]
+    pub fn eval_opamp_blend(
+        &self,
+        v_in: f64,
+        v_out: f64,
+        sat: Saturation,
+        amount: f64,
+    ) -> (f64, [f64; 2]) {
+        let (residue, jacobian) = self.eval_opamp(v_in, v_out, sat);
+        if amount >= 1. {
+            return (residue, jacobian);
+        }
+        (
+            amount * (residue + v_out) + (1. - amount) * v_in - v_out,
+            [amount * jacobian[0] + 1. - amount, -1.],
+        )
+    }
     #[inline]
     pub fn eval_ota(&self, q: &[f64]) -> (f64, [f64; 2]) {
         let v_in = q[0];
@@ -206,6 +226,28 @@ impl<const N_N: usize, const N_P: usize, const P_LEN: usize> DKSolver<N_N, N_P,
         let jacobian = cosh_vin.clamp(-LIM, LIM) * v_t_inv;
         (residue, [jacobian, -1.])
     }
+    /// Diode pair blended with a resistor that has the same conductance as the diodes around 0 V.
+    /// `amount` of 1 is the plain diode pair, 0 makes the element linear
+    #[inline(always)]
+    pub fn eval_diodepair_blend(
+        &self,
+        v_in: f64,
+        i_out: f64,
+        i_s: f64,
+        eta: f64,
+        amount: f64,
+    ) -> (f64, [f64; 2]) {
+        const V_T: f64 = 25e-3;
+        let (residue, jacobian) = self.eval_diodepair(v_in, i_out, i_s, eta);
+        if amount >= 1. {
+            return (residue, jacobian);
+        }
+        let g_0 = 2. * i_s / (V_T * eta);
+        (
+            amount * (residue + i_out) + (1. - amount) * g_0 * v_in - i_out,
+            [amount * jacobian[0] + (1. - amount) * g_0, -1.],
+        )
+    }
     // simple shockley diode equation
     pub fn eval_diode(&self, q: &[f64], i_s: f64, eta: f64) -> (f64, [f64; 2]) {
         // thermal voltage
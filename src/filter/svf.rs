@@ -1,8 +1,11 @@
 use std::sync::Arc;
 
-use core_simd::simd::f32x4;
+use core_simd::simd::*;
 
-use crate::filter_params::{FilterParams, SvfMode};
+#[cfg(test)]
+use crate::filter_params::SvfSlope;
+use crate::filter_params::{Character, FilterParams, Saturation, Solver, SvfMode, SVF_MAX_STAGES};
+use crate::smoothing::{CoefficientSmoother, ParamSmoother};
 use crate::utils::AtomicOps;
 
 use super::solver::DKSolver;
@@ -13,34 +16,242 @@ const P_LEN: usize = 8;
 const N_OUTS: usize = 3;
 const N_STATES: usize = 2;
 const TOL: f64 = 1e-5;
+/// how many newton steps `SvfCoreFast::tick_dk()` takes before it turns to the homotopy
+const MAX_ITERATIONS: usize = 100;
+/// the largest residue `tick_pivotal()` takes a single step from, past it a step can throw the state off for good
+const PIVOT_MAX_RESIDUE: f64 = 0.5;
+/// the jacobian of the opamps and the damping diodes as straight lines, what the blends give at 0.
+/// The diodes are linearized around 0 V
+const LINEAR_JQ: [f64; P_LEN] = [1., -1., 1., -1., 1., -1., 2. * 1e-12 / (25e-3 * 1.28), -1.];
+
+/// how many samples a change in the number of stages is crossfaded over
+const STAGE_FADE_LEN: usize = 512;
+/// The stages resonate at sqrt(1.6) times the cutoff, whatever their damping. A single stage is damped enough
+/// for its corner to land on the cutoff anyway, but a butterworth cascade has its corner right where they resonate
+const CASCADE_TUNING: f32 = 0.7906;
+/// how long toggling bp norm takes to fade the bandpass's level over
+const BP_NORM_FADE_MS: f32 = 20.;
 
 pub struct Svf {
-    filters: [SvfCoreFast; 2],
+    params: Arc<FilterParams>,
+    /// the stages of each channel, in series. Only the first `stages` of them run
+    filters: [[SvfCoreFast; SVF_MAX_STAGES]; 2],
     // filters: [SvfCore; 2],
+    stages: usize,
+    /// the cascade from before the number of stages changed, fading out with its coefficients left as they were.
+    /// The damping and tuning of the first stage jump with the slope, so its output can't be faded from on its own
+    fading: [[SvfCoreFast; SVF_MAX_STAGES]; 2],
+    fade_from: usize,
+    /// how many samples of the fade are left, and how long it is
+    fade_left: usize,
+    fade_len: usize,
+    /// gain into each stage after the first, making up for the level the mode loses in its passband
+    makeup: f32,
+    fade_makeup: f32,
+    /// how far bp norm is faded in, and the gain it fades to
+    bp_norm: ParamSmoother,
+    bp_norm_gain: f32,
 }
 
 impl Svf {
     pub fn new(params: Arc<FilterParams>) -> Self {
+        let channel = |channel| {
+            std::array::from_fn(|stage| {
+                let mut filter = SvfCoreFast::new_stage(params.clone(), stage);
+                filter.channel = channel;
+                filter
+            })
+        };
+        let (stages, makeup) = cascade(&params);
+        let bp_norm = ParamSmoother::new(false, BP_NORM_FADE_MS, bp_norm_target(&params));
+        let bp_norm_gain = params.svf_bp_norm.get();
         Self {
-            filters: [SvfCoreFast::new(params.clone()), SvfCoreFast::new(params)],
+            filters: [channel(0), channel(1)],
             // filters: [SvfCore::new(params.clone()), SvfCore::new(params)],
+            fading: [channel(0), channel(1)],
+            params,
+            stages,
+            fade_from: stages,
+            fade_left: 0,
+            fade_len: STAGE_FADE_LEN,
+            makeup,
+            fade_makeup: makeup,
+            bp_norm,
+            bp_norm_gain,
         }
     }
     pub fn process(&mut self, input: f32x4) -> f32x4 {
+        let solver = match self.params.drive.character.value() {
+            Character::Clean => Solver::Linear,
+            Character::Driven => self.params.drive.solver.value(),
+        };
+        let mut output = tick_cascade(&mut self.filters, self.stages, self.makeup, input, solver);
+        if self.fade_left > 0 {
+            let fade = f32x4::splat(self.fade_left as f32 / self.fade_len as f32);
+            let faded = tick_cascade(
+                &mut self.fading,
+                self.fade_from,
+                self.fade_makeup,
+                input,
+                solver,
+            );
+            output += fade * (faded - output);
+            self.fade_left -= 1;
+        }
+        let norm = self.bp_norm.next();
+        if self.filters[0][0].mode == SvfMode::BP1 {
+            output *= f32x4::splat(1. + norm * (self.bp_norm_gain - 1.));
+        }
+        output
+    }
+    /// The bandpass and highpass of the first stage from the last `process()`, as [bp left, bp right, hp left, hp right].
+    /// They come out of the same state as the selected response, so they're phase coherent with it
+    pub fn bands(&self) -> f32x4 {
+        let [left, right] = &self.filters;
         f32x4::from_array([
-            self.filters[0].tick_dk(input[0]),
-            self.filters[1].tick_dk(input[1]),
-            0.,
-            0.,
+            left[0].vout[1],
+            right[0].vout[1],
+            left[0].vout[2],
+            right[0].vout[2],
         ])
     }
     pub fn update(&mut self) {
-        self.filters[0].update_matrices();
-        self.filters[1].update_matrices();
+        let (stages, makeup) = cascade(&self.params);
+        if stages != self.stages {
+            for (fading, filters) in self.fading.iter_mut().zip(&mut self.filters) {
+                fading.clone_from(filters);
+                // the added stages start from silence, the fade hides them settling
+                for filter in filters.iter_mut().take(stages).skip(self.stages) {
+                    filter.reset();
+                }
+            }
+            self.fade_from = self.stages;
+            self.fade_makeup = self.makeup;
+            self.fade_left = STAGE_FADE_LEN;
+            self.fade_len = STAGE_FADE_LEN;
+            self.stages = stages;
+        }
+        self.makeup = makeup;
+        self.bp_norm.set_target(
+            self.params.sample_rate.get(),
+            bp_norm_target(&self.params),
+            BP_NORM_FADE_MS,
+        );
+        self.bp_norm_gain = self.params.svf_bp_norm.get();
+        let tuning = if stages > 1 { CASCADE_TUNING } else { 1. };
+        for filter in self.filters.iter_mut().flatten() {
+            filter.tuning = tuning;
+            filter.update_matrices();
+        }
+    }
+    /// Only the cutoff of the stages that are running, for the audio rate fm that moves it every sample.
+    /// Everything else stays as the last `update()` left it
+    pub fn update_g(&mut self) {
+        for filters in &mut self.filters {
+            for filter in &mut filters[..self.stages] {
+                filter.update_g();
+            }
+        }
+    }
+    /// Renders `input` offline, with the cutoff and res following `coefficients` the same way they do in the plugin.
+    /// `input` and `output` need to be the same length
+    pub fn process_block(
+        &mut self,
+        coefficients: &mut CoefficientSmoother,
+        input: &[f32x4],
+        output: &mut [f32x4],
+    ) {
+        assert_eq!(input.len(), output.len());
+        let len = self.params.sub_block_len();
+        for (input, output) in input.chunks(len).zip(output.chunks_mut(len)) {
+            if coefficients.advance(input.len(), 0., 0.) {
+                self.update();
+            }
+            for (x, y) in input.iter().zip(output.iter_mut()) {
+                *y = self.process(*x);
+            }
+        }
     }
     pub fn reset(&mut self) {
-        self.filters[0].reset();
-        self.filters[1].reset();
+        for filter in self.filters.iter_mut().flatten() {
+            filter.reset();
+        }
+        self.fade_left = 0;
+        self.bp_norm.reset(bp_norm_target(&self.params));
+    }
+    /// Starts over from silence like `reset()`, but crossfades from where it was over `RESET_FADE_MS` so it
+    /// doesn't click
+    pub fn fade_reset(&mut self) {
+        self.fading.clone_from(&self.filters);
+        self.fade_from = self.stages;
+        self.fade_makeup = self.makeup;
+        // the level of the bandpass carries on fading as it was
+        for filter in self.filters.iter_mut().flatten() {
+            filter.reset();
+        }
+        self.fade_len = super::reset_fade_len(&self.params);
+        self.fade_left = self.fade_len;
+    }
+    pub fn is_finite(&self) -> bool {
+        self.filters
+            .iter()
+            .flatten()
+            .all(|filter| filter.is_finite())
+    }
+    /// The largest level into the opamps of any stage since the last call
+    pub fn take_saturation_peak(&mut self) -> f32 {
+        self.filters
+            .iter_mut()
+            .chain(&mut self.fading)
+            .flatten()
+            .map(|filter| filter.take_saturation_peak())
+            .fold(0., f32::max)
+    }
+}
+fn tick_cascade(
+    filters: &mut [[SvfCoreFast; SVF_MAX_STAGES]; 2],
+    stages: usize,
+    makeup: f32,
+    input: f32x4,
+    solver: Solver,
+) -> f32x4 {
+    let [left, right] = filters;
+    let mut x = input;
+    for (i, (left, right)) in left[..stages]
+        .iter_mut()
+        .zip(&mut right[..stages])
+        .enumerate()
+    {
+        if i > 0 {
+            x *= f32x4::splat(makeup);
+        }
+        x = match solver {
+            Solver::Linear => {
+                f32x4::from_array([left.tick_linear(x[0]), right.tick_linear(x[1]), 0., 0.])
+            }
+            Solver::FixedPivot => {
+                f32x4::from_array([left.tick_pivotal(x[0]), right.tick_pivotal(x[1]), 0., 0.])
+            }
+            Solver::Newton => tick_dk_lanes([left, right], x),
+        };
+    }
+    x
+}
+/// 1 with bp norm on, 0 with it off
+fn bp_norm_target(params: &FilterParams) -> f32 {
+    if params.filter.bp_norm.value() {
+        1.
+    } else {
+        0.
+    }
+}
+/// How many stages the slope needs in the current mode, and the gain into each stage after the first
+fn cascade(params: &FilterParams) -> (usize, f32) {
+    match params.filter.mode.value() {
+        // the lowpass passes half the level, and the highpass a fifth
+        SvfMode::LP => (params.filter.svf_slope.value().stages(), 2.),
+        SvfMode::HP => (params.filter.svf_slope.value().stages(), 5.),
+        _ => (1, 1.),
     }
 }
 /// 2-pole state-variable filter
@@ -67,6 +278,11 @@ pub struct SvfCore {
     ey: [f32; N_OUTS],
     fy: [[f32; N_N]; N_OUTS],
 
+    // how much of the diode pair in the damping path is used
+    antisat: f64,
+    // how much the opamps saturate, from res character
+    squash: f64,
+    saturation: Saturation,
     solver: DKSolver<N_N, N_P, P_LEN>,
 }
 
@@ -117,6 +333,9 @@ impl SvfCore {
             ey: [0., 0., 0.],
             fy: [[0., 0., 0., 1.], [0., 0., 1., 0.], [0., 1., 0., 0.]],
 
+            antisat: 1.,
+            squash: 1.,
+            saturation: Saturation::Tanh,
             solver: DKSolver::new(),
         };
         a.solver.set_p([0.; N_P], &pexps);
@@ -136,7 +355,11 @@ impl SvfCore {
         self.solver.set_extrapolation_origin([0.; N_P], [0.; N_N]);
     }
     pub fn update_matrices(&mut self) {
-        let g = self.params.g.get() * 2.;
+        self.squash = self.params.res_squash.get() as f64;
+        // at 0 res character the damping is a plain resistor, whatever the antisat
+        self.antisat = self.params.damping_antisat.get() as f64 * self.squash;
+        self.saturation = self.params.saturation();
+        let g = self.params.g() * 2.;
         let res = self.params.zeta.get();
         let g_f64 = g as f64;
         let res_f64 = res as f64;
@@ -156,8 +379,16 @@ impl SvfCore {
         self.c[1][2] = -4. * g;
     }
     pub fn tick_dk(&mut self, input: f32) -> f32 {
+        let driven = input * self.params.drive_gain();
+        // below full res character the loop saturates less, so the input gets saturated on its own instead
+        let driven = if self.squash < 1. {
+            let squash = self.squash as f32;
+            squash * driven + (1. - squash) * self.saturation.eval_f64(driven as f64).0 as f32
+        } else {
+            driven
+        };
         // -input since the svf inverts it
-        let input = -input * (self.params.drive.value());
+        let input = -driven;
 
         let mut p = [0.; N_P];
         p[0] = (self.dq[0][0] * self.s[0] + self.dq[0][1] * self.s[1] + self.eq[0] * input) as f64;
@@ -289,11 +520,19 @@ impl SvfCore {
             }
             q[i] += dot_p[i];
         }
-        let (res1, jq1) = self.solver.eval_opamp(q[0], q[1]);
-        let (res2, jq2) = self.solver.eval_opamp(q[2], q[3]);
-        let (res3, jq3) = self.solver.eval_opamp(q[4], q[5]);
-
-        let (res4, jq4) = self.solver.eval_diodepair(q[6], q[7], 1e-12, 1.28);
+        let (res1, jq1) = self
+            .solver
+            .eval_opamp_blend(q[0], q[1], self.saturation, self.squash);
+        let (res2, jq2) = self
+            .solver
+            .eval_opamp_blend(q[2], q[3], self.saturation, self.squash);
+        let (res3, jq3) = self
+            .solver
+            .eval_opamp_blend(q[4], q[5], self.saturation, self.squash);
+
+        let (res4, jq4) = self
+            .solver
+            .eval_diodepair_blend(q[6], q[7], 1e-12, 1.28, self.antisat);
 
         self.solver.jq[0][0] = jq1[0];
         self.solver.jq[0][1] = jq1[1];
@@ -321,7 +560,7 @@ impl SvfCore {
     // highpass and notch doesn't work right, likely because `input` isn't quite defined right. Prolly doesn't need to be subtracted?
     // ^ seems to be fixed now?
     fn get_output(&self, input: f32, k: f32) -> f32 {
-        match self.params.mode.value() {
+        match self.params.filter.mode.value() {
             SvfMode::LP => self.vout[0],  // lowpass
             SvfMode::HP => self.vout[2],  // highpass
             SvfMode::BP1 => self.vout[1], // bandpass
@@ -334,6 +573,7 @@ impl SvfCore {
     }
 }
 
+#[derive(Clone)]
 pub struct SvfCoreFast {
     pub params: Arc<FilterParams>,
     pub vout: [f32; N_OUTS],
@@ -344,12 +584,31 @@ pub struct SvfCoreFast {
     c2: f64,
     // for storing the jacobian for the q (p + dot(z, fq) vector
     jq: [f64; P_LEN],
+    // how much of the diode pair in the damping path is used
+    antisat: f64,
+    // how much the opamps saturate, from res character
+    squash: f64,
+    saturation: Saturation,
+    mode: SvfMode,
     solver: DKSolver<N_N, N_P, P_LEN>,
+    /// where in the cascade this is. Only the first stage is driven and gets the resonance
+    stage: usize,
+    /// ratio of the cutoff the stage is tuned to
+    tuning: f32,
+    /// which side's cutoff it follows, they differ with the spread
+    pub channel: usize,
+    /// the largest level into the opamps at the last evaluation, and since `take_saturation_peak()`
+    sat_level: f64,
+    sat_peak: f32,
 }
 
 impl SvfCoreFast {
     pub fn new(params: Arc<FilterParams>) -> Self {
+        Self::new_stage(params, 0)
+    }
+    pub fn new_stage(params: Arc<FilterParams>, stage: usize) -> Self {
         let fs = params.sample_rate.get();
+        let mode = params.filter.mode.value();
         let g = (std::f32::consts::PI * 1000. / (fs as f32)).tan();
         let res = 0.1;
         let g_f64 = g as f64;
@@ -364,35 +623,119 @@ impl SvfCoreFast {
             c2: res_f64,
 
             jq: [0., -1., 0., -1., 0., -1., 0., -1.],
+            antisat: 1.,
+            squash: 1.,
+            saturation: Saturation::Tanh,
+            mode,
             solver: DKSolver::new(),
+            stage,
+            tuning: 1.,
+            channel: 0,
+            sat_level: 0.,
+            sat_peak: 0.,
         };
         a.reset();
         a
     }
 
     pub fn update_matrices(&mut self) {
-        let g = self.params.g.get() * 2.;
-        let res = self.params.zeta.get();
-        let g_f64 = g as f64;
-        let res_f64 = res as f64;
-
-        self.c1 = 2. * g_f64;
-        self.c2 = res_f64;
+        self.squash = self.params.res_squash.get() as f64;
+        // at 0 res character the damping is a plain resistor, whatever the antisat
+        self.antisat = self.params.damping_antisat.get() as f64 * self.squash;
+        self.saturation = self.params.saturation();
+        self.mode = self.params.filter.mode.value();
+        self.c2 = self.zeta() as f64;
+        self.update_g();
+    }
+    pub fn update_g(&mut self) {
+        let g = self.params.g_channel(self.channel) * self.tuning * 2.;
+        self.c1 = 2. * g as f64;
     }
     pub fn tick_dk(&mut self, input: f32) -> f32 {
-        // -input since the svf inverts it
-        let input = -input * (self.params.drive.value());
-
-        let mut p = [0.; N_P];
-
-        p[0] = -self.s[0] as f64;
-        p[1] = -self.s[1] as f64;
-        p[2] = input as f64;
+        let input = self.drive(input);
+        let p = [-self.s[0] as f64, -self.s[1] as f64, input as f64];
 
         // find nonlinear contributions (solver.z), applying homotopy if it fails to converge
         self.homotopy_solver(p);
         // self.nonlinear_contribs(p);
+        self.sat_peak = self.sat_peak.max(self.sat_level as f32);
+
+        self.update_state(input)
+    }
+    /// A single newton step from the extrapolated solution, so the nonlinearities are linearized once around
+    /// where the last sample left them. Cheaper than `tick_dk()`, and it goes off at fast, loud transients.
+    /// When the extrapolation lands too far off for one step, mostly on jumps of the cutoff, it's solved fully instead
+    pub fn tick_pivotal(&mut self, input: f32) -> f32 {
+        let input = self.drive(input);
+        let p = [-self.s[0] as f64, -self.s[1] as f64, input as f64];
+        self.extrapolate(p);
+        self.evaluate_nonlinearities(self.solver.z);
+        // also catches a residue that's already NaN
+        if self
+            .solver
+            .residue
+            .iter()
+            .any(|r| !(r.abs() < PIVOT_MAX_RESIDUE))
+        {
+            self.homotopy_solver(p);
+            self.sat_peak = self.sat_peak.max(self.sat_level as f32);
+            return self.update_state(input);
+        }
+        let step = self.solve_lin_equations(self.solver.residue);
+        for (z, step) in self.solver.z.iter_mut().zip(step) {
+            *z -= step;
+        }
+        // a step that blew up would be the next sample's starting point, keep the last good one instead
+        if self.solver.z.iter().all(|z| z.is_finite()) {
+            self.solver.set_extrapolation_origin(p, self.solver.z);
+        }
+        self.sat_peak = self.sat_peak.max(self.sat_level as f32);
+        self.update_state(input)
+    }
+    /// Applies the drive to the first stage, and inverts the input like the svf does
+    fn drive(&self, input: f32) -> f32 {
+        // the stages after the first get its output, which is already driven
+        let driven = if self.stage == 0 {
+            input * self.params.drive_gain()
+        } else {
+            input
+        };
+        // below full res character the loop saturates less, so the input gets saturated on its own instead
+        let driven = if self.stage == 0 && self.squash < 1. {
+            let squash = self.squash as f32;
+            squash * driven + (1. - squash) * self.saturation.eval_f64(driven as f64).0 as f32
+        } else {
+            driven
+        };
+        -driven
+    }
+    /// The same circuit with the opamps and the damping as straight lines, which takes a single linear solve.
+    /// It keeps the state and the solver's starting point up to date, so it can be switched with `tick_dk()` any time
+    pub fn tick_linear(&mut self, input: f32) -> f32 {
+        let driven = if self.stage == 0 {
+            input * self.params.drive_gain()
+        } else {
+            input
+        };
+        // -input since the svf inverts it
+        let input = -driven;
+        let p = [-self.s[0] as f64, -self.s[1] as f64, input as f64];
+        self.solver.p_full[2] = p[0];
+        self.solver.p_full[4] = p[1];
+        self.solver.p_full[7] = p[2];
+
+        // the residue at z = 0, which a single newton step takes to zero when it's linear.
+        // The jacobian of the nonlinear solve is put back afterwards, it's the best guess for when it takes over
+        let jq = std::mem::replace(&mut self.jq, LINEAR_JQ);
+        let step = self.solve_lin_equations([0., p[0], p[1], -p[2]]);
+        self.jq = jq;
+        self.solver.z = step.map(|x| -x);
+        self.solver.set_extrapolation_origin(p, self.solver.z);
 
+        self.update_state(input)
+    }
+    /// Reads the outputs from the solution in `solver.z` and advances the capacitors
+    fn update_state(&mut self, input: f32) -> f32 {
         self.vout[0] = self.solver.z[3] as f32;
         self.vout[1] = self.solver.z[2] as f32;
         self.vout[2] = self.solver.z[1] as f32;
@@ -400,12 +743,21 @@ impl SvfCoreFast {
         self.s[0] = self.s[0] - 2. * (self.c1 * self.solver.z[1]) as f32;
         self.s[1] = self.s[1] - 2. * (self.c1 * self.solver.z[2]) as f32;
 
-        self.get_output(input, self.params.zeta.get())
+        self.get_output(input, self.c2 as f32)
+    }
+    fn zeta(&self) -> f32 {
+        match self.stage {
+            0 => self.params.zeta.get(),
+            stage => self.params.svf_cascade_zeta[stage - 1].get(),
+        }
     }
 
     pub fn homotopy_solver(&mut self, p: [f64; N_P]) {
         self.nonlinear_contribs(p);
-        // if the newton solver failed to converge, apply homotopy
+        self.homotopy(p);
+    }
+    /// If the newton solver failed to converge, walks `p` over from the last sample's in smaller and smaller steps
+    fn homotopy(&mut self, p: [f64; N_P]) {
         if self.solver.resmaxabs >= TOL {
             // println!("needs homotopy");
             let mut a = 0.5;
@@ -435,43 +787,20 @@ impl SvfCoreFast {
 
     // uses newton's method to find the nonlinear contributions in the circuit. Not guaranteed to converge
     fn nonlinear_contribs(&mut self, p: [f64; N_P]) {
-        self.solver.p_full[2] = p[0];
-        self.solver.p_full[4] = p[1];
-        self.solver.p_full[7] = p[2];
-
-        let mut tmp_np = [0.; N_P];
-
-        tmp_np[0] = p[0] - self.solver.last_p[0];
-        tmp_np[1] = p[1] - self.solver.last_p[1];
-        tmp_np[2] = p[2] - self.solver.last_p[2];
-
-        let mut tmp_nn = [
-            0.,
-            self.jq[2] * tmp_np[0],
-            self.jq[4] * tmp_np[1],
-            -tmp_np[2],
-        ];
-        tmp_nn = self.solve_lin_equations(tmp_nn);
-        for i in 0..N_N {
-            self.solver.z[i] = self.solver.last_z[i] - tmp_nn[i];
-        }
+        self.extrapolate(p);
 
-        for _plsconverge in 0..100 {
+        for _plsconverge in 0..MAX_ITERATIONS {
             self.evaluate_nonlinearities(self.solver.z);
 
-            self.solver.resmaxabs = 0.;
-            for x in &self.solver.residue {
-                if x.is_finite() {
-                    if x.abs() > self.solver.resmaxabs {
-                        self.solver.resmaxabs = x.abs();
-                    }
-                } else {
-                    // if any of the residue have become NaN/inf, stop early.
-                    // If using the homotopy solver, it will kick in and find an alternate, slower path to convergence
+            self.solver.resmaxabs = match self.max_residue() {
+                Some(max) => max,
+                // if any of the residue have become NaN/inf, stop early.
+                // If using the homotopy solver, it will kick in and find an alternate, slower path to convergence
+                None => {
                     self.solver.resmaxabs = 1000.;
                     return;
                 }
-            }
+            };
 
             // self.solver.set_lin_solver(self.solver.j);
             if self.solver.resmaxabs < TOL {
@@ -480,7 +809,7 @@ impl SvfCoreFast {
             }
 
             // update z with the linsolver according to the residue
-            tmp_nn = self.solve_lin_equations(self.solver.residue);
+            let tmp_nn = self.solve_lin_equations(self.solver.residue);
             // tmp_nn = self.solver.solve_linear_equations(self.solver.residue);
 
             for i in 0..self.solver.z.len() {
@@ -495,6 +824,42 @@ impl SvfCoreFast {
         // println!("failed to converge. residue: {:?}", self.solver.residue);
         // }
     }
+    /// The largest of the residues, or nothing if any of them isn't finite
+    fn max_residue(&self) -> Option<f64> {
+        let mut max = 0.;
+        for x in &self.solver.residue {
+            if !x.is_finite() {
+                return None;
+            }
+            if x.abs() > max {
+                max = x.abs();
+            }
+        }
+        Some(max)
+    }
+    /// Loads `p` and starts `solver.z` off from the last solution, moved along the last jacobian
+    fn extrapolate(&mut self, p: [f64; N_P]) {
+        self.solver.p_full[2] = p[0];
+        self.solver.p_full[4] = p[1];
+        self.solver.p_full[7] = p[2];
+
+        let mut tmp_np = [0.; N_P];
+
+        tmp_np[0] = p[0] - self.solver.last_p[0];
+        tmp_np[1] = p[1] - self.solver.last_p[1];
+        tmp_np[2] = p[2] - self.solver.last_p[2];
+
+        let mut tmp_nn = [
+            0.,
+            self.jq[2] * tmp_np[0],
+            self.jq[4] * tmp_np[1],
+            -tmp_np[2],
+        ];
+        tmp_nn = self.solve_lin_equations(tmp_nn);
+        for i in 0..N_N {
+            self.solver.z[i] = self.solver.last_z[i] - tmp_nn[i];
+        }
+    }
     #[inline]
     fn evaluate_nonlinearities(&mut self, z: [f64; N_N]) {
         let mut q = self.solver.p_full;
@@ -508,12 +873,25 @@ impl SvfCoreFast {
         q[6] += -z[0] - z[2];
         q[7] += 4. * z[0] + z[1] + self.c2 * z[2] + 2. * z[3];
         // q[7] += 3. * z[0] + z[1] + self.c2 * z[2] + z[3];
-
-        let (res1, jq1) = self.solver.eval_opamp(q[0], q[1]);
-        let (res2, jq2) = self.solver.eval_opamp(q[2], q[3]);
-        let (res3, jq3) = self.solver.eval_opamp(q[4], q[5]);
-
-        let (res4, jq4) = self.solver.eval_diodepair(q[6], q[7], 1e-12, 1.28);
+        self.evaluate_q(q);
+    }
+    /// Evaluates the nonlinearities at the voltages and currents on their pins
+    #[inline]
+    fn evaluate_q(&mut self, q: [f64; P_LEN]) {
+        self.sat_level = q[0].abs().max(q[2].abs()).max(q[4].abs());
+        let (res1, jq1) = self
+            .solver
+            .eval_opamp_blend(q[0], q[1], self.saturation, self.squash);
+        let (res2, jq2) = self
+            .solver
+            .eval_opamp_blend(q[2], q[3], self.saturation, self.squash);
+        let (res3, jq3) = self
+            .solver
+            .eval_opamp_blend(q[4], q[5], self.saturation, self.squash);
+
+        let (res4, jq4) = self
+            .solver
+            .eval_diodepair_blend(q[6], q[7], 1e-12, 1.28, self.antisat);
 
         self.jq[0] = jq1[0];
         self.jq[2] = jq2[0];
@@ -557,10 +935,17 @@ impl SvfCoreFast {
         self.evaluate_nonlinearities([0.; N_N]);
         self.solver.set_extrapolation_origin([0.; N_P], [0.; N_N]);
     }
+    /// The largest level that went into the opamps since the last call. The linear solver leaves it at 0
+    pub fn take_saturation_peak(&mut self) -> f32 {
+        std::mem::take(&mut self.sat_peak)
+    }
+    pub fn is_finite(&self) -> bool {
+        (self.s[0] + self.s[1]).is_finite() && self.solver.last_z.iter().sum::<f64>().is_finite()
+    }
     // highpass and notch doesn't work right, likely because `input` isn't quite defined right. Prolly doesn't need to be subtracted?
     // ^ seems to be fixed now?
     fn get_output(&self, input: f32, k: f32) -> f32 {
-        match self.params.mode.value() {
+        match self.mode {
             SvfMode::LP => self.vout[0],  // lowpass
             SvfMode::HP => self.vout[2],  // highpass
             SvfMode::BP1 => self.vout[1], // bandpass
@@ -573,10 +958,113 @@ impl SvfCoreFast {
     }
 }
 
+/// `SvfCoreFast::tick_dk()` for the same stage of both channels at once, lanes 0 and 1 being left and right.
+/// The newton steps run on both channels in the lanes of an `f64x4`, only the nonlinearities are evaluated one
+/// channel at a time. Each lane stops once it has converged and is held there while the other one carries on,
+/// so the channels come out exactly as they would one by one. Lanes 2 and 3 are spare and come out silent
+pub fn tick_dk_lanes(mut cores: [&mut SvfCoreFast; 2], input: f32x4) -> f32x4 {
+    let mut driven = [0.; 2];
+    let mut p = [[0.; N_P]; 2];
+    for (lane, core) in cores.iter_mut().enumerate() {
+        driven[lane] = core.drive(input[lane]);
+        p[lane] = [-core.s[0] as f64, -core.s[1] as f64, driven[lane] as f64];
+        core.extrapolate(p[lane]);
+    }
+    let lanes = |cores: &[&mut SvfCoreFast; 2], value: &dyn Fn(&SvfCoreFast) -> f64| {
+        f64x4::from_array([value(&cores[0]), value(&cores[1]), 0., 0.])
+    };
+    let c1 = lanes(&cores, &|core| core.c1);
+    let c2 = lanes(&cores, &|core| core.c2);
+    let p_full: [f64x4; P_LEN] =
+        std::array::from_fn(|i| lanes(&cores, &|core| core.solver.p_full[i]));
+    let mut z: [f64x4; N_N] = std::array::from_fn(|i| lanes(&cores, &|core| core.solver.z[i]));
+
+    // the lanes still iterating, and the ones that got there
+    let mut active = [true, true, false, false];
+    let mut converged = [false; 2];
+    for _ in 0..MAX_ITERATIONS {
+        // the same sums as `evaluate_nonlinearities()`, in the same order
+        let q = [
+            p_full[0] + z[0],
+            p_full[1] + z[1],
+            p_full[2] + (c1 * z[1] - z[2]),
+            p_full[3] + z[2],
+            p_full[4] + (c1 * z[2] - z[3]),
+            p_full[5] + z[3],
+            p_full[6] + (-z[0] - z[2]),
+            p_full[7] + (f64x4::splat(4.) * z[0] + z[1] + c2 * z[2] + f64x4::splat(2.) * z[3]),
+        ];
+        for (lane, core) in cores.iter_mut().enumerate() {
+            if !active[lane] {
+                continue;
+            }
+            core.evaluate_q(q.map(|q| q[lane]));
+            match core.max_residue() {
+                Some(max) => {
+                    core.solver.resmaxabs = max;
+                    converged[lane] = max < TOL;
+                    active[lane] = !converged[lane];
+                }
+                None => {
+                    core.solver.resmaxabs = 1000.;
+                    active[lane] = false;
+                }
+            }
+        }
+        if !active.contains(&true) {
+            break;
+        }
+        let jq: [f64x4; P_LEN] = std::array::from_fn(|i| lanes(&cores, &|core| core.jq[i]));
+        let residue = std::array::from_fn(|i| lanes(&cores, &|core| core.solver.residue[i]));
+        let step = solve_lin_equations_lanes(&jq, c1, c2, residue);
+        let mask = mask64x4::from_array(active);
+        for (z, step) in z.iter_mut().zip(step) {
+            *z = mask.select(*z - step, *z);
+        }
+    }
+
+    let mut output = [0.; 4];
+    for (lane, core) in cores.iter_mut().enumerate() {
+        core.solver.z = z.map(|z| z[lane]);
+        if converged[lane] {
+            core.solver.set_extrapolation_origin(p[lane], core.solver.z);
+        } else {
+            core.homotopy(p[lane]);
+        }
+        core.sat_peak = core.sat_peak.max(core.sat_level as f32);
+        output[lane] = core.update_state(driven[lane]);
+    }
+    f32x4::from_array(output)
+}
+/// `SvfCoreFast::solve_lin_equations()` on every lane
+#[inline(always)]
+fn solve_lin_equations_lanes(
+    jq: &[f64x4; P_LEN],
+    c1: f64x4,
+    c2: f64x4,
+    b: [f64x4; N_N],
+) -> [f64x4; N_N] {
+    let (one, two, half) = (f64x4::splat(1.), f64x4::splat(2.), f64x4::splat(0.5));
+    let j00 = jq[0];
+    let j11 = jq[2] * c1;
+    let j12 = -jq[2] - one;
+    let j22 = jq[4] * c1;
+    let j23 = -jq[4] - one;
+    let j30 = -jq[6] - f64x4::splat(4.);
+    let j32 = -jq[6] - c2;
+    let mut x = [f64x4::splat(0.); N_N];
+    x[0] = (((-b[0] + b[3]) * j12 - j32 * (b[0] * j11 + b[1])) * j23 + two * b[2] * j12
+        - two * j22 * (b[0] * j11 + b[1]))
+        / (((j30 - j00) * j12 - j32 * j00 * j11) * j23 - two * j00 * j11 * j22);
+    x[1] = j00 * x[0] - b[0];
+    x[2] = (-j11 * x[1] + b[1]) / j12;
+    x[3] = half * (j30 * x[0] + j32 * x[2] - b[3] - x[1]);
+    x
+}
+
 #[test]
 fn test_stepresponse() {
-    let should_update_filter = Arc::new(std::sync::atomic::AtomicBool::new(false));
-    let params = Arc::new(FilterParams::new(should_update_filter.clone()));
+    let params = Arc::new(FilterParams::new());
     params.sample_rate.set(44100.);
     params.update_g(10000.);
     params.zeta.set(0.1);
@@ -593,8 +1081,7 @@ fn test_stepresponse() {
 
 #[test]
 fn test_stepresponse_fast() {
-    let should_update_filter = Arc::new(std::sync::atomic::AtomicBool::new(false));
-    let params = Arc::new(FilterParams::new(should_update_filter.clone()));
+    let params = Arc::new(FilterParams::new());
     params.sample_rate.set(44100.);
     params.update_g(10000.);
     params.zeta.set(0.1);
@@ -609,3 +1096,237 @@ fn test_stepresponse_fast() {
     }
     dbg!(out);
 }
+
+#[test]
+fn test_damping_antisat_blend() {
+    let solver: DKSolver<N_N, N_P, P_LEN> = DKSolver::new();
+    // full amount is the plain diode pair
+    for v in [-0.5, -0.01, 0., 0.02, 0.3] {
+        assert_eq!(
+            solver.eval_diodepair_blend(v, 0.1, 1e-12, 1.28, 1.),
+            solver.eval_diodepair(v, 0.1, 1e-12, 1.28)
+        );
+    }
+    // zero amount is a resistor, and it matches the diodes' small-signal slope
+    let (_, j_diode) = solver.eval_diodepair(0., 0., 1e-12, 1.28);
+    let (res_a, j_a) = solver.eval_diodepair_blend(0.3, 0., 1e-12, 1.28, 0.);
+    let (res_b, j_b) = solver.eval_diodepair_blend(0.6, 0., 1e-12, 1.28, 0.);
+    assert!((j_a[0] - j_diode[0]).abs() < 1e-9 * j_diode[0]);
+    assert_eq!(j_a, j_b);
+    assert!((2. * res_a - res_b).abs() < 1e-9 * res_b.abs());
+}
+
+#[test]
+fn test_slope_change_fades() {
+    let make_params = |slope| {
+        let mut params = FilterParams::new();
+        params.filter.svf_slope = nih_plug::prelude::EnumParam::new("slope", slope);
+        params.sample_rate.set(48000.);
+        params.update_g(500.);
+        params.set_resonances(1.5);
+        Arc::new(params)
+    };
+    let mut svf = Svf::new(make_params(SvfSlope::Db12));
+    svf.update();
+    // a sine at the cutoff, where the extra stage shifts the phase the most
+    let mut sine = (0..).map(|i| (i as f32 * 2. * std::f32::consts::PI * 500. / 48000.).sin());
+    let mut last = [0.; 2];
+    // a click is a jump in the slope of the signal, so it stands out in the second difference
+    let mut max_kink = |svf: &mut Svf, len: usize| {
+        let mut max_kink: f32 = 0.;
+        for x in sine.by_ref().take(len) {
+            let y = svf.process(f32x4::splat(0.1 * x))[0];
+            max_kink = max_kink.max((y - 2. * last[0] + last[1]).abs());
+            last = [y, last[0]];
+        }
+        max_kink
+    };
+    max_kink(&mut svf, 4800);
+    let settled = max_kink(&mut svf, 4800);
+    for slope in [SvfSlope::Db36, SvfSlope::Db12] {
+        // what the parameter changing does, minus the host
+        svf.params = make_params(slope);
+        for filter in svf.filters.iter_mut().flatten() {
+            filter.params = svf.params.clone();
+        }
+        svf.update();
+        let fading = max_kink(&mut svf, STAGE_FADE_LEN);
+        assert!(
+            fading < 2. * settled,
+            "kinks of {} while fading to {:?}, {} before",
+            fading,
+            slope,
+            settled
+        );
+        max_kink(&mut svf, 4800);
+    }
+}
+
+#[test]
+fn test_bands_match_modes() {
+    let make_params = |mode| {
+        let mut params = FilterParams::new();
+        params.filter.mode = nih_plug::prelude::EnumParam::new("mode", mode);
+        params.sample_rate.set(96000.);
+        params.update_g(2000.);
+        params.set_resonances(3.);
+        Arc::new(params)
+    };
+    let mut svfs = [SvfMode::LP, SvfMode::BP1, SvfMode::HP].map(|mode| {
+        let mut svf = Svf::new(make_params(mode));
+        svf.update();
+        svf
+    });
+    for i in 0..2000 {
+        let input = f32x4::from_array([(i as f32 * 0.07).sin(), (i as f32 * 0.011).sin(), 0., 0.]);
+        let [lp, bp, hp] = &mut svfs;
+        lp.process(input);
+        let bands = lp.bands();
+        // the other modes run the same circuit, so their outputs are the bands exactly
+        let (bp, hp) = (bp.process(input), hp.process(input));
+        assert_eq!(&bands.as_array()[..2], &bp.as_array()[..2]);
+        assert_eq!(&bands.as_array()[2..], &hp.as_array()[..2]);
+    }
+}
+
+#[test]
+fn test_clean_character() {
+    let make_params = |character| {
+        let mut params = FilterParams::new();
+        params.drive.character = nih_plug::prelude::EnumParam::new("character", character);
+        params.filter.svf_slope = nih_plug::prelude::EnumParam::new("slope", SvfSlope::Db24);
+        params.sample_rate.set(48000.);
+        params.update_g(1000.);
+        params.set_resonances(3.);
+        Arc::new(params)
+    };
+    let [mut clean, mut driven] = [Character::Clean, Character::Driven].map(|character| {
+        let mut svf = Svf::new(make_params(character));
+        svf.update();
+        svf
+    });
+    let input = |i: usize| (i as f32 * 0.07).sin() + (i as f32 * 0.011).sin();
+    // quiet enough that the circuit stays linear, so the two agree
+    let mut peak: f32 = 0.;
+    let mut max_error: f32 = 0.;
+    for i in 0..4000 {
+        let x = f32x4::splat(1e-3 * input(i));
+        let (a, b) = (clean.process(x)[0], driven.process(x)[0]);
+        peak = peak.max(b.abs());
+        max_error = max_error.max((a - b).abs());
+    }
+    assert!(max_error < 1e-3 * peak, "{} off, peak {}", max_error, peak);
+
+    // and loud, clean is still just a scaled version of the quiet output
+    let mut loud = Svf::new(make_params(Character::Clean));
+    let mut quiet = Svf::new(make_params(Character::Clean));
+    loud.update();
+    quiet.update();
+    for i in 0..4000 {
+        let x = f32x4::splat(input(i));
+        let (a, b) = (loud.process(x)[0], quiet.process(x * f32x4::splat(1e-3))[0]);
+        assert!((a * 1e-3 - b).abs() < 1e-4 * peak, "{} vs {}", a * 1e-3, b);
+    }
+}
+
+#[test]
+fn test_solvers() {
+    let make_params = |solver| {
+        let mut params = FilterParams::new();
+        params.drive.solver = nih_plug::prelude::EnumParam::new("solver", solver);
+        params.sample_rate.set(48000.);
+        params.update_g(1000.);
+        params.set_resonances(3.);
+        Arc::new(params)
+    };
+    let input = |i: usize| (i as f32 * 0.07).sin() + (i as f32 * 0.011).sin();
+    let [mut newton, mut pivotal, mut linear] =
+        [Solver::Newton, Solver::FixedPivot, Solver::Linear].map(|solver| {
+            let mut svf = Svf::new(make_params(solver));
+            svf.update();
+            svf
+        });
+    // quiet enough that the circuit stays linear, so they all agree
+    let mut peak: f32 = 0.;
+    let (mut pivotal_error, mut linear_error): (f32, f32) = (0., 0.);
+    for i in 0..4000 {
+        let x = f32x4::splat(1e-3 * input(i));
+        let reference = newton.process(x)[0];
+        peak = peak.max(reference.abs());
+        pivotal_error = pivotal_error.max((pivotal.process(x)[0] - reference).abs());
+        linear_error = linear_error.max((linear.process(x)[0] - reference).abs());
+    }
+    assert!(
+        pivotal_error < 1e-3 * peak,
+        "{} off, peak {}",
+        pivotal_error,
+        peak
+    );
+    assert!(
+        linear_error < 1e-3 * peak,
+        "{} off, peak {}",
+        linear_error,
+        peak
+    );
+
+    // switching to the fixed pivot halfway through a loud signal carries on from the same state
+    let params = make_params(Solver::Newton);
+    let [mut newton, mut switched] = [0, 1].map(|_| {
+        let mut svf = SvfCoreFast::new(params.clone());
+        svf.update_matrices();
+        svf
+    });
+    let mut peak: f32 = 0.;
+    let mut max_error: f32 = 0.;
+    for i in 0..4000 {
+        let reference = newton.tick_dk(input(i));
+        let out = if i < 2000 {
+            switched.tick_dk(input(i))
+        } else {
+            switched.tick_pivotal(input(i))
+        };
+        peak = peak.max(reference.abs());
+        max_error = max_error.max((out - reference).abs());
+    }
+    assert!(max_error < 0.05 * peak, "{} off, peak {}", max_error, peak);
+}
+
+#[test]
+fn test_dk_lanes() {
+    let params = FilterParams::new();
+    params.sample_rate.set(48000.);
+    // the channels on different cutoffs, so they don't converge after the same number of steps
+    params.set_spread(0.3);
+    params.update_g(2000.);
+    params.set_resonances(8.);
+    params.drive_mod.set(10.);
+    let params = Arc::new(params);
+    let [mut left, mut right, mut scalar_left, mut scalar_right] = [0, 1, 0, 1].map(|channel| {
+        let mut core = SvfCoreFast::new(params.clone());
+        core.channel = channel;
+        core.update_matrices();
+        core
+    });
+    for i in 0..4000 {
+        // loud, and jumping on the right
+        let x = f32x4::from_array([
+            2. * (i as f32 * 0.05).sin(),
+            if (i / 300) % 2 == 0 { 1.5 } else { -1.5 },
+            0.,
+            0.,
+        ]);
+        let out = tick_dk_lanes([&mut left, &mut right], x);
+        assert_eq!(out[0], scalar_left.tick_dk(x[0]), "{}", i);
+        assert_eq!(out[1], scalar_right.tick_dk(x[1]), "{}", i);
+        assert_eq!(out.to_array()[2..], [0.; 2]);
+    }
+    assert_eq!(
+        (left.s, left.solver.last_z, right.s, right.solver.last_z),
+        (
+            scalar_left.s,
+            scalar_left.solver.last_z,
+            scalar_right.s,
+            scalar_right.solver.last_z
+        )
+    );
+}
@@ -142,7 +142,7 @@ impl SvfCore {
     pub fn update_matrices(&mut self) {
         // let fs = self.params.sample_rate.get();
 
-        let g = self.params.g.get() * 2.;
+        let g = self.params.g() * 2.;
         let res = self.params.zeta.get() - 1.0;
         let g_f64 = g as f64;
         let res_f64 = res as f64;
@@ -170,7 +170,7 @@ impl SvfCore {
     // TODO: pls roll the updates into for loops
     pub fn tick_dk(&mut self, input: f32) -> f32 {
         // -input since the svf inverts it
-        let input = -input * (self.params.drive.value());
+        let input = -input * (self.params.drive.amount.value());
 
         // let p = dot(dq, s) + dot(eq, input);
         let mut p = [0.; N_P];
@@ -345,7 +345,7 @@ impl SvfCore {
     // highpass and notch doesn't work right, likely because `input` isn't quite defined right. Prolly doesn't need to be subtracted?
     // ^ seems to be fixed now?
     fn get_output(&self, input: f32, k: f32) -> f32 {
-        match self.params.mode.value() {
+        match self.params.filter.mode.value() {
             SvfMode::LP => self.vout[0],  // lowpass
             SvfMode::HP => self.vout[2],  // highpass
             SvfMode::BP1 => self.vout[1], // bandpass
@@ -412,7 +412,7 @@ impl SvfCoreFast {
         self.solver.set_extrapolation_origin([0.; N_P], [0.; N_N]);
     }
     pub fn update_matrices(&mut self) {
-        let g = self.params.g.get() * 2.;
+        let g = self.params.g() * 2.;
         let res = self.params.zeta.get();
         let g_f64 = g as f64;
         let res_f64 = res as f64;
@@ -431,7 +431,7 @@ impl SvfCoreFast {
     }
     pub fn tick_dk(&mut self, input: f32) -> f32 {
         // - input since the svf inverts the signal
-        let input = (-input * self.params.drive.value()).clamp(-10., 10.);
+        let input = (-input * self.params.drive.amount.value()).clamp(-10., 10.);
 
         let mut p = [0.; N_P];
         p[0] = (self.c1 * self.s[0]) as f64;
@@ -592,7 +592,7 @@ impl SvfCoreFast {
 
     #[inline]
     fn get_output(&self, input: f32, k: f32) -> f32 {
-        match self.params.mode.value() {
+        match self.params.filter.mode.value() {
             SvfMode::LP => self.vout[0], // lowpass
             // the highpass is really quiet for some reason,
             // just multiplied it by 2 here, seems a generally good compromise
@@ -1,22 +1,38 @@
 #![feature(portable_simd)]
-use core_simd::simd::f32x4;
+use core_simd::simd::*;
 use filter::{preprocess, LadderFilter};
 
+use std::f32::consts::FRAC_PI_2;
+use std::ops::Range;
 use std::sync::Arc;
 
+use nih_plug::wrapper::state::PluginState;
 use nih_plug::{nih_export_vst3, prelude::*};
 
+#[cfg(feature = "gui")]
 mod editor;
+#[cfg(feature = "gui")]
 use editor::*;
 pub mod utils;
 use utils::AtomicOps;
 pub mod filter_params;
-use filter_params::FilterParams;
+use filter_params::{EnvTrigger, FilterParams, ModDestination, ModSource, MOD_SLOTS};
 
 mod resampling;
-use resampling::HalfbandFilter;
+pub mod smoothing;
+use resampling::{Downsampler, Upsampler, MAX_FACTOR};
+use smoothing::{CoefficientSmoother, ParamSmoother, MAX_SUB_BLOCK_LEN};
 
+pub mod analyzer;
 pub mod filter;
+pub mod loudness;
+pub mod meter;
+pub mod midi_learn;
+pub mod modulation;
+pub mod presets;
+pub mod scope;
+use modulation::{Drift, Envelope, ModSources, StepSequencer};
+#[cfg(feature = "gui")]
 mod ui;
 
 pub struct VaFilter {
@@ -26,37 +42,169 @@ pub struct VaFilter {
 
     svf_stereo: filter::svf::Svf,
     sallenkey_stereo: filter::sallen_key::SallenKey,
+    comb: filter::Comb,
 
-    should_update_filter: Arc<std::sync::atomic::AtomicBool>,
-
-    upsampler: HalfbandFilter,
-    downsampler: HalfbandFilter,
+    upsampler: Upsampler,
+    downsampler: Downsampler,
+    // the svf's bandpass and highpass for the extra outputs, all four in the lanes of one filter
+    band_downsampler: Downsampler,
     dc_filter: preprocess::DcFilter,
+    // after the circuits, at the host's rate
+    dc_blocker: preprocess::DcFilter,
+    dc_detector: preprocess::DcDetector,
+    // smooths the resonance compensation gain so automating res or toggling it doesn't zipper
+    res_comp: Smoother<f32>,
+    res_comp_target: f32,
+    coefficients: CoefficientSmoother,
+    // how much of the dry input is let through, 1 when bypassed
+    bypass_fade: ParamSmoother,
+    // how much of the output limiter is mixed in, so switching it doesn't click
+    limiter_fade: ParamSmoother,
+    output_gain: ParamSmoother,
+    // the loudest sample going into the output limiter this block, while it's on
+    limiter_peak: f32,
+    // the levels of this block for the meters
+    input_levels: meter::LevelAccumulator,
+    output_levels: meter::LevelAccumulator,
+
+    sequencer: StepSequencer,
+    envelope: Envelope,
+    drift: Drift,
+    // the latest pitch bend (-1 to 1) and channel pressure (0 to 1), and the cutoff offset they make in octaves
+    pitch_bend: f32,
+    pressure: f32,
+    // the last note played, for key tracking
+    note: u8,
+    // the key tracking in octaves from middle c, sliding from the last note to the new one over the glide time
+    key_glide: ParamSmoother,
+    midi_mod: ParamSmoother,
+    // the mod wheel from 0 to 1, smoothed like the pressure since it only has 128 steps too
+    mod_wheel: ParamSmoother,
+    // what the indices of `FilterParams::midi_learn` point to
+    param_ptrs: Vec<nih_plug::param::internals::ParamPtr>,
 
     oversample_factor: usize,
+    // the highest factor it can go to without reallocating, which the lower ones are delayed to line up with
+    max_oversample_factor: usize,
+    // how many times auto oversampling doubles the rate, following the drive
+    auto_oversampling_level: usize,
+    // what the host was last told
+    reported_latency: u32,
+
+    // how many samples the input has been below `SILENCE_THRESHOLD`, and how many it takes to go idle
+    silent_samples: usize,
+    silence_gate_len: usize,
+    // while idle the states are all zero and frames skip the circuits, until the input comes back
+    idle: bool,
+    // samples into the current sub-block. The sub-blocks run on across buffers, so where the coefficients
+    // get updated doesn't depend on how the host splits them up
+    sub_block_phase: usize,
+    // whether the last sub-block ran with filter fm, so `g` can be put back when it's switched off
+    fm_active: bool,
+    // whether the host gave us the layout with the bandpass and highpass outputs
+    band_outputs: bool,
+    // whether the host gave us a single input for the stereo output, which then only has the input in its first channel
+    mono_input: bool,
+    // the svf's bandpass and highpass for the last frame, laid out like `Svf::bands()`, and for each frame of the sub-block
+    bands: f32x4,
+    band_frames: [f32x4; MAX_SUB_BLOCK_LEN],
+}
+
+/// Pitch bend and aftertouch are smoothed over this, channel pressure only has 128 steps
+const MIDI_SMOOTHING_MS: f32 = 10.;
+/// The controller number of the mod wheel
+const MOD_WHEEL_CC: u8 = 1;
+/// The circuits keep state for two channels
+const MAX_CHANNELS: usize = 2;
+/// Stereo in, and out the selected response, the bandpass and the highpass as three stereo pairs
+const BAND_OUTPUT_CHANNELS: usize = 6;
+/// the note key tracking leaves the cutoff alone at, middle C
+const KEY_TRACK_CENTER: u8 = 60;
+/// how long the output limiter takes to fade in and out
+const LIMITER_FADE_MS: f32 = 10.;
+/// how long the output gain takes to get to a new value
+const OUTPUT_GAIN_SMOOTHING_MS: f32 = 20.;
+/// -140 dBFS, far below any noise floor
+const SILENCE_THRESHOLD: f32 = 1e-7;
+/// -80 dBFS. What's left of the output when going idle, mostly the slow tail of the dc filter
+const IDLE_OUTPUT_THRESHOLD: f32 = 1e-4;
+/// how long switching the oversampling factor crossfades between the two
+const OVERSAMPLING_FADE_MS: f32 = 5.;
+
+/// How much lower the oversampling factors are at the host's rate `fs`, 2 from 88.2 kHz up
+fn rate_divisor(fs: f32) -> usize {
+    if fs >= 88200. {
+        2
+    } else {
+        1
+    }
 }
 
 impl Default for VaFilter {
     fn default() -> Self {
-        let should_update_filter = Arc::new(std::sync::atomic::AtomicBool::new(false));
-        let params = Arc::new(FilterParams::new(should_update_filter.clone()));
+        let params = Arc::new(FilterParams::new());
 
         let ladder = LadderFilter::new(params.clone());
         let svf_stereo = filter::svf::Svf::new(params.clone());
         let sallenkey_stereo = filter::sallen_key::SallenKey::new(params.clone());
+        let comb = filter::Comb::new(params.clone());
+
+        let res_comp = Smoother::new(SmoothingStyle::Linear(20.0));
+        res_comp.reset(1.);
+        let coefficients = CoefficientSmoother::new(params.clone());
+        let bypass_fade = ParamSmoother::new(false, params.output.bypass_fade_ms.value(), 0.);
+        let param_ptrs = params
+            .param_map()
+            .into_iter()
+            .map(|(_, param_ptr, _)| param_ptr)
+            .collect();
 
         Self {
             params,
-            should_update_filter,
 
             svf_stereo,
             sallenkey_stereo,
+            comb,
             ladder,
 
-            upsampler: HalfbandFilter::new(8, true),
-            downsampler: HalfbandFilter::new(8, true),
+            upsampler: Upsampler::default(),
+            downsampler: Downsampler::new(2),
+            band_downsampler: Downsampler::new(2),
             dc_filter: preprocess::DcFilter::default(),
+            dc_blocker: preprocess::DcFilter::new(preprocess::DC_BLOCKER_HZ, 48000.),
+            dc_detector: preprocess::DcDetector::default(),
+            res_comp,
+            res_comp_target: 1.,
+            coefficients,
+            bypass_fade,
+            limiter_fade: ParamSmoother::new(false, LIMITER_FADE_MS, 0.),
+            output_gain: ParamSmoother::new(true, OUTPUT_GAIN_SMOOTHING_MS, 1.),
+            limiter_peak: 0.,
+            input_levels: Default::default(),
+            output_levels: Default::default(),
+            sequencer: StepSequencer::default(),
+            envelope: Envelope::default(),
+            drift: Drift::default(),
+            pitch_bend: 0.,
+            pressure: 0.,
+            note: KEY_TRACK_CENTER,
+            key_glide: ParamSmoother::new(false, 0., 0.),
+            midi_mod: ParamSmoother::new(false, MIDI_SMOOTHING_MS, 0.),
+            mod_wheel: ParamSmoother::new(false, MIDI_SMOOTHING_MS, 0.),
+            param_ptrs,
             oversample_factor: 2,
+            max_oversample_factor: 2,
+            auto_oversampling_level: 0,
+            reported_latency: 0,
+            silent_samples: 0,
+            silence_gate_len: usize::MAX,
+            idle: false,
+            sub_block_phase: 0,
+            fm_active: false,
+            band_outputs: false,
+            mono_input: false,
+            bands: f32x4::splat(0.),
+            band_frames: [f32x4::splat(0.); MAX_SUB_BLOCK_LEN],
         }
     }
 }
@@ -72,127 +220,834 @@ impl Plugin for VaFilter {
     const DEFAULT_INPUT_CHANNELS: u32 = 2;
     const DEFAULT_OUTPUT_CHANNELS: u32 = 2;
 
-    const MIDI_INPUT: MidiConfig = MidiConfig::None;
+    // pitch bend and channel pressure only come through with CCs enabled
+    const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
+    // blocks get split at parameter changes, so the smoothers and the bypass fade start on the exact sample
+    const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
+    // without the gui feature this is left to the default, which has no editor
+    #[cfg(feature = "gui")]
     fn editor(&self) -> Option<Box<dyn Editor>> {
         let params = self.params.clone();
+        let scale_params = self.params.clone();
 
-        create_vizia_editor(move |cx, context| {
-            ui::plugin_gui(cx, params.clone(), context.clone());
-        })
+        create_vizia_editor(
+            move || scale_params.editor_scale(),
+            move |cx, context| {
+                ui::plugin_gui(cx, params.clone(), context.clone());
+            },
+        )
     }
 
     fn accepts_bus_config(&self, config: &BusConfig) -> bool {
-        // This works with any symmetrical IO layout
-        config.num_input_channels == config.num_output_channels && config.num_input_channels > 0
+        // mono or stereo, the circuits only have two channels' worth of state
+        (config.num_input_channels == config.num_output_channels
+            && (1..=MAX_CHANNELS as u32).contains(&config.num_input_channels))
+            || (config.num_input_channels == MAX_CHANNELS as u32
+                && config.num_output_channels == BAND_OUTPUT_CHANNELS as u32)
+            // a mono track into a stereo chain, the input goes into both sides
+            || (config.num_input_channels == 1 && config.num_output_channels == MAX_CHANNELS as u32)
+    }
+
+    fn filter_state(state: &mut PluginState) {
+        filter_params::migrate_state(state);
     }
 
     fn initialize(
         &mut self,
-        _bus_config: &BusConfig,
-        _buffer_config: &BufferConfig,
-        _context: &mut impl InitContext,
+        bus_config: &BusConfig,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext,
     ) -> bool {
-        let fs = _buffer_config.sample_rate;
-        if fs >= 88200. {
-            self.params.sample_rate.set(fs);
-            self.oversample_factor = 1;
-        } else {
-            self.params.sample_rate.set(2. * fs);
-            self.oversample_factor = 2;
-        }
+        self.prepare(
+            bus_config.num_input_channels as usize,
+            bus_config.num_output_channels as usize,
+            buffer_config.sample_rate,
+        );
+        context.set_latency_samples(self.reported_latency);
         true
     }
     fn reset(&mut self) {
         self.sallenkey_stereo.reset();
         self.svf_stereo.reset();
-        self.ladder.s = [f32x4::splat(0.); 4];
+        self.ladder.reset();
+        self.comb.reset();
+        self.dc_filter.reset();
+        self.dc_blocker.reset();
+        self.dc_detector.reset();
+        self.upsampler.reset();
+        self.downsampler.reset();
+        self.band_downsampler.reset();
+        self.bands = f32x4::splat(0.);
+        self.res_comp.reset(self.res_comp_target);
+        self.coefficients.reset();
+        self.bypass_fade.reset(self.bypass_target());
+        self.limiter_fade.reset(self.limiter_target());
+        self.output_gain.reset(self.params.output.gain.value());
+        self.limiter_peak = 0.;
+        self.silent_samples = 0;
+        self.idle = false;
+        self.sub_block_phase = 0;
+        self.pitch_bend = 0.;
+        self.pressure = 0.;
+        self.midi_mod.reset(0.);
+        self.mod_wheel.reset(0.);
+        self.params.mod_wheel.set(0.);
+        self.envelope.reset();
+        self.drift.reset();
+        self.key_glide.reset(self.key_track());
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
         _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext,
+        context: &mut impl ProcessContext,
     ) -> ProcessStatus {
-        if self
-            .should_update_filter
-            .compare_exchange(
-                true,
-                false,
-                std::sync::atomic::Ordering::Acquire,
-                std::sync::atomic::Ordering::Relaxed,
-            )
-            .is_ok()
-        {
-            self.params.update_g(self.params.cutoff.value());
-            self.params.set_resonances(self.params.res.value());
+        let transport = context.transport();
+        let pos_beats = if transport.playing {
+            transport.pos_beats()
+        } else {
+            None
+        };
+        let tempo = transport.tempo.unwrap_or(120.);
+        self.process_buffer(buffer.as_slice(), pos_beats, tempo, || context.next_event());
+        if let Some(latency) = self.latency_change() {
+            context.set_latency_samples(latency);
+        }
+        self.process_status()
+    }
+}
+
+impl VaFilter {
+    /// What `initialize()` does, for the host's channel counts and sample rate. The host gets told `reported_latency`
+    /// after
+    fn prepare(&mut self, num_input_channels: usize, num_output_channels: usize, sample_rate: f32) {
+        self.band_outputs = num_output_channels == BAND_OUTPUT_CHANNELS;
+        self.mono_input = num_input_channels == 1 && num_output_channels > 1;
+        // initialize gets called again after loading state, which might name a saturation by a different index
+        self.params.restore_saturation();
+        self.params.restore_view_state();
+        self.params.restore_midi_map();
+        self.drift.set_seed(self.params.restore_analog_seed());
+        self.params
+            .state_loads
+            .set(self.params.state_loads.get() + 1);
+        self.params.dirty.mark(filter_params::DIRTY_ALL);
+        // and whenever the sample rate changes
+        self.set_sample_rate(sample_rate);
+        self.reported_latency = self.latency();
+    }
+
+    /// What `process()` does, with the transport's position in beats while it's playing, its tempo and the
+    /// block's events
+    fn process_buffer(
+        &mut self,
+        channels: &mut [&mut [f32]],
+        pos_beats: Option<f64>,
+        tempo: f64,
+        mut next_event: impl FnMut() -> Option<NoteEvent>,
+    ) {
+        let fs = self.params.sample_rate.get() / self.oversample_factor as f32;
+        self.update_oversampling(fs);
+        self.sequencer.start_block(pos_beats, tempo, fs);
+        self.silence_gate_len = (self.params.output.silence_gate.value() / 1000. * fs) as usize;
+        self.params.sync_saturation_id();
+        self.coefficients
+            .set_targets(fs, self.params.filter.cutoff.value(), self.params.q());
+        self.bypass_fade.set_target(
+            fs,
+            self.bypass_target(),
+            self.params.output.bypass_fade_ms.value(),
+        );
+        self.limiter_fade
+            .set_target(fs, self.limiter_target(), LIMITER_FADE_MS);
+        self.output_gain.set_target(
+            fs,
+            self.params.output.gain.value(),
+            OUTPUT_GAIN_SMOOTHING_MS,
+        );
+        self.midi_mod
+            .set_target(fs, self.midi_mod_target(), MIDI_SMOOTHING_MS);
+        self.envelope.set_params(
+            fs,
+            self.params.modulation.env_attack.value(),
+            self.params.modulation.env_decay.value(),
+            self.params.modulation.env_sustain.value(),
+            self.params.modulation.env_release.value(),
+        );
+
+        // the gui might be busy editing the steps, in which case we just use the ones from last block
+        if let Ok(steps) = self.params.seq_steps.try_read() {
+            self.sequencer.steps = *steps;
+        }
+
+        // the analyzer is only fed while the editor is open
+        let analyzing = self.params.analyzer.is_active();
+        let tap_input = analyzing
+            && self
+                .params
+                .analyzer
+                .pre_filter
+                .load(std::sync::atomic::Ordering::Relaxed);
+        let len = channels[0].len();
+        // notes and the cutoff offsets from midi start at the sample their event is on
+        let legato = self.params.modulation.env_trigger.value() == EnvTrigger::Legato;
+        let mut start = 0;
+        while let Some(event) = next_event() {
+            let timing = (event.timing() as usize).clamp(start, len);
+            self.process_channels(channels, start..timing, analyzing, tap_input);
+            start = timing;
+            match event {
+                NoteEvent::MidiPitchBend { value, .. } => self.pitch_bend = 2. * value - 1.,
+                NoteEvent::MidiChannelPressure { pressure, .. } => self.pressure = pressure,
+                NoteEvent::NoteOn { note, .. } => {
+                    self.note = note;
+                    self.envelope.note_on(note, legato);
+                    self.glide_to_note(fs);
+                    if self.params.modulation.retrigger.value() {
+                        self.fade_reset_circuit();
+                    }
+                }
+                NoteEvent::NoteOff { note, .. } => self.envelope.note_off(note),
+                NoteEvent::MidiCC { cc, value, .. } => {
+                    // the wheel is an offset on top of the target, so it can still be learned for something else
+                    if cc == MOD_WHEEL_CC {
+                        self.mod_wheel.set_target(fs, value, MIDI_SMOOTHING_MS);
+                    }
+                    self.set_learned_param(cc, value, fs);
+                    continue;
+                }
+                _ => continue,
+            }
+            self.midi_mod
+                .set_target(fs, self.midi_mod_target(), MIDI_SMOOTHING_MS);
+        }
+        self.process_channels(channels, start..len, analyzing, tap_input);
+        self.watchdog();
+        self.params.input_dc_warning.store(
+            self.dc_detector.detected(),
+            std::sync::atomic::Ordering::Relaxed,
+        );
+        self.report_limiting();
+        self.report_saturation();
+        self.report_modulation();
+        let n_channels = channels.len().min(MAX_CHANNELS);
+        self.params
+            .input_meter
+            .add(&mut self.input_levels, n_channels);
+        self.params
+            .output_meter
+            .add(&mut self.output_levels, n_channels);
+    }
+
+    /// Runs the host's channels through `process_sub_block()`, one channel per lane.
+    /// With sample accurate automation this gets called for every stretch between parameter changes.
+    /// Mono only uses the first lane, the second one stays silent so it doesn't affect the left channel's output.
+    /// A mono input to a stereo output goes into both lanes, so the spread and the drift can still make it wider
+    fn process_channels(
+        &mut self,
+        channels: &mut [&mut [f32]],
+        range: Range<usize>,
+        analyzing: bool,
+        tap_input: bool,
+    ) {
+        let n_channels = channels.len().min(MAX_CHANNELS);
+        let n_inputs = if self.mono_input { 1 } else { n_channels };
+        let mut frames = [f32x4::splat(0.); MAX_SUB_BLOCK_LEN];
+        // the mono input, kept for the scope until the output of the same frames is there
+        let scoping = self.params.scope.is_active();
+        let mut scope_input = [0.; MAX_SUB_BLOCK_LEN];
+        let sub_block_len = self.params.sub_block_len();
+        // a shorter control rate than before starts the next sub-block right away
+        if self.sub_block_phase >= sub_block_len {
+            self.sub_block_phase = 0;
+        }
+        let mut start = range.start;
+        while start < range.end {
+            // cut short at the end of the range, the next one carries on with the rest
+            let n = (sub_block_len - self.sub_block_phase).min(range.end - start);
+            self.sub_block_phase = (self.sub_block_phase + n) % sub_block_len;
+            for i in 0..n {
+                let mut frame = [0.; 4];
+                for (n, lane) in frame[..n_channels].iter_mut().enumerate() {
+                    *lane = channels[n.min(n_inputs - 1)][start + i];
+                }
+                frames[i] = f32x4::from_array(frame);
+                self.input_levels.add(frames[i]);
+                scope_input[i] = frame[..n_channels].iter().sum::<f32>() / n_channels as f32;
+                if tap_input {
+                    self.params.analyzer.push(frame[0], frame[n_channels - 1]);
+                }
+            }
+            self.process_sub_block(&mut frames[..n]);
+            for i in 0..n {
+                self.output_levels.add(frames[i]);
+                let frame_out = frames[i].as_array();
+                for (channel, lane) in channels[..n_channels].iter_mut().zip(frame_out) {
+                    channel[start + i] = *lane;
+                }
+                if self.band_outputs {
+                    let bands = self.band_frames[i].as_array();
+                    for (channel, lane) in channels[MAX_CHANNELS..].iter_mut().zip(bands) {
+                        channel[start + i] = *lane;
+                    }
+                }
+                let mono_out = frame_out[..n_channels].iter().sum::<f32>() / n_channels as f32;
+                if analyzing && !tap_input {
+                    self.params
+                        .analyzer
+                        .push(frame_out[0], frame_out[n_channels - 1]);
+                }
+                if scoping {
+                    self.params.scope.push(scope_input[i], mono_out);
+                }
+            }
+            start += n;
+        }
+    }
+
+    /// Runs a single frame through the dc filter, the oversampling and the selected circuit.
+    /// With the band outputs on, the svf's bandpass and highpass go to `bands`
+    fn process_frame(&mut self, mut frame: f32x4) -> f32x4 {
+        self.dc_detector.process(frame);
+        // filter before oversampling to remove dc-offset, since offsets can make the models behave weirdly
+        frame = self.dc_filter.process(frame);
+
+        let factor = self.oversample_factor;
+        let input = self
+            .upsampler
+            .process(frame, factor, self.max_oversample_factor);
+        let mut output = [f32x4::splat(0.); MAX_FACTOR];
+        let mut bands = [f32x4::splat(0.); MAX_FACTOR];
+        for (i, frame) in input[..factor].iter().enumerate() {
+            output[i] = self.process_circuit(*frame);
+            bands[i] = self.bands;
+        }
+        // downsample filter, removing frequencies above nyquist
+        let processed = self.downsampler.process(&output[..factor]);
+        if self.band_outputs {
+            self.bands = self.band_downsampler.process(&bands[..factor]);
+        }
+        let gain = f32x4::splat(self.next_res_comp() * self.output_gain.next());
+        self.bands *= gain;
+        // kept running while it's off, so switching it on doesn't start from a jump
+        let blocked = self.dc_blocker.process(processed * gain);
+        let out = if self.params.output.dc_block.value() {
+            blocked
+        } else {
+            processed * gain
+        };
+        self.limit(out)
+    }
+
+    /// The output limiter, faded in as far as `limiter_fade` says
+    fn limit(&mut self, frame: f32x4) -> f32x4 {
+        let amount = self.limiter_fade.next();
+        if amount == 0. {
+            return frame;
+        }
+        for level in frame.abs().to_array() {
+            self.limiter_peak = self.limiter_peak.max(level);
+        }
+        frame + f32x4::splat(amount) * (preprocess::soft_limit(frame) - frame)
+    }
+
+    fn limiter_target(&self) -> f32 {
+        if self.params.output.limiter.value() {
+            1.
+        } else {
+            0.
+        }
+    }
+
+    /// Tells the gui how hard the limiter worked this block. The gui resets it once it's seen it,
+    /// so this only ever raises it
+    fn report_limiting(&mut self) {
+        let peak = std::mem::take(&mut self.limiter_peak);
+        if peak > preprocess::LIMITER_KNEE {
+            let limited = preprocess::soft_limit(f32x4::splat(peak))[0];
+            let reduction = 20. * (peak / limited).log10();
+            if reduction > self.params.limiter_reduction.get() {
+                self.params.limiter_reduction.set(reduction);
+            }
+        }
+    }
+
+    /// Tells the gui how far into the saturation the circuit went this block, from 0 while it stays linear.
+    /// Like the limiter, the gui resets it once it's seen it
+    fn report_saturation(&mut self) {
+        // the circuits that didn't run this block have nothing, or what's left from before a switch
+        let peak = self
+            .ladder
+            .take_saturation_peak()
+            .max(self.svf_stereo.take_saturation_peak())
+            .max(self.sallenkey_stereo.take_saturation_peak())
+            .max(self.comb.take_saturation_peak());
+        if peak > 0. {
+            let (saturated, _) = self.params.saturation().eval_f64(peak as f64);
+            let amount = (1. - saturated / peak as f64).clamp(0., 1.) as f32;
+            if amount > self.params.saturation_amount.get() {
+                self.params.saturation_amount.set(amount);
+            }
+        }
+    }
+
+    /// Tells the gui where the modulation has the cutoff, q and drive at the end of this block. It's read from what the
+    /// circuits run with, so the rings on the knobs show what's heard
+    fn report_modulation(&self) {
+        let modulated = &self.params.modulated;
+        modulated[ModDestination::Cutoff.to_index()].set(self.coefficients.cutoff());
+        modulated[ModDestination::Resonance.to_index()].set(self.coefficients.q());
+        modulated[ModDestination::Drive.to_index()].set(self.params.drive_gain());
+    }
 
+    /// One tick of the selected circuit, which also sets `bands`.
+    /// The other circuits don't have the svf's separate responses, so their band outputs stay silent
+    fn process_circuit(&mut self, frame: f32x4) -> f32x4 {
+        self.bands = f32x4::splat(0.);
+        match self.params.filter.filter_type.value() {
+            filter_params::Circuits::SallenKey => self.sallenkey_stereo.process(frame),
+            filter_params::Circuits::Comb => self.comb.process(frame),
+            filter_params::Circuits::SVF => {
+                let out = self.svf_stereo.process(frame);
+                if self.band_outputs {
+                    self.bands = self.svf_stereo.bands();
+                }
+                out
+            }
+            _ => self.ladder.tick(frame),
+        }
+    }
+
+    /// Starts the selected circuit over from silence, fading out what it was doing
+    fn fade_reset_circuit(&mut self) {
+        match self.params.filter.filter_type.value() {
+            filter_params::Circuits::SallenKey => self.sallenkey_stereo.fade_reset(),
+            filter_params::Circuits::Comb => self.comb.fade_reset(),
+            filter_params::Circuits::SVF => self.svf_stereo.fade_reset(),
+            _ => self.ladder.fade_reset(),
+        }
+    }
+
+    /// Advances the smoothers and modulation over the sub-block, updates the coefficients once if anything moved,
+    /// and then runs the frames through the filter in place. `frames` can't be longer than `MAX_SUB_BLOCK_LEN`
+    pub fn process_sub_block(&mut self, frames: &mut [f32x4]) {
+        let n = frames.len();
+        let (cutoff_mod, res_mod) = self.next_modulation(n);
+        let fm = self.params.filter.fm.value();
+        // what the parameter callbacks left since the last sub-block, from where the smoothers are before they move on
+        let mut update = self.coefficients.apply_dirty(self.params.dirty.take());
+        update |= self.coefficients.advance(n, cutoff_mod, res_mod);
+        if self.fm_active && !fm {
+            self.params.update_g(self.params.cutoff_hz.get());
+            update = true;
+        }
+        self.fm_active = fm;
+        if update {
             self.sallenkey_stereo.update();
             self.svf_stereo.update();
+            self.comb.update();
         }
-        for mut channel_samples in buffer.iter_samples() {
-            if self.params.cutoff.smoothed.is_smoothing() {
-                let cut_smooth = self.params.cutoff.smoothed.next();
-                self.params.update_g(cut_smooth);
 
-                self.sallenkey_stereo.update();
-                self.svf_stereo.update();
-            }
-            if self.params.res.smoothed.is_smoothing() {
-                let res_smooth = self.params.res.smoothed.next();
-                self.params.set_resonances(res_smooth);
+        let fm_amount = self.params.filter.fm_amount.value();
+        for (i, frame) in frames.iter_mut().enumerate() {
+            // anything that skips the circuits leaves the band outputs silent
+            self.bands = f32x4::splat(0.);
+            *frame = self.process_bypassable(*frame, fm.then(|| fm_amount));
+            self.band_frames[i] = self.bands;
+        }
+    }
+
+    /// Filters the left channel with its cutoff moved by the right one, `fm_amount` octaves for a full scale modulator.
+    /// The result goes out on both channels. This is only the wet side, `process_bypassable()` fades it against the
+    /// stereo input
+    fn process_fm(&mut self, frame: f32x4, fm_amount: f32) -> f32x4 {
+        let modulator = frame[1];
+        self.params
+            .update_g_fm(self.params.cutoff_hz.get() * (modulator * fm_amount).exp2());
+        // only the selected circuit, and only its cutoff. The ladder reads g on every tick anyway
+        match self.params.filter.filter_type.value() {
+            filter_params::Circuits::SallenKey => self.sallenkey_stereo.update_g(),
+            filter_params::Circuits::SVF => self.svf_stereo.update_g(),
+            filter_params::Circuits::Comb => self.comb.update_delay(),
+            filter_params::Circuits::Ladder => {}
+        }
+        let out = self.process_gated(f32x4::from_array([frame[0], 0., 0., 0.]));
+        self.bands =
+            f32x4::from_array([self.bands[0], self.bands[0], self.bands[2], self.bands[2]]);
+        f32x4::from_array([out[0], out[0], 0., 0.])
+    }
+
+    fn bypass_target(&self) -> f32 {
+        if self.params.output.bypass.value() {
+            1.
+        } else {
+            0.
+        }
+    }
 
-                self.sallenkey_stereo.update();
-                self.svf_stereo.update();
+    /// Crossfades between `process_gated()`, or `process_fm()` with an `fm_amount`, and the dry input following the
+    /// bypass fade. The circuits keep running through the fade, so the wet side never jumps.
+    /// Once fully bypassed the circuits are skipped, and they start over from silence when the filter comes back
+    fn process_bypassable(&mut self, frame: f32x4, fm_amount: Option<f32>) -> f32x4 {
+        let dry = self.bypass_fade.next();
+        if dry >= 1. {
+            if !self.idle {
+                self.go_idle();
             }
+            return frame;
+        }
+        let wet = match fm_amount {
+            Some(fm_amount) => self.process_fm(frame, fm_amount),
+            None => self.process_gated(frame),
+        };
+        // equal power, so uncorrelated wet and dry don't dip in the middle of the fade
+        let (dry_gain, wet_gain) = (dry * FRAC_PI_2).sin_cos();
+        // the band outputs have no dry signal to fade to
+        self.bands *= f32x4::splat(wet_gain);
+        wet * f32x4::splat(wet_gain) + frame * f32x4::splat(dry_gain)
+    }
+
+    /// Runs `process_frame()`, unless the input has been silent for longer than the silence gate.
+    /// Then all the states get snapped to zero and the output is exactly zero until the input comes back,
+    /// instead of residue ringing out forever.
+    /// The output has to have died down as well, so ringing at high res and self-oscillation don't get cut off
+    fn process_gated(&mut self, frame: f32x4) -> f32x4 {
+        let input_silent = frame.abs().simd_lt(f32x4::splat(SILENCE_THRESHOLD)).all();
+        if !input_silent {
+            self.idle = false;
+            self.silent_samples = 0;
+        } else if self.idle {
+            return f32x4::splat(0.);
+        } else {
+            self.silent_samples += 1;
+        }
+        let processed = self.process_frame(frame);
+        if self.silent_samples >= self.silence_gate_len
+            && processed
+                .abs()
+                .simd_lt(f32x4::splat(IDLE_OUTPUT_THRESHOLD))
+                .all()
+        {
+            self.go_idle();
+            return f32x4::splat(0.);
+        }
+        processed
+    }
+
+    /// What to tell the host after a block. While there's input it keeps going, and once the input stops there's a
+    /// tail for as long as the resonance takes to ring out and the silence gate to zero the states. The tail is only
+    /// over once they're zero, so a host that stops calling starts the circuits again from silence, not with a click.
+    /// Self-oscillation never rings out, so it keeps going like with input
+    fn process_status(&self) -> ProcessStatus {
+        // the dry input goes straight through, with nothing to ring out
+        if self.bypass_target() == 1. {
+            return ProcessStatus::Normal;
+        }
+        if self.idle {
+            return ProcessStatus::Tail(0);
+        }
+        if self.silent_samples == 0 {
+            return ProcessStatus::Normal;
+        }
+        let tail = match self.ring_len() {
+            Some(ring) => ring.max(self.silence_gate_len),
+            None => return ProcessStatus::Normal,
+        };
+        if self.silent_samples < tail {
+            ProcessStatus::Tail((tail - self.silent_samples).min(u32::MAX as usize) as u32)
+        } else {
+            // still going past the estimate, so it's left to the silence gate
+            ProcessStatus::Normal
+        }
+    }
 
-            let in_l = *channel_samples.get_mut(0).unwrap();
-            let in_r = *channel_samples.get_mut(1).unwrap();
-            let mut frame = f32x4::from_array([in_l, in_r, 0.0, 0.0]);
-
-            // filter before oversampling to remove dc-offset, since offsets can make the models behave weirdly
-            frame = self.dc_filter.process(frame);
-
-            let processed;
-            if self.oversample_factor == 2 {
-                // zero-stuff input
-                let input = [frame, f32x4::splat(0.)];
-                let mut output = f32x4::splat(0.);
-                for i in 0..2 {
-                    // run input audio through a half-band filter
-                    // multiply by oversample factor (2) to avoid the volume loss from zero-stuffing
-                    let frame = self.upsampler.process(f32x4::splat(2.) * input[i]);
-
-                    // perform filtering with the cool filters
-                    let filter_out = match self.params.filter_type.value() {
-                        // filter_params_nih::Circuits::SVF => self.svf.tick_newton(frame),
-                        filter_params::Circuits::SallenKey => self.sallenkey_stereo.process(frame),
-                        filter_params::Circuits::SVF => self.svf_stereo.process(frame),
-                        _ => self.ladder.tick_newton(frame),
-                    };
-
-                    // downsample filter, removing frequencies above nyquist
-                    output = self.downsampler.process(filter_out);
+    /// How many samples at the host's rate the selected circuit takes to ring out from a full scale resonance to
+    /// `IDLE_OUTPUT_THRESHOLD`, or `None` when it self-oscillates and doesn't
+    fn ring_len(&self) -> Option<usize> {
+        let q = self.coefficients.q();
+        if q >= filter_params::Q_SELF_OSC {
+            return None;
+        }
+        let cutoff = self.coefficients.cutoff();
+        // the resonance peaks at about q times the input
+        let decay = (q.max(1.) / IDLE_OUTPUT_THRESHOLD).ln();
+        let seconds = match self.params.filter.filter_type.value() {
+            // every echo comes back with the feedback times the one before, a period apart
+            filter_params::Circuits::Comb => {
+                let feedback = self.params.comb_feedback.get();
+                if feedback >= 1. {
+                    return None;
                 }
-                processed = output;
-            } else {
-                processed = match self.params.filter_type.value() {
-                    filter_params::Circuits::SallenKey => self.sallenkey_stereo.process(frame),
-                    filter_params::Circuits::SVF => self.svf_stereo.process(frame),
-                    _ => self.ladder.tick_newton(frame),
-                };
+                decay / -feedback.ln() / cutoff
             }
-            let frame_out = *processed.as_array();
-            *channel_samples.get_mut(0).unwrap() = frame_out[0];
-            *channel_samples.get_mut(1).unwrap() = frame_out[1];
+            // the others fall by e every q / (pi f) seconds
+            _ => q * decay / (std::f32::consts::PI * cutoff),
+        };
+        let fs = self.params.sample_rate.get() / self.oversample_factor as f32;
+        Some((seconds * fs) as usize)
+    }
+
+    fn go_idle(&mut self) {
+        self.idle = true;
+        self.bands = f32x4::splat(0.);
+        self.silent_samples = 0;
+        self.dc_filter.reset();
+        self.dc_blocker.reset();
+        self.dc_detector.reset();
+        self.upsampler.reset();
+        self.downsampler.reset();
+        self.band_downsampler.reset();
+        self.ladder.reset();
+        self.svf_stereo.reset();
+        self.sallenkey_stereo.reset();
+        self.comb.reset();
+    }
+
+    /// Sets up everything that depends on the host's sample rate, with the circuits running at the factor from
+    /// `oversampling_target()`
+    fn set_sample_rate(&mut self, fs: f32) {
+        let (factor, max_factor) = self.oversampling_target(fs);
+        self.set_oversampling(fs, factor, max_factor, 0);
+        self.dc_detector.set_sample_rate(fs);
+        self.dc_blocker.set_cutoff(preprocess::DC_BLOCKER_HZ, fs);
+        self.params.analyzer.sample_rate.set(fs);
+        self.params.scope.sample_rate.set(fs);
+        // the comb's delay lines depend on the rate, and auto oversampling can take it up to 4x without reallocating.
+        // This only runs from `initialize()`, where allocating is fine
+        let max_fs = fs * (MAX_FACTOR / rate_divisor(fs)) as f32;
+        self.comb.allocate(max_fs);
+        // g and the damping are worked out for a sample rate, so they can't wait for a parameter to change
+        self.coefficients.reset();
+        self.coefficients.apply();
+        self.sallenkey_stereo.update();
+        self.svf_stereo.update();
+        self.comb.update();
+        // the smoothers take the sample rate when they get a new target, so they're reset instead of carrying on with
+        // steps for the old one. The states of the circuits and the resamplers don't make sense at the new rate either.
+        // The envelope and the sequencer pick up the new rate on the next block
+        self.reset();
+    }
+
+    /// The factor the circuits run at for the host's rate `fs`, and the highest auto oversampling could go to.
+    /// That's 2x, or with auto oversampling 1x for a clean drive up to 4x for a heavy one. Both are halved from
+    /// 88.2 kHz up, which already has the headroom of 2x at 44.1 kHz
+    fn oversampling_target(&mut self, fs: f32) -> (usize, usize) {
+        let divisor = rate_divisor(fs);
+        if !self.params.drive.auto_oversampling.value() {
+            let factor = (2 / divisor).max(1);
+            return (factor, factor);
+        }
+        let drive_db = nih_plug::util::gain_to_db(self.params.drive_gain());
+        self.auto_oversampling_level =
+            resampling::auto_level(self.auto_oversampling_level, drive_db);
+        let factor = ((1 << self.auto_oversampling_level) / divisor).max(1);
+        (factor, MAX_FACTOR / divisor)
+    }
+
+    /// Switches to the factor `oversampling_target()` picks at the start of a buffer, crossfading from the old one
+    fn update_oversampling(&mut self, fs: f32) {
+        let (factor, max_factor) = self.oversampling_target(fs);
+        if (factor, max_factor) != (self.oversample_factor, self.max_oversample_factor) {
+            let fade_len = (OVERSAMPLING_FADE_MS / 1000. * fs) as usize;
+            self.set_oversampling(fs, factor, max_factor, fade_len);
+        }
+    }
+
+    /// Runs the circuits at `factor` times the host's rate `fs` from here on. Their states carry over, and the
+    /// resampling crossfades from the old factor over `fade_len` samples
+    fn set_oversampling(&mut self, fs: f32, factor: usize, max_factor: usize, fade_len: usize) {
+        self.comb
+            .resample(factor as f32 / self.oversample_factor as f32);
+        self.oversample_factor = factor;
+        self.max_oversample_factor = max_factor;
+        self.params.sample_rate.set(fs * factor as f32);
+        self.params.oversampling_factor.set(factor);
+        self.downsampler.set_factor(factor, max_factor, fade_len);
+        self.band_downsampler
+            .set_factor(factor, max_factor, fade_len);
+        // g and the damping are worked out for the rate
+        self.params
+            .dirty
+            .mark(filter_params::DIRTY_CUTOFF | filter_params::DIRTY_RES);
+    }
+
+    /// The latency to report, which only auto oversampling has, so it stays the same whatever factor it's at
+    fn latency(&self) -> u32 {
+        if self.params.drive.auto_oversampling.value() {
+            resampling::latency(self.max_oversample_factor) as u32
+        } else {
+            0
+        }
+    }
+
+    /// The latency to tell the host after a block, if it's different from what it was last told.
+    /// It only moves when auto oversampling gets switched on or off
+    fn latency_change(&mut self) -> Option<u32> {
+        let latency = self.latency();
+        if latency == self.reported_latency {
+            return None;
+        }
+        self.reported_latency = latency;
+        Some(latency)
+    }
+
+    /// Moves the parameter `cc` is bound to, or binds it to the one the editor is learning for.
+    /// The host doesn't hear about it, same as with any other midi controller mapped inside a plugin
+    fn set_learned_param(&mut self, cc: u8, value: f32, fs: f32) {
+        let learned = self.params.midi_learn.handle_cc(cc);
+        let param_ptr = match learned.and_then(|param| self.param_ptrs.get(param)) {
+            Some(param_ptr) => param_ptr,
+            None => return,
+        };
+        unsafe {
+            param_ptr.set_normalized_value(value);
+            param_ptr.update_smoother(fs, false);
+        }
+    }
+
+    /// Where pitch bend and channel pressure put the cutoff, in octaves
+    fn midi_mod_target(&self) -> f32 {
+        self.pitch_bend * self.params.modulation.bend_range.value() / 12.
+            + self.pressure * self.params.modulation.aftertouch.value()
+    }
+
+    /// Octaves from middle c to the last note
+    fn key_track(&self) -> f32 {
+        (self.note as f32 - KEY_TRACK_CENTER as f32) / 12.
+    }
+
+    /// Starts the key tracking's slide to the last note, or jumps there without glide
+    fn glide_to_note(&mut self, fs: f32) {
+        let glide = self.params.modulation.glide.value();
+        if glide == 0. {
+            self.key_glide.reset(self.key_track());
+        } else {
+            self.key_glide.set_target(fs, self.key_track(), glide);
+        }
+    }
+
+    /// Sums up the modulation of the cutoff and of q for the next sample in octaves, and moves the sources `n` samples
+    /// on. The drive's goes straight to `drive_mod`
+    fn next_modulation(&mut self, n: usize) -> (f32, f32) {
+        let mut octaves = 0.;
+        let seq_depth = self.params.modulation.seq_depth.value();
+        let seq = if seq_depth != 0. || self.mod_source_used(ModSource::Sequencer) {
+            self.sequencer.next(
+                self.params.modulation.seq_division.value(),
+                self.params.modulation.seq_swing.value(),
+                self.params.modulation.seq_glide.value(),
+            )
+        } else {
+            self.sequencer.skip(1);
+            0.
+        };
+        octaves += seq_depth * seq;
+        self.sequencer.skip(n - 1);
+        // the envelope keeps running at zero amount, so turning it up mid-note picks up where it is
+        let env = self.envelope.next_step(n);
+        octaves += self.params.modulation.env_amount.value() * env;
+
+        let sources = ModSources {
+            envelope: env,
+            sequencer: seq,
+            pitch_bend: self.pitch_bend,
+            aftertouch: self.pressure,
+            key_track: self.key_glide.next_step(n),
+            macro_amount: self.next_macro(n),
+            mod_wheel: self.mod_wheel.next_step(n),
+        };
+        self.params.mod_wheel.set(sources.mod_wheel);
+        let (cutoff_octaves, res_octaves, drive_db) = sources.route(&self.params);
+        octaves += cutoff_octaves;
+        self.params.drive_mod.set(if drive_db == 0. {
+            1.
+        } else {
+            nih_plug::util::db_to_gain(drive_db)
+        });
+        // the drift keeps wandering at zero amount, so turning it up doesn't start it over from the middle
+        let fs = self.params.sample_rate.get() / self.oversample_factor as f32;
+        octaves += self.params.filter.analog.value() * self.drift.next_step(n, fs);
+        (octaves + self.midi_mod.next_step(n), res_octaves)
+    }
+
+    /// Moves the macro's smoother `n` samples on, unless it has no depth anywhere
+    fn next_macro(&self, n: usize) -> f32 {
+        if !self.params.macro_routed() {
+            return 0.;
+        }
+        let smoothed = &self.params.modulation.macro_amount.smoothed;
+        let mut amount = 0.;
+        for _ in 0..n {
+            amount = smoothed.next();
         }
+        amount
+    }
+
+    fn mod_source_used(&self, source: ModSource) -> bool {
+        (0..MOD_SLOTS).any(|slot| self.params.mod_slot(slot).0.value() == source)
+    }
+
+    /// Advances the resonance compensation gain towards the one for the current res and circuit.
+    fn next_res_comp(&mut self) -> f32 {
+        let target = if self.params.output.res_comp.value() {
+            self.params.res_comp_gain.get()
+        } else {
+            1.
+        };
+        if target != self.res_comp_target {
+            self.res_comp_target = target;
+            let fs = self.params.sample_rate.get() / self.oversample_factor as f32;
+            self.res_comp.set_target(fs, target);
+        }
+        self.res_comp.next()
+    }
 
-        ProcessStatus::Normal
+    /// Checks once per buffer whether a NaN or inf has gotten into the state of the circuits
+    /// (or the dc filter and resamplers in front of them), and clears the offending state if so.
+    /// Otherwise the filter would stay silent until the plugin gets reloaded.
+    fn watchdog(&mut self) {
+        let mut tripped = false;
+        if !self.ladder.is_finite() {
+            self.ladder.reset();
+            tripped = true;
+        }
+        if !self.svf_stereo.is_finite() {
+            self.svf_stereo.reset();
+            tripped = true;
+        }
+        if !self.sallenkey_stereo.is_finite() {
+            self.sallenkey_stereo.reset();
+            tripped = true;
+        }
+        if !self.comb.is_finite() {
+            self.comb.reset();
+            tripped = true;
+        }
+        if !(self.dc_filter.is_finite()
+            && self.dc_blocker.is_finite()
+            && self.dc_detector.is_finite()
+            && self.upsampler.is_finite()
+            && self.downsampler.is_finite()
+            && self.band_downsampler.is_finite())
+        {
+            self.dc_filter.reset();
+            self.dc_blocker.reset();
+            self.dc_detector.reset();
+            self.upsampler.reset();
+            self.downsampler.reset();
+            self.band_downsampler.reset();
+            self.bands = f32x4::splat(0.);
+            tripped = true;
+        }
+        if tripped {
+            self.params.nan_resets.set(self.params.nan_resets.get() + 1);
+        }
     }
 }
 
@@ -202,3 +1057,942 @@ impl Vst3Plugin for VaFilter {
 }
 
 nih_export_vst3!(VaFilter);
+
+#[test]
+fn test_watchdog_recovers_from_nan() {
+    use filter_params::Circuits;
+    let block_len = 256;
+    // a block through `process_buffer()` like the host would send it, with the band outputs on so their
+    // downsampler gets the NaN as well
+    let block = |plugin: &mut VaFilter, input: fn(usize) -> f32| {
+        let mut channels = vec![vec![0.; block_len]; BAND_OUTPUT_CHANNELS];
+        for channel in &mut channels[..MAX_CHANNELS] {
+            for (i, x) in channel.iter_mut().enumerate() {
+                *x = input(i);
+            }
+        }
+        let mut slices: Vec<&mut [f32]> = channels
+            .iter_mut()
+            .map(|channel| &mut channel[..])
+            .collect();
+        plugin.process_buffer(&mut slices, None, 120., || None);
+        channels
+    };
+    for circuit in [
+        Circuits::SVF,
+        Circuits::Ladder,
+        Circuits::SallenKey,
+        Circuits::Comb,
+    ]
+    .iter()
+    .copied()
+    {
+        let mut plugin = VaFilter::default();
+        let filter_type = &plugin.params.filter.filter_type;
+        unsafe {
+            filter_type
+                .as_ptr()
+                .set_normalized_value(filter_type.preview_normalized(circuit))
+        };
+        plugin.prepare(MAX_CHANNELS, BAND_OUTPUT_CHANNELS, 48000.);
+        plugin.reset();
+        block(&mut plugin, |_| f32::NAN);
+        assert_eq!(plugin.params.nan_resets.get(), 1, "{:?}", circuit);
+
+        // the next block should be back to normal
+        let output = block(&mut plugin, |i| 0.5 * (i as f32 * 0.1).sin());
+        assert!(
+            output.iter().flatten().all(|x| x.is_finite()),
+            "{:?}",
+            circuit
+        );
+        assert_eq!(plugin.params.nan_resets.get(), 1, "{:?}", circuit);
+    }
+}
+
+#[test]
+fn test_silence_gate() {
+    let mut plugin = VaFilter::default();
+    plugin.silence_gate_len = 4410;
+    let sine = |i: usize| f32x4::splat((i as f32 * 0.05).sin());
+    for i in 0..4410 {
+        plugin.process_gated(sine(i));
+    }
+    assert!(!plugin.idle);
+
+    // the output is exactly zero within a second of the input stopping, and stays that way
+    let mut first_zero = None;
+    for i in 0..44100 {
+        let out = plugin.process_gated(f32x4::splat(0.));
+        if out == f32x4::splat(0.) {
+            first_zero.get_or_insert(i);
+        } else {
+            assert!(
+                first_zero.is_none(),
+                "output came back at {} without input",
+                i
+            );
+        }
+    }
+    assert!(plugin.idle);
+    assert!(first_zero.unwrap() >= 4410 - 1);
+
+    // and comes straight back on signal
+    let out = plugin.process_gated(f32x4::splat(0.5));
+    assert!(!plugin.idle);
+    assert!(out[0] != 0. && out[1] != 0.);
+}
+
+#[test]
+fn test_process_status() {
+    let mut plugin = VaFilter::default();
+    plugin.prepare(1, 1, 48000.);
+    plugin.reset();
+    // what the plugin tells the host after a block of a sine at `level`
+    let block = |plugin: &mut VaFilter, level: f32| {
+        let mut buffer: Vec<f32> = (0..480).map(|i| level * (i as f32 * 0.05).sin()).collect();
+        plugin.process_buffer(&mut [&mut buffer[..]], None, 120., || None);
+        plugin.process_status()
+    };
+    let set_q = |plugin: &VaFilter, q: f32| {
+        let res = &plugin.params.filter.res;
+        unsafe { res.as_ptr().set_normalized_value(res.preview_normalized(q)) };
+    };
+    for _ in 0..10 {
+        assert!(matches!(block(&mut plugin, 0.5), ProcessStatus::Normal));
+    }
+    // once the input stops the tail counts down, and it's over when the states have gone to zero
+    let mut left = match block(&mut plugin, 0.) {
+        ProcessStatus::Tail(left) => left,
+        _ => panic!("no tail after the input stopped"),
+    };
+    assert!(left > 0);
+    for _ in 0..1000 {
+        if plugin.idle {
+            break;
+        }
+        if let ProcessStatus::Tail(next) = block(&mut plugin, 0.) {
+            assert!(next < left, "{} after {}", next, left);
+            left = next;
+        }
+    }
+    assert!(plugin.idle);
+    assert!(matches!(plugin.process_status(), ProcessStatus::Tail(0)));
+
+    // the more resonance, the longer it rings
+    let ring = plugin.ring_len().unwrap();
+    set_q(&plugin, 15.);
+    for _ in 0..10 {
+        block(&mut plugin, 0.5);
+    }
+    assert!(plugin.ring_len().unwrap() > 10 * ring);
+    // and a self-oscillating filter never stops
+    set_q(&plugin, filter_params::Q_MAX);
+    for _ in 0..10 {
+        block(&mut plugin, 0.5);
+    }
+    assert_eq!(plugin.ring_len(), None);
+    assert!(matches!(block(&mut plugin, 0.), ProcessStatus::Normal));
+}
+
+#[test]
+fn test_bypass_gate_edges() {
+    let mut plugin = VaFilter::default();
+    let fs = 48000.;
+    // 1 ms at 48 kHz
+    let fade_ms = 1.;
+    let fade_len = 48;
+    // bypass toggling every 16th note at 174 bpm
+    let sixteenth = 60. / 174. / 4. * fs;
+    let edges: Vec<usize> = (0..=16)
+        .map(|i| (i as f32 * sixteenth).round() as usize)
+        .collect();
+    let input = |i: usize| {
+        let t = i as f32;
+        f32x4::splat(0.3 * (t * 0.05).sin() + 0.2 * (t * 0.31).sin())
+    };
+
+    // like a host with sample accurate automation, every change starts a new block
+    let mut output = Vec::new();
+    for (n, block) in edges.windows(2).enumerate() {
+        let bypassed = n % 2 == 1;
+        plugin
+            .bypass_fade
+            .set_target(fs, if bypassed { 1. } else { 0. }, fade_ms);
+        let mut frames: Vec<f32x4> = (block[0]..block[1]).map(input).collect();
+        for sub_block in frames.chunks_mut(smoothing::SUB_BLOCK_LEN) {
+            plugin.process_sub_block(sub_block);
+        }
+        output.extend(frames);
+    }
+
+    for (n, &edge) in edges[1..16].iter().enumerate() {
+        if n % 2 == 0 {
+            // bypassing: fully dry exactly `fade_len` samples after the edge
+            let dry_from = (edge..edges[n + 2])
+                .find(|&i| output[i] == input(i))
+                .unwrap();
+            assert_eq!(dry_from, edge + fade_len - 1, "bypass at {}", edge);
+        } else {
+            // enabling: dry up to the sample before the edge
+            assert_eq!(output[edge - 1], input(edge - 1), "enable at {}", edge);
+            assert!(output[edge] != input(edge), "enable at {}", edge);
+        }
+    }
+}
+
+#[test]
+fn test_output_limiter() {
+    let mut plugin = VaFilter::default();
+    plugin.limiter_fade.reset(1.);
+    for i in 0..10000 {
+        let out = plugin.process_frame(f32x4::splat(8. * (i as f32 * 0.01).sin()));
+        assert!(out.abs().simd_le(f32x4::splat(1.)).all(), "{:?}", out);
+    }
+    plugin.report_limiting();
+    assert!(plugin.params.limiter_reduction.get() > 0.);
+
+    // and stays out of the way when it's off
+    let mut plugin = VaFilter::default();
+    for i in 0..10000 {
+        plugin.process_frame(f32x4::splat(8. * (i as f32 * 0.01).sin()));
+    }
+    plugin.report_limiting();
+    assert_eq!(plugin.params.limiter_reduction.get(), 0.);
+}
+
+#[test]
+fn test_saturation_meter() {
+    let mut plugin = VaFilter::default();
+    let input = |i: usize| f32x4::splat(8. * (i as f32 * 0.01).sin());
+    for i in 0..2000 {
+        plugin.process_frame(input(i));
+    }
+    plugin.report_saturation();
+    assert!(plugin.params.saturation_amount.get() > 0.5);
+
+    // and reads 0 once it's idle, after the gui took what was left from the tail
+    for _ in 0..4000 {
+        plugin.process_frame(f32x4::splat(0.));
+    }
+    plugin.report_saturation();
+    plugin.params.saturation_amount.set(0.);
+    for _ in 0..100 {
+        plugin.process_frame(f32x4::splat(0.));
+    }
+    plugin.report_saturation();
+    assert!(plugin.params.saturation_amount.get() < 1e-3);
+
+    // the other circuits keep track the same way, and taking the peak resets it
+    let mut svf = filter::svf::Svf::new(plugin.params.clone());
+    svf.update();
+    let mut sallen_key = filter::sallen_key::SallenKey::new(plugin.params.clone());
+    sallen_key.update();
+    for i in 0..2000 {
+        svf.process(input(i));
+        sallen_key.process(input(i));
+    }
+    assert!(svf.take_saturation_peak() > 1.);
+    assert!(sallen_key.take_saturation_peak() > 1.);
+    assert_eq!(svf.take_saturation_peak(), 0.);
+    assert_eq!(sallen_key.take_saturation_peak(), 0.);
+}
+
+#[test]
+fn test_level_meters() {
+    let mut plugin = VaFilter::default();
+    let mut left: Vec<f32> = (0..1000).map(|i| 0.5 * (i as f32 * 0.03).sin()).collect();
+    let mut right = vec![0.; 1000];
+    plugin.process_channels(&mut [&mut left[..], &mut right[..]], 0..1000, false, false);
+    plugin.params.input_meter.add(&mut plugin.input_levels, 2);
+    plugin.params.output_meter.add(&mut plugin.output_levels, 2);
+    let [input_left, input_right] = plugin.params.input_meter.take().unwrap();
+    assert!((input_left.0 - 0.5).abs() < 1e-3);
+    assert_eq!(input_right, (0., 0.));
+    let [output_left, _] = plugin.params.output_meter.take().unwrap();
+    assert!(output_left.0 > 0. && output_left.1 > 0.);
+    // nothing processed since the last look
+    assert_eq!(plugin.params.input_meter.take(), None);
+}
+
+#[test]
+fn test_scope_tap() {
+    use scope::SCOPE_LEN;
+    let mut plugin = VaFilter::default();
+    let mut signal: Vec<f32> = (0..1000).map(|i| 0.5 * (i as f32 * 0.03).sin()).collect();
+    let (mut input, mut output) = ([0.; SCOPE_LEN], [0.; SCOPE_LEN]);
+    // only fed while the editor shows it
+    plugin.process_channels(&mut [&mut signal[..]], 0..1000, false, false);
+    assert!(!plugin.params.scope.read(&mut input, &mut output));
+    plugin.params.scope.add_viewer();
+    let mut signal: Vec<f32> = (0..1000).map(|i| 0.5 * (i as f32 * 0.03).sin()).collect();
+    plugin.process_channels(&mut [&mut signal[..]], 0..1000, false, false);
+    assert!(plugin.params.scope.read(&mut input, &mut output));
+    assert_eq!(input[100], 0.5 * (100. * 0.03f32).sin());
+    assert_eq!(output[100], signal[100]);
+}
+
+#[test]
+fn test_midi_learn_sets_param() {
+    let mut plugin = VaFilter::default();
+    let cutoff = plugin
+        .params
+        .param_ids()
+        .iter()
+        .position(|id| id == "cutoff")
+        .unwrap();
+    plugin.set_learned_param(74, 0.25, 48000.);
+    plugin.params.midi_learn.learn(cutoff);
+    plugin.set_learned_param(74, 0.25, 48000.);
+    assert_eq!(plugin.params.filter.cutoff.normalized_value(), 0.25);
+    plugin.set_learned_param(71, 0.75, 48000.);
+    assert_eq!(plugin.params.filter.cutoff.normalized_value(), 0.25);
+
+    // survives saving and loading the state
+    plugin.params.save_midi_map();
+    let other = VaFilter::default();
+    *other.params.midi_map.write().unwrap() = plugin.params.midi_map.read().unwrap().clone();
+    other.params.restore_midi_map();
+    assert_eq!(other.params.midi_learn.cc(cutoff), Some(74));
+}
+
+#[test]
+fn test_mono_matches_stereo() {
+    let input: Vec<f32> = (0..1000)
+        .map(|i| 0.8 * (i as f32 * 0.03).sin() + 0.3 * (i as f32 * 0.7).sin())
+        .collect();
+    // the same signal on both channels, which a mono track should give the same output as
+    let mut stereo_plugin = VaFilter::default();
+    let (mut left, mut right) = (input.clone(), input.clone());
+    stereo_plugin.process_channels(&mut [&mut left[..], &mut right[..]], 0..1000, false, false);
+    let mut mono_plugin = VaFilter::default();
+    let mut mono = input.clone();
+    mono_plugin.process_channels(&mut [&mut mono[..]], 0..1000, false, false);
+    assert_eq!(mono, left);
+    assert!(mono != input);
+
+    // the newton solvers iterate until every lane has converged, a silent lane mustn't change the others
+    let mut svf = (
+        filter::svf::Svf::new(mono_plugin.params.clone()),
+        filter::svf::Svf::new(mono_plugin.params.clone()),
+    );
+    let mut sallen_key = (
+        filter::sallen_key::SallenKey::new(mono_plugin.params.clone()),
+        filter::sallen_key::SallenKey::new(mono_plugin.params.clone()),
+    );
+    svf.0.update();
+    svf.1.update();
+    sallen_key.0.update();
+    sallen_key.1.update();
+    for x in input {
+        let (mono_frame, stereo_frame) = (
+            f32x4::from_array([x, 0., 0., 0.]),
+            f32x4::from_array([x, x, 0., 0.]),
+        );
+        assert_eq!(svf.0.process(mono_frame)[0], svf.1.process(stereo_frame)[0]);
+        assert_eq!(
+            sallen_key.0.process(mono_frame)[0],
+            sallen_key.1.process(stereo_frame)[0]
+        );
+    }
+}
+
+#[test]
+fn test_automation_independent_of_buffer_size() {
+    let fs = 48000.;
+    let len = 8192;
+    // a new automation point every 100 samples, so the changes don't line up with the sub-blocks
+    let interval = 100;
+    let automation = |i: usize| {
+        let t = (i / interval * interval) as f32 / len as f32;
+        (200. * 25f32.powf(t), 0.5 * 30f32.powf(t))
+    };
+    let input: Vec<f32> = (0..len)
+        .map(|i| 0.5 * (i as f32 * 0.02).sin() + 0.2 * (i as f32 * 0.45).sin())
+        .collect();
+    let render = |buffer_len: usize| {
+        let mut plugin = VaFilter::default();
+        let mut output = input.clone();
+        for buffer_start in (0..len).step_by(buffer_len) {
+            // like nih-plug, every automation point inside the buffer starts a new `process()` call
+            let mut splits: Vec<usize> = (buffer_start..buffer_start + buffer_len)
+                .filter(|&i| i == buffer_start || i % interval == 0)
+                .collect();
+            splits.push(buffer_start + buffer_len);
+            for split in splits.windows(2) {
+                let (cutoff, res) = automation(split[0]);
+                plugin.coefficients.set_targets(fs, cutoff, res);
+                plugin.process_channels(&mut [&mut output[..]], split[0]..split[1], false, false);
+            }
+        }
+        output
+    };
+    let output = render(2048);
+    assert!(output != input);
+    assert_eq!(output, render(64));
+}
+
+#[test]
+fn test_pitch_bend_and_pressure() {
+    let mut plugin = VaFilter::default();
+    let fs = 48000.;
+    let cutoff = plugin.params.filter.cutoff.value();
+    plugin
+        .coefficients
+        .set_targets(fs, cutoff, plugin.params.q());
+    // full bend up is the default two semitones, and pressure does nothing until it's given an amount
+    plugin.pitch_bend = 1.;
+    plugin.pressure = 1.;
+    plugin
+        .midi_mod
+        .set_target(fs, plugin.midi_mod_target(), MIDI_SMOOTHING_MS);
+    let mut buffer = vec![0.1; 960];
+    plugin.process_channels(&mut [&mut buffer[..]], 0..960, false, false);
+    let semitones = 12. * (plugin.params.cutoff_hz.get() / cutoff).log2();
+    assert!((semitones - 2.).abs() < 1e-3, "{} semitones", semitones);
+}
+
+#[test]
+fn test_sequencer_keeps_time_while_unused() {
+    let mut plugin = VaFilter::default();
+    let fs = 48000.;
+    for (i, step) in plugin.sequencer.steps.iter_mut().enumerate() {
+        *step = i as f32 / modulation::N_STEPS as f32;
+    }
+    plugin.sequencer.start_block(Some(0.), 120., fs);
+    // nothing listens to the sequencer, so it only moves on. 390 sub-blocks are a bit over a 16th at 120 bpm,
+    // one sample short for each of them would still be in the first step
+    let (n, sub_blocks) = (16, 390);
+    for _ in 0..sub_blocks {
+        plugin.next_modulation(n);
+    }
+    let beats = (sub_blocks * n) as f64 * 2. / fs as f64;
+    let division = filter_params::SeqDivision::Sixteenth;
+    assert_eq!(
+        plugin.sequencer.next(division, 0., 0.),
+        plugin.sequencer.value_at(beats, division, 0., 0.)
+    );
+    assert_eq!(plugin.sequencer.value_at(beats, division, 0., 0.), 1. / 16.);
+}
+
+#[test]
+fn test_mod_wheel() {
+    let mut plugin = VaFilter::default();
+    let fs = 48000.;
+    plugin.prepare(1, 1, fs);
+    plugin.reset();
+    let params = plugin.params.clone();
+    let depth = &params.modulation.mod_wheel_depth;
+    unsafe {
+        depth
+            .as_ptr()
+            .set_normalized_value(depth.preview_normalized(0.2));
+    }
+    let cutoff = params.filter.cutoff.value();
+    let mut events = vec![NoteEvent::MidiCC {
+        timing: 0,
+        channel: 0,
+        cc: MOD_WHEEL_CC,
+        value: 1.,
+    }]
+    .into_iter();
+    let mut buffer = vec![0.1; 960];
+    plugin.process_buffer(&mut [&mut buffer[..]], None, 120., || events.next());
+    // a fifth of the way up from wherever the cutoff is set, which stays where it was
+    let octaves = (params.cutoff_hz.get() / cutoff).log2();
+    assert!(
+        (octaves - 0.2 * filter_params::MOD_CUTOFF_OCTAVES).abs() < 1e-3,
+        "{} octaves",
+        octaves
+    );
+    assert_eq!(params.filter.cutoff.value(), cutoff);
+    assert_eq!(params.mod_wheel.get(), 1.);
+}
+
+#[test]
+fn test_report_modulation() {
+    let mut plugin = VaFilter::default();
+    plugin.prepare(1, 1, 48000.);
+    plugin.reset();
+    let params = plugin.params.clone();
+    let mut buffer = vec![0.1; 960];
+    plugin.process_buffer(&mut [&mut buffer[..]], None, 120., || None);
+    // nothing modulating, so the rings sit exactly on the knobs
+    assert_eq!(
+        params.modulated(ModDestination::Cutoff),
+        params.filter.cutoff.value()
+    );
+    assert_eq!(
+        params.modulated(ModDestination::Resonance),
+        params.filter.res.value()
+    );
+    assert_eq!(
+        params.modulated(ModDestination::Drive),
+        params.drive.amount.value()
+    );
+
+    // the mod wheel takes the cutoff an octave up, and leaves the rest alone
+    let depth = &params.modulation.mod_wheel_depth;
+    unsafe {
+        depth
+            .as_ptr()
+            .set_normalized_value(depth.preview_normalized(0.2));
+    }
+    let mut events = vec![NoteEvent::MidiCC {
+        timing: 0,
+        channel: 0,
+        cc: MOD_WHEEL_CC,
+        value: 1.,
+    }]
+    .into_iter();
+    plugin.process_buffer(&mut [&mut buffer[..]], None, 120., || events.next());
+    let octaves = (params.modulated(ModDestination::Cutoff) / params.filter.cutoff.value()).log2();
+    assert!((octaves - 1.).abs() < 1e-3, "{} octaves", octaves);
+    assert_eq!(
+        params.modulated(ModDestination::Resonance),
+        params.filter.res.value()
+    );
+    assert_eq!(
+        params.modulated(ModDestination::Drive),
+        params.drive.amount.value()
+    );
+}
+
+#[test]
+fn test_filter_fm() {
+    let plugin = || {
+        let plugin = VaFilter::default();
+        plugin.params.sample_rate.set(96000.);
+        plugin.coefficients.apply();
+        plugin
+    };
+    let (mut plain, mut fm) = (plugin(), plugin());
+    let input = |i: usize| 0.5 * (i as f32 * 0.05).sin();
+    // with a silent modulator, only the tan approximation is different
+    for i in 0..2000 {
+        let frame = f32x4::from_array([input(i), 0., 0., 0.]);
+        let (plain_out, fm_out) = (
+            plain.process_bypassable(frame, None),
+            fm.process_bypassable(frame, Some(4.)),
+        );
+        assert!((plain_out[0] - fm_out[0]).abs() < 1e-4, "{}", i);
+        assert_eq!(fm_out[1], fm_out[0]);
+    }
+    // modulating far past nyquist, which the clamp has to keep the solvers stable through
+    for i in 0..48000 {
+        let modulator = (i as f32 * 0.3).sin();
+        let out = fm.process_fm(f32x4::from_array([input(i), modulator, 0., 0.]), 8.);
+        assert!(
+            out[0].is_finite() && out[0].abs() < 10.,
+            "{}: {}",
+            i,
+            out[0]
+        );
+    }
+    // a modulator held up opens the filter and one held down closes it, so the noise through the first comes out
+    // brighter. Brightness here is the energy of the first difference over the energy
+    let brightness = |modulator: f32| {
+        let mut plugin = plugin();
+        let mut noise = 1;
+        let (mut last, mut diff, mut energy) = (0., 0., 0.);
+        for _ in 0..9600 {
+            let x = 0.5 * utils::random_bipolar(&mut noise);
+            let out = plugin.process_fm(f32x4::from_array([x, modulator, 0., 0.]), 2.)[0];
+            diff += (out - last) * (out - last);
+            energy += out * out;
+            last = out;
+        }
+        diff / energy
+    };
+    let (up, down) = (brightness(1.), brightness(-1.));
+    assert!(up > 2. * down, "{} vs {}", up, down);
+    // the dry side is the stereo input, not the mono fm path
+    fm.bypass_fade.reset(1.);
+    let frame = f32x4::from_array([0.3, -0.2, 0., 0.]);
+    assert_eq!(fm.process_bypassable(frame, Some(4.)), frame);
+}
+
+#[test]
+fn test_sample_rate_change() {
+    // steady state gain of a sine through the plugin at the host's rate `fs`
+    fn sine_gain(plugin: &mut VaFilter, fs: f32, freq: f32) -> f32 {
+        plugin.reset();
+        let amp = 0.01;
+        let len = fs as usize / 4;
+        let mut signal: Vec<f32> = (0..len)
+            .map(|i| amp * (2. * std::f32::consts::PI * freq * i as f32 / fs).sin())
+            .collect();
+        plugin.process_channels(&mut [&mut signal[..]], 0..len, false, false);
+        let tail = &signal[len / 2..];
+        (2. * tail.iter().map(|x| x * x).sum::<f32>() / tail.len() as f32).sqrt() / amp
+    }
+    // the level at the cutoff over the passband, which stays the same as long as the corner doesn't move
+    fn corner_db(plugin: &mut VaFilter, fs: f32) -> f32 {
+        plugin.set_sample_rate(fs);
+        let cutoff = plugin.params.filter.cutoff.value();
+        20. * (sine_gain(plugin, fs, cutoff) / sine_gain(plugin, fs, cutoff / 16.)).log10()
+    }
+    let mut plugin = VaFilter::default();
+    let before = corner_db(&mut plugin, 48000.);
+    assert!(before < -3., "{} dB at the cutoff", before);
+    // 48 kHz gets oversampled and 192 kHz doesn't, so coefficients left over would put the corner an octave up
+    let after = corner_db(&mut plugin, 192000.);
+    assert!(
+        (after - before).abs() < 0.5,
+        "{} dB at the cutoff at 48 kHz, {} dB at 192 kHz",
+        before,
+        after
+    );
+}
+
+#[test]
+fn test_auto_oversampling() {
+    let mut plugin = VaFilter::default();
+    plugin.prepare(1, 1, 48000.);
+    assert_eq!((plugin.oversample_factor, plugin.latency()), (2, 0));
+    let auto = &plugin.params.drive.auto_oversampling;
+    unsafe { auto.as_ptr().set_normalized_value(1.) };
+    let set_drive = |plugin: &VaFilter, db: f32| {
+        let amount = &plugin.params.drive.amount;
+        let gain = nih_plug::util::db_to_gain(db);
+        unsafe {
+            amount
+                .as_ptr()
+                .set_normalized_value(amount.preview_normalized(gain))
+        };
+    };
+    // a sine running on through every switch, which it shouldn't jump at
+    let mut phase = 0;
+    let mut last = 0.;
+    let mut block = |plugin: &mut VaFilter| {
+        let mut buffer: Vec<f32> = (phase..phase + 480)
+            .map(|i| 0.5 * (i as f32 * 0.05).sin())
+            .collect();
+        phase += 480;
+        plugin.process_buffer(&mut [&mut buffer[..]], None, 120., || None);
+        for x in buffer {
+            assert!(
+                x.is_finite() && (x - last).abs() < 0.2,
+                "{} after {}",
+                x,
+                last
+            );
+            last = x;
+        }
+        plugin.oversample_factor
+    };
+    // clean, moderate and heavy, and back down past the thresholds by more than the hysteresis
+    for (db, factor) in [(0., 1), (9., 2), (20., 4), (14., 4), (7., 2), (0., 1)] {
+        set_drive(&plugin, db);
+        for _ in 0..10 {
+            assert_eq!(block(&mut plugin), factor, "at {} dB", db);
+        }
+        assert_eq!(plugin.params.oversampling_factor.get(), factor);
+        // lined up with 4x whatever the factor is
+        assert_eq!(plugin.latency(), 4);
+    }
+    // at 96 kHz it only goes up to 2x
+    plugin.set_sample_rate(96000.);
+    set_drive(&plugin, 20.);
+    block(&mut plugin);
+    assert_eq!((plugin.oversample_factor, plugin.latency()), (2, 2));
+}
+
+#[test]
+fn test_band_outputs() {
+    let input: Vec<f32> = (0..1000)
+        .map(|i| 0.8 * (i as f32 * 0.03).sin() + 0.3 * (i as f32 * 0.7).sin())
+        .collect();
+    let mut stereo_plugin = VaFilter::default();
+    let (mut left, mut right) = (input.clone(), input.clone());
+    stereo_plugin.process_channels(&mut [&mut left[..], &mut right[..]], 0..1000, false, false);
+
+    // the extra outputs leave the main pair alone. The default circuit is the ladder,
+    // which doesn't have the bands, so they get overwritten with silence
+    let mut plugin = VaFilter::default();
+    plugin.band_outputs = true;
+    let mut channels = vec![
+        input.clone(),
+        input,
+        vec![1.; 1000],
+        vec![1.; 1000],
+        vec![1.; 1000],
+        vec![1.; 1000],
+    ];
+    let mut slices: Vec<&mut [f32]> = channels
+        .iter_mut()
+        .map(|channel| &mut channel[..])
+        .collect();
+    plugin.process_channels(&mut slices, 0..1000, false, false);
+    assert_eq!(channels[0], left);
+    assert_eq!(channels[1], right);
+    for channel in &channels[MAX_CHANNELS..] {
+        assert!(channel.iter().all(|x| *x == 0.));
+    }
+}
+
+#[test]
+fn test_state_compat() {
+    use filter_params::{
+        Character, Circuits, EnvTrigger, LadderResponse, LadderSlope, Saturation, SeqDivision,
+        SvfMode, SvfSlope,
+    };
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-3 * b.abs().max(1.);
+    // a state saved before the parameters were grouped, as the host stores it: plain values by id.
+    // Each value differs from its default
+    let params = FilterParams::new();
+    filter_params::load_state(
+        &params,
+        include_str!("../tests/fixtures/state_ungrouped.json"),
+    );
+    let (filter, drive, modulation, output) = (
+        &params.filter,
+        &params.drive,
+        &params.modulation,
+        &params.output,
+    );
+    assert!(close(filter.cutoff.value(), 2200.));
+    assert!(close(params.q(), 4.));
+    assert!(filter.legacy_res.value() <= 0.);
+    assert!(close(drive.amount.value(), 2.5));
+    assert!(close(filter.spread.value(), -0.5));
+    assert!(close(filter.smoothing.value(), 50.));
+    assert!(close(output.silence_gate.value(), 400.));
+    assert!(output.bypass.value());
+    assert!(close(output.bypass_fade_ms.value(), 20.));
+    assert!(close(filter.antisat.value(), 0.35));
+    assert!(close(filter.res_character.value(), 0.45));
+    assert_eq!(filter.mode.value(), SvfMode::Notch);
+    assert_eq!(filter.svf_slope.value(), SvfSlope::Db36);
+    assert_eq!(filter.slope.value(), LadderSlope::LP6);
+    assert_eq!(filter.ladder_response.value(), LadderResponse::HP12);
+    // the enums are saved by index, so the circuits that came after it don't move the sallen-key
+    assert_eq!(filter.filter_type.value(), Circuits::SallenKey);
+    assert_eq!(drive.character.value(), Character::Clean);
+    assert_eq!(drive.saturation.value(), Saturation::HardClip);
+    params.restore_saturation();
+    assert_eq!(params.saturation(), Saturation::HardClip);
+    assert!(output.res_comp.value());
+    assert!(close(filter.analog.value(), 0.55));
+    assert_eq!(*params.analog_seed.read().unwrap(), 777);
+    assert!(!output.dc_block.value());
+    assert!(output.limiter.value());
+    assert!(close(modulation.seq_depth.value(), 1.2));
+    assert_eq!(modulation.seq_division.value(), SeqDivision::EighthTriplet);
+    assert!(close(modulation.seq_swing.value(), 0.3));
+    assert!(close(modulation.seq_glide.value(), 0.9));
+    assert!(close(modulation.bend_range.value(), 12.));
+    assert!(close(modulation.aftertouch.value(), -1.5));
+    assert!(close(modulation.env_attack.value(), 25.));
+    assert!(close(modulation.env_decay.value(), 800.));
+    assert!(close(modulation.env_sustain.value(), 0.1));
+    assert!(close(modulation.env_release.value(), 1500.));
+    assert!(close(modulation.env_amount.value(), 2.));
+    assert_eq!(modulation.env_trigger.value(), EnvTrigger::Legato);
+    assert!(filter.fm.value());
+    assert!(close(filter.fm_amount.value(), 0.2));
+    let slots = [
+        (ModSource::Envelope, ModDestination::Resonance, 0.12),
+        (ModSource::Sequencer, ModDestination::Drive, -0.23),
+        (ModSource::Aftertouch, ModDestination::Cutoff, 0.34),
+        (ModSource::KeyTrack, ModDestination::Drive, -0.87),
+    ];
+    for (slot, (source, destination, depth)) in slots.iter().enumerate() {
+        let (loaded_source, loaded_destination, loaded_depth) = params.mod_slot(slot);
+        assert_eq!(loaded_source.value(), *source, "slot {}", slot);
+        assert_eq!(loaded_destination.value(), *destination, "slot {}", slot);
+        assert!(close(loaded_depth.value(), *depth), "slot {}", slot);
+    }
+}
+
+#[test]
+fn test_key_glide() {
+    let mut plugin = VaFilter::default();
+    let fs = 48000.;
+    // without glide the key tracking jumps to the note, like it always has
+    plugin.note = 72;
+    plugin.glide_to_note(fs);
+    assert_eq!(plugin.key_glide.next_step(1), 1.);
+
+    // 125 ms, linear in octaves
+    let glide = plugin.params.modulation.glide.as_ptr();
+    unsafe { glide.set_normalized_value(0.5) };
+    plugin.note = 48;
+    plugin.glide_to_note(fs);
+    let halfway = plugin.key_glide.next_step(3000);
+    assert!(halfway.abs() < 1e-3, "{}", halfway);
+    let end = plugin.key_glide.next_step(3000);
+    assert!((end + 1.).abs() < 1e-6, "{}", end);
+
+    // and reset takes it straight to the last note
+    plugin.note = 60;
+    plugin.glide_to_note(fs);
+    plugin.reset();
+    assert_eq!(plugin.key_glide.next_step(1), 0.);
+}
+
+// `initialize()` and `process()` themselves aren't run here: nih-plug only builds the `Transport` a stub
+// `ProcessContext` would hand out inside its wrappers, and clap-validator can't load a vst3-only plugin. What they
+// add on top of `prepare()` and `process_buffer()` is reading the transport and passing the latency on
+#[test]
+fn test_process_blocks() {
+    use filter_params::{Circuits, SvfSlope};
+    use nih_plug::param::internals::ParamPtr;
+    let fs = 48000.;
+    let block_len = 256;
+    // like the wrapper does it between blocks
+    let set = |param: ParamPtr, value: f32| unsafe {
+        param.set_normalized_value(value);
+        param.update_smoother(fs, false);
+    };
+    let input = |i: usize| 0.7 * (i as f32 * 0.013).sin() + 0.3 * ((i % 97) as f32 / 48. - 1.);
+    // everything a host throws at it, with or without the filter getting to hear about the slope changing
+    let render = |num_channels: usize, handshake: bool| {
+        let mut plugin = VaFilter::default();
+        plugin.prepare(num_channels, num_channels, fs);
+        plugin.reset();
+        let params = plugin.params.clone();
+        let filter = &params.filter;
+        let mut output = Vec::new();
+        for n in 0..200 {
+            let circuit = match n {
+                0..=49 => Circuits::Ladder,
+                50..=99 => Circuits::SallenKey,
+                _ => Circuits::SVF,
+            };
+            set(
+                filter.filter_type.as_ptr(),
+                filter.filter_type.preview_normalized(circuit),
+            );
+            set(filter.cutoff.as_ptr(), 0.5 + 0.3 * (n as f32 * 0.2).sin());
+            if n == 150 {
+                set(
+                    filter.svf_slope.as_ptr(),
+                    filter.svf_slope.preview_normalized(SvfSlope::Db24),
+                );
+                if !handshake {
+                    params.dirty.take();
+                }
+            }
+            let mut events = match n % 20 {
+                0 => vec![NoteEvent::NoteOn {
+                    timing: 100,
+                    voice_id: None,
+                    channel: 0,
+                    note: 48 + n as u8 / 10,
+                    velocity: 1.,
+                }],
+                10 => vec![NoteEvent::NoteOff {
+                    timing: 0,
+                    voice_id: None,
+                    channel: 0,
+                    note: 48 + n as u8 / 10 - 1,
+                    velocity: 0.,
+                }],
+                _ => Vec::new(),
+            }
+            .into_iter();
+            let mut channels: Vec<Vec<f32>> = (0..num_channels)
+                .map(|_| (0..block_len).map(|i| input(n * block_len + i)).collect())
+                .collect();
+            let mut slices: Vec<&mut [f32]> = channels
+                .iter_mut()
+                .map(|channel| &mut channel[..])
+                .collect();
+            plugin.process_buffer(&mut slices, None, 120., || events.next());
+            // whatever changed got picked up by this block
+            assert_eq!(params.dirty.get(), 0);
+            for (i, x) in channels[0].iter().enumerate() {
+                assert!(
+                    x.is_finite() && x.abs() < 10.,
+                    "{} at block {}, {}",
+                    x,
+                    n,
+                    i
+                );
+            }
+            output.extend_from_slice(&channels[0]);
+        }
+        output
+    };
+    let output = render(2, true);
+    // mono takes the same path, and only the first lane
+    assert_eq!(render(1, true), output);
+    // the slope is only taken in by `update()`, so without the handshake the svf would carry on at 12 dB
+    let stale = render(2, false);
+    let switch = 150 * block_len;
+    assert_eq!(stale[..switch], output[..switch]);
+    assert!(stale[switch..] != output[switch..]);
+
+    // the rest of what `process()` hands back to the host: the latency when auto oversampling goes on or off,
+    // and the tail once the input stops
+    let mut plugin = VaFilter::default();
+    plugin.prepare(2, 2, fs);
+    plugin.reset();
+    assert_eq!(plugin.reported_latency, 0);
+    let block = |plugin: &mut VaFilter, level: f32| {
+        let mut channels: Vec<Vec<f32>> = (0..2)
+            .map(|_| (0..block_len).map(|i| level * input(i)).collect())
+            .collect();
+        let mut slices: Vec<&mut [f32]> = channels
+            .iter_mut()
+            .map(|channel| &mut channel[..])
+            .collect();
+        plugin.process_buffer(&mut slices, None, 120., || None);
+        (plugin.latency_change(), plugin.process_status())
+    };
+    assert!(matches!(
+        block(&mut plugin, 1.),
+        (None, ProcessStatus::Normal)
+    ));
+    let auto = plugin.params.drive.auto_oversampling.as_ptr();
+    set(auto, 1.);
+    let latency = block(&mut plugin, 1.).0;
+    assert!(latency.unwrap() > 0);
+    assert_eq!(latency, Some(plugin.latency()));
+    // and only once
+    assert!(matches!(
+        block(&mut plugin, 1.),
+        (None, ProcessStatus::Normal)
+    ));
+    set(auto, 0.);
+    assert_eq!(block(&mut plugin, 1.).0, Some(0));
+    assert!(matches!(
+        block(&mut plugin, 0.),
+        (None, ProcessStatus::Tail(left)) if left > 0
+    ));
+    for _ in 0..1000 {
+        if plugin.idle {
+            break;
+        }
+        block(&mut plugin, 0.);
+    }
+    assert!(matches!(
+        block(&mut plugin, 0.),
+        (None, ProcessStatus::Tail(0))
+    ));
+}
+
+#[test]
+fn test_mono_to_stereo() {
+    let input: Vec<f32> = (0..1000)
+        .map(|i| 0.8 * (i as f32 * 0.03).sin() + 0.3 * (i as f32 * 0.7).sin())
+        .collect();
+    let mut stereo_plugin = VaFilter::default();
+    stereo_plugin.prepare(2, 2, 48000.);
+    let (mut left, mut right) = (input.clone(), input.clone());
+    stereo_plugin.process_channels(&mut [&mut left[..], &mut right[..]], 0..1000, false, false);
+
+    // the input is only in the first output, the second one has whatever the host left in it
+    let mut plugin = VaFilter::default();
+    plugin.prepare(1, 2, 48000.);
+    let (mut mono_left, mut mono_right) = (input, vec![0.5; 1000]);
+    plugin.process_channels(
+        &mut [&mut mono_left[..], &mut mono_right[..]],
+        0..1000,
+        false,
+        false,
+    );
+    assert_eq!(mono_left, left);
+    assert_eq!(mono_right, right);
+}
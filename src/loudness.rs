@@ -0,0 +1,162 @@
+// renders presets through the whole plugin, for keeping the factory presets about as loud as each other.
+// The level is a plain rms over a fixed stretch of program material, which is all comparing presets needs
+use crate::filter_params::{FilterParams, OUTPUT_GAIN_DB};
+use crate::presets::{self, Preset};
+use crate::utils::random_bipolar;
+use crate::VaFilter;
+use nih_plug::prelude::{util, Param};
+
+pub const SAMPLE_RATE: f32 = 48000.;
+/// How loud the presets should come out, relative to the fixture going in
+pub const TARGET_DB: f32 = 0.;
+/// How far off the target a factory preset can be before `normalize_presets` needs running
+pub const WINDOW_DB: f32 = 2.;
+/// Four bars at 120 bpm
+const FIXTURE_SECONDS: f32 = 8.;
+/// What the host would hand over at a time
+const BLOCK_LEN: usize = 512;
+
+/// Something like a dance loop: a kick on every beat, a saw bass on the eighths in between and noise hats on the
+/// offbeats, at about -12 dB rms. The same every time
+pub fn program_fixture() -> Vec<f32> {
+    let beat = (SAMPLE_RATE / 2.) as usize;
+    let eighth = beat / 2;
+    let mut noise = 1;
+    (0..(FIXTURE_SECONDS * SAMPLE_RATE) as usize)
+        .map(|i| {
+            let t = (i % beat) as f32 / SAMPLE_RATE;
+            // swept from 150 Hz down to 50 Hz
+            let kick_phase =
+                2. * std::f32::consts::PI * (50. * t + 100. * (1. - (-t / 0.03).exp()) * 0.03);
+            let kick = 0.8 * kick_phase.sin() * (-t / 0.15).exp();
+            // the root for three bars and a fifth above it for the fourth
+            let bass_freq = if i / (4 * beat) == 3 { 82.41 } else { 55. };
+            let bass_t = (i % eighth) as f32 / SAMPLE_RATE;
+            let saw = 2. * (bass_t * bass_freq).fract() - 1.;
+            let bass = if (i / eighth) % 2 == 1 {
+                0.4 * saw * (-bass_t / 0.2).exp()
+            } else {
+                0.
+            };
+            let hat_t = ((i + eighth) % beat) as f32 / SAMPLE_RATE;
+            let hat = 0.15 * random_bipolar(&mut noise) * (-hat_t / 0.02).exp();
+            kick + bass + hat
+        })
+        .collect()
+}
+
+/// The rms level in dB
+pub fn level_db(signal: &[f32]) -> f32 {
+    let power = signal.iter().map(|x| x * x).sum::<f32>() / signal.len().max(1) as f32;
+    10. * power.max(1e-20).log10()
+}
+
+/// Runs `input` through a freshly loaded plugin with the preset's values, in blocks like a host
+pub fn render(preset: &Preset, input: &[f32]) -> Vec<f32> {
+    let mut plugin = VaFilter::default();
+    for (id, value) in &preset.values {
+        if let Some(param_ptr) = presets::param_ptr(&plugin.params, id) {
+            unsafe { param_ptr.set_normalized_value(*value) };
+        }
+    }
+    let saturation = &plugin.params.drive.saturation;
+    unsafe {
+        saturation
+            .as_ptr()
+            .set_normalized_value(saturation.preview_normalized(preset.saturation()))
+    };
+    if let Ok(mut steps) = plugin.params.seq_steps.write() {
+        *steps = preset.seq_steps;
+    }
+    plugin.prepare(1, 1, SAMPLE_RATE);
+    plugin.reset();
+    let mut output = input.to_vec();
+    for block in output.chunks_mut(BLOCK_LEN) {
+        plugin.process_buffer(&mut [block], None, 120., || None);
+    }
+    output
+}
+
+/// How much louder than `fixture` the preset comes out, in dB
+pub fn relative_level(preset: &Preset, fixture: &[f32]) -> f32 {
+    level_db(&render(preset, fixture)) - level_db(fixture)
+}
+
+/// The output gain that brings the preset to `TARGET_DB`, from the gain it has now and the level it comes out at with
+/// it. Clamped to the parameter's range
+pub fn output_gain(preset: &Preset, level: f32) -> f32 {
+    let params = FilterParams::new();
+    let gain = &params.output.gain;
+    let current = gain.preview_plain(
+        preset
+            .value("gain")
+            .unwrap_or(gain.default_normalized_value()),
+    );
+    gain.preview_plain(gain.preview_normalized(current * util::db_to_gain(TARGET_DB - level)))
+}
+
+/// The preset's text with its output gain line set to `gain`, and everything else left as it was
+pub fn set_gain_line(text: &str, gain: f32) -> String {
+    let mut lines: Vec<String> = text
+        .lines()
+        .filter(|line| {
+            line.split_once('=')
+                .map_or(true, |(id, _)| id.trim() != "gain")
+        })
+        .map(str::to_string)
+        .collect();
+    lines.push(format!("gain = {}", gain));
+    lines.join("\n") + "\n"
+}
+
+#[test]
+fn test_set_gain_line() {
+    let text = "# comment\nversion = 3\ncutoff = 500\ngain = 0.25\nq = 1\n";
+    assert_eq!(
+        set_gain_line(text, 2.),
+        "# comment\nversion = 3\ncutoff = 500\nq = 1\ngain = 2\n"
+    );
+    let params = FilterParams::new();
+    let preset = Preset::parse("Gain", &set_gain_line(text, 2.)).unwrap();
+    let gain = params
+        .output
+        .gain
+        .preview_plain(preset.value("gain").unwrap());
+    assert!((gain - 2.).abs() < 1e-4, "{}", gain);
+}
+
+#[test]
+fn test_output_gain() {
+    let preset = Preset::init();
+    // 6 dB too quiet gets 6 dB more
+    let gain = output_gain(&preset, TARGET_DB - 6.);
+    assert!((util::gain_to_db(gain) - 6.).abs() < 0.01, "{}", gain);
+    // and never more than the parameter goes to
+    let gain = output_gain(&preset, -100.);
+    assert!(
+        (util::gain_to_db(gain) - OUTPUT_GAIN_DB).abs() < 0.01,
+        "{}",
+        gain
+    );
+}
+
+#[test]
+fn test_factory_presets() {
+    let fixture = program_fixture();
+    assert!((level_db(&fixture) + 12.).abs() < 3.);
+    for preset in presets::factory_presets() {
+        let output = render(&preset, &fixture);
+        assert!(
+            output.iter().all(|x| x.is_finite()),
+            "{} isn't finite",
+            preset.name
+        );
+        let level = level_db(&output) - level_db(&fixture);
+        assert!(
+            (level - TARGET_DB).abs() < WINDOW_DB,
+            "{} comes out at {} dB, run normalize_presets",
+            preset.name,
+            level
+        );
+    }
+}
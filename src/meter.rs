@@ -0,0 +1,190 @@
+// input and output level meters
+use crate::utils::{AtomicF32, AtomicOps, AtomicUsize};
+use core_simd::simd::*;
+
+/// How long the peak hold line stays put before following the peak down, in seconds
+const HOLD_TIME: f32 = 1.5;
+/// Time constant of the peak's fall and the rms' rise and fall, in seconds
+const BALLISTICS_TIME: f32 = 0.3;
+/// Below this the meters show -inf, about -120 dB
+const SILENCE: f32 = 1e-6;
+/// The range the meters show, in dBFS
+pub const METER_FLOOR_DB: f32 = -60.;
+pub const METER_CEILING_DB: f32 = 6.;
+
+/// Peak and sum of squares of the frames of a block, kept by the audio thread until it hands them to a `LevelMeter`
+#[derive(Default)]
+pub struct LevelAccumulator {
+    peak: f32x4,
+    sum_squares: f32x4,
+    samples: usize,
+}
+
+impl LevelAccumulator {
+    #[inline]
+    pub fn add(&mut self, frame: f32x4) {
+        self.peak = self.peak.simd_max(frame.abs());
+        self.sum_squares += frame * frame;
+        self.samples += 1;
+    }
+}
+
+/// The levels of both channels since the gui last took them. The audio thread adds to it once per block with
+/// plain loads and stores, so it never waits on the gui. A block that lands while the gui takes the levels can
+/// get lost, which a meter doesn't show
+pub struct LevelMeter {
+    peak: [AtomicF32; 2],
+    sum_squares: [AtomicF32; 2],
+    samples: AtomicUsize,
+}
+
+impl Default for LevelMeter {
+    fn default() -> Self {
+        Self {
+            peak: [AtomicF32::new(0.), AtomicF32::new(0.)],
+            sum_squares: [AtomicF32::new(0.), AtomicF32::new(0.)],
+            samples: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl LevelMeter {
+    /// Adds what `block` collected and clears it. A mono block only has the first lane, which goes to both sides
+    pub fn add(&self, block: &mut LevelAccumulator, n_channels: usize) {
+        let block = std::mem::take(block);
+        if block.samples == 0 {
+            return;
+        }
+        for channel in 0..2 {
+            let lane = if n_channels < 2 { 0 } else { channel };
+            if block.peak[lane] > self.peak[channel].get() {
+                self.peak[channel].set(block.peak[lane]);
+            }
+            self.sum_squares[channel]
+                .set(self.sum_squares[channel].get() + block.sum_squares[lane]);
+        }
+        self.samples.set(self.samples.get() + block.samples);
+    }
+    /// The peak and rms of each channel since the last call, or `None` when nothing was processed in between
+    pub fn take(&self) -> Option<[(f32, f32); 2]> {
+        let samples = self.samples.get();
+        if samples == 0 {
+            return None;
+        }
+        self.samples.set(0);
+        Some([0, 1].map(|channel| {
+            let peak = self.peak[channel].get();
+            let sum_squares = self.sum_squares[channel].get();
+            self.peak[channel].set(0.);
+            self.sum_squares[channel].set(0.);
+            (peak, (sum_squares / samples as f32).sqrt())
+        }))
+    }
+}
+
+/// What one channel of a meter shows, moved along by the gui's timer
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MeterBallistics {
+    /// jumps up with the signal, and falls with `BALLISTICS_TIME`
+    pub peak: f32,
+    /// averaged over `BALLISTICS_TIME`
+    pub rms: f32,
+    /// the highest recent peak, held for `HOLD_TIME`
+    pub hold: f32,
+    hold_left: f32,
+    power: f32,
+}
+
+impl MeterBallistics {
+    /// Advances by `dt` seconds with the peak and rms `levels` measured since the last update.
+    /// `None` means nothing was processed, which lets everything fall down to silence
+    pub fn update(&mut self, levels: Option<(f32, f32)>, dt: f32) {
+        let (peak, rms) = levels.unwrap_or((0., 0.));
+        let fall = (-dt / BALLISTICS_TIME).exp();
+        self.peak = peak.max(self.peak * fall);
+        self.power = rms * rms + (self.power - rms * rms) * fall;
+        self.rms = self.power.sqrt();
+        self.hold_left -= dt;
+        if self.peak >= self.hold || self.hold_left <= 0. {
+            if self.peak >= self.hold {
+                self.hold_left = HOLD_TIME;
+            }
+            self.hold = self.peak;
+        }
+        // the decays never reach 0 on their own
+        for level in [&mut self.peak, &mut self.rms, &mut self.hold] {
+            if *level < SILENCE {
+                *level = 0.;
+            }
+        }
+        if self.power < SILENCE * SILENCE {
+            self.power = 0.;
+        }
+    }
+}
+
+/// Where `level` goes on a meter, from 0 at `METER_FLOOR_DB` and below to 1 at `METER_CEILING_DB`
+pub fn meter_fraction(level: f32) -> f32 {
+    let db = 20. * level.log10();
+    ((db - METER_FLOOR_DB) / (METER_CEILING_DB - METER_FLOOR_DB)).clamp(0., 1.)
+}
+
+#[test]
+fn test_level_meter() {
+    let meter = LevelMeter::default();
+    assert_eq!(meter.take(), None);
+    let mut block = LevelAccumulator::default();
+    for i in 0..4800 {
+        let x = (i as f32 * 0.1).sin();
+        block.add(f32x4::from_array([x, 0.5 * x, 0., 0.]));
+    }
+    meter.add(&mut block, 2);
+    let [left, right] = meter.take().unwrap();
+    assert!((left.0 - 1.).abs() < 1e-3 && (right.0 - 0.5).abs() < 1e-3);
+    assert!((left.1 - 0.5f32.sqrt()).abs() < 1e-2, "{}", left.1);
+    assert!((right.1 - 0.5 * 0.5f32.sqrt()).abs() < 1e-2, "{}", right.1);
+    // taking clears it, and so does adding
+    assert_eq!(meter.take(), None);
+    meter.add(&mut block, 2);
+    assert_eq!(meter.take(), None);
+
+    // mono shows on both sides
+    block.add(f32x4::from_array([0.25, 0., 0., 0.]));
+    meter.add(&mut block, 1);
+    let [left, right] = meter.take().unwrap();
+    assert_eq!(left, right);
+}
+
+#[test]
+fn test_meter_ballistics() {
+    let dt = 1. / 30.;
+    let mut meter = MeterBallistics::default();
+    meter.update(Some((1., 0.5)), dt);
+    // the peak jumps straight up, the rms takes its time
+    assert_eq!(meter.peak, 1.);
+    assert_eq!(meter.hold, 1.);
+    assert!(meter.rms < 0.5);
+    for _ in 0..30 {
+        meter.update(Some((0.5, 0.5)), dt);
+    }
+    assert!((meter.rms - 0.5).abs() < 0.01);
+    // the peak has fallen back down to the signal
+    assert_eq!(meter.peak, 0.5);
+    // still holding after a second
+    assert_eq!(meter.hold, 1.);
+    for _ in 0..30 {
+        meter.update(Some((0.5, 0.5)), dt);
+    }
+    assert_eq!(meter.hold, meter.peak);
+
+    // once processing stops, it all falls to -inf
+    for _ in 0..300 {
+        meter.update(None, dt);
+    }
+    assert_eq!((meter.peak, meter.rms, meter.hold), (0., 0., 0.));
+    assert_eq!(meter_fraction(meter.peak), 0.);
+    assert_eq!(
+        meter_fraction(1.),
+        -METER_FLOOR_DB / (METER_CEILING_DB - METER_FLOOR_DB)
+    );
+}
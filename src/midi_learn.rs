@@ -0,0 +1,168 @@
+// midi ccs bound to parameters from the editor
+use crate::utils::{AtomicOps, AtomicUsize};
+use std::sync::atomic::{self, Ordering};
+
+const N_CCS: usize = 128;
+// an unbound cc, or nothing being learned
+const NO_PARAM: usize = usize::MAX;
+
+/// Which parameter each cc moves, as indices into `FilterParams::param_map()`.
+/// The gui starts learning and forgets bindings, the audio thread reads the table and finishes learning once a cc
+/// comes in. It's all atomics, so neither side ever waits on the other.
+/// Every parameter has at most one cc, and a cc learned for another parameter leaves the old one
+pub struct MidiLearn {
+    params: [AtomicUsize; N_CCS],
+    // the parameter waiting for a cc
+    learning: atomic::AtomicUsize,
+    // bumped on every change to the bindings, so the gui knows when to save them
+    version: atomic::AtomicUsize,
+}
+
+impl Default for MidiLearn {
+    fn default() -> Self {
+        Self {
+            params: std::array::from_fn(|_| AtomicUsize::new(NO_PARAM)),
+            learning: atomic::AtomicUsize::new(NO_PARAM),
+            version: atomic::AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MidiLearn {
+    /// Binds the next cc that comes in to `param`
+    pub fn learn(&self, param: usize) {
+        self.learning.store(param, Ordering::Relaxed);
+    }
+    pub fn cancel_learning(&self) {
+        self.learning.store(NO_PARAM, Ordering::Relaxed);
+    }
+    /// The parameter waiting for a cc
+    pub fn learning(&self) -> Option<usize> {
+        let param = self.learning.load(Ordering::Relaxed);
+        (param != NO_PARAM).then(|| param)
+    }
+    pub fn forget(&self, param: usize) {
+        for bound in self.params.iter() {
+            if bound.get() == param {
+                bound.set(NO_PARAM);
+            }
+        }
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn bind(&self, cc: u8, param: usize) {
+        if let Some(bound) = self.params.get(cc as usize) {
+            for other in self.params.iter() {
+                if other.get() == param {
+                    other.set(NO_PARAM);
+                }
+            }
+            bound.set(param);
+            self.version.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    pub fn cc(&self, param: usize) -> Option<u8> {
+        self.params
+            .iter()
+            .position(|bound| bound.get() == param)
+            .map(|cc| cc as u8)
+    }
+    /// Called by the audio thread for every incoming cc. Finishes learning if a parameter is waiting for one, and
+    /// returns the parameter the cc moves
+    pub fn handle_cc(&self, cc: u8) -> Option<usize> {
+        let learning = self.learning.load(Ordering::Relaxed);
+        if learning != NO_PARAM
+            && self
+                .learning
+                .compare_exchange(learning, NO_PARAM, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.bind(cc, learning);
+        }
+        let param = self.params.get(cc as usize)?.get();
+        (param != NO_PARAM).then(|| param)
+    }
+    pub fn version(&self) -> usize {
+        self.version.load(Ordering::Relaxed)
+    }
+    /// The bindings as parameter ids and ccs, for the plugin's state. `ids` are the parameters' ids in the order of
+    /// `param_map()`
+    pub fn to_saved(&self, ids: &[String]) -> Vec<(String, u8)> {
+        self.params
+            .iter()
+            .enumerate()
+            .filter_map(|(cc, param)| Some((ids.get(param.get())?.clone(), cc as u8)))
+            .collect()
+    }
+    /// Replaces the bindings with the saved ones, leaving out parameters this build doesn't have
+    pub fn restore(&self, saved: &[(String, u8)], ids: &[String]) {
+        for bound in self.params.iter() {
+            bound.set(NO_PARAM);
+        }
+        for (id, cc) in saved {
+            if let Some(param) = ids.iter().position(|known| known == id) {
+                self.bind(*cc, param);
+            }
+        }
+        self.cancel_learning();
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_midi_learn() {
+    let learn = MidiLearn::default();
+    assert_eq!(learn.handle_cc(74), None);
+    learn.learn(3);
+    assert_eq!(learn.learning(), Some(3));
+    // the first cc after starting is the one that's learned
+    assert_eq!(learn.handle_cc(74), Some(3));
+    assert_eq!(learn.learning(), None);
+    assert_eq!(learn.handle_cc(71), None);
+    assert_eq!(learn.cc(3), Some(74));
+
+    // a cc learned for another parameter moves over
+    learn.learn(5);
+    learn.handle_cc(74);
+    assert_eq!((learn.cc(3), learn.cc(5)), (None, Some(74)));
+    // and a parameter only has one cc
+    learn.learn(5);
+    learn.handle_cc(1);
+    assert_eq!(learn.handle_cc(74), None);
+    assert_eq!(learn.handle_cc(1), Some(5));
+
+    let version = learn.version();
+    learn.forget(5);
+    assert_eq!(learn.handle_cc(1), None);
+    assert!(learn.version() != version);
+    // out of range ccs don't go anywhere
+    learn.bind(200, 2);
+    assert_eq!(learn.cc(2), None);
+}
+
+#[test]
+fn test_midi_learn_state() {
+    let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+    let all = ids(&["cutoff", "res", "drive"]);
+    let learn = MidiLearn::default();
+    learn.bind(74, 0);
+    learn.bind(1, 2);
+    let saved = learn.to_saved(&all);
+    assert_eq!(
+        saved,
+        [("drive".to_string(), 1), ("cutoff".to_string(), 74)]
+    );
+
+    // a build with the parameters in another order, and without one of them
+    let other = MidiLearn::default();
+    other.bind(10, 1);
+    other.learn(1);
+    other.restore(&saved, &ids(&["drive", "cutoff"]));
+    assert_eq!((other.cc(0), other.cc(1)), (Some(1), Some(74)));
+    assert_eq!(other.learning(), None);
+    let fewer = MidiLearn::default();
+    fewer.restore(&saved, &ids(&["cutoff"]));
+    assert_eq!(
+        fewer.to_saved(&ids(&["cutoff"])),
+        [("cutoff".to_string(), 74)]
+    );
+}
@@ -0,0 +1,634 @@
+// modulation sources that get summed into the cutoff before `update_g`
+use crate::filter_params::{
+    FilterParams, ModDestination, ModSource, SeqDivision, MOD_CUTOFF_OCTAVES, MOD_DRIVE_DB,
+    MOD_RES_OCTAVES, MOD_SLOTS,
+};
+use crate::smoothing::SUB_BLOCK_LEN;
+use crate::utils::random_bipolar;
+
+pub const N_STEPS: usize = 16;
+
+impl SeqDivision {
+    /// length of a step in quarter notes
+    pub fn beats(&self) -> f64 {
+        match self {
+            SeqDivision::Quarter => 1.,
+            SeqDivision::Eighth => 0.5,
+            SeqDivision::Sixteenth => 0.25,
+            SeqDivision::ThirtySecond => 0.125,
+            SeqDivision::EighthTriplet => 1. / 3.,
+            SeqDivision::SixteenthTriplet => 1. / 6.,
+        }
+    }
+}
+
+/// 16-step sequencer clocked from the host's musical position.
+///
+/// The output only depends on the position in beats, so it stays locked to the project
+/// when looping or rendering offline.
+pub struct StepSequencer {
+    pub steps: [f32; N_STEPS],
+    // position at the start of the current block, and how far each sample moves it
+    block_start: f64,
+    beats_per_sample: f64,
+    sample_idx: usize,
+}
+
+impl Default for StepSequencer {
+    fn default() -> Self {
+        Self {
+            steps: [0.; N_STEPS],
+            block_start: 0.,
+            beats_per_sample: 0.,
+            sample_idx: 0,
+        }
+    }
+}
+
+impl StepSequencer {
+    /// Syncs the sequencer to the host at the start of a block. If the host doesn't report a position,
+    /// the sequencer just keeps running from wherever the last block ended.
+    pub fn start_block(&mut self, pos_beats: Option<f64>, tempo: f64, sample_rate: f32) {
+        self.block_start = match pos_beats {
+            Some(pos) => pos,
+            None => self.position(),
+        };
+        self.beats_per_sample = tempo / 60. / sample_rate as f64;
+        self.sample_idx = 0;
+    }
+
+    /// position in beats of the next sample.
+    // computed from the block start rather than accumulated, so rounding errors don't build up
+    fn position(&self) -> f64 {
+        self.block_start + self.sample_idx as f64 * self.beats_per_sample
+    }
+
+    /// Bipolar output for the next sample, in the range -1 to 1
+    pub fn next(&mut self, division: SeqDivision, swing: f32, glide: f32) -> f32 {
+        let out = self.value_at(self.position(), division, swing, glide);
+        self.sample_idx += 1;
+        out
+    }
+
+    /// Moves the sequencer `n` samples on without computing its output
+    pub fn skip(&mut self, n: usize) {
+        self.sample_idx += n;
+    }
+
+    /// `swing` delays every second step by up to a full step length (0-1),
+    /// `glide` is the part of each step spent sliding over from the previous step's value (0-1)
+    pub fn value_at(&self, beats: f64, division: SeqDivision, swing: f32, glide: f32) -> f32 {
+        let (step, phase) = step_at(beats, division.beats(), swing as f64);
+        let value = self.steps[step];
+        if (phase as f32) < glide {
+            let prev = self.steps[(step + N_STEPS - 1) % N_STEPS];
+            prev + (value - prev) * (phase as f32 / glide)
+        } else {
+            value
+        }
+    }
+}
+
+/// How far the decay and release get in their set time
+const ENV_SETTLE_RATIO: f32 = 0.01;
+/// Below this the release has died out and the envelope goes idle
+const ENV_FLOOR: f32 = 1e-5;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum EnvStage {
+    Idle,
+    Attack,
+    // heads for the sustain level and stays there, so turning the sustain knob while a note is held glides too
+    Decay,
+    Release,
+}
+
+/// ADSR triggered by the notes coming in, from 0 to 1.
+///
+/// The attack is linear and starts from wherever the envelope is, so retriggering doesn't click.
+/// Decay and release are exponential like on most analog envelopes, and their times are how long
+/// they take to get within 1 % of where they're going
+pub struct Envelope {
+    stage: EnvStage,
+    value: f32,
+    // the notes that are held down, one bit per note number
+    held: u128,
+    sustain: f32,
+    // the attack, decay and release times in ms and the sample rate the coefficients are for
+    times: [f32; 4],
+    attack_step: f32,
+    decay_coef: f32,
+    release_coef: f32,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self {
+            stage: EnvStage::Idle,
+            value: 0.,
+            held: 0,
+            sustain: 1.,
+            times: [0.; 4],
+            attack_step: 1.,
+            decay_coef: 0.,
+            release_coef: 0.,
+        }
+    }
+}
+
+impl Envelope {
+    /// Cheap to call every block, the coefficients only get recomputed when a time or the sample rate changed
+    pub fn set_params(
+        &mut self,
+        sample_rate: f32,
+        attack_ms: f32,
+        decay_ms: f32,
+        sustain: f32,
+        release_ms: f32,
+    ) {
+        self.sustain = sustain;
+        let times = [attack_ms, decay_ms, release_ms, sample_rate];
+        if times == self.times {
+            return;
+        }
+        self.times = times;
+        let samples = |ms: f32| (ms / 1000. * sample_rate).max(1.);
+        self.attack_step = 1. / samples(attack_ms);
+        self.decay_coef = ENV_SETTLE_RATIO.powf(1. / samples(decay_ms));
+        self.release_coef = ENV_SETTLE_RATIO.powf(1. / samples(release_ms));
+    }
+
+    /// With `legato`, a note that comes in while another one is held carries on without restarting the attack
+    pub fn note_on(&mut self, note: u8, legato: bool) {
+        let overlapping = self.held != 0;
+        self.held |= 1 << (note & 127);
+        if !(legato && overlapping) {
+            self.stage = EnvStage::Attack;
+        }
+    }
+
+    /// Releases once the last held note is let go
+    pub fn note_off(&mut self, note: u8) {
+        self.held &= !(1 << (note & 127));
+        if self.held == 0 && self.stage != EnvStage::Idle {
+            self.stage = EnvStage::Release;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.stage = EnvStage::Idle;
+        self.value = 0.;
+        self.held = 0;
+    }
+
+    pub fn next(&mut self) -> f32 {
+        match self.stage {
+            EnvStage::Idle => (),
+            EnvStage::Attack => {
+                self.value += self.attack_step;
+                if self.value >= 1. {
+                    self.value = 1.;
+                    self.stage = EnvStage::Decay;
+                }
+            }
+            EnvStage::Decay => {
+                self.value = self.sustain + (self.value - self.sustain) * self.decay_coef;
+            }
+            EnvStage::Release => {
+                self.value *= self.release_coef;
+                if self.value < ENV_FLOOR {
+                    self.value = 0.;
+                    self.stage = EnvStage::Idle;
+                }
+            }
+        }
+        self.value
+    }
+
+    /// Moves `n` samples on, and returns where that ends up
+    pub fn next_step(&mut self, n: usize) -> f32 {
+        if self.stage == EnvStage::Idle {
+            return self.value;
+        }
+        for _ in 0..n {
+            self.next();
+        }
+        self.value
+    }
+}
+
+/// Where each source of the modulation matrix is at for the current sub-block
+#[derive(Default)]
+pub struct ModSources {
+    pub envelope: f32,
+    pub sequencer: f32,
+    pub pitch_bend: f32,
+    pub aftertouch: f32,
+    /// octaves from middle c
+    pub key_track: f32,
+    /// the smoothed macro knob, 0 while `FilterParams::macro_routed()` is false
+    pub macro_amount: f32,
+    /// the smoothed mod wheel, from 0 to 1
+    pub mod_wheel: f32,
+}
+
+impl ModSources {
+    /// Sums up what the slots of the matrix do to the cutoff and q in octaves, and to the drive in dB.
+    /// Slots that are off get skipped before anything else is read
+    pub fn route(&self, params: &FilterParams) -> (f32, f32, f32) {
+        let (mut cutoff, mut res, mut drive) = (0., 0., 0.);
+        let mut add = |destination: ModDestination, amount: f32| match destination {
+            ModDestination::Cutoff => cutoff += MOD_CUTOFF_OCTAVES * amount,
+            ModDestination::Resonance => res += MOD_RES_OCTAVES * amount,
+            ModDestination::Drive => drive += MOD_DRIVE_DB * amount,
+        };
+        for slot in 0..MOD_SLOTS {
+            let (source, destination, depth) = params.mod_slot(slot);
+            let value = match source.value() {
+                ModSource::Off => continue,
+                ModSource::Envelope => self.envelope,
+                ModSource::Sequencer => self.sequencer,
+                ModSource::PitchBend => self.pitch_bend,
+                ModSource::Aftertouch => self.aftertouch,
+                // full depth follows the keyboard
+                ModSource::KeyTrack => self.key_track / MOD_CUTOFF_OCTAVES,
+            };
+            add(destination.value(), depth.value() * value);
+        }
+        // the macro and the mod wheel on top of the matrix, at the same full depth
+        let modulation = &params.modulation;
+        if self.macro_amount != 0. {
+            add(
+                ModDestination::Cutoff,
+                modulation.macro_cutoff.value() * self.macro_amount,
+            );
+            add(
+                ModDestination::Resonance,
+                modulation.macro_res.value() * self.macro_amount,
+            );
+            add(
+                ModDestination::Drive,
+                modulation.macro_drive.value() * self.macro_amount,
+            );
+        }
+        if self.mod_wheel != 0. {
+            add(
+                modulation.mod_wheel_target.value(),
+                modulation.mod_wheel_depth.value() * self.mod_wheel,
+            );
+        }
+        (cutoff, res, drive)
+    }
+}
+
+/// how far the drift wanders at full analog, as a standard deviation in octaves. About 5 cents
+const DRIFT_OCTAVES: f32 = 5. / 1200.;
+/// how fast it wanders. The walk gets pulled back towards the middle with this corner, so it doesn't run off
+const DRIFT_HZ: f32 = 0.2;
+/// takes the corners off the steps the walk takes every `SUB_BLOCK_LEN` samples
+const DRIFT_SMOOTHING_HZ: f32 = 2.;
+
+/// Slow random walk of the cutoff, in octaves. Starts over from the seed on reset,
+/// so a project renders the same every time
+pub struct Drift {
+    seed: u64,
+    rng: u64,
+    walk: f32,
+    value: f32,
+    // samples since the walk last took a step
+    pending: usize,
+}
+
+impl Default for Drift {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            rng: 0,
+            walk: 0.,
+            value: 0.,
+            pending: 0,
+        }
+    }
+}
+
+impl Drift {
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.reset();
+    }
+    pub fn reset(&mut self) {
+        self.rng = self.seed;
+        self.walk = 0.;
+        self.value = 0.;
+        self.pending = 0;
+    }
+    /// Moves `n` samples on, and returns where that ends up. The walk takes a step every `SUB_BLOCK_LEN` samples
+    /// however they're handed in, so the drift doesn't depend on the buffer size
+    pub fn next_step(&mut self, n: usize, sample_rate: f32) -> f32 {
+        self.pending += n;
+        let dt = SUB_BLOCK_LEN as f32 / sample_rate;
+        let pull = 2. * std::f32::consts::PI * DRIFT_HZ * dt;
+        let smoothing = 1. - (-2. * std::f32::consts::PI * DRIFT_SMOOTHING_HZ * dt).exp();
+        while self.pending >= SUB_BLOCK_LEN {
+            self.pending -= SUB_BLOCK_LEN;
+            // uniform noise has a standard deviation of 1 / sqrt(3)
+            let kick = (3. * 2. * pull).sqrt() * random_bipolar(&mut self.rng);
+            self.walk += kick - pull * self.walk;
+            self.value += smoothing * (self.walk - self.value);
+        }
+        DRIFT_OCTAVES * self.value
+    }
+}
+
+/// Finds which step `beats` falls into, and how far into the step it is (0-1).
+/// Steps are grouped in pairs, where the second step of each pair starts later the more swing there is.
+pub fn step_at(beats: f64, step_len: f64, swing: f64) -> (usize, f64) {
+    let pair_len = 2. * step_len;
+    // tiny offset so positions that should land exactly on a step boundary don't end up a rounding error before it
+    let beats = beats.max(0.) + 1e-9;
+    let pair = (beats / pair_len).floor();
+    let in_pair = beats - pair * pair_len;
+    let split = step_len * (1. + swing);
+
+    let (step, phase) = if in_pair < split {
+        (2 * pair as usize, in_pair / split)
+    } else {
+        (
+            2 * pair as usize + 1,
+            (in_pair - split) / (pair_len - split),
+        )
+    };
+    (step % N_STEPS, phase)
+}
+
+#[cfg(test)]
+fn sequencer_with_ramp() -> StepSequencer {
+    let mut seq = StepSequencer::default();
+    for i in 0..N_STEPS {
+        seq.steps[i] = i as f32 / N_STEPS as f32;
+    }
+    seq
+}
+
+#[cfg(test)]
+fn find_step_changes(seq: &mut StepSequencer, n: usize, changes: &mut Vec<usize>, offset: usize) {
+    let mut last = seq.next(SeqDivision::Sixteenth, 0., 0.);
+    for i in 1..n {
+        let value = seq.next(SeqDivision::Sixteenth, 0., 0.);
+        if value != last {
+            changes.push(offset + i);
+        }
+        last = value;
+    }
+}
+
+#[test]
+fn test_step_boundaries() {
+    for (tempo, fs) in [(120., 48000.), (90., 48000.), (174., 44100.), (60., 96000.)] {
+        let mut seq = sequencer_with_ramp();
+        seq.start_block(Some(0.), tempo, fs);
+        let samples_per_step = 0.25 * 60. / tempo * fs as f64;
+        let mut changes = vec![];
+        find_step_changes(&mut seq, (samples_per_step * 8.) as usize, &mut changes, 0);
+        assert_eq!(changes.len(), 7);
+        for (i, change) in changes.iter().enumerate() {
+            let expected = ((i + 1) as f64 * samples_per_step).ceil() as usize;
+            assert!(
+                (*change as i64 - expected as i64).abs() <= 1,
+                "tempo {tempo}: step {} at {change}, expected {expected}",
+                i + 1
+            );
+        }
+    }
+}
+
+#[test]
+fn test_tempo_change_mid_block() {
+    let fs = 48000.;
+    let mut seq = sequencer_with_ramp();
+    let mut changes = vec![];
+    // first block at 120 bpm, 6000 samples per 16th
+    let first_len = 10000;
+    seq.start_block(Some(0.), 120., fs);
+    find_step_changes(&mut seq, first_len, &mut changes, 0);
+    // the host switches to 90 bpm (8000 samples per 16th) from where the first block ended
+    let pos = first_len as f64 * 120. / 60. / fs as f64;
+    seq.start_block(Some(pos), 90., fs);
+    find_step_changes(&mut seq, 30000, &mut changes, first_len);
+
+    // step 2 starts at 0.5 beats, which at 90 bpm is 0.5 - pos beats after the tempo change
+    let beats_per_sample = 90. / 60. / fs as f64;
+    let expected = first_len + ((0.5 - pos) / beats_per_sample).round() as usize;
+    assert_eq!(changes[0], 6000);
+    assert!((changes[1] as i64 - expected as i64).abs() <= 1);
+    assert!((changes[2] as i64 - (expected + 8000) as i64).abs() <= 1);
+}
+
+#[test]
+fn test_swing_and_glide() {
+    let seq = sequencer_with_ramp();
+    // at 50% swing the second 16th starts at 0.375 beats instead of 0.25
+    assert_eq!(
+        seq.value_at(0.3, SeqDivision::Sixteenth, 0.5, 0.),
+        seq.steps[0]
+    );
+    assert_eq!(
+        seq.value_at(0.4, SeqDivision::Sixteenth, 0.5, 0.),
+        seq.steps[1]
+    );
+    // halfway through a full-length glide into step 2
+    let mid = seq.value_at(0.625, SeqDivision::Sixteenth, 0., 1.);
+    assert!((mid - 0.5 * (seq.steps[1] + seq.steps[2])).abs() < 1e-5);
+}
+
+#[test]
+fn test_envelope_stages() {
+    let fs = 48000.;
+    let mut env = Envelope::default();
+    env.set_params(fs, 10., 100., 0.5, 200.);
+    env.note_on(60, false);
+    // the attack takes exactly its time
+    let attack_len = 480;
+    for _ in 0..attack_len - 1 {
+        assert!(env.next() < 1.);
+    }
+    assert_eq!(env.next(), 1.);
+    // within 1 % of the way to the sustain level after the decay time
+    let decayed = env.next_step(4800);
+    assert!((decayed - 0.505).abs() < 1e-3, "{}", decayed);
+    let sustained = env.next_step(48000);
+    assert!((sustained - 0.5).abs() < 1e-4);
+
+    env.note_off(60);
+    let released = env.next_step(9600);
+    assert!((released - 0.005).abs() < 1e-4, "{}", released);
+    env.next_step(48000);
+    assert_eq!(env.stage, EnvStage::Idle);
+    assert_eq!(env.next(), 0.);
+}
+
+#[test]
+fn test_envelope_legato() {
+    let fs = 48000.;
+    for legato in [false, true] {
+        let mut env = Envelope::default();
+        env.set_params(fs, 10., 100., 0.2, 200.);
+        env.note_on(60, legato);
+        let sustained = env.next_step(48000);
+        // overlapping note, then the first one is let go
+        env.note_on(64, legato);
+        env.note_off(60);
+        let after = env.next_step(240);
+        if legato {
+            assert!((after - sustained).abs() < 1e-6);
+        } else {
+            // half the attack time on from the sustain level
+            assert!((after - 0.7).abs() < 1e-3, "{}", after);
+        }
+        // still held, so no release yet
+        assert!(env.next_step(48000) >= 0.2 - 1e-4);
+        env.note_off(64);
+        assert!(env.next_step(48000) < 0.2);
+    }
+}
+
+#[test]
+fn test_drift() {
+    let fs = 48000.;
+    let run = |seed| {
+        let mut drift = Drift::default();
+        drift.set_seed(seed);
+        // a minute, a sub-block at a time
+        (0..fs as usize * 60 / 32)
+            .map(|_| drift.next_step(32, fs))
+            .collect::<Vec<_>>()
+    };
+    let drift = run(1);
+    assert_eq!(drift, run(1));
+    assert!(drift != run(2));
+    // a few cents, and slow
+    let rms = (drift.iter().map(|x| x * x).sum::<f32>() / drift.len() as f32).sqrt();
+    assert!(
+        rms > 0.3 * DRIFT_OCTAVES && rms < 3. * DRIFT_OCTAVES,
+        "{}",
+        rms * 1200.
+    );
+    assert!(drift.iter().all(|x| x.abs() < 6. * DRIFT_OCTAVES));
+    for pair in drift.windows(2) {
+        assert!((pair[1] - pair[0]).abs() < 0.01 * DRIFT_OCTAVES);
+    }
+
+    // the same walk whatever the sub-blocks are, as long as they add up to the same
+    let mut chunked = Drift::default();
+    chunked.set_seed(1);
+    let mut chunks = [7, 64, 1, 24, 13, 19].iter().cycle();
+    let mut samples = 0;
+    while samples + 64 <= drift.len() * 32 {
+        let n = *chunks.next().unwrap();
+        let last = chunked.next_step(n, fs);
+        samples += n;
+        if samples % 32 == 0 {
+            assert_eq!(last, drift[samples / 32 - 1], "after {} samples", samples);
+        }
+    }
+    assert!(last != 0.);
+}
+
+#[test]
+fn test_mod_matrix() {
+    use nih_plug::prelude::{EnumParam, FloatParam, FloatRange};
+    let mut params = FilterParams::new();
+    let sources = ModSources {
+        envelope: 1.,
+        sequencer: -0.5,
+        pitch_bend: 0.25,
+        aftertouch: 1.,
+        key_track: 1.,
+        macro_amount: 0.,
+        mod_wheel: 0.,
+    };
+    // every slot starts out off, whatever the sources are doing
+    assert_eq!(sources.route(&params), (0., 0., 0.));
+
+    let depth = |value| FloatParam::new("", value, FloatRange::Linear { min: -1., max: 1. });
+    params.modulation.mod1_source = EnumParam::new("", ModSource::KeyTrack);
+    params.modulation.mod1_depth = depth(1.);
+    params.modulation.mod2_source = EnumParam::new("", ModSource::Envelope);
+    params.modulation.mod2_destination = EnumParam::new("", ModDestination::Drive);
+    params.modulation.mod2_depth = depth(0.5);
+    params.modulation.mod3_source = EnumParam::new("", ModSource::Sequencer);
+    params.modulation.mod3_destination = EnumParam::new("", ModDestination::Resonance);
+    params.modulation.mod3_depth = depth(-1.);
+    // a slot that's off doesn't count, whatever its depth
+    params.modulation.mod4_depth = depth(1.);
+    let (cutoff, res, drive) = sources.route(&params);
+    // an octave up the keyboard is an octave up the cutoff
+    assert!((cutoff - 1.).abs() < 1e-6, "{}", cutoff);
+    assert!((res - 0.5 * MOD_RES_OCTAVES).abs() < 1e-6, "{}", res);
+    assert!((drive - 0.5 * MOD_DRIVE_DB).abs() < 1e-6, "{}", drive);
+}
+
+#[test]
+fn test_macro() {
+    use nih_plug::prelude::{FloatParam, FloatRange};
+    let mut params = FilterParams::new();
+    let sources = ModSources {
+        macro_amount: 0.5,
+        ..Default::default()
+    };
+    assert!(!params.macro_routed());
+    assert_eq!(sources.route(&params), (0., 0., 0.));
+
+    let depth = |value| FloatParam::new("", value, FloatRange::Linear { min: -1., max: 1. });
+    params.modulation.macro_cutoff = depth(1.);
+    params.modulation.macro_res = depth(-0.5);
+    params.modulation.macro_drive = depth(0.25);
+    assert!(params.macro_routed());
+    let (cutoff, res, drive) = sources.route(&params);
+    assert!(
+        (cutoff - 0.5 * MOD_CUTOFF_OCTAVES).abs() < 1e-6,
+        "{}",
+        cutoff
+    );
+    assert!((res + 0.25 * MOD_RES_OCTAVES).abs() < 1e-6, "{}", res);
+    assert!((drive - 0.125 * MOD_DRIVE_DB).abs() < 1e-6, "{}", drive);
+
+    // on top of whatever the matrix does to the same destination
+    params.modulation.mod1_source = nih_plug::prelude::EnumParam::new("", ModSource::KeyTrack);
+    params.modulation.mod1_depth = depth(1.);
+    let sources = ModSources {
+        key_track: 1.,
+        macro_amount: 0.5,
+        ..Default::default()
+    };
+    let (stacked, _, _) = sources.route(&params);
+    assert!((stacked - cutoff - 1.).abs() < 1e-6, "{}", stacked);
+}
+
+#[test]
+fn test_mod_wheel() {
+    use nih_plug::prelude::{EnumParam, FloatParam, FloatRange};
+    let mut params = FilterParams::new();
+    let sources = ModSources {
+        mod_wheel: 0.5,
+        ..Default::default()
+    };
+    // no depth, nothing moves
+    assert_eq!(sources.route(&params), (0., 0., 0.));
+
+    params.modulation.mod_wheel_depth =
+        FloatParam::new("", -1., FloatRange::Linear { min: -1., max: 1. });
+    let (cutoff, res, drive) = sources.route(&params);
+    assert!(
+        (cutoff + 0.5 * MOD_CUTOFF_OCTAVES).abs() < 1e-6,
+        "{}",
+        cutoff
+    );
+    assert_eq!((res, drive), (0., 0.));
+
+    // only ever the one destination
+    params.modulation.mod_wheel_target = EnumParam::new("", ModDestination::Drive);
+    let (cutoff, res, drive) = sources.route(&params);
+    assert_eq!((cutoff, res), (0., 0.));
+    assert!((drive + 0.5 * MOD_DRIVE_DB).abs() < 1e-6, "{}", drive);
+}
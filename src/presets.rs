@@ -0,0 +1,1120 @@
+// named snapshots of the parameters, saved as small text files or compiled in as the factory bank. They can also be
+// exported as json with plain values, for reading and editing by hand
+use crate::filter_params::{
+    cutoff_range, drive_range, legacy_res_to_q, q_range, Circuits, FilterParams, Saturation,
+    SvfMode, Q_BUTTERWORTH,
+};
+use crate::modulation::N_STEPS;
+use crate::utils::random_bipolar;
+use nih_plug::param::internals::ParamPtr;
+use nih_plug::prelude::{BoolParam, Enum, EnumParam, FloatParam, Param};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+pub const EXTENSION: &str = "vapreset";
+/// Written into every preset. Presets without it are from before the comb, when the normalized circuit was spread
+/// over three circuits
+const VERSION: u32 = 3;
+/// The last version that stored normalized values. Later ones store plain values, which keep their meaning when a
+/// range or an enum's variants change
+const LAST_NORMALIZED_VERSION: u32 = 2;
+/// How many circuits there were before the comb
+const LEGACY_CIRCUITS: usize = 3;
+/// Written into every json preset. Json presets from newer versions are refused rather than half loaded
+const JSON_VERSION: u32 = 1;
+/// What the last scan of the preset directory found, kept in the directory so the next one only reads changed files
+const INDEX_FILE: &str = ".index.json";
+/// Written into the index. An index from another version is thrown away and the directory scanned from scratch
+const INDEX_VERSION: u64 = 1;
+/// Units that go into the json keys of the parameters that have one, so the plain values read on their own
+const JSON_UNITS: [(&str, &str); 13] = [
+    ("cutoff", "hz"),
+    ("spread", "oct"),
+    ("smoothing", "ms"),
+    ("fm_amount", "oct"),
+    ("seq_depth", "oct"),
+    ("bend_range", "st"),
+    ("aftertouch", "oct"),
+    ("glide", "ms"),
+    ("env_attack", "ms"),
+    ("env_decay", "ms"),
+    ("env_release", "ms"),
+    ("env_amount", "oct"),
+    ("silence_gate", "ms"),
+];
+
+/// Every parameter that's part of a preset, as (id, pointer, normalized value, the parameter for json).
+/// Bypass and the control rate are left out, loading a preset shouldn't switch them. The saturation is kept by its id
+/// in `Preset::saturation` instead
+macro_rules! preset_params {
+    ($params:expr, $($id:literal => $($field:ident).+,)*) => {
+        vec![$((
+            $id,
+            $params.$($field).+.as_ptr(),
+            $params.$($field).+.normalized_value(),
+            &$params.$($field).+ as &dyn PlainParam,
+        ),)*]
+    };
+}
+
+fn preset_params(params: &FilterParams) -> Vec<(&'static str, ParamPtr, f32, &dyn PlainParam)> {
+    preset_params!(params,
+        "cutoff" => filter.cutoff,
+        "q" => filter.res,
+        "drive" => drive.amount,
+        "spread" => filter.spread,
+        "smoothing" => filter.smoothing,
+        "silence_gate" => output.silence_gate,
+        "antisat" => filter.antisat,
+        "res_character" => filter.res_character,
+        "mode" => filter.mode,
+        "svf_slope" => filter.svf_slope,
+        "bp_norm" => filter.bp_norm,
+        "slope" => filter.slope,
+        "ladder_response" => filter.ladder_response,
+        "fat" => filter.fat,
+        "comb_negative" => filter.comb_negative,
+        "circuit" => filter.filter_type,
+        "character" => drive.character,
+        "feedback_drive" => drive.feedback,
+        "res_comp" => output.res_comp,
+        "analog" => filter.analog,
+        "dc_block" => output.dc_block,
+        "limiter" => output.limiter,
+        "gain" => output.gain,
+        "seq_depth" => modulation.seq_depth,
+        "seq_division" => modulation.seq_division,
+        "seq_swing" => modulation.seq_swing,
+        "seq_glide" => modulation.seq_glide,
+        "bend_range" => modulation.bend_range,
+        "aftertouch" => modulation.aftertouch,
+        "glide" => modulation.glide,
+        "env_attack" => modulation.env_attack,
+        "env_decay" => modulation.env_decay,
+        "env_sustain" => modulation.env_sustain,
+        "env_release" => modulation.env_release,
+        "env_amount" => modulation.env_amount,
+        "env_trigger" => modulation.env_trigger,
+        "retrigger" => modulation.retrigger,
+        "macro" => modulation.macro_amount,
+        "macro_cutoff" => modulation.macro_cutoff,
+        "macro_res" => modulation.macro_res,
+        "macro_drive" => modulation.macro_drive,
+        "mod_wheel_target" => modulation.mod_wheel_target,
+        "mod_wheel_depth" => modulation.mod_wheel_depth,
+        "fm" => filter.fm,
+        "fm_amount" => filter.fm_amount,
+        "mod1_source" => modulation.mod1_source,
+        "mod1_destination" => modulation.mod1_destination,
+        "mod1_depth" => modulation.mod1_depth,
+        "mod2_source" => modulation.mod2_source,
+        "mod2_destination" => modulation.mod2_destination,
+        "mod2_depth" => modulation.mod2_depth,
+        "mod3_source" => modulation.mod3_source,
+        "mod3_destination" => modulation.mod3_destination,
+        "mod3_depth" => modulation.mod3_depth,
+        "mod4_source" => modulation.mod4_source,
+        "mod4_destination" => modulation.mod4_destination,
+        "mod4_depth" => modulation.mod4_depth,
+    )
+}
+
+/// The parameter a preset line refers to
+pub fn param_ptr(params: &FilterParams, id: &str) -> Option<ParamPtr> {
+    preset_params(params)
+        .into_iter()
+        .find(|(param_id, _, _, _)| *param_id == id)
+        .map(|(_, ptr, _, _)| ptr)
+}
+
+/// The key of a parameter in json presets
+fn json_key(id: &str) -> String {
+    match JSON_UNITS.iter().find(|(param_id, _)| *param_id == id) {
+        Some((_, unit)) => format!("{}_{}", id, unit),
+        None => id.to_string(),
+    }
+}
+
+/// Rounds to five significant digits. Json presets are read by people, and a value rounded this far comes back the
+/// same after being normalized and turned into a plain value again
+fn round_plain(value: f32) -> f64 {
+    if value == 0. || !value.is_finite() {
+        return value as f64;
+    }
+    let decimals = (4 - value.abs().log10().floor() as i32).max(0) as usize;
+    format!("{:.*}", decimals, value)
+        .parse()
+        .unwrap_or(value as f64)
+}
+
+/// A parameter's plain value in a json or text preset
+trait PlainParam {
+    fn to_json(&self, normalized: f32) -> Value;
+    /// The normalized value for a json value, or `None` if it's of the wrong kind or out of the parameter's range
+    fn parse_json(&self, value: &Value) -> Option<f32>;
+    /// The value in a text preset, which is read back with `parse_json()`
+    fn to_text(&self, normalized: f32) -> String {
+        self.to_json(normalized).to_string()
+    }
+}
+
+impl PlainParam for FloatParam {
+    fn to_json(&self, normalized: f32) -> Value {
+        round_plain(self.preview_plain(normalized)).into()
+    }
+    /// Not rounded like the json, the A/B slots go through text presets and shouldn't move the knobs
+    fn to_text(&self, normalized: f32) -> String {
+        self.preview_plain(normalized).to_string()
+    }
+    fn parse_json(&self, value: &Value) -> Option<f32> {
+        let plain = value.as_f64()? as f32;
+        let range = self.preview_plain(0.)..=self.preview_plain(1.);
+        if range.contains(&plain) {
+            Some(self.preview_normalized(plain))
+        } else {
+            None
+        }
+    }
+}
+
+impl PlainParam for BoolParam {
+    fn to_json(&self, normalized: f32) -> Value {
+        self.preview_plain(normalized).into()
+    }
+    fn parse_json(&self, value: &Value) -> Option<f32> {
+        value.as_bool().map(|plain| self.preview_normalized(plain))
+    }
+}
+
+/// Enums are written as the name of their variant
+impl<T: Enum + PartialEq> PlainParam for EnumParam<T> {
+    fn to_json(&self, normalized: f32) -> Value {
+        T::variants()[self.preview_plain(normalized).to_index()].into()
+    }
+    fn parse_json(&self, value: &Value) -> Option<f32> {
+        let name = value.as_str()?;
+        let index = T::variants().iter().position(|variant| *variant == name)?;
+        Some(self.preview_normalized(T::from_index(index)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    /// normalized values by parameter id
+    pub values: Vec<(String, f32)>,
+    /// the `Saturation::id()`, so a preset loads the same one in builds with a different registry
+    pub saturation: String,
+    pub seq_steps: [f32; N_STEPS],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PresetError {
+    Io(String),
+    /// line number and the line
+    Parse(usize, String),
+    /// a json preset that isn't json, or isn't laid out like a preset
+    Json(String),
+    /// a json preset from a newer version
+    Version(u64),
+    /// the json key of a value of the wrong kind or out of range
+    Value(String),
+}
+
+impl fmt::Display for PresetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresetError::Io(e) => write!(f, "couldn't read preset: {}", e),
+            PresetError::Parse(line, text) => {
+                write!(f, "couldn't parse line {} of preset: \"{}\"", line, text)
+            }
+            PresetError::Json(e) => write!(f, "couldn't read json preset: {}", e),
+            PresetError::Version(version) => write!(
+                f,
+                "json preset is version {}, this version of va-filter reads up to {}",
+                version, JSON_VERSION
+            ),
+            PresetError::Value(key) => write!(f, "json preset has an invalid value for {}", key),
+        }
+    }
+}
+
+impl Preset {
+    /// Snapshot of the current parameter values
+    pub fn capture(name: &str, params: &FilterParams) -> Self {
+        Self {
+            name: name.to_string(),
+            values: preset_params(params)
+                .into_iter()
+                .map(|(id, _, value, _)| (id.to_string(), value))
+                .collect(),
+            saturation: params.saturation().id().to_string(),
+            seq_steps: params
+                .seq_steps
+                .read()
+                .map(|steps| *steps)
+                .unwrap_or([0.; N_STEPS]),
+        }
+    }
+
+    /// The snapshot of a freshly loaded plugin, which presets start from before their own values are applied
+    pub fn init() -> Self {
+        Self::capture("Init", &FilterParams::new())
+    }
+
+    /// The preset with plain values by id, enums by the name of their variant
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# va-filter preset\n");
+        text.push_str(&format!("version = {}\n", VERSION));
+        let params = FilterParams::new();
+        for (id, _, _, param) in preset_params(&params) {
+            if let Some(normalized) = self.value(id) {
+                text.push_str(&format!("{} = {}\n", id, param.to_text(normalized)));
+            }
+        }
+        text.push_str(&format!("saturation = {}\n", self.saturation));
+        let steps: Vec<String> = self.seq_steps.iter().map(|step| step.to_string()).collect();
+        text.push_str(&format!("seq_steps = {}\n", steps.join(" ")));
+        text
+    }
+
+    /// Parses a preset on top of `Preset::init()`, so presets from older versions get defaults for newer parameters.
+    /// Lines for parameters this version doesn't know are skipped
+    pub fn parse(name: &str, text: &str) -> Result<Self, PresetError> {
+        let params = FilterParams::new();
+        let mut preset = Self::capture(name, &params);
+        // the resonance of presets from before it was a q, which depends on the circuit
+        let mut legacy_res = None;
+        // up front, since it decides how the values before it are read
+        let mut version = 1;
+        for (i, line) in text.lines().enumerate() {
+            if let Some(("version", value)) =
+                line.split_once('=').map(|(id, v)| (id.trim(), v.trim()))
+            {
+                version = value
+                    .parse()
+                    .map_err(|_| PresetError::Parse(i + 1, line.trim().to_string()))?;
+            }
+        }
+        let param_list = preset_params(&params);
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = || PresetError::Parse(i + 1, line.to_string());
+            let (id, value) = line.split_once('=').ok_or_else(error)?;
+            let (id, value) = (id.trim(), value.trim());
+            if id == "seq_steps" {
+                let steps: Vec<f32> = value
+                    .split_whitespace()
+                    .map(|step| step.parse::<f32>().map(|step| step.clamp(-1., 1.)))
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| error())?;
+                if steps.len() != N_STEPS {
+                    return Err(error());
+                }
+                preset.seq_steps.copy_from_slice(&steps);
+            } else if id == "version" {
+                continue;
+            } else if id == "saturation" {
+                preset.saturation = value.to_string();
+            } else if id == "res" {
+                let value: f32 = value.parse().map_err(|_| error())?;
+                if !(0. ..=1.).contains(&value) {
+                    return Err(error());
+                }
+                legacy_res = Some(value);
+            } else if let Some(entry) = preset.values.iter_mut().find(|(known, _)| known == id) {
+                let value = if version <= LAST_NORMALIZED_VERSION {
+                    let value: f32 = value.parse().map_err(|_| error())?;
+                    Some(value).filter(|value| (0. ..=1.).contains(value))
+                } else {
+                    let (_, _, _, param) = param_list
+                        .iter()
+                        .find(|(param_id, _, _, _)| *param_id == id)
+                        .expect("preset values come from the same list");
+                    serde_json::from_str(value)
+                        .ok()
+                        .and_then(|value| param.parse_json(&value))
+                };
+                entry.1 = value.ok_or_else(error)?;
+            }
+        }
+        if version < 2 {
+            if let Some(entry) = preset.values.iter_mut().find(|(id, _)| id == "circuit") {
+                let circuit = (entry.1 * (LEGACY_CIRCUITS - 1) as f32).round();
+                entry.1 = circuit / (Circuits::variants().len() - 1) as f32;
+            }
+        }
+        if let Some(res) = legacy_res {
+            let circuit = preset.value("circuit").unwrap_or(0.);
+            let circuit = Circuits::from_index(
+                (circuit * (Circuits::variants().len() - 1) as f32).round() as usize,
+            );
+            let q = q_range().normalize(legacy_res_to_q(res, circuit));
+            if let Some(entry) = preset.values.iter_mut().find(|(id, _)| id == "q") {
+                entry.1 = q;
+            }
+        }
+        Ok(preset)
+    }
+
+    /// The preset as a versioned json document with plain values, enums by the name of their variant.
+    /// `params` is only asked for the parameters' ranges
+    pub fn to_json(&self, params: &FilterParams) -> String {
+        let mut values = Map::new();
+        for (id, _, _, param) in preset_params(params) {
+            if let Some(normalized) = self.value(id) {
+                values.insert(json_key(id), param.to_json(normalized));
+            }
+        }
+        values.insert("saturation".to_string(), self.saturation.as_str().into());
+        let steps: Vec<Value> = self
+            .seq_steps
+            .iter()
+            .map(|step| round_plain(*step).into())
+            .collect();
+        let mut document = Map::new();
+        document.insert("version".to_string(), JSON_VERSION.into());
+        document.insert("name".to_string(), self.name.as_str().into());
+        document.insert("params".to_string(), values.into());
+        document.insert("seq_steps".to_string(), steps.into());
+        serde_json::to_string_pretty(&Value::Object(document))
+            .expect("json values always serialize")
+    }
+
+    /// The normalized value of the parameter with this id
+    pub fn value(&self, id: &str) -> Option<f32> {
+        self.values
+            .iter()
+            .find(|(known, _)| known == id)
+            .map(|(_, value)| *value)
+    }
+
+    /// The saturation the preset's id stands for, falling back to tanh like `FilterParams::restore_saturation()`
+    /// when it isn't in this build
+    pub fn saturation(&self) -> Saturation {
+        Saturation::from_id(&self.saturation).unwrap_or(Saturation::Tanh)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, PresetError> {
+        let text = std::fs::read_to_string(path).map_err(|e| PresetError::Io(e.to_string()))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Self::parse(&name, &text)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_text())
+    }
+}
+
+impl FilterParams {
+    /// The current values as a json preset, see `Preset::to_json()`
+    pub fn to_json(&self, name: &str) -> String {
+        Preset::capture(name, self).to_json(self)
+    }
+
+    /// Reads a json preset on top of `Preset::init()`, like `Preset::parse()`: missing parameters keep their default
+    /// and ones this version doesn't know are skipped. Nothing is set here, the editor applies the preset through the
+    /// host like any other
+    pub fn from_json(&self, json: &str) -> Result<Preset, PresetError> {
+        let document: Value =
+            serde_json::from_str(json).map_err(|e| PresetError::Json(e.to_string()))?;
+        let version = document
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| PresetError::Json("no version".to_string()))?;
+        if version == 0 || version > JSON_VERSION as u64 {
+            return Err(PresetError::Version(version));
+        }
+        let mut preset = Preset::init();
+        preset.name = document
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        if let Some(values) = document.get("params") {
+            let values = values
+                .as_object()
+                .ok_or_else(|| PresetError::Json("params isn't an object".to_string()))?;
+            for (id, _, _, param) in preset_params(self) {
+                let key = json_key(id);
+                if let Some(value) = values.get(&key) {
+                    let normalized = param.parse_json(value).ok_or(PresetError::Value(key))?;
+                    if let Some(entry) = preset.values.iter_mut().find(|(known, _)| known == id) {
+                        entry.1 = normalized;
+                    }
+                }
+            }
+            if let Some(id) = values.get("saturation") {
+                let id = id
+                    .as_str()
+                    .ok_or_else(|| PresetError::Value("saturation".to_string()))?;
+                // the first json presets had the name of the variant
+                let variant = Saturation::variants().iter().position(|name| *name == id);
+                preset.saturation = match variant {
+                    Some(index) => Saturation::from_index(index).id().to_string(),
+                    None => id.to_string(),
+                };
+            }
+        }
+        if let Some(steps) = document.get("seq_steps") {
+            let error = || PresetError::Value("seq_steps".to_string());
+            let steps = steps.as_array().ok_or_else(error)?;
+            if steps.len() != N_STEPS {
+                return Err(error());
+            }
+            for (step, value) in preset.seq_steps.iter_mut().zip(steps) {
+                let value = value.as_f64().ok_or_else(error)?;
+                if !(-1. ..=1.).contains(&value) {
+                    return Err(error());
+                }
+                *step = value as f32;
+            }
+        }
+        Ok(preset)
+    }
+}
+
+/// The factory presets, by name. Their files are in `presets/`, where `normalize_presets` sets their output gain
+const FACTORY_PRESETS: [(&str, &str); 3] = [
+    (
+        "Acid Squelch",
+        include_str!("../presets/Acid Squelch.vapreset"),
+    ),
+    ("Dub Siren", include_str!("../presets/Dub Siren.vapreset")),
+    ("Gentle LP", include_str!("../presets/Gentle LP.vapreset")),
+];
+
+/// How many presets `factory_presets()` has, the user's come after them
+pub fn factory_count() -> usize {
+    FACTORY_PRESETS.len() + 1
+}
+
+/// The compiled-in presets, starting with the init patch
+pub fn factory_presets() -> Vec<Preset> {
+    let mut presets = vec![Preset::init()];
+    for (name, text) in FACTORY_PRESETS {
+        presets.push(Preset::parse(name, text).expect("factory presets should parse"));
+    }
+    presets
+}
+
+/// Where the user presets go, following each OS's convention for application data
+pub fn presets_dir() -> Option<PathBuf> {
+    let base = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+    };
+    base.map(|base| base.join("va-filter").join("presets"))
+}
+
+/// What the scan of the preset directory found in one file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scanned {
+    Preset(Preset),
+    /// the file name and why it didn't load, which the editor lists instead of leaving the preset out silently
+    Invalid(String, String),
+}
+
+/// A file of the preset directory as the index remembers it. It's only read again once its size or modification
+/// time changes
+struct IndexEntry {
+    file: String,
+    modified: (u64, u32),
+    len: u64,
+    scanned: Scanned,
+}
+
+impl IndexEntry {
+    fn to_json(&self) -> Value {
+        let mut entry = Map::new();
+        entry.insert("file".to_string(), self.file.as_str().into());
+        entry.insert("modified_secs".to_string(), self.modified.0.into());
+        entry.insert("modified_nanos".to_string(), self.modified.1.into());
+        entry.insert("len".to_string(), self.len.into());
+        match &self.scanned {
+            Scanned::Preset(preset) => {
+                entry.insert("name".to_string(), preset.name.as_str().into());
+                entry.insert("text".to_string(), preset.to_text().into());
+            }
+            Scanned::Invalid(_, error) => {
+                entry.insert("error".to_string(), error.as_str().into());
+            }
+        }
+        entry.into()
+    }
+    fn from_json(entry: &Value) -> Option<Self> {
+        let file = entry.get("file")?.as_str()?.to_string();
+        let scanned = match entry.get("error") {
+            Some(error) => Scanned::Invalid(file.clone(), error.as_str()?.to_string()),
+            None => {
+                let name = entry.get("name")?.as_str()?;
+                Scanned::Preset(Preset::parse(name, entry.get("text")?.as_str()?).ok()?)
+            }
+        };
+        Some(Self {
+            modified: (
+                entry.get("modified_secs")?.as_u64()?,
+                entry.get("modified_nanos")?.as_u64()? as u32,
+            ),
+            len: entry.get("len")?.as_u64()?,
+            file,
+            scanned,
+        })
+    }
+}
+
+/// The entries of the directory's index, or nothing if there isn't one or it can't be read
+fn read_index(dir: &Path) -> Vec<IndexEntry> {
+    let document: Value = match std::fs::read_to_string(dir.join(INDEX_FILE))
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+    {
+        Some(document) => document,
+        None => return Vec::new(),
+    };
+    if document.get("version").and_then(Value::as_u64) != Some(INDEX_VERSION) {
+        return Vec::new();
+    }
+    document
+        .get("files")
+        .and_then(Value::as_array)
+        .map(|files| files.iter().filter_map(IndexEntry::from_json).collect())
+        .unwrap_or_default()
+}
+
+fn write_index(dir: &Path, entries: &[IndexEntry]) -> std::io::Result<()> {
+    let mut document = Map::new();
+    document.insert("version".to_string(), INDEX_VERSION.into());
+    let files: Vec<Value> = entries.iter().map(IndexEntry::to_json).collect();
+    document.insert("files".to_string(), files.into());
+    std::fs::write(
+        dir.join(INDEX_FILE),
+        serde_json::to_string(&Value::Object(document)).expect("json values always serialize"),
+    )
+}
+
+/// Reads the presets in `dir` in the order of their file names, handing each one to `found` as soon as it's there.
+/// Files the index has at the same size and modification time aren't read again, and files that don't load are
+/// handed over as `Scanned::Invalid` without stopping the scan. It stops early once `cancel` is set or `found`
+/// returns false, and returns whether it got through the whole directory. Only a finished scan writes the index
+pub fn scan_presets(
+    dir: &Path,
+    cancel: &AtomicBool,
+    mut found: impl FnMut(Scanned) -> bool,
+) -> bool {
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == EXTENSION))
+            .collect(),
+        Err(_) => return true,
+    };
+    paths.sort();
+    let mut index = read_index(dir);
+    let mut entries = Vec::with_capacity(paths.len());
+    for path in paths {
+        if cancel.load(Ordering::Relaxed) {
+            return false;
+        }
+        let file = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let metadata = match std::fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or((0, 0), |since| (since.as_secs(), since.subsec_nanos()));
+        let cached = index.iter().position(|entry| {
+            entry.file == file && entry.modified == modified && entry.len == metadata.len()
+        });
+        let entry = match cached {
+            Some(i) => index.swap_remove(i),
+            None => IndexEntry {
+                scanned: match Preset::load(&path) {
+                    Ok(preset) => Scanned::Preset(preset),
+                    Err(e) => Scanned::Invalid(file.clone(), e.to_string()),
+                },
+                file,
+                modified,
+                len: metadata.len(),
+            },
+        };
+        if !found(entry.scanned.clone()) {
+            return false;
+        }
+        entries.push(entry);
+    }
+    // files that are gone drop out of it
+    if let Err(e) = write_index(dir, &entries) {
+        nih_plug::nih_log!("couldn't write the preset index to {:?}: {}", dir, e);
+    }
+    true
+}
+
+/// Stops the scan it was handed out by once it's dropped
+pub struct ScanGuard {
+    cancel: Arc<AtomicBool>,
+}
+
+impl Drop for ScanGuard {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Runs `scan_presets()` on the user's preset directory on a thread of its own, so opening the editor doesn't wait
+/// for it. `found` gets every preset, and `None` once the scan is through
+pub fn spawn_scan(mut found: impl FnMut(Option<Scanned>) -> bool + Send + 'static) -> ScanGuard {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let guard = ScanGuard {
+        cancel: cancel.clone(),
+    };
+    std::thread::spawn(move || {
+        let finished = match presets_dir() {
+            Some(dir) => scan_presets(&dir, &cancel, |scanned| found(Some(scanned))),
+            None => true,
+        };
+        if finished {
+            found(None);
+        }
+    });
+    guard
+}
+
+/// The parameters the random button changes, by id. Output and bypass are never among them
+pub const RANDOMIZED: [&str; 5] = ["cutoff", "q", "drive", "mode", "circuit"];
+
+/// Random normalized values for the parameters in `RANDOMIZED` that aren't `locked`.
+/// They stay where things sound like a filter: the cutoff in the middle of the audio range, no self-oscillation
+/// and at most 18 dB of drive
+pub fn random_values(locked: &[&str], state: &mut u64) -> Vec<(&'static str, f32)> {
+    let mut uniform = |min: f32, max: f32| min + (max - min) * 0.5 * (random_bipolar(state) + 1.);
+    // the variant of an enum parameter at `x` from 0 to 1, as the normalized value they're spread evenly over
+    let variant = |count: usize, x: f32| {
+        let index = ((x * count as f32) as usize).min(count - 1);
+        index as f32 / (count - 1) as f32
+    };
+    let values = [
+        (
+            "cutoff",
+            cutoff_range().normalize(uniform(100f32.log2(), 8000f32.log2()).exp2()),
+        ),
+        ("q", q_range().normalize(uniform(Q_BUTTERWORTH, 6.))),
+        (
+            "drive",
+            drive_range().normalize(nih_plug::util::db_to_gain(uniform(0., 18.))),
+        ),
+        ("mode", variant(SvfMode::variants().len(), uniform(0., 1.))),
+        (
+            "circuit",
+            variant(Circuits::variants().len(), uniform(0., 1.)),
+        ),
+    ];
+    values
+        .iter()
+        .filter(|(id, _)| !locked.contains(id))
+        .copied()
+        .collect()
+}
+
+#[test]
+fn test_round_trip() {
+    let params = FilterParams::new();
+    params.seq_steps.write().unwrap()[3] = -0.25;
+    let preset = Preset::capture("Test", &params);
+    let parsed = Preset::parse("Test", &preset.to_text()).unwrap();
+    // the plain values are turned back into normalized ones, which can be an ulp off
+    for ((id, value), (_, parsed)) in preset.values.iter().zip(&parsed.values) {
+        assert!(
+            (value - parsed).abs() < 1e-6,
+            "{}: {} became {}",
+            id,
+            value,
+            parsed
+        );
+    }
+    assert_eq!(parsed.seq_steps, preset.seq_steps);
+    assert_eq!(parsed.to_text(), preset.to_text());
+    // every value in the preset can be set
+    for (id, _) in &preset.values {
+        assert!(param_ptr(&params, id).is_some(), "{}", id);
+    }
+}
+
+#[test]
+fn test_factory_presets() {
+    let init = Preset::init();
+    let presets = factory_presets();
+    assert_eq!(presets.len(), FACTORY_PRESETS.len() + 1);
+    for (preset, (name, text)) in presets[1..].iter().zip(FACTORY_PRESETS) {
+        assert_eq!(preset.name, name);
+        // every line of the factory presets has to refer to something, or it'd silently do nothing
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let id = line.split('=').next().unwrap().trim();
+            assert!(
+                id == "seq_steps"
+                    || id == "saturation"
+                    || id == "version"
+                    || init.values.iter().any(|(known, _)| known == id),
+                "{} sets unknown parameter {}",
+                name,
+                id
+            );
+        }
+        assert!(preset != &init);
+        // the comment up top says which circuit it is and where the cutoff is, which is what it has to load as
+        let params = FilterParams::new();
+        let comment = text.lines().next().unwrap();
+        let words: Vec<&str> = comment
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty())
+            .collect();
+        let circuit = params
+            .filter
+            .filter_type
+            .preview_plain(preset.value("circuit").unwrap());
+        let circuit_name = Circuits::variants()[circuit.to_index()].to_lowercase();
+        assert!(
+            circuit_name
+                .split(' ')
+                .any(|part| words.iter().any(|word| word.eq_ignore_ascii_case(part))),
+            "{} is the {:?}",
+            name,
+            circuit
+        );
+        let unit = words
+            .iter()
+            .position(|word| word.eq_ignore_ascii_case("hz") || word.eq_ignore_ascii_case("khz"))
+            .unwrap();
+        let stated = crate::filter_params::s2v_cutoff()(&words[unit - 1..=unit].join(" ")).unwrap();
+        let cutoff = params
+            .filter
+            .cutoff
+            .preview_plain(preset.value("cutoff").unwrap());
+        assert!(
+            (cutoff / stated).log2().abs() < 0.01,
+            "{} is at {} Hz instead of {} Hz",
+            name,
+            cutoff,
+            stated
+        );
+        assert!(
+            preset.saturation.is_empty() || Saturation::from_id(&preset.saturation).is_some(),
+            "{} sets unknown saturation {}",
+            name,
+            preset.saturation
+        );
+    }
+    // the files in `presets/` are what gets compiled in, so none of them can be missing from the list
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("presets");
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_stem().unwrap().to_string_lossy();
+        assert!(
+            FACTORY_PRESETS.iter().any(|(known, _)| *known == name),
+            "{} isn't a factory preset",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_parse_errors() {
+    // parameters from newer versions are skipped, and missing ones keep the init value
+    let preset = Preset::parse("Newer", "q = 0.25\nfuture_param = 0.5").unwrap();
+    assert_eq!(preset.value("q"), Some(0.25));
+    assert_eq!(preset.values.len(), Preset::init().values.len());
+
+    assert_eq!(
+        Preset::parse("Broken", "q 0.5"),
+        Err(PresetError::Parse(1, "q 0.5".to_string()))
+    );
+    assert!(Preset::parse("Out of range", "\ncutoff = 1.5").is_err());
+    assert!(Preset::parse("Short steps", "seq_steps = 1 0 1").is_err());
+}
+
+#[test]
+fn test_saturation_by_id() {
+    let params = FilterParams::new();
+    let preset = Preset::capture("Default", &params);
+    assert_eq!(preset.saturation, "tanh");
+    assert!(preset.to_text().contains("saturation = tanh\n"));
+
+    let preset = Preset::parse("Clipped", "saturation = hard_clip").unwrap();
+    assert_eq!(preset.saturation(), Saturation::HardClip);
+    let json = params.from_json(&preset.to_json(&params)).unwrap();
+    assert_eq!(json.saturation(), Saturation::HardClip);
+    // from a build with a saturation this one doesn't have
+    let preset = Preset::parse("Fork", "saturation = only_in_some_fork").unwrap();
+    assert_eq!(preset.saturation(), Saturation::Tanh);
+}
+
+#[test]
+fn test_legacy_res() {
+    // the old ladder factory preset, which should keep its resonance
+    let preset = Preset::parse("Old", "circuit = 0.5\nres = 0.85").unwrap();
+    let q = preset.value("q").unwrap();
+    assert!((q - 0.4985).abs() < 1e-4, "{}", q);
+    assert!(Preset::parse("Out of range", "res = 2").is_err());
+}
+
+#[test]
+fn test_legacy_circuits() {
+    let circuit = |text: &str| {
+        let value = Preset::parse("Circuit", text)
+            .unwrap()
+            .value("circuit")
+            .unwrap();
+        Circuits::from_index((value * (Circuits::variants().len() - 1) as f32).round() as usize)
+    };
+    // without a version, the circuits are where they were before the comb
+    assert_eq!(circuit("circuit = 0.5"), Circuits::Ladder);
+    assert_eq!(circuit("circuit = 1"), Circuits::SallenKey);
+    assert_eq!(circuit("version = 2\ncircuit = 1"), Circuits::Comb);
+    assert!(Preset::parse("Broken", "version = two").is_err());
+}
+
+#[test]
+fn test_plain_values() {
+    let params = FilterParams::new();
+    let preset = Preset::parse(
+        "Plain",
+        "cutoff = 350\ncircuit = \"Transistor Ladder\"\nlimiter = true\nversion = 3",
+    )
+    .unwrap();
+    let cutoff = params
+        .filter
+        .cutoff
+        .preview_plain(preset.value("cutoff").unwrap());
+    assert!((cutoff - 350.).abs() < 0.01, "{}", cutoff);
+    assert_eq!(
+        preset.value("circuit"),
+        Some(
+            params
+                .filter
+                .filter_type
+                .preview_normalized(Circuits::Ladder)
+        )
+    );
+    assert_eq!(preset.value("limiter"), Some(1.));
+    // what's written is plain too
+    let text = preset.to_text();
+    assert!(
+        text.contains("\ncircuit = \"Transistor Ladder\"\n"),
+        "{}",
+        text
+    );
+    assert!(text.contains("\nlimiter = true\n"), "{}", text);
+
+    // out of range, or of the wrong kind
+    assert!(Preset::parse("High", "version = 3\ncutoff = 50000").is_err());
+    assert!(Preset::parse("Normalized", "version = 3\nlimiter = 1").is_err());
+    assert!(Preset::parse("Unknown", "version = 3\ncircuit = \"Moog\"").is_err());
+    // and version 2 still reads normalized values
+    let preset = Preset::parse("Old", "version = 2\ncutoff = 0.48789").unwrap();
+    let cutoff = params
+        .filter
+        .cutoff
+        .preview_plain(preset.value("cutoff").unwrap());
+    assert!((cutoff - 350.).abs() < 0.1, "{}", cutoff);
+}
+
+#[test]
+fn test_random_values() {
+    let mut state = 1;
+    let mut circuits = vec![false; Circuits::variants().len()];
+    for _ in 0..100 {
+        let values = random_values(&["drive"], &mut state);
+        assert_eq!(values.len(), RANDOMIZED.len() - 1);
+        assert!(values.iter().all(|(id, _)| *id != "drive"));
+        for (id, value) in values {
+            assert!((0. ..=1.).contains(&value), "{} = {}", id, value);
+            if id == "circuit" {
+                let last = circuits.len() - 1;
+                circuits[(value * last as f32).round() as usize] = true;
+            }
+        }
+    }
+    // every circuit comes up
+    assert!(circuits.iter().all(|circuit| *circuit));
+    assert!(random_values(&RANDOMIZED, &mut state).is_empty());
+}
+
+#[test]
+fn test_json_round_trip() {
+    let params = FilterParams::new();
+    params.seq_steps.write().unwrap()[3] = -0.25;
+    let mut presets = factory_presets();
+    presets.push(Preset::capture("Live", &params));
+    for preset in presets {
+        let json = preset.to_json(&params);
+        let imported = params.from_json(&json).unwrap();
+        assert_eq!(imported.to_json(&params), json, "{}", preset.name);
+        assert_eq!(imported.name, preset.name);
+        assert_eq!(imported.seq_steps, preset.seq_steps);
+    }
+    assert_eq!(
+        params.to_json("Live"),
+        Preset::capture("Live", &params).to_json(&params)
+    );
+}
+
+#[test]
+fn test_json_fixture() {
+    // written by the first version with json presets, which every later version has to keep reading
+    let json = include_str!("../tests/fixtures/preset_v1.json");
+    let params = FilterParams::new();
+    let preset = params.from_json(json).unwrap();
+    assert_eq!(preset.name, "Acid Squelch");
+    let cutoff = preset.value("cutoff").unwrap();
+    assert!(
+        (cutoff - cutoff_range().normalize(436.26)).abs() < 1e-4,
+        "{}",
+        cutoff
+    );
+    let circuit = preset.value("circuit").unwrap();
+    assert_eq!(
+        circuit,
+        params
+            .filter
+            .filter_type
+            .preview_normalized(Circuits::Ladder)
+    );
+    assert_eq!(preset.seq_steps[6], -0.5);
+    // everything in it is written back the same, next to the parameters that came after it
+    let fixture: Value = serde_json::from_str(json).unwrap();
+    let exported: Value = serde_json::from_str(&preset.to_json(&params)).unwrap();
+    for (key, value) in fixture["params"].as_object().unwrap() {
+        if key != "saturation" {
+            assert_eq!(exported["params"].get(key), Some(value), "{}", key);
+        }
+    }
+    // which was the name of the variant then, and is its id now
+    assert_eq!(preset.saturation(), Saturation::Tanh);
+    assert_eq!(exported["params"]["saturation"], "tanh");
+    assert_eq!(exported["seq_steps"], fixture["seq_steps"]);
+}
+
+#[test]
+fn test_json_errors() {
+    let params = FilterParams::new();
+    let import = |json: &str| params.from_json(json);
+    // missing parameters keep the init value, and ones from newer versions are skipped
+    let preset = import(r#"{"version": 1, "params": {"q": 2.0, "future_param": 1}}"#).unwrap();
+    assert_eq!(preset.value("q"), Some(q_range().normalize(2.)));
+    let init = Preset::init();
+    for ((id, value), (_, init)) in preset.values.iter().zip(&init.values) {
+        assert!(id == "q" || value == init, "{}", id);
+    }
+    assert_eq!(preset.seq_steps, init.seq_steps);
+
+    assert!(matches!(import("q = 0.5"), Err(PresetError::Json(_))));
+    assert!(matches!(
+        import(r#"{"params": {}}"#),
+        Err(PresetError::Json(_))
+    ));
+    assert_eq!(import(r#"{"version": 2}"#), Err(PresetError::Version(2)));
+    let invalid = |json: &str, key: &str| {
+        assert_eq!(
+            import(json),
+            Err(PresetError::Value(key.to_string())),
+            "{}",
+            json
+        );
+    };
+    invalid(
+        r#"{"version": 1, "params": {"cutoff_hz": 50000}}"#,
+        "cutoff_hz",
+    );
+    invalid(
+        r#"{"version": 1, "params": {"circuit": "Moog"}}"#,
+        "circuit",
+    );
+    invalid(r#"{"version": 1, "params": {"limiter": 1}}"#, "limiter");
+    invalid(r#"{"version": 1, "seq_steps": [1, 0, 1]}"#, "seq_steps");
+}
+
+#[test]
+fn test_scan_presets() {
+    let dir = std::env::temp_dir().join(format!("va-filter-scan-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let params = FilterParams::new();
+    Preset::capture("A", &params)
+        .save(&dir.join(format!("A.{}", EXTENSION)))
+        .unwrap();
+    std::fs::write(dir.join(format!("B.{}", EXTENSION)), "q 0.5").unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a preset").unwrap();
+    let scan = |cancel: &AtomicBool| {
+        let mut found = Vec::new();
+        let finished = scan_presets(&dir, cancel, |scanned| {
+            found.push(scanned);
+            true
+        });
+        (finished, found)
+    };
+    let not_cancelled = AtomicBool::new(false);
+
+    // a broken file is listed with its error, and the ones after it still load
+    let (finished, found) = scan(&not_cancelled);
+    assert!(finished);
+    assert_eq!(found.len(), 2);
+    assert!(matches!(&found[0], Scanned::Preset(preset) if preset.name == "A"));
+    assert!(matches!(&found[1], Scanned::Invalid(file, _) if file == "B.vapreset"));
+    // the next scan gets the same from the index
+    assert!(dir.join(INDEX_FILE).exists());
+    assert_eq!(scan(&not_cancelled).1, found);
+
+    // a file that changed is read again instead of taken from the index
+    std::fs::write(dir.join(format!("B.{}", EXTENSION)), "q = 0.25").unwrap();
+    let (_, found) = scan(&not_cancelled);
+    assert!(matches!(&found[1], Scanned::Preset(preset) if preset.value("q") == Some(0.25)));
+    let (_, again) = scan(&not_cancelled);
+    assert_eq!(again, found);
+
+    // cancelled before it starts, nothing is handed over and the index stays as it was
+    let index = std::fs::read_to_string(dir.join(INDEX_FILE)).unwrap();
+    std::fs::remove_file(dir.join(format!("A.{}", EXTENSION))).unwrap();
+    assert_eq!(scan(&AtomicBool::new(true)), (false, Vec::new()));
+    assert_eq!(
+        std::fs::read_to_string(dir.join(INDEX_FILE)).unwrap(),
+        index
+    );
+    // and cancelled halfway, it stops at the next file
+    Preset::capture("A", &params)
+        .save(&dir.join(format!("A.{}", EXTENSION)))
+        .unwrap();
+    let cancel = AtomicBool::new(false);
+    let mut found = 0;
+    assert!(!scan_presets(&dir, &cancel, |_| {
+        found += 1;
+        cancel.store(true, Ordering::Relaxed);
+        true
+    }));
+    assert_eq!(found, 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -1,4 +1,4 @@
-use core_simd::simd::f32x4;
+use core_simd::simd::*;
 // use std::simd::f32x4;
 
 // below is a polyphase iir halfband filter (cutoff at fs/4) consisting of cascades of allpasses
@@ -48,6 +48,14 @@ impl Allpass {
         self.y0 = output;
         output
     }
+    fn reset(&mut self) {
+        self.x0 = f32x4::splat(0.);
+        self.x1 = f32x4::splat(0.);
+        self.x2 = f32x4::splat(0.);
+        self.y0 = f32x4::splat(0.);
+        self.y1 = f32x4::splat(0.);
+        self.y2 = f32x4::splat(0.);
+    }
 }
 #[derive(Copy, Clone)]
 struct AllpassCascade {
@@ -63,6 +71,18 @@ impl AllpassCascade {
         }
         output
     }
+    fn reset(&mut self) {
+        for allpass in self.allpasses.iter_mut() {
+            allpass.reset();
+        }
+    }
+    fn is_finite(&self) -> bool {
+        let mut sum = f32x4::splat(0.);
+        for allpass in &self.allpasses[..self.num_filters] {
+            sum += allpass.y0 + allpass.y1 + allpass.y2;
+        }
+        sum.is_finite().all()
+    }
 }
 #[derive(Copy, Clone)]
 pub struct HalfbandFilter {
@@ -248,6 +268,14 @@ impl HalfbandFilter {
         self.old_out = self.filter_b.process(input);
         output
     }
+    pub fn reset(&mut self) {
+        self.filter_a.reset();
+        self.filter_b.reset();
+        self.old_out = f32x4::splat(0.);
+    }
+    pub fn is_finite(&self) -> bool {
+        self.filter_a.is_finite() && self.filter_b.is_finite() && self.old_out.is_finite().all()
+    }
 }
 
 impl Default for HalfbandFilter {
@@ -292,3 +320,301 @@ impl Default for HalfbandFilter {
         }
     }
 }
+
+/// The circuits run at up to this many times the host's rate
+pub const MAX_FACTOR: usize = 4;
+/// The group delay at low frequencies of going up, or back down, through no stages, the 2x one and both,
+/// in samples at the host's rate. Measured in `test_stage_delays()`
+const STAGE_DELAYS: [f32; 3] = [0., 1.1, 1.65];
+/// The lower factors get delayed by up to this many samples on each side to line up with the highest
+const MAX_PAD: usize = 2;
+/// The drive in dB past which auto oversampling goes to 2x and to 4x
+const AUTO_THRESHOLDS_DB: [f32; 2] = [6., 15.];
+/// How far past a threshold the drive has to go to switch, so automation hovering around one doesn't keep switching
+const AUTO_HYSTERESIS_DB: f32 = 1.5;
+
+/// How many halfband stages `factor` takes
+fn stage_count(factor: usize) -> usize {
+    factor.trailing_zeros() as usize
+}
+
+/// How many samples `factor` gets delayed by on each side to line up with `max_factor`
+fn pad_len(factor: usize, max_factor: usize) -> usize {
+    (STAGE_DELAYS[stage_count(max_factor)] - STAGE_DELAYS[stage_count(factor)]).round() as usize
+}
+
+/// The latency to report when the factor can go anywhere up to `max_factor`, which is what the padded 1x has.
+/// The others come within a sample of it
+pub fn latency(max_factor: usize) -> usize {
+    2 * pad_len(1, max_factor)
+}
+
+/// How many times auto oversampling doubles the rate for `drive_db`, from `level` which it was at before
+pub fn auto_level(level: usize, drive_db: f32) -> usize {
+    let mut level = level.min(AUTO_THRESHOLDS_DB.len());
+    while level < AUTO_THRESHOLDS_DB.len()
+        && drive_db > AUTO_THRESHOLDS_DB[level] + AUTO_HYSTERESIS_DB
+    {
+        level += 1;
+    }
+    while level > 0 && drive_db < AUTO_THRESHOLDS_DB[level - 1] - AUTO_HYSTERESIS_DB {
+        level -= 1;
+    }
+    level
+}
+
+/// Delays frames by up to `MAX_PAD`
+#[derive(Copy, Clone)]
+struct Pad {
+    frames: [f32x4; MAX_PAD + 1],
+    pos: usize,
+    len: usize,
+}
+
+impl Pad {
+    const ZERO: Self = Self {
+        frames: [f32x4::from_array([0.; 4]); MAX_PAD + 1],
+        pos: 0,
+        len: 0,
+    };
+    fn process(&mut self, frame: f32x4) -> f32x4 {
+        self.frames[self.pos] = frame;
+        let out = self.frames[(self.pos + MAX_PAD + 1 - self.len) % (MAX_PAD + 1)];
+        self.pos = (self.pos + 1) % (MAX_PAD + 1);
+        out
+    }
+    fn reset(&mut self) {
+        *self = Self {
+            len: self.len,
+            ..Self::ZERO
+        };
+    }
+}
+
+/// Zero-stuffs and filters frames up by 1, 2 or 4 in halfband stages, delayed to line up with the highest factor
+pub struct Upsampler {
+    stages: [HalfbandFilter; 2],
+    /// what came out of no stages, the first and both for the last few frames, for delaying the lower factors
+    history: [[[f32x4; MAX_FACTOR]; 3]; MAX_PAD + 1],
+    pos: usize,
+}
+
+impl Default for Upsampler {
+    fn default() -> Self {
+        Self {
+            stages: [HalfbandFilter::new(8, true); 2],
+            history: [[[f32x4::splat(0.); MAX_FACTOR]; 3]; MAX_PAD + 1],
+            pos: 0,
+        }
+    }
+}
+
+impl Upsampler {
+    /// `frame` at `factor` times the rate, in the first `factor` frames. The stages keep running up to `max_factor`,
+    /// so switching up doesn't start one of them from silence
+    pub fn process(
+        &mut self,
+        frame: f32x4,
+        factor: usize,
+        max_factor: usize,
+    ) -> [f32x4; MAX_FACTOR] {
+        let mut frames = [[f32x4::splat(0.); MAX_FACTOR]; 3];
+        frames[0][0] = frame;
+        for (n, stage) in self.stages[..stage_count(max_factor)]
+            .iter_mut()
+            .enumerate()
+        {
+            for i in 0..1 << n {
+                // multiply by 2 to avoid the volume loss from zero-stuffing
+                frames[n + 1][2 * i] = stage.process(f32x4::splat(2.) * frames[n][i]);
+                frames[n + 1][2 * i + 1] = stage.process(f32x4::splat(0.));
+            }
+        }
+        self.history[self.pos] = frames;
+        let delayed = (self.pos + MAX_PAD + 1 - pad_len(factor, max_factor)) % (MAX_PAD + 1);
+        self.pos = (self.pos + 1) % (MAX_PAD + 1);
+        self.history[delayed][stage_count(factor)]
+    }
+    pub fn reset(&mut self) {
+        for stage in &mut self.stages {
+            stage.reset();
+        }
+        self.history = [[[f32x4::splat(0.); MAX_FACTOR]; 3]; MAX_PAD + 1];
+    }
+    pub fn is_finite(&self) -> bool {
+        self.stages.iter().all(|stage| stage.is_finite())
+    }
+}
+
+/// Filters and decimates frames back down from 1, 2 or 4 times the host's rate, delayed to line up with the highest
+/// factor. A new factor crossfades from the old one, which keeps running on the new rate's frames by taking every
+/// other one or repeating them, so neither side starts with a jump
+pub struct Downsampler {
+    path: DownPath,
+    /// the path from before the last `set_factor()`, still running while it fades out
+    fading: DownPath,
+    /// how many frames of the fade are left, and how long it is
+    fade_left: usize,
+    fade_len: usize,
+}
+
+#[derive(Copy, Clone)]
+struct DownPath {
+    factor: usize,
+    stages: [HalfbandFilter; 2],
+    pad: Pad,
+}
+
+impl DownPath {
+    /// Takes `factor` frames to one
+    fn process(&mut self, frames: &[f32x4]) -> f32x4 {
+        let mut frames: [f32x4; MAX_FACTOR] =
+            std::array::from_fn(|i| frames.get(i).copied().unwrap_or_default());
+        let mut len = self.factor;
+        while len > 1 {
+            let stage = &mut self.stages[stage_count(len) - 1];
+            for i in 0..len / 2 {
+                // only every other output is kept, but the filter needs to see both
+                stage.process(frames[2 * i]);
+                frames[i] = stage.process(frames[2 * i + 1]);
+            }
+            len /= 2;
+        }
+        self.pad.process(frames[0])
+    }
+    /// Takes frames at another factor to one, by dropping or repeating them to get to this one
+    fn process_resampled(&mut self, frames: &[f32x4]) -> f32x4 {
+        let mut resampled = [f32x4::splat(0.); MAX_FACTOR];
+        for (i, frame) in resampled[..self.factor].iter_mut().enumerate() {
+            *frame = frames[i * frames.len() / self.factor];
+        }
+        self.process(&resampled[..self.factor])
+    }
+}
+
+impl Downsampler {
+    pub fn new(factor: usize) -> Self {
+        let path = DownPath {
+            factor,
+            stages: [HalfbandFilter::new(8, true); 2],
+            pad: Pad::ZERO,
+        };
+        Self {
+            path,
+            fading: path,
+            fade_left: 0,
+            fade_len: 1,
+        }
+    }
+    /// Goes on at `factor` times the host's rate, lined up with `max_factor`, crossfading from the old factor over
+    /// `fade_len` frames
+    pub fn set_factor(&mut self, factor: usize, max_factor: usize, fade_len: usize) {
+        if fade_len > 0 {
+            self.fading = self.path;
+            self.fade_len = fade_len;
+        }
+        self.fade_left = fade_len;
+        // stages coming back into use have the state from whenever they last ran
+        for stage in stage_count(self.path.factor)..stage_count(factor) {
+            self.path.stages[stage].reset();
+        }
+        self.path.factor = factor;
+        self.path.pad.len = pad_len(factor, max_factor);
+    }
+    /// Takes the frames at the factor to one at the host's rate
+    pub fn process(&mut self, frames: &[f32x4]) -> f32x4 {
+        let out = self.path.process(frames);
+        if self.fade_left == 0 {
+            return out;
+        }
+        let faded = self.fading.process_resampled(frames);
+        let fade = f32x4::splat(self.fade_left as f32 / self.fade_len as f32);
+        self.fade_left -= 1;
+        out + fade * (faded - out)
+    }
+    pub fn reset(&mut self) {
+        for stage in &mut self.path.stages {
+            stage.reset();
+        }
+        self.path.pad.reset();
+        self.fade_left = 0;
+    }
+    pub fn is_finite(&self) -> bool {
+        self.path.stages.iter().all(|stage| stage.is_finite())
+    }
+}
+
+#[test]
+fn test_stage_delays() {
+    // the centroid of the impulse response, which is the group delay at dc
+    for max_factor in [2, 4] {
+        let mut upsampler = Upsampler::default();
+        let mut downsampler = Downsampler::new(1);
+        downsampler.set_factor(max_factor, max_factor, 0);
+        let (mut sum, mut moment) = (0., 0.);
+        for i in 0..512 {
+            let impulse = if i == 0 { 1. } else { 0. };
+            let frames = upsampler.process(f32x4::splat(impulse), max_factor, max_factor);
+            let out = downsampler.process(&frames[..max_factor])[0];
+            sum += out;
+            moment += i as f32 * out;
+        }
+        assert!((sum - 1.).abs() < 1e-3);
+        let delay = moment / sum;
+        let expected = 2. * STAGE_DELAYS[stage_count(max_factor)];
+        assert!(
+            (delay - expected).abs() < 0.05,
+            "{} at {}x",
+            delay,
+            max_factor
+        );
+    }
+}
+
+#[test]
+fn test_auto_level_hysteresis() {
+    assert_eq!(auto_level(0, 0.), 0);
+    assert_eq!(auto_level(0, 24.), 2);
+    assert_eq!(auto_level(2, 0.), 0);
+    // hovering around a threshold stays where it is
+    for db in [5., 6., 7., 6., 5.] {
+        assert_eq!(auto_level(0, db), 0);
+        assert_eq!(auto_level(1, db), 1);
+    }
+    assert_eq!(auto_level(0, 8.), 1);
+    assert_eq!(auto_level(1, 4.), 0);
+}
+
+#[test]
+fn test_factor_switch_is_smooth() {
+    // a slow sine through each switch, which shouldn't jump much more than the sine does from sample to sample.
+    // Without the crossfade it jumps by about twice that
+    let step = 2. * std::f32::consts::PI * 0.01;
+    for (from, to) in [(1, 2), (2, 4), (4, 1), (1, 4), (4, 2), (2, 1)] {
+        let mut upsampler = Upsampler::default();
+        let mut downsampler = Downsampler::new(1);
+        downsampler.set_factor(from, MAX_FACTOR, 0);
+        let mut factor = from;
+        let mut last = 0.;
+        for i in 0..2000 {
+            if i == 1000 {
+                downsampler.set_factor(to, MAX_FACTOR, 64);
+                factor = to;
+            }
+            let x = (i as f32 * step).sin();
+            let frames = upsampler.process(f32x4::splat(x), factor, MAX_FACTOR);
+            let out = downsampler.process(&frames[..factor])[0];
+            if i > 100 {
+                assert!(
+                    (out - last).abs() < 1.5 * step,
+                    "{} to {}x at {}: {}",
+                    from,
+                    to,
+                    i,
+                    (out - last).abs() / step
+                );
+            }
+            last = out;
+        }
+    }
+}
@@ -0,0 +1,155 @@
+// input against output snippets for the transfer scope
+use crate::filter_params::{Character, FilterParams};
+use crate::utils::{AtomicF32, AtomicOps, AtomicUsize};
+use std::sync::atomic::{self, Ordering};
+
+/// How many samples each snippet has, about 5 ms at 48 kHz
+pub const SCOPE_LEN: usize = 256;
+/// Seconds between the starts of two snippets
+const CAPTURE_INTERVAL: f32 = 0.125;
+// set on the middle buffer's index when the audio thread put a snippet there the gui hasn't taken yet
+const FRESH: usize = 1 << 2;
+
+/// Triple buffer of input and output snippets, written by the audio thread and read by the gui.
+/// The audio thread fills its own buffer and swaps it with the middle one once it's full, the gui swaps its
+/// own buffer with the middle one when that has something new. Neither of them locks or allocates, and the
+/// gui never sees a snippet that's only partly written
+pub struct ScopeTap {
+    // the input followed by the output, for each of the three buffers
+    buffers: [Vec<AtomicF32>; 3],
+    // only touched by the audio thread
+    back: AtomicUsize,
+    middle: atomic::AtomicUsize,
+    // only touched by the gui
+    front: AtomicUsize,
+    write_pos: AtomicUsize,
+    // samples left until the next snippet starts
+    countdown: AtomicUsize,
+    // how many editors are showing the scope. While there are none, the audio thread skips the tap entirely
+    viewers: atomic::AtomicUsize,
+    /// the sample rate the tap is fed at
+    pub sample_rate: AtomicF32,
+}
+
+impl Default for ScopeTap {
+    fn default() -> Self {
+        let buffer = || (0..2 * SCOPE_LEN).map(|_| AtomicF32::new(0.)).collect();
+        Self {
+            buffers: [buffer(), buffer(), buffer()],
+            back: AtomicUsize::new(0),
+            middle: atomic::AtomicUsize::new(1),
+            front: AtomicUsize::new(2),
+            write_pos: AtomicUsize::new(0),
+            countdown: AtomicUsize::new(0),
+            viewers: atomic::AtomicUsize::new(0),
+            sample_rate: AtomicF32::new(48000.),
+        }
+    }
+}
+
+impl ScopeTap {
+    pub fn is_active(&self) -> bool {
+        self.viewers.load(Ordering::Relaxed) > 0
+    }
+    pub fn add_viewer(&self) {
+        self.viewers.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn remove_viewer(&self) {
+        self.viewers.fetch_sub(1, Ordering::Relaxed);
+    }
+    /// Adds a sample going into the filter and the one coming out of it for the same frame
+    pub fn push(&self, input: f32, output: f32) {
+        let countdown = self.countdown.get();
+        if countdown > 0 {
+            self.countdown.set(countdown - 1);
+            return;
+        }
+        let pos = self.write_pos.get();
+        let buffer = &self.buffers[self.back.get()];
+        buffer[pos].set(input);
+        buffer[SCOPE_LEN + pos].set(output);
+        if pos + 1 < SCOPE_LEN {
+            self.write_pos.set(pos + 1);
+            return;
+        }
+        // full, hand it over and carry on in whatever the gui left in the middle
+        let old = self.middle.swap(self.back.get() | FRESH, Ordering::AcqRel);
+        self.back.set(old & !FRESH);
+        self.write_pos.set(0);
+        self.countdown
+            .set(((self.sample_rate.get() * CAPTURE_INTERVAL) as usize).saturating_sub(SCOPE_LEN));
+    }
+    /// Copies the latest snippet into `input` and `output`, and returns whether it's new since the last read
+    pub fn read(&self, input: &mut [f32; SCOPE_LEN], output: &mut [f32; SCOPE_LEN]) -> bool {
+        let fresh = self.middle.load(Ordering::Relaxed) & FRESH != 0;
+        if fresh {
+            let old = self.middle.swap(self.front.get(), Ordering::AcqRel);
+            self.front.set(old & !FRESH);
+        }
+        let buffer = &self.buffers[self.front.get()];
+        for i in 0..SCOPE_LEN {
+            input[i] = buffer[i].get();
+            output[i] = buffer[SCOPE_LEN + i].get();
+        }
+        fresh
+    }
+}
+
+/// What the circuit's saturation does to a constant input `x`, for showing when there's no signal.
+/// Driven into the nonlinearity and scaled back down, clean goes straight through
+pub fn static_curve(params: &FilterParams, x: f32) -> f32 {
+    if params.drive.character.value() == Character::Clean {
+        return x;
+    }
+    let drive = params.drive_gain() as f64;
+    let (y, _) = params.saturation().eval_f64(drive * x as f64);
+    (y / drive) as f32
+}
+
+#[test]
+fn test_scope_triple_buffer() {
+    let tap = ScopeTap::default();
+    let (mut input, mut output) = ([0.; SCOPE_LEN], [0.; SCOPE_LEN]);
+    assert!(!tap.read(&mut input, &mut output));
+    // nothing's handed over before a snippet is full
+    for i in 0..SCOPE_LEN - 1 {
+        tap.push(i as f32, -(i as f32));
+    }
+    assert!(!tap.read(&mut input, &mut output));
+    tap.push((SCOPE_LEN - 1) as f32, -((SCOPE_LEN - 1) as f32));
+    assert!(tap.read(&mut input, &mut output));
+    assert_eq!(input[SCOPE_LEN - 1], (SCOPE_LEN - 1) as f32);
+    assert_eq!(output[10], -10.);
+    // the same snippet again, and it's not new anymore
+    assert!(!tap.read(&mut input, &mut output));
+    assert_eq!(input[10], 10.);
+
+    // waits a while before the next snippet, which then starts from the first sample after the wait
+    let wait = (48000. * CAPTURE_INTERVAL) as usize - SCOPE_LEN;
+    for i in 0..wait + SCOPE_LEN {
+        tap.push(1000. + i as f32, 0.);
+    }
+    assert!(tap.read(&mut input, &mut output));
+    assert_eq!(input[0], 1000. + wait as f32);
+
+    // the gui missing snippets only ever gets the latest
+    for i in 0..3 * (wait + SCOPE_LEN) {
+        tap.push(i as f32, 0.);
+    }
+    assert!(tap.read(&mut input, &mut output));
+    assert_eq!(input[0], (2 * (wait + SCOPE_LEN) + wait) as f32);
+}
+
+#[test]
+fn test_static_curve() {
+    use crate::filter_params::Saturation;
+    let params = FilterParams::new();
+    for saturation in [Saturation::Tanh, Saturation::Asinh] {
+        *params.saturation_id.write().unwrap() = saturation.id().to_string();
+        params.restore_saturation();
+        // about unity for small inputs, and bends over for large ones
+        assert!((static_curve(&params, 1e-3) - 1e-3).abs() < 1e-5);
+        assert!(static_curve(&params, 1.) < 1.);
+        assert_eq!(static_curve(&params, -0.5), -static_curve(&params, 0.5));
+    }
+}
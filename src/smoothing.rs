@@ -0,0 +1,553 @@
+// smoothing for the cutoff and res, with a time that follows the smoothing parameter
+use crate::filter_params::{
+    FilterParams, CUTOFF_MAX, CUTOFF_MIN, DIRTY_CUTOFF, DIRTY_RES, Q_MAX, Q_MIN,
+};
+use nih_plug::prelude::{Smoother, SmoothingStyle};
+use std::sync::Arc;
+
+/// How many samples the coefficients are held for while smoothing or modulating, unless the control rate says otherwise.
+/// Short enough that sweeps don't audibly step, even at 44.1 kHz
+pub const SUB_BLOCK_LEN: usize = 16;
+/// The longest sub-block any control rate asks for
+pub const MAX_SUB_BLOCK_LEN: usize = 64;
+/// How often the coefficients get recomputed from scratch when nothing is smoothing,
+/// so rounding errors can't pile up over a long session
+const RESYNC_SECONDS: f32 = 10.;
+/// How long a change of the spread takes, whatever the smoothing parameter says
+const SPREAD_SMOOTHING_MS: f32 = 20.;
+
+/// Wraps a nih-plug `Smoother` so its smoothing time can be changed while running.
+/// The smoothers of the params themselves can't be restyled from behind the `Arc`.
+pub struct ParamSmoother {
+    smoother: Smoother<f32>,
+    // moves in equal ratios instead of equal steps, so cutoff sweeps are linear in pitch
+    logarithmic: bool,
+    time_ms: f32,
+    current: f32,
+    target: f32,
+}
+
+impl ParamSmoother {
+    pub fn new(logarithmic: bool, time_ms: f32, value: f32) -> Self {
+        let mut smoother = Self {
+            smoother: Smoother::new(SmoothingStyle::None),
+            logarithmic,
+            time_ms,
+            current: value,
+            target: value,
+        };
+        smoother.restyle();
+        smoother
+    }
+    fn restyle(&mut self) {
+        self.smoother = Smoother::new(if self.logarithmic {
+            SmoothingStyle::Logarithmic(self.time_ms)
+        } else {
+            SmoothingStyle::Linear(self.time_ms)
+        });
+        self.smoother.reset(self.current);
+    }
+    /// Heads towards `target`, taking `time_ms` to get there from wherever the smoother currently is.
+    /// Cheap to call every block, the smoother only restarts when the target or time changed
+    pub fn set_target(&mut self, sample_rate: f32, target: f32, time_ms: f32) {
+        if time_ms != self.time_ms {
+            self.time_ms = time_ms;
+            self.restyle();
+            if self.current != self.target {
+                self.smoother.set_target(sample_rate, self.target);
+            }
+        }
+        if target != self.target {
+            self.target = target;
+            self.smoother.set_target(sample_rate, target);
+        }
+    }
+    /// Jumps straight to `value`
+    pub fn reset(&mut self, value: f32) {
+        self.current = value;
+        self.target = value;
+        self.smoother.reset(value);
+    }
+    pub fn is_smoothing(&self) -> bool {
+        self.smoother.is_smoothing()
+    }
+    pub fn next(&mut self) -> f32 {
+        self.current = self.smoother.next();
+        self.current
+    }
+    /// Moves `n` samples on, and returns where that ends up
+    pub fn next_step(&mut self, n: usize) -> f32 {
+        for _ in 0..n {
+            if !self.smoother.is_smoothing() {
+                break;
+            }
+            self.current = self.smoother.next();
+        }
+        self.current
+    }
+    /// the value returned by the last `next()`
+    pub fn value(&self) -> f32 {
+        self.current
+    }
+    /// Snaps exactly to the target, unless it's still on the way there
+    pub fn resync(&mut self) {
+        if !self.is_smoothing() {
+            self.reset(self.target);
+        }
+    }
+}
+
+/// Smooths the cutoff and res and writes them to the coefficients in `FilterParams` once per sub-block.
+/// Shared by the plugin and the filters' `process_block()`, so realtime and offline rendering sound the same
+pub struct CoefficientSmoother {
+    params: Arc<FilterParams>,
+    cutoff: ParamSmoother,
+    res: ParamSmoother,
+    // the spread in octaves, which goes on top of the smoothed cutoff so both channels sweep together
+    spread: ParamSmoother,
+    // the summed modulation of the cutoff in octaves, as of the last `update_g`
+    cutoff_mod: f32,
+    // and of q, as of the last `set_resonances`
+    res_mod: f32,
+    // samples since the last resync, and how many there are between resyncs
+    since_resync: usize,
+    resync_len: usize,
+}
+
+impl CoefficientSmoother {
+    /// Starts out at the current values of the params
+    pub fn new(params: Arc<FilterParams>) -> Self {
+        let smoothing = params.filter.smoothing.value();
+        let cutoff = ParamSmoother::new(true, smoothing, params.filter.cutoff.value());
+        // q spans a factor of 50, so it's smoothed in ratios like the cutoff
+        let res = ParamSmoother::new(true, smoothing, params.q());
+        let spread = ParamSmoother::new(false, SPREAD_SMOOTHING_MS, params.filter.spread.value());
+        Self {
+            params,
+            cutoff,
+            res,
+            spread,
+            cutoff_mod: 0.,
+            res_mod: 0.,
+            since_resync: 0,
+            // set along with the sample rate
+            resync_len: usize::MAX,
+        }
+    }
+    /// Jumps to the current values of the params
+    pub fn reset(&mut self) {
+        self.cutoff.reset(self.params.filter.cutoff.value());
+        self.res.reset(self.params.q());
+        self.spread.reset(self.params.filter.spread.value());
+    }
+    /// Heads towards `cutoff` and `res`, taking as long as the smoothing parameter says, and towards the spread
+    pub fn set_targets(&mut self, sample_rate: f32, cutoff: f32, res: f32) {
+        let smoothing = self.params.filter.smoothing.value();
+        self.cutoff.set_target(sample_rate, cutoff, smoothing);
+        self.res.set_target(sample_rate, res, smoothing);
+        self.spread.set_target(
+            sample_rate,
+            self.params.filter.spread.value(),
+            SPREAD_SMOOTHING_MS,
+        );
+        self.resync_len = (RESYNC_SECONDS * sample_rate) as usize;
+    }
+    /// Writes the coefficients for where the smoothers are right now
+    pub fn apply(&self) {
+        self.apply_cutoff();
+        self.apply_res();
+    }
+    /// Redoes what the `DIRTY_*` flags in `dirty` ask for, from where the smoothers are right now.
+    /// Returns whether the circuits need to update their matrices, which is whenever any flag is set
+    pub fn apply_dirty(&self, dirty: u32) -> bool {
+        if dirty & DIRTY_CUTOFF != 0 {
+            self.apply_cutoff();
+        }
+        if dirty & DIRTY_RES != 0 {
+            self.apply_res();
+        }
+        dirty != 0
+    }
+    fn apply_cutoff(&self) {
+        // where the spread is smoothed to, it only gets to its target in `advance()`
+        self.params.set_spread(self.spread.value());
+        self.params.set_analog(self.params.filter.analog.value());
+        self.params
+            .update_g(self.modulated_cutoff(self.cutoff.value()));
+    }
+    fn apply_res(&self) {
+        self.params
+            .set_resonances(self.modulated_q(self.res.value()));
+        self.params
+            .set_damping_antisat(self.params.filter.antisat.value());
+        self.params
+            .set_res_squash(self.params.filter.res_character.value());
+        self.params
+            .set_feedback_drive(self.params.drive.feedback.value());
+    }
+    /// Moves the smoothers `n` samples on and updates the coefficients if anything moved.
+    /// `cutoff_mod` and `res_mod` are the modulation of the cutoff and of q in octaves.
+    /// Returns whether the circuits need to update their matrices
+    pub fn advance(&mut self, n: usize, cutoff_mod: f32, res_mod: f32) -> bool {
+        let mut update = false;
+        self.since_resync += n;
+        if self.since_resync >= self.resync_len
+            && !self.cutoff.is_smoothing()
+            && !self.res.is_smoothing()
+            && !self.spread.is_smoothing()
+        {
+            self.since_resync = 0;
+            self.cutoff.resync();
+            self.res.resync();
+            self.spread.resync();
+            self.apply();
+            update = true;
+        }
+        if self.cutoff.is_smoothing() || self.spread.is_smoothing() || cutoff_mod != self.cutoff_mod
+        {
+            self.cutoff_mod = cutoff_mod;
+            let cut_smooth = self.cutoff.next_step(n);
+            // the channels' cutoffs come from the same smoothed cutoff and spread, in a single update
+            self.params.set_spread(self.spread.next_step(n));
+            self.params.update_g(self.modulated_cutoff(cut_smooth));
+            update = true;
+        }
+        if self.res.is_smoothing() || res_mod != self.res_mod {
+            self.res_mod = res_mod;
+            let res_smooth = self.res.next_step(n);
+            self.params.set_resonances(self.modulated_q(res_smooth));
+            update = true;
+        }
+        if self.params.filter.antisat.smoothed.is_smoothing() {
+            let mut antisat_smooth = 0.;
+            for _ in 0..n {
+                antisat_smooth = self.params.filter.antisat.smoothed.next();
+            }
+            self.params.set_damping_antisat(antisat_smooth);
+            update = true;
+        }
+        if self.params.filter.res_character.smoothed.is_smoothing() {
+            let mut squash_smooth = 0.;
+            for _ in 0..n {
+                squash_smooth = self.params.filter.res_character.smoothed.next();
+            }
+            self.params.set_res_squash(squash_smooth);
+            update = true;
+        }
+        if self.params.drive.feedback.smoothed.is_smoothing() {
+            let mut feedback_smooth = 0.;
+            for _ in 0..n {
+                feedback_smooth = self.params.drive.feedback.smoothed.next();
+            }
+            self.params.set_feedback_drive(feedback_smooth);
+        }
+        update
+    }
+
+    /// The cutoff the coefficients are at right now, with the modulation
+    pub fn cutoff(&self) -> f32 {
+        self.modulated_cutoff(self.cutoff.value())
+    }
+    /// and the q
+    pub fn q(&self) -> f32 {
+        self.modulated_q(self.res.value())
+    }
+
+    /// Applies the summed modulation to the cutoff. Clamped after summing so stacked modulation stays in range
+    fn modulated_cutoff(&self, cutoff: f32) -> f32 {
+        (cutoff * self.cutoff_mod.exp2()).clamp(CUTOFF_MIN, CUTOFF_MAX)
+    }
+    fn modulated_q(&self, q: f32) -> f32 {
+        if self.res_mod == 0. {
+            // left exactly as it was without modulation
+            return q;
+        }
+        (q * self.res_mod.exp2()).clamp(Q_MIN, Q_MAX)
+    }
+}
+
+#[test]
+fn test_octave_jump_settle_time() {
+    let fs = 48000.;
+    for time_ms in [1., 20., 200.] {
+        let mut smoother = ParamSmoother::new(true, time_ms, 1000.);
+        smoother.set_target(fs, 2000., time_ms);
+        let expected_steps = (time_ms / 1000. * fs).round() as usize;
+        let mut steps = 0;
+        while smoother.is_smoothing() {
+            smoother.next();
+            steps += 1;
+            // halfway through an octave jump is half an octave up, not 1500 Hz
+            if steps == expected_steps / 2 {
+                let cents = 1200. * (smoother.value() / 1000.).log2();
+                assert!((cents - 600.).abs() < 5., "{} ms: {} cents", time_ms, cents);
+            }
+        }
+        assert_eq!(steps, expected_steps, "{time_ms} ms");
+        assert_eq!(smoother.value(), 2000.);
+    }
+}
+
+#[test]
+fn test_retime_mid_sweep() {
+    let fs = 48000.;
+    let mut smoother = ParamSmoother::new(true, 100., 1000.);
+    smoother.set_target(fs, 4000., 100.);
+    for _ in 0..2400 {
+        smoother.next();
+    }
+    let halfway = smoother.value();
+    // changing the time carries on from where it was, and takes the new time for the rest
+    smoother.set_target(fs, 4000., 10.);
+    let first = smoother.next();
+    assert!(first > halfway && first < halfway * 1.01);
+    let mut steps = 1;
+    while smoother.is_smoothing() {
+        smoother.next();
+        steps += 1;
+    }
+    assert_eq!(steps, 480);
+    assert_eq!(smoother.value(), 4000.);
+}
+
+#[test]
+fn test_process_block_sweep() {
+    use crate::filter::svf::Svf;
+    use crate::utils::AtomicOps;
+    use core_simd::simd::f32x4;
+
+    let fs = 48000.;
+    let params = Arc::new(FilterParams::new());
+    params.sample_rate.set(fs);
+    let mut coefficients = CoefficientSmoother::new(params.clone());
+    coefficients.apply();
+    let mut svf = Svf::new(params.clone());
+    svf.update();
+
+    // an octave up over the default 20 ms of smoothing, rendered in one go
+    coefficients.set_targets(fs, 2. * params.filter.cutoff.value(), params.q());
+    let input: Vec<f32x4> = (0..960)
+        .map(|i| f32x4::splat((i as f32 * 0.05).sin()))
+        .collect();
+    let mut output = vec![f32x4::splat(0.); input.len()];
+    svf.process_block(&mut coefficients, &input[..480], &mut output[..480]);
+    // halfway there is half an octave up, give or take a sub-block
+    let cents = 1200. * (params.cutoff_hz.get() / params.filter.cutoff.value()).log2();
+    assert!((cents - 600.).abs() < 25., "{} cents", cents);
+    svf.process_block(&mut coefficients, &input[480..], &mut output[480..]);
+    assert_eq!(params.cutoff_hz.get(), 2. * params.filter.cutoff.value());
+    assert!(output.iter().all(|y| y[0].is_finite() && y[1].is_finite()));
+}
+
+#[test]
+fn test_control_rate_sweep() {
+    use crate::filter::svf::Svf;
+    use crate::filter_params::ControlRate;
+    use crate::utils::AtomicOps;
+    use core_simd::simd::f32x4;
+    use nih_plug::prelude::EnumParam;
+
+    let fs = 48000.;
+    let input: Vec<f32x4> = (0..960)
+        .map(|i| f32x4::splat((i as f32 * 0.05).sin()))
+        .collect();
+    // the same octave sweep as above, returning how far it got halfway through and what came out
+    let render = |rate| {
+        let mut params = FilterParams::new();
+        params.filter.control_rate = EnumParam::new("Control Rate", rate);
+        let params = Arc::new(params);
+        params.sample_rate.set(fs);
+        let mut coefficients = CoefficientSmoother::new(params.clone());
+        coefficients.apply();
+        let mut svf = Svf::new(params.clone());
+        svf.update();
+        coefficients.set_targets(fs, 2. * params.filter.cutoff.value(), params.q());
+        let mut output = vec![f32x4::splat(0.); input.len()];
+        svf.process_block(&mut coefficients, &input[..480], &mut output[..480]);
+        let cents = 1200. * (params.cutoff_hz.get() / params.filter.cutoff.value()).log2();
+        svf.process_block(&mut coefficients, &input[480..], &mut output[480..]);
+        // the sweep takes as long whatever the rate
+        assert_eq!(params.cutoff_hz.get(), 2. * params.filter.cutoff.value());
+        (cents, output)
+    };
+    let (cents, smooth) = render(ControlRate::EverySample);
+    assert!((cents - 600.).abs() < 2., "{} cents", cents);
+    for (rate, tolerance) in [
+        (ControlRate::Samples16, 0.02),
+        (ControlRate::Samples64, 0.05),
+    ] {
+        let (cents, stepped) = render(rate);
+        // the smoothers move on by the whole sub-block, so it's just as far along
+        assert!((cents - 600.).abs() < 2., "{:?}: {} cents", rate, cents);
+        let error = smooth
+            .iter()
+            .zip(&stepped)
+            .map(|(a, b)| (a[0] - b[0]).abs())
+            .fold(0., f32::max);
+        assert!(error < tolerance, "{:?}: {}", rate, error);
+    }
+}
+
+#[test]
+fn test_long_session_resync() {
+    use crate::utils::AtomicOps;
+
+    // a low sample rate, so hours of automation run quickly
+    let fs = 1000.;
+    let params = Arc::new(FilterParams::new());
+    params.sample_rate.set(fs);
+    let mut coefficients = CoefficientSmoother::new(params.clone());
+    coefficients.apply();
+
+    let seconds = 4 * 60 * 60;
+    let mut cutoff = 100.;
+    coefficients.set_targets(fs, cutoff, 0.5);
+    for second in 1..=seconds {
+        if second % 600 == 0 {
+            // a checkpoint every 10 minutes, after holding still for long enough to resync
+            for _ in 0..(RESYNC_SECONDS * fs) as usize / SUB_BLOCK_LEN + 1 {
+                coefficients.advance(SUB_BLOCK_LEN, 0., 0.);
+            }
+            assert_eq!(params.cutoff_hz.get(), cutoff, "after {} s", second);
+            let g = (std::f32::consts::PI * cutoff / fs).tan();
+            assert!((params.g() - g).abs() <= f32::EPSILON * g);
+        }
+        // a slow sweep with a new target every second
+        cutoff = 100. * (0.1 * (second as f32 * 0.01).sin()).exp2();
+        coefficients.set_targets(fs, cutoff, 0.5);
+        for _ in 0..fs as usize / SUB_BLOCK_LEN {
+            coefficients.advance(SUB_BLOCK_LEN, 0., 0.);
+        }
+    }
+}
+
+#[test]
+fn test_apply_dirty() {
+    use crate::filter_params::{Circuits, DIRTY_ALL, DIRTY_TOPOLOGY};
+    use crate::utils::AtomicOps;
+
+    // the ladder's resonance also moves g with its pitch correction
+    let mut params = FilterParams::new();
+    params.filter.filter_type = nih_plug::prelude::EnumParam::new("", Circuits::SVF);
+    let params = Arc::new(params);
+    params.sample_rate.set(48000.);
+    let coefficients = CoefficientSmoother::new(params.clone());
+    coefficients.apply();
+    let (g, k) = (params.g(), params.k_ladder());
+    // left out of date, so it shows which of them get redone
+    params.update_g(5000.);
+    params.set_resonances(Q_MAX);
+    let stale = (params.g(), params.k_ladder());
+
+    assert!(!coefficients.apply_dirty(0));
+    // only the matrices, which is up to the circuits
+    assert!(coefficients.apply_dirty(DIRTY_TOPOLOGY));
+    assert_eq!((params.g(), params.k_ladder()), stale);
+    assert!(coefficients.apply_dirty(DIRTY_RES));
+    assert_eq!((params.g(), params.k_ladder()), (stale.0, k));
+    assert!(coefficients.apply_dirty(DIRTY_CUTOFF));
+    assert_eq!((params.g(), params.k_ladder()), (g, k));
+
+    params.update_g(5000.);
+    params.set_resonances(Q_MAX);
+    assert!(coefficients.apply_dirty(DIRTY_ALL));
+    assert_eq!((params.g(), params.k_ladder()), (g, k));
+}
+
+#[test]
+fn test_circuit_switch_res_comp() {
+    use crate::filter_params::Circuits;
+    use crate::utils::AtomicOps;
+
+    let params = Arc::new(FilterParams::new());
+    params.sample_rate.set(48000.);
+    let res = params.filter.res.as_ptr();
+    unsafe { res.set_normalized_value(1.) };
+    let coefficients = CoefficientSmoother::new(params.clone());
+    coefficients.apply();
+    params.dirty.take();
+    // the ladder makes up its own passband loss
+    assert_eq!(params.res_comp_gain.get(), 1.);
+
+    let filter_type = params.filter.filter_type.as_ptr();
+    unsafe {
+        filter_type
+            .set_normalized_value(params.filter.filter_type.preview_normalized(Circuits::SVF))
+    };
+    coefficients.apply_dirty(params.dirty.take());
+    assert!((params.res_comp_gain.get() - 2.).abs() < 1e-3);
+    unsafe {
+        filter_type.set_normalized_value(
+            params
+                .filter
+                .filter_type
+                .preview_normalized(Circuits::SallenKey),
+        )
+    };
+    coefficients.apply_dirty(params.dirty.take());
+    assert!((params.res_comp_gain.get() - 1.5).abs() < 1e-3);
+}
+
+#[test]
+fn test_spread_sweep() {
+    use crate::filter_params::Circuits;
+    use crate::utils::AtomicOps;
+    use nih_plug::prelude::Param;
+    use std::f32::consts::PI;
+
+    let fs = 48000.;
+    let mut params = FilterParams::new();
+    params.filter.filter_type = nih_plug::prelude::EnumParam::new("", Circuits::SVF);
+    let params = Arc::new(params);
+    params.sample_rate.set(fs);
+    // from the bottom of the range, with the right channel as far over the left as it goes
+    unsafe {
+        params.filter.cutoff.as_ptr().set_normalized_value(0.);
+        params.filter.spread.as_ptr().set_normalized_value(1.);
+    }
+    let mut coefficients = CoefficientSmoother::new(params.clone());
+    coefficients.apply();
+    // where each channel's cutoff is, in octaves
+    let octaves = || [0, 1].map(|channel| (params.g_channel(channel).atan() * fs / PI).log2());
+
+    // the whole range over the default 20 ms of smoothing, up past where the right channel hits the top
+    coefficients.set_targets(fs, CUTOFF_MAX, params.q());
+    let mut last = octaves();
+    assert!((last[1] - last[0] - 2.).abs() < 1e-3, "{:?}", last);
+    for _ in 0..1200 / SUB_BLOCK_LEN {
+        coefficients.advance(SUB_BLOCK_LEN, 0., 0.);
+        let [left, right] = octaves();
+        let hz = params.cutoff_hz.get();
+        // both channels move together, and stay as far apart
+        assert!(
+            ((left - last[0]) - (right - last[1])).abs() < 1e-3,
+            "left moved {} octaves and right {} at {} Hz",
+            left - last[0],
+            right - last[1],
+            hz
+        );
+        assert!(
+            (right - left - 2.).abs() < 1e-3,
+            "{} octaves apart at {} Hz",
+            right - left,
+            hz
+        );
+        last = [left, right];
+    }
+    assert_eq!(params.cutoff_hz.get(), CUTOFF_MAX);
+
+    // flipping the spread glides over to the other side, even with the dirty flag its callback leaves
+    unsafe { params.filter.spread.as_ptr().set_normalized_value(0.) };
+    coefficients.set_targets(fs, CUTOFF_MAX, params.q());
+    coefficients.apply_dirty(DIRTY_CUTOFF);
+    let [left, right] = octaves();
+    assert!((right - left - 2.).abs() < 1e-3, "{}", right - left);
+    coefficients.advance(SUB_BLOCK_LEN, 0., 0.);
+    let [left, right] = octaves();
+    assert!(right - left > 1.9 && right - left < 2., "{}", right - left);
+    for _ in 0..1200 / SUB_BLOCK_LEN {
+        coefficients.advance(SUB_BLOCK_LEN, 0., 0.);
+    }
+    let [left, right] = octaves();
+    assert!((right - left + 2.).abs() < 1e-3, "{}", right - left);
+}
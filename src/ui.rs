@@ -1,13 +1,22 @@
 // use crate::editor::EditorState;
+mod export;
+#[cfg(feature = "dev-ui")]
+mod layout;
 mod plot;
-use crate::filter_params::Circuits;
+mod undo;
+use crate::analyzer::{AnalyzerView, Spectrum, FLOOR_DB};
+use crate::filter_params::{Circuits, LadderResponse, SvfMode, MOD_SLOTS};
+use crate::meter::{meter_fraction, MeterBallistics};
+use crate::modulation::N_STEPS;
+use crate::presets::{self, Preset, Scanned};
+use crate::scope::{static_curve, SCOPE_LEN};
 use nih_plug::context::GuiContext;
 use nih_plug::param::internals::ParamPtr;
-use plot::{get_amplitude_response, get_phase_response};
+use plot::{get_amplitude_response, get_phase_response, LADDER_MIX_MODES};
 // use crate::editor::{get_amplitude_response, get_phase_response};
 use crate::utils::*;
 use crate::FilterParams;
-use nih_plug::prelude::Param;
+use nih_plug::prelude::{Enum, EnumParam, FloatParam, Param, Params};
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::Arc;
@@ -19,7 +28,30 @@ use vizia::*;
 // use vst::host::Host;
 // use vst::plugin::HostCallback;
 // use vst::plugin::PluginParameters;
-const ICON_DOWN_OPEN: &str = "\u{e75c}";
+const ICON_ATTENTION: &str = "\u{26a0}";
+// pixels of dragging for the whole range of most knobs
+const KNOB_TRAVEL: f32 = 200.;
+/// as wide as a knob
+const SATURATION_METER_WIDTH: f32 = 70.;
+/// how much of the saturation meter is left after each frame once the level drops, about 200 ms to fall by half
+const SATURATION_METER_DECAY: f32 = 0.9;
+/// seconds between two looks at the meters
+const METER_INTERVAL: f32 = 0.033;
+/// a snippet quieter than this shows the static curve instead, about -80 dB
+const SCOPE_SILENCE: f32 = 1e-4;
+/// how long the scope waits for a new snippet before it takes the plugin for stopped, in seconds
+const SCOPE_TIMEOUT: f32 = 0.5;
+// pixels of dragging per position of a stepped knob
+const STEP_TRAVEL: f32 = 30.;
+// how much finer dragging and the wheel get with shift held
+const FINE_SCALE: f32 = 0.1;
+// how far one notch of the mouse wheel moves a continuous knob
+const WHEEL_STEP: f32 = 0.02;
+// how far the arrow keys and page up/down move a focused continuous knob
+const KEY_STEP: f32 = 0.01;
+const KEY_COARSE_STEP: f32 = 0.1;
+/// the tabs of the modulation column
+const MOD_PAGES: [&str; 4] = ["Sequencer", "Envelope", "Keyboard", "Matrix"];
 use std::f32::consts::PI;
 use vizia::prelude::*;
 
@@ -28,8 +60,71 @@ pub struct UiData {
     pub gui_context: Arc<dyn GuiContext>,
     params: Arc<FilterParams>,
     // host: Option<HostCallback>,
-    filter_circuits: Vec<String>,
     show_phase: bool,
+    // mirrors `FilterParams::input_dc_warning`
+    dc_warning: bool,
+    /// the most the output limiter took off over the last meter update, in dB
+    limiter_reduction: f32,
+    /// how far into the saturation the circuit goes, decaying from the peaks
+    saturation_amount: f32,
+    // mirrors `FilterParams::oversampling_factor`
+    oversampling_factor: usize,
+    /// what the level meters on the left and right edges show, per channel
+    input_levels: [MeterBallistics; 2],
+    output_levels: [MeterBallistics; 2],
+    /// the analyzer's spectrum or channel delta in dB per pixel of the bode plot, and which of them it is
+    spectrum: Vec<f32>,
+    spectrum_view: AnalyzerView,
+    /// the scope's latest input and output snippet, or `None` while it's silent
+    scope: Option<(Vec<f32>, Vec<f32>)>,
+    // mirrors `FilterParams::scope_time`
+    scope_time: bool,
+    analyze_input: bool,
+    // mirrors `FilterParams::analyzer_view`
+    analyzer_view: AnalyzerView,
+    // mirrors `FilterParams::editor_scale`
+    editor_scale: f32,
+    // mirrors `FilterParams::ab_b_active`
+    ab_b_active: bool,
+    // mirrors `FilterParams::cutoff_note_names`
+    note_names: bool,
+    /// which of `MOD_PAGES` the modulation column shows
+    mod_page: usize,
+    /// factory presets followed by the user's, which come in from the scan as it finds them
+    presets: Vec<Preset>,
+    preset_index: usize,
+    preset_name: String,
+    /// the files of the preset directory that didn't load, with why
+    invalid_presets: Vec<String>,
+    scanning_presets: bool,
+    /// the scan of the user's presets, stopped when the editor closes
+    preset_scan: presets::ScanGuard,
+    /// name of the parameter whose value is being typed in
+    text_entry: Option<String>,
+    /// what was typed on a focused knob to start the text entry, which the textbox starts out with
+    typed_entry: String,
+    /// the parameters in the order `FilterParams::midi_learn` indexes them
+    param_ptrs: Vec<ParamPtr>,
+    /// the learned ccs, and the parameter waiting for one
+    midi_ccs: Vec<(ParamPtr, u8)>,
+    midi_learning: Option<ParamPtr>,
+    /// the knob whose midi learn menu is open
+    midi_menu: Option<ParamPtr>,
+    /// the knobs of the cutoff, res and drive, and how far round the modulation has them, laid out like `ModDestination`
+    mod_rings: [(ParamPtr, f32); 3],
+    /// the parameters the random button leaves alone, by preset id
+    random_locks: Vec<&'static str>,
+    /// edits made from the editor, for ctrl+z and ctrl+shift+z
+    undo: undo::UndoStack<ParamPtr>,
+    /// the analyzer's, the scope's and the meters', dropped with the editor
+    viewers: [ViewerGuard; 3],
+    #[cfg(feature = "dev-ui")]
+    layout: layout::KnobLayout,
+    #[cfg(feature = "dev-ui")]
+    layout_error: Option<String>,
+    /// the theme and layout files' watcher, dropped with the editor
+    #[cfg(feature = "dev-ui")]
+    layout_watcher: ViewerGuard,
 }
 
 #[derive(Debug)]
@@ -38,12 +133,76 @@ pub enum ParamChangeEvent {
     EndSet(ParamPtr),
     SetParam(ParamPtr, f32),
 
-    CircuitEvent(usize),
     ChangeBodeView(),
+    ExportPlot(),
+    ToggleAnalyzerSource(),
+    /// steps through the spectrum and the channel delta views
+    CycleAnalyzerView(),
+    ToggleNoteNames(),
+    ToggleScopeView(),
+    SetScale(f32),
+    /// shows another page of the modulation column
+    SetModPage(usize),
+    SwitchAbSlot(),
+    /// copies the active slot over the other one
+    CopyAbSlot(),
+    /// steps through the presets, wrapping around at either end
+    StepPreset(isize),
+    SavePreset(),
+    /// writes the current values to a json file with plain values
+    ExportJson(),
+    ImportJson(),
+    /// sets the parameters of a preset back to their defaults
+    InitPatch(),
+    /// picks new values for the unlocked parameters of `presets::RANDOMIZED`
+    Randomize(),
+    ToggleRandomLock(&'static str),
+    StartTextEntry(String),
+    /// starts the text entry for a parameter with what was typed while its knob had focus
+    TypeIntoEntry(String, String),
+    /// typed in value for a parameter, parsed the same way the host parses it
+    TextEntry(ParamPtr, String),
+    CancelTextEntry(),
+    /// sets a parameter back to its default, as a single edit
+    ResetParam(ParamPtr),
+    /// opens or closes the midi learn menu of a knob
+    ToggleMidiMenu(ParamPtr),
+    /// binds the next incoming cc to the parameter
+    MidiLearn(ParamPtr),
+    /// removes the parameter's cc, or stops waiting for one
+    MidiForget(ParamPtr),
+    /// steps a discrete parameter to its next value, wrapping around at the end, as a single edit
+    CycleParam(ParamPtr),
+}
+
+#[derive(Debug)]
+pub enum MeterEvent {
+    DcWarning(bool),
+    LimiterReduction(f32),
+    SaturationAmount(f32),
+    OversamplingFactor(usize),
+    /// the input and output meters
+    Levels([MeterBallistics; 2], [MeterBallistics; 2]),
+    Spectrum(AnalyzerView, Vec<f32>),
+    Scope(Option<(Vec<f32>, Vec<f32>)>),
+    /// a cc was learned, or the bindings changed some other way
+    MidiBindings,
+    /// the host loaded a new state, which the undo history doesn't know how to get back to
+    StateLoaded,
+    /// where the modulation has the cutoff, res and drive, as positions of their knobs
+    Modulation([f32; 3]),
+}
+
+#[derive(Debug)]
+pub enum PresetScanEvent {
+    /// a preset from the user's directory, or a file there that didn't load
+    Found(Scanned),
+    /// the scan got through the whole directory
+    Done,
 }
 
 impl Model for UiData {
-    fn event(&mut self, _cx: &mut EventContext, event: &mut Event) {
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
         // let setter = ParamSetter::new(self.gui_context.as_ref());
         event.map(|event, _| match event {
             ParamChangeEvent::SetParam(param_ptr, new_value) => {
@@ -53,143 +212,1538 @@ impl Model for UiData {
                 };
             }
 
-            ParamChangeEvent::BeginSet(param_ptr) => {
-                unsafe { self.gui_context.raw_begin_set_parameter(*param_ptr) };
+            ParamChangeEvent::BeginSet(param_ptr) => self.begin_set(*param_ptr),
+            ParamChangeEvent::EndSet(param_ptr) => self.end_set(*param_ptr),
+            ParamChangeEvent::ChangeBodeView() => {
+                self.show_phase = !self.show_phase;
+                save_view_flag(&self.params.show_phase, self.show_phase);
             }
-            ParamChangeEvent::EndSet(param_ptr) => {
-                unsafe { self.gui_context.raw_end_set_parameter(*param_ptr) };
+            ParamChangeEvent::ToggleAnalyzerSource() => {
+                self.analyze_input = !self.analyze_input;
+                self.params
+                    .analyzer
+                    .pre_filter
+                    .store(self.analyze_input, std::sync::atomic::Ordering::Relaxed);
+                save_view_flag(&self.params.analyze_input, self.analyze_input);
             }
-            ParamChangeEvent::CircuitEvent(idx) => {
-                // self.choice = self
-                // self.choice = circuit_name.to_owned();
-                unsafe {
-                    self.gui_context
-                        .raw_begin_set_parameter(self.params.filter_type.as_ptr())
+            ParamChangeEvent::CycleAnalyzerView() => {
+                self.analyzer_view = self.analyzer_view.next();
+                let index = self.analyzer_view.to_index();
+                self.params.analyzer.view.set(index);
+                if let Ok(mut view) = self.params.analyzer_view.write() {
+                    *view = index;
+                }
+            }
+            ParamChangeEvent::ToggleMidiMenu(param_ptr) => {
+                self.midi_menu = if self.midi_menu == Some(*param_ptr) {
+                    None
+                } else {
+                    Some(*param_ptr)
                 };
-                unsafe {
-                    self.gui_context.raw_set_parameter_normalized(
-                        self.params.filter_type.as_ptr(),
-                        *idx as f32 / 2.,
-                    )
+            }
+            ParamChangeEvent::MidiLearn(param_ptr) => {
+                if let Some(param) = self.param_ptrs.iter().position(|ptr| ptr == param_ptr) {
+                    self.params.midi_learn.learn(param);
+                    self.midi_learning = Some(*param_ptr);
+                }
+                self.midi_menu = None;
+            }
+            ParamChangeEvent::MidiForget(param_ptr) => {
+                if let Some(param) = self.param_ptrs.iter().position(|ptr| ptr == param_ptr) {
+                    if self.midi_learning == Some(*param_ptr) {
+                        self.params.midi_learn.cancel_learning();
+                    }
+                    self.params.midi_learn.forget(param);
+                    self.refresh_midi();
+                }
+                self.midi_menu = None;
+            }
+            ParamChangeEvent::StartTextEntry(name) => {
+                self.typed_entry.clear();
+                self.text_entry = Some(name.clone());
+            }
+            ParamChangeEvent::TypeIntoEntry(name, text) => {
+                self.typed_entry.clone_from(text);
+                self.text_entry = Some(name.clone());
+            }
+            ParamChangeEvent::TextEntry(param_ptr, text) => {
+                // anything that doesn't parse leaves the parameter where it was
+                if let Some(value) = unsafe { param_ptr.string_to_normalized_value(text) } {
+                    self.set_once(*param_ptr, value);
+                }
+                self.text_entry = None;
+            }
+            ParamChangeEvent::CancelTextEntry() => {
+                self.text_entry = None;
+            }
+            ParamChangeEvent::ResetParam(param_ptr) => {
+                let default = unsafe { param_ptr.default_normalized_value() };
+                self.set_once(*param_ptr, default);
+            }
+            ParamChangeEvent::CycleParam(param_ptr) => {
+                let steps = unsafe { param_ptr.step_count() }.unwrap_or(1).max(1);
+                let current =
+                    (unsafe { param_ptr.normalized_value() } * steps as f32).round() as usize;
+                let next = (current + 1) % (steps + 1);
+                self.set_once(*param_ptr, next as f32 / steps as f32);
+            }
+            ParamChangeEvent::StepPreset(step) => {
+                let len = self.presets.len() as isize;
+                self.preset_index = (self.preset_index as isize + step).rem_euclid(len) as usize;
+                self.load_preset(self.preset_index);
+            }
+            ParamChangeEvent::InitPatch() => {
+                self.apply(&Preset::init());
+                self.preset_name = "Init".to_string();
+            }
+            // through the host like every other edit, which also has the coefficients updated right away
+            ParamChangeEvent::Randomize() => {
+                let mut state = random_seed();
+                self.undo.begin_group();
+                for (id, value) in presets::random_values(&self.random_locks, &mut state) {
+                    if let Some(param_ptr) = presets::param_ptr(&self.params, id) {
+                        self.set_once(param_ptr, value);
+                    }
+                }
+                self.undo
+                    .end(|param_ptr| unsafe { param_ptr.normalized_value() });
+                self.preset_name = "Random".to_string();
+            }
+            ParamChangeEvent::ToggleRandomLock(id) => {
+                if let Some(i) = self.random_locks.iter().position(|locked| locked == id) {
+                    self.random_locks.remove(i);
+                } else {
+                    self.random_locks.push(id);
+                }
+            }
+            ParamChangeEvent::SavePreset() => {
+                let preset_dir = match presets::presets_dir() {
+                    Some(dir) => dir,
+                    None => return,
                 };
-                unsafe {
-                    self.gui_context
-                        .raw_end_set_parameter(self.params.filter_type.as_ptr())
+                // the dialog doesn't create the directory, and won't start in one that doesn't exist
+                if let Err(e) = std::fs::create_dir_all(&preset_dir) {
+                    nih_plug::nih_log!("couldn't create {:?}: {}", preset_dir, e);
+                }
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("va-filter preset", &[presets::EXTENSION])
+                    .set_directory(&preset_dir)
+                    .set_file_name(&format!("{}.{}", self.preset_name, presets::EXTENSION))
+                    .save_file()
+                {
+                    let path = path.with_extension(presets::EXTENSION);
+                    let name = path
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    let preset = Preset::capture(&name, &self.params);
+                    if let Err(e) = preset.save(&path) {
+                        nih_plug::nih_log!("couldn't save the preset to {:?}: {}", path, e);
+                        return;
+                    }
+                    // keep it selected so next/previous continue from there
+                    self.preset_index = self.add_user_preset(preset);
+                    self.preset_name = name;
+                }
+            }
+            ParamChangeEvent::ExportJson() => {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("JSON preset", &["json"])
+                    .set_file_name(&format!("{}.json", self.preset_name))
+                    .save_file()
+                {
+                    if let Err(e) = std::fs::write(&path, self.params.to_json(&self.preset_name)) {
+                        nih_plug::nih_log!("couldn't export the preset to {:?}: {}", path, e);
+                    }
+                }
+            }
+            ParamChangeEvent::ImportJson() => {
+                let path = match rfd::FileDialog::new()
+                    .add_filter("JSON preset", &["json"])
+                    .pick_file()
+                {
+                    Some(path) => path,
+                    None => return,
                 };
+                let preset = std::fs::read_to_string(&path)
+                    .map_err(|e| presets::PresetError::Io(e.to_string()))
+                    .and_then(|json| self.params.from_json(&json));
+                match preset {
+                    Ok(mut preset) => {
+                        // documents written by hand might not have a name
+                        if preset.name.is_empty() {
+                            preset.name = path
+                                .file_stem()
+                                .map(|stem| stem.to_string_lossy().to_string())
+                                .unwrap_or_default();
+                        }
+                        self.apply(&preset);
+                        self.preset_name = preset.name;
+                    }
+                    Err(e) => nih_plug::nih_log!("couldn't import {:?}: {}", path, e),
+                }
             }
-            ParamChangeEvent::ChangeBodeView() => {
-                self.show_phase = !self.show_phase;
+            ParamChangeEvent::SetScale(scale) => {
+                if let Ok(mut editor_scale) = self.params.editor_scale.write() {
+                    *editor_scale = *scale;
+                }
+                self.editor_scale = *scale;
+                cx.set_user_scale_factor(*scale as f64);
+                // the host asks the editor for its new size, and resizes its frame to fit
+                if !self.gui_context.request_resize() {
+                    nih_plug::nih_log!("the host didn't resize the editor");
+                }
+            }
+            ParamChangeEvent::SetModPage(page) => self.mod_page = *page,
+            ParamChangeEvent::SwitchAbSlot() => self.switch_ab_slot(),
+            ParamChangeEvent::CopyAbSlot() => {
+                if let Ok(mut other) = self.params.ab_other_slot.write() {
+                    *other = Preset::capture("", &self.params).to_text();
+                }
+            }
+            ParamChangeEvent::ToggleNoteNames() => {
+                self.note_names = !self.note_names;
+                self.params
+                    .cutoff_note_names
+                    .store(self.note_names, std::sync::atomic::Ordering::Relaxed);
+                save_view_flag(&self.params.note_names, self.note_names);
+            }
+            ParamChangeEvent::ToggleScopeView() => {
+                self.scope_time = !self.scope_time;
+                save_view_flag(&self.params.scope_time, self.scope_time);
+            }
+            ParamChangeEvent::ExportPlot() => {
+                // taken before the dialog opens, so it's what the plot showed when the button was pressed
+                let snapshot = plot_snapshot(&self.params, self.show_phase);
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("SVG image", &["svg"])
+                    .set_file_name("va-filter.svg")
+                    .save_file()
+                {
+                    if let Err(e) = std::fs::write(&path, export::render_svg(&snapshot)) {
+                        nih_plug::nih_log!("couldn't export the plot to {:?}: {}", path, e);
+                    }
+                }
+            }
+        });
+        event.map(|event, _| match event {
+            MeterEvent::DcWarning(warning) => self.dc_warning = *warning,
+            MeterEvent::LimiterReduction(reduction) => self.limiter_reduction = *reduction,
+            MeterEvent::SaturationAmount(amount) => self.saturation_amount = *amount,
+            MeterEvent::OversamplingFactor(factor) => self.oversampling_factor = *factor,
+            MeterEvent::Levels(input, output) => {
+                self.input_levels = *input;
+                self.output_levels = *output;
+                cx.need_redraw();
+            }
+            MeterEvent::Spectrum(view, spectrum) => {
+                self.spectrum_view = *view;
+                self.spectrum.clone_from(spectrum);
+                cx.need_redraw();
+            }
+            MeterEvent::Scope(scope) => {
+                self.scope.clone_from(scope);
+                cx.need_redraw();
+            }
+            MeterEvent::MidiBindings => self.refresh_midi(),
+            MeterEvent::StateLoaded => self.undo.clear(),
+            MeterEvent::Modulation(positions) => {
+                for ((_, ring), position) in self.mod_rings.iter_mut().zip(positions) {
+                    *ring = *position;
+                }
+            }
+        });
+        event.map(|event, _| match event {
+            PresetScanEvent::Found(Scanned::Preset(preset)) => {
+                self.add_user_preset(preset.clone());
+            }
+            PresetScanEvent::Found(Scanned::Invalid(file, error)) => {
+                self.invalid_presets.push(format!("{}: {}", file, error));
+            }
+            PresetScanEvent::Done => self.scanning_presets = false,
+        });
+        event.map(|window_event, _| match window_event {
+            // left to the text box while a value is being typed in
+            WindowEvent::KeyDown(Code::KeyZ, _) if self.text_entry.is_none() => {
+                if !cx.modifiers.contains(Modifiers::CTRL)
+                    && !cx.modifiers.contains(Modifiers::LOGO)
+                {
+                    return;
+                }
+                let values = if cx.modifiers.contains(Modifiers::SHIFT) {
+                    self.undo.redo()
+                } else {
+                    self.undo.undo()
+                };
+                if let Some(values) = values {
+                    self.restore_values(&values);
+                }
             }
+            _ => {}
+        });
+        #[cfg(feature = "dev-ui")]
+        event.map(|event, _| match event {
+            layout::DevUiEvent::ThemeChanged => {
+                if cx.reload_styles().is_err() {
+                    self.layout_error = Some("couldn't reload the theme".to_string());
+                }
+            }
+            // keep the old layout around if the new one is broken, so there's still something to look at
+            layout::DevUiEvent::LayoutChanged => match layout::load_layout() {
+                Ok(layout) => {
+                    self.layout = layout;
+                    self.layout_error = None;
+                }
+                Err(e) => self.layout_error = Some(e.to_string()),
+            },
+        });
+    }
+}
+
+impl UiData {
+    /// Puts a user preset in the list after the factory presets, sorted by name, in place of one with the same name.
+    /// Returns where it went
+    fn add_user_preset(&mut self, preset: Preset) -> usize {
+        let user = presets::factory_count()..self.presets.len();
+        if let Some(i) = self.presets[user.clone()]
+            .iter()
+            .position(|known| known.name == preset.name)
+        {
+            self.presets[user.start + i] = preset;
+            return user.start + i;
+        }
+        let i = user.start
+            + self.presets[user]
+                .iter()
+                .take_while(|known| known.name < preset.name)
+                .count();
+        self.presets.insert(i, preset);
+        // the one that's loaded stays selected
+        if self.preset_index >= i {
+            self.preset_index += 1;
+        }
+        i
+    }
+    /// Sets every parameter of the preset the same way a knob would, so the host records it
+    fn load_preset(&mut self, index: usize) {
+        let preset = self.presets[index].clone();
+        self.apply(&preset);
+        self.preset_name = preset.name;
+    }
+    /// Sets the preset's parameters as one edit
+    fn apply(&mut self, preset: &Preset) {
+        self.undo.begin_group();
+        for (id, value) in &preset.values {
+            if let Some(param_ptr) = presets::param_ptr(&self.params, id) {
+                self.set_once(param_ptr, *value);
+            }
+        }
+        let saturation = &self.params.drive.saturation;
+        let (param_ptr, value) = (
+            saturation.as_ptr(),
+            saturation.preview_normalized(preset.saturation()),
+        );
+        self.set_once(param_ptr, value);
+        self.undo
+            .end(|param_ptr| unsafe { param_ptr.normalized_value() });
+        if let Ok(mut steps) = self.params.seq_steps.write() {
+            *steps = preset.seq_steps;
+        }
+    }
+    /// Starts a gesture on a parameter, the undo history takes note of the value it had before
+    fn begin_set(&mut self, param_ptr: ParamPtr) {
+        unsafe {
+            self.undo.begin(param_ptr, param_ptr.normalized_value());
+            self.gui_context.raw_begin_set_parameter(param_ptr);
+        }
+    }
+    fn end_set(&mut self, param_ptr: ParamPtr) {
+        unsafe { self.gui_context.raw_end_set_parameter(param_ptr) };
+        self.undo
+            .end(|param_ptr| unsafe { param_ptr.normalized_value() });
+    }
+    /// Sets a parameter as a single edit
+    fn set_once(&mut self, param_ptr: ParamPtr, value: f32) {
+        self.begin_set(param_ptr);
+        unsafe {
+            self.gui_context
+                .raw_set_parameter_normalized(param_ptr, value)
+        };
+        self.end_set(param_ptr);
+    }
+    /// Puts back values from the undo history without recording them again. They go through the host like a knob's,
+    /// so an undone circuit switch happens the same way as picking the circuit on its selector
+    fn restore_values(&self, values: &[(ParamPtr, f32)]) {
+        for (param_ptr, value) in values {
+            unsafe {
+                self.gui_context.raw_begin_set_parameter(*param_ptr);
+                self.gui_context
+                    .raw_set_parameter_normalized(*param_ptr, *value);
+                self.gui_context.raw_end_set_parameter(*param_ptr);
+            }
+        }
+    }
+    /// Picks up what `FilterParams::midi_learn` has now
+    fn refresh_midi(&mut self) {
+        self.midi_ccs = learned_ccs(&self.params, &self.param_ptrs);
+        let learning = self.params.midi_learn.learning();
+        self.midi_learning = learning.and_then(|param| self.param_ptrs.get(param).copied());
+    }
+    /// Stashes the current state in the other slot, and loads what was there
+    fn switch_ab_slot(&mut self) {
+        let current = Preset::capture("", &self.params).to_text();
+        let other = match self.params.ab_other_slot.write() {
+            Ok(mut other) => std::mem::replace(&mut *other, current),
+            Err(_) => return,
+        };
+        // an empty slot starts out as a copy, so there's nothing to load
+        if !other.is_empty() {
+            match Preset::parse("", &other) {
+                Ok(preset) => self.apply(&preset),
+                Err(e) => nih_plug::nih_log!("couldn't load the other A/B slot: {}", e),
+            }
+        }
+        self.ab_b_active = !self.ab_b_active;
+        if let Ok(mut b_active) = self.params.ab_b_active.write() {
+            *b_active = self.ab_b_active;
+        }
+    }
+}
+
+/// The knobs the modulation can move, laid out like `ModDestination`
+fn modulated_knobs(params: &FilterParams) -> [&FloatParam; 3] {
+    [
+        &params.filter.cutoff,
+        &params.filter.res,
+        &params.drive.amount,
+    ]
+}
+
+/// Where the ring of a modulated knob goes for the value the modulation has it at.
+/// Right on the knob while nothing modulates it, without the round trip through the range moving it a hair
+fn mod_ring_position(param: &FloatParam, modulated: f32) -> f32 {
+    if modulated == param.value() {
+        param.normalized_value()
+    } else {
+        param.preview_normalized(modulated)
+    }
+}
+
+/// Keeps a toggle of the editor in the plugin state, so it's the same the next time the editor opens
+fn save_view_flag(flag: &std::sync::RwLock<bool>, value: bool) {
+    if let Ok(mut flag) = flag.write() {
+        *flag = value;
+    }
+}
+
+fn learned_ccs(params: &FilterParams, param_ptrs: &[ParamPtr]) -> Vec<(ParamPtr, u8)> {
+    param_ptrs
+        .iter()
+        .enumerate()
+        .filter_map(|(param, param_ptr)| Some((*param_ptr, params.midi_learn.cc(param)?)))
+        .collect()
+}
+
+/// Reads the user's presets in the background, handing them to the editor one at a time as they come in.
+/// The scan stops when the returned guard goes away with the editor
+fn spawn_preset_scan(cx: &mut Context) -> presets::ScanGuard {
+    let mut proxy = cx.get_proxy();
+    presets::spawn_scan(move |scanned| {
+        let event = match scanned {
+            Some(scanned) => PresetScanEvent::Found(scanned),
+            None => PresetScanEvent::Done,
+        };
+        proxy.emit(event).is_ok()
+    })
+}
+
+pub fn plugin_gui(cx: &mut Context, params: Arc<FilterParams>, context: Arc<dyn GuiContext>) {
+    // let _shutup = crate::filter::NewSVF::new(params.clone());
+    let param_ptrs: Vec<ParamPtr> = params
+        .param_map()
+        .into_iter()
+        .map(|(_, param_ptr, _)| param_ptr)
+        .collect();
+    // learning from a closed editor doesn't show anywhere
+    params.midi_learn.cancel_learning();
+
+    UiData {
+        gui_context: context.clone(),
+        params: params.clone(),
+        // host: state.host,
+        // choice: if params.filter.filter_type.value() == Circuits::SVF {
+        //     "SVF".to_string()
+        // } else {
+        //     "Transistor Ladder".to_string()
+        // },
+        show_phase: params
+            .show_phase
+            .read()
+            .map_or(false, |show_phase| *show_phase),
+        dc_warning: false,
+        limiter_reduction: 0.,
+        saturation_amount: 0.,
+        oversampling_factor: params.oversampling_factor.get(),
+        input_levels: Default::default(),
+        output_levels: Default::default(),
+        spectrum: vec![FLOOR_DB; export::PLOT_WIDTH],
+        spectrum_view: AnalyzerView::Spectrum,
+        scope: None,
+        scope_time: params
+            .scope_time
+            .read()
+            .map_or(false, |scope_time| *scope_time),
+        analyze_input: params
+            .analyzer
+            .pre_filter
+            .load(std::sync::atomic::Ordering::Relaxed),
+        analyzer_view: params.analyzer.view(),
+        editor_scale: params.editor_scale(),
+        ab_b_active: params
+            .ab_b_active
+            .read()
+            .map_or(false, |b_active| *b_active),
+        note_names: params
+            .cutoff_note_names
+            .load(std::sync::atomic::Ordering::Relaxed),
+        mod_page: 0,
+        presets: presets::factory_presets(),
+        preset_index: 0,
+        preset_name: "Init".to_string(),
+        invalid_presets: Vec::new(),
+        scanning_presets: true,
+        preset_scan: spawn_preset_scan(cx),
+        text_entry: None,
+        typed_entry: String::new(),
+        midi_ccs: learned_ccs(&params, &param_ptrs),
+        param_ptrs,
+        midi_learning: None,
+        midi_menu: None,
+        mod_rings: modulated_knobs(&params).map(|param| (param.as_ptr(), param.normalized_value())),
+        random_locks: Vec::new(),
+        undo: Default::default(),
+        viewers: [
+            spawn_analyzer(cx, params.clone()),
+            spawn_scope(cx, params.clone()),
+            spawn_meter_watcher(cx, params.clone()),
+        ],
+        #[cfg(feature = "dev-ui")]
+        layout: layout::load_layout().unwrap_or_default(),
+        #[cfg(feature = "dev-ui")]
+        layout_error: layout::load_layout().err().map(|e| e.to_string()),
+        #[cfg(feature = "dev-ui")]
+        layout_watcher: layout::spawn_watcher(cx, params.clone()),
+    }
+    .build(cx);
+
+    #[cfg(feature = "dev-ui")]
+    {
+        // load the theme from disk instead of the compiled-in one, so it can be reloaded
+        if cx.add_stylesheet(layout::theme_path()).is_err() {
+            nih_plug::nih_log!("couldn't load {:?}", layout::theme_path());
+        }
+    }
+
+    VStack::new(cx, |cx| {
+        // the filter and its plot on the left, the modulation on the right, so 100 % fits on a laptop screen
+        HStack::new(cx, |cx| {
+            VStack::new(cx, |cx| {
+                HStack::new(cx, |cx| {
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ParamChangeEvent::StepPreset(-1)),
+                        |cx| Label::new(cx, "<"),
+                    );
+                    Label::new(cx, UiData::preset_name).class("preset_name");
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ParamChangeEvent::StepPreset(1)),
+                        |cx| Label::new(cx, ">"),
+                    );
+                    Binding::new(cx, UiData::scanning_presets, |cx, scanning| {
+                        if scanning.get(cx) {
+                            Label::new(cx, "...").class("preset_scanning");
+                        }
+                    });
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ParamChangeEvent::SavePreset()),
+                        |cx| Label::new(cx, "Save"),
+                    );
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ParamChangeEvent::ImportJson()),
+                        |cx| Label::new(cx, "Import"),
+                    );
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ParamChangeEvent::ExportJson()),
+                        |cx| Label::new(cx, "Export"),
+                    );
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ParamChangeEvent::SwitchAbSlot()),
+                        |cx| {
+                            Label::new(
+                                cx,
+                                UiData::ab_b_active.map(|b| if *b { "B" } else { "A" }.to_string()),
+                            )
+                        },
+                    );
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ParamChangeEvent::CopyAbSlot()),
+                        |cx| {
+                            Label::new(
+                                cx,
+                                UiData::ab_b_active
+                                    .map(|b| if *b { "Copy B>A" } else { "Copy A>B" }.to_string()),
+                            )
+                        },
+                    );
+                })
+                .class("preset_bar");
+                // the user's presets that didn't load, so they don't just go missing from the list
+                Binding::new(cx, UiData::invalid_presets, |cx, invalid| {
+                    for error in invalid.get(cx) {
+                        HStack::new(cx, |cx| {
+                            Label::new(cx, ICON_ATTENTION).class("icon");
+                            Label::new(cx, &error);
+                        })
+                        .class("preset_error");
+                    }
+                });
+                // Filter circuit selection
+                HStack::new(cx, |cx| {
+                    Label::new(cx, "Filter Circuit");
+                    make_enum_selector(cx, params.filter.filter_type.as_ptr(), |params| {
+                        &params.filter.filter_type
+                    });
+                })
+                .class("circuit_selector");
+                HStack::new(cx, |cx| {
+                    Label::new(cx, "Character");
+                    make_enum_selector(cx, params.drive.character.as_ptr(), |params| {
+                        &params.drive.character
+                    });
+                })
+                .class("circuit_selector");
+                HStack::new(cx, |cx| {
+                    Label::new(cx, "Solver");
+                    make_enum_selector(cx, params.drive.solver.as_ptr(), |params| {
+                        &params.drive.solver
+                    });
+                })
+                .class("circuit_selector");
+                make_mode_selector(cx, params.clone());
+
+                Binding::new(cx, UiData::dc_warning, |cx, warning| {
+                    if warning.get(cx) {
+                        VStack::new(cx, |cx| {
+                            HStack::new(cx, |cx| {
+                                Label::new(cx, ICON_ATTENTION).class("icon");
+                                Label::new(cx, "DC offset or subsonic content at the input");
+                            });
+                            // the dc filter in front of the circuits only goes down to ~1 Hz
+                            Label::new(cx, "Try high-passing the source around 10 Hz")
+                                .class("hint");
+                        })
+                        .class("dc_warning");
+                    }
+                });
+
+                Binding::new(cx, UiData::limiter_reduction, |cx, reduction| {
+                    let reduction = reduction.get(cx);
+                    if reduction > 0. {
+                        Label::new(cx, &format!("Output limiter: -{:.1} dB", reduction))
+                            .class("limiter");
+                    }
+                });
+
+                #[cfg(feature = "dev-ui")]
+                Binding::new(cx, UiData::layout_error, |cx, error| {
+                    if let Some(error) = error.get(cx) {
+                        Label::new(cx, &error).class("layout_error");
+                    }
+                });
+
+                // The filter control knobs
+                #[cfg(not(feature = "dev-ui"))]
+                HStack::new(cx, |cx| {
+                    make_knob(
+                        cx,
+                        params.filter.cutoff.as_ptr(),
+                        2. * KNOB_TRAVEL,
+                        |params| &params.filter.cutoff,
+                    );
+                    make_knob(
+                        cx,
+                        params.filter.res.as_ptr(),
+                        1.5 * KNOB_TRAVEL,
+                        |params| &params.filter.res,
+                    );
+                    make_drive_knob(cx, &params);
+                    make_knob(cx, params.filter.antisat.as_ptr(), KNOB_TRAVEL, |params| {
+                        &params.filter.antisat
+                    });
+                    make_knob(cx, params.filter.spread.as_ptr(), KNOB_TRAVEL, |params| {
+                        &params.filter.spread
+                    });
+                    make_mode_knob(cx, params.clone());
+                })
+                .class("knobs");
+                #[cfg(feature = "dev-ui")]
+                let layout_params = params.clone();
+                // rebuilt from scratch whenever the layout file changes
+                #[cfg(feature = "dev-ui")]
+                Binding::new(cx, UiData::layout, move |cx, layout| {
+                    let layout = layout.get(cx);
+                    let params = layout_params.clone();
+                    HStack::new(cx, |cx| {
+                        for id in &layout.knobs {
+                            match id.as_str() {
+                                "cutoff" => make_knob(
+                                    cx,
+                                    params.filter.cutoff.as_ptr(),
+                                    2. * KNOB_TRAVEL,
+                                    |params| &params.filter.cutoff,
+                                ),
+                                "res" => make_knob(
+                                    cx,
+                                    params.filter.res.as_ptr(),
+                                    1.5 * KNOB_TRAVEL,
+                                    |params| &params.filter.res,
+                                ),
+                                "drive" => make_drive_knob(cx, &params),
+                                "antisat" => make_knob(
+                                    cx,
+                                    params.filter.antisat.as_ptr(),
+                                    KNOB_TRAVEL,
+                                    |params| &params.filter.antisat,
+                                ),
+                                "spread" => make_knob(
+                                    cx,
+                                    params.filter.spread.as_ptr(),
+                                    KNOB_TRAVEL,
+                                    |params| &params.filter.spread,
+                                ),
+                                // `validate_layout()` already rejected anything else
+                                _ => continue,
+                            };
+                        }
+                        make_mode_knob(cx, params.clone());
+                    })
+                    .class("knobs");
+                    if layout.show_plot {
+                        make_bode_plot(cx);
+                    }
+                });
+                #[cfg(not(feature = "dev-ui"))]
+                make_bode_plot(cx);
+            })
+            .class("column");
+
+            VStack::new(cx, |cx| {
+                HStack::new(cx, |cx| {
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ParamChangeEvent::InitPatch()),
+                        |cx| Label::new(cx, "Init"),
+                    );
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ParamChangeEvent::Randomize()),
+                        |cx| Label::new(cx, "Random"),
+                    );
+                    // what the random button keeps
+                    Label::new(cx, "Lock");
+                    for id in presets::RANDOMIZED {
+                        let name =
+                            presets::param_ptr(&params, id).map_or(id, |ptr| unsafe { ptr.name() });
+                        Button::new(
+                            cx,
+                            move |cx| cx.emit(ParamChangeEvent::ToggleRandomLock(id)),
+                            move |cx| Label::new(cx, name),
+                        )
+                        .checked(UiData::random_locks.map(move |locks| locks.contains(&id)));
+                    }
+                })
+                .class("random_bar");
+                #[cfg(not(feature = "dev-ui"))]
+                make_scope(cx);
+                #[cfg(feature = "dev-ui")]
+                Binding::new(
+                    cx,
+                    UiData::layout.map(|layout| layout.show_plot),
+                    |cx, show_plot| {
+                        if show_plot.get(cx) {
+                            make_scope(cx);
+                        }
+                    },
+                );
+                // one page of the modulation at a time
+                HStack::new(cx, |cx| {
+                    for (page, name) in MOD_PAGES.iter().copied().enumerate() {
+                        Button::new(
+                            cx,
+                            move |cx| cx.emit(ParamChangeEvent::SetModPage(page)),
+                            move |cx| Label::new(cx, name),
+                        )
+                        .checked(UiData::mod_page.map(move |current| *current == page));
+                    }
+                })
+                .class("mod_tabs");
+                let page_params = params.clone();
+                Binding::new(cx, UiData::mod_page, move |cx, page| {
+                    make_mod_page(cx, page_params.clone(), page.get(cx));
+                });
+                HStack::new(cx, |cx| {
+                    Label::new(cx, "Size");
+                    for scale in crate::editor::SCALES {
+                        Button::new(
+                            cx,
+                            move |cx| cx.emit(ParamChangeEvent::SetScale(scale)),
+                            move |cx| Label::new(cx, &format!("{:.0} %", scale * 100.)),
+                        )
+                        .checked(UiData::editor_scale.map(move |current| *current == scale));
+                    }
+                })
+                .class("scale_selector");
+            })
+            .class("column");
         })
+        .class("columns");
+
+        LevelMeterView::new(cx, false)
+            .class("level_meter")
+            .class("input_meter");
+        LevelMeterView::new(cx, true)
+            .class("level_meter")
+            .class("output_meter");
+    })
+    .class("container");
+}
+
+/// One page of the modulation column, by its index in `MOD_PAGES`
+fn make_mod_page(cx: &mut Context, params: Arc<FilterParams>, page: usize) {
+    VStack::new(cx, |cx| match page {
+        0 => {
+            // step sequencer modulating the cutoff
+            HStack::new(cx, |cx| {
+                make_knob(
+                    cx,
+                    params.modulation.seq_depth.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.seq_depth,
+                );
+                make_steppy_knob(
+                    cx,
+                    6,
+                    270.,
+                    params.modulation.seq_division.as_ptr(),
+                    |params| &params.modulation.seq_division,
+                );
+                make_knob(
+                    cx,
+                    params.modulation.seq_swing.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.seq_swing,
+                );
+                make_knob(
+                    cx,
+                    params.modulation.seq_glide.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.seq_glide,
+                );
+            })
+            .class("knobs");
+            StepEditor::new(cx, params.clone()).class("steps");
+        }
+        1 => {
+            // envelope from incoming notes
+            HStack::new(cx, |cx| {
+                make_knob(
+                    cx,
+                    params.modulation.env_attack.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.env_attack,
+                );
+                make_knob(
+                    cx,
+                    params.modulation.env_decay.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.env_decay,
+                );
+                make_knob(
+                    cx,
+                    params.modulation.env_sustain.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.env_sustain,
+                );
+                make_knob(
+                    cx,
+                    params.modulation.env_release.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.env_release,
+                );
+                make_knob(
+                    cx,
+                    params.modulation.env_amount.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.env_amount,
+                );
+            })
+            .class("knobs");
+            HStack::new(cx, |cx| {
+                make_steppy_knob(
+                    cx,
+                    2,
+                    90.,
+                    params.modulation.env_trigger.as_ptr(),
+                    |params| &params.modulation.env_trigger,
+                );
+                make_steppy_knob(cx, 2, 90., params.modulation.retrigger.as_ptr(), |params| {
+                    &params.modulation.retrigger
+                });
+            })
+            .class("knobs");
+        }
+        2 => {
+            // cutoff control from a keyboard's pitch bend and channel pressure and the mod wheel
+            HStack::new(cx, |cx| {
+                make_knob(
+                    cx,
+                    params.modulation.bend_range.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.bend_range,
+                );
+                make_knob(
+                    cx,
+                    params.modulation.aftertouch.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.aftertouch,
+                );
+                make_knob(
+                    cx,
+                    params.modulation.mod_wheel_depth.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.mod_wheel_depth,
+                );
+                make_cycle_button(cx, params.modulation.mod_wheel_target.as_ptr(), |params| {
+                    &params.modulation.mod_wheel_target
+                });
+                make_knob(
+                    cx,
+                    params.modulation.glide.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.modulation.glide,
+                );
+            })
+            .class("knobs");
+            // and from the right channel with fm
+            HStack::new(cx, |cx| {
+                make_steppy_knob(cx, 2, 90., params.filter.fm.as_ptr(), |params| {
+                    &params.filter.fm
+                });
+                make_knob(
+                    cx,
+                    params.filter.fm_amount.as_ptr(),
+                    KNOB_TRAVEL,
+                    |params| &params.filter.fm_amount,
+                );
+            })
+            .class("knobs");
+        }
+        _ => {
+            make_mod_matrix(cx, params.clone());
+            make_macro(cx, &params);
+        }
+    })
+    .class("mod_page");
+}
+
+/// Polls the values the audio thread reports back and forwards changes to the gui.
+/// The saturation and level meters move about 30 times a second, the rest only needs a look every 250 ms.
+/// It also keeps the saved midi bindings up to date.
+/// The thread stops once the returned guard is dropped with the editor, or the events can't be delivered anymore
+fn spawn_meter_watcher(cx: &mut Context, params: Arc<FilterParams>) -> ViewerGuard {
+    let mut proxy = cx.get_proxy();
+    // nothing on the audio side waits for a viewer here, the guard is only for the open flag
+    let guard = ViewerGuard::new(params.clone(), |_| {}, |_| {});
+    let open = guard.open();
+    std::thread::spawn(move || {
+        let mut dc_warning = false;
+        let mut limiter_reduction = 0.;
+        let mut saturation_amount: f32 = 0.;
+        let mut oversampling_factor = params.oversampling_factor.get();
+        let mut input_levels = [MeterBallistics::default(); 2];
+        let mut output_levels = [MeterBallistics::default(); 2];
+        let mut midi_version = params.midi_learn.version();
+        let mut state_loads = params.state_loads.get();
+        let mut mod_positions = [0.; 3];
+        for frame in 0usize.. {
+            std::thread::sleep(std::time::Duration::from_secs_f32(METER_INTERVAL));
+            if !open.load(std::sync::atomic::Ordering::Relaxed) {
+                break;
+            }
+            // taken and cleared like the limiter, and falls off smoothly from the peaks
+            let new_amount = params
+                .saturation_amount
+                .get()
+                .max(saturation_amount * SATURATION_METER_DECAY);
+            params.saturation_amount.set(0.);
+            // snaps to 0 at the end, so it reads empty while the plugin is idle
+            let new_amount = if new_amount < 1e-3 { 0. } else { new_amount };
+            if new_amount != saturation_amount {
+                saturation_amount = new_amount;
+                if proxy
+                    .emit(MeterEvent::SaturationAmount(saturation_amount))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            // keeps falling while nothing is processed, and only goes quiet once both meters are empty
+            let idle = input_levels
+                .iter()
+                .chain(output_levels.iter())
+                .all(|levels| *levels == MeterBallistics::default());
+            for (meter, levels) in [
+                (&params.input_meter, &mut input_levels),
+                (&params.output_meter, &mut output_levels),
+            ] {
+                let new_levels = meter.take();
+                for (channel, levels) in levels.iter_mut().enumerate() {
+                    levels.update(new_levels.map(|l| l[channel]), METER_INTERVAL);
+                }
+            }
+            let new_idle = input_levels
+                .iter()
+                .chain(output_levels.iter())
+                .all(|levels| *levels == MeterBallistics::default());
+            if !(idle && new_idle)
+                && proxy
+                    .emit(MeterEvent::Levels(input_levels, output_levels))
+                    .is_err()
+            {
+                break;
+            }
+            // the audio thread learns ccs without touching the state, that's left to here
+            let new_midi_version = params.midi_learn.version();
+            if new_midi_version != midi_version {
+                midi_version = new_midi_version;
+                params.save_midi_map();
+                if proxy.emit(MeterEvent::MidiBindings).is_err() {
+                    break;
+                }
+            }
+            let new_state_loads = params.state_loads.get();
+            if new_state_loads != state_loads {
+                state_loads = new_state_loads;
+                if proxy.emit(MeterEvent::StateLoaded).is_err() {
+                    break;
+                }
+            }
+            let mut new_positions = [0.; 3];
+            for ((position, param), modulated) in new_positions
+                .iter_mut()
+                .zip(modulated_knobs(&params))
+                .zip(&params.modulated)
+            {
+                *position = mod_ring_position(param, modulated.get());
+            }
+            if new_positions != mod_positions {
+                mod_positions = new_positions;
+                if proxy.emit(MeterEvent::Modulation(mod_positions)).is_err() {
+                    break;
+                }
+            }
+            if frame % 8 != 0 {
+                continue;
+            }
+            let new_dc_warning = params
+                .input_dc_warning
+                .load(std::sync::atomic::Ordering::Relaxed);
+            if new_dc_warning != dc_warning {
+                dc_warning = new_dc_warning;
+                if proxy.emit(MeterEvent::DcWarning(dc_warning)).is_err() {
+                    break;
+                }
+            }
+            // taken and cleared, so it's the peak since the last look
+            let new_reduction = params.limiter_reduction.get();
+            params.limiter_reduction.set(0.);
+            if new_reduction != limiter_reduction {
+                limiter_reduction = new_reduction;
+                if proxy
+                    .emit(MeterEvent::LimiterReduction(limiter_reduction))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            let new_factor = params.oversampling_factor.get();
+            if new_factor != oversampling_factor {
+                oversampling_factor = new_factor;
+                if proxy
+                    .emit(MeterEvent::OversamplingFactor(oversampling_factor))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        }
+        // nothing can show a cc being learned anymore, and one that came in since the last look still gets saved
+        params.midi_learn.cancel_learning();
+        if params.midi_learn.version() != midi_version {
+            params.save_midi_map();
+        }
+    });
+    guard
+}
+
+/// Registers the editor as a viewer of the analyzer's or the scope's tap, which the audio thread only feeds while
+/// they have one. It's kept in `UiData`, so it goes away with the editor's view: that unregisters the viewer and
+/// stops the thread handing the data to the editor, whether or not that thread still had anything to send.
+/// The meters' and the dev-ui files' watchers have no tap to register for and only use the open flag
+struct ViewerGuard {
+    params: Arc<FilterParams>,
+    remove_viewer: fn(&FilterParams),
+    open: Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl ViewerGuard {
+    fn new(
+        params: Arc<FilterParams>,
+        add_viewer: fn(&FilterParams),
+        remove_viewer: fn(&FilterParams),
+    ) -> Self {
+        add_viewer(&params);
+        Self {
+            params,
+            remove_viewer,
+            open: Arc::new(std::sync::atomic::AtomicBool::new(true)),
+        }
+    }
+    /// Stays true until the guard is dropped, for the thread to check before each update
+    fn open(&self) -> Arc<std::sync::atomic::AtomicBool> {
+        self.open.clone()
+    }
+}
+
+impl Drop for ViewerGuard {
+    fn drop(&mut self) {
+        self.open.store(false, std::sync::atomic::Ordering::Relaxed);
+        (self.remove_viewer)(&self.params);
+    }
+}
+
+/// Computes the spectrum of what the audio thread puts in the analyzer's tap, about 30 times a second.
+/// The tap is only fed, and the thread only runs, while the returned guard is alive
+fn spawn_analyzer(cx: &mut Context, params: Arc<FilterParams>) -> ViewerGuard {
+    let mut proxy = cx.get_proxy();
+    let guard = ViewerGuard::new(
+        params.clone(),
+        |params| params.analyzer.add_viewer(),
+        |params| params.analyzer.remove_viewer(),
+    );
+    let open = guard.open();
+    std::thread::spawn(move || {
+        let mut spectrum = Spectrum::new(export::PLOT_WIDTH);
+        loop {
+            std::thread::sleep(std::time::Duration::from_millis(33));
+            if !open.load(std::sync::atomic::Ordering::Relaxed) {
+                return;
+            }
+            spectrum.update(&params.analyzer);
+            if proxy
+                .emit(MeterEvent::Spectrum(spectrum.view, spectrum.values.clone()))
+                .is_err()
+            {
+                return;
+            }
+        }
+    });
+    guard
+}
+
+/// Takes the scope's snippets as the audio thread hands them over, a few times a second.
+/// Like the analyzer, the tap is only fed while the returned guard is alive
+fn spawn_scope(cx: &mut Context, params: Arc<FilterParams>) -> ViewerGuard {
+    let mut proxy = cx.get_proxy();
+    let guard = ViewerGuard::new(
+        params.clone(),
+        |params| params.scope.add_viewer(),
+        |params| params.scope.remove_viewer(),
+    );
+    let open = guard.open();
+    std::thread::spawn(move || {
+        let (mut input, mut output) = ([0.; SCOPE_LEN], [0.; SCOPE_LEN]);
+        let mut silent = true;
+        let mut waited = 0.;
+        loop {
+            std::thread::sleep(std::time::Duration::from_secs_f32(METER_INTERVAL));
+            if !open.load(std::sync::atomic::Ordering::Relaxed) {
+                return;
+            }
+            let scope = if params.scope.read(&mut input, &mut output) {
+                waited = 0.;
+                let peak = input
+                    .iter()
+                    .chain(output.iter())
+                    .fold(0., |max: f32, x| max.max(x.abs()));
+                if peak < SCOPE_SILENCE {
+                    None
+                } else {
+                    Some((input.to_vec(), output.to_vec()))
+                }
+            } else {
+                // no snippets at all when the host stops processing
+                waited += METER_INTERVAL;
+                if waited < SCOPE_TIMEOUT || silent {
+                    continue;
+                }
+                None
+            };
+            if scope.is_none() && silent {
+                continue;
+            }
+            silent = scope.is_none();
+            if proxy.emit(MeterEvent::Scope(scope)).is_err() {
+                return;
+            }
+        }
+    });
+    guard
+}
+
+// the slope and bp norm knobs for the svf, the response, fat and feedback drive knobs for the ladder, or the comb's polarity
+fn make_mode_knob(cx: &mut Context, params: Arc<FilterParams>) {
+    Binding::new(
+        cx,
+        UiData::params.map(|params| params.filter.filter_type.value() as usize),
+        move |cx, ft| match ft.get(cx) {
+            0 => {
+                make_steppy_knob(cx, 3, 270., params.filter.svf_slope.as_ptr(), |params| {
+                    &params.filter.svf_slope
+                });
+                make_steppy_knob(cx, 2, 90., params.filter.bp_norm.as_ptr(), |params| {
+                    &params.filter.bp_norm
+                });
+            }
+            1 => {
+                make_steppy_knob(
+                    cx,
+                    6,
+                    270.,
+                    params.filter.ladder_response.as_ptr(),
+                    |params| &params.filter.ladder_response,
+                );
+                make_steppy_knob(cx, 2, 90., params.filter.fat.as_ptr(), |params| {
+                    &params.filter.fat
+                });
+                make_knob(cx, params.drive.feedback.as_ptr(), KNOB_TRAVEL, |params| {
+                    &params.drive.feedback
+                });
+            }
+            3 => {
+                make_steppy_knob(cx, 2, 90., params.filter.comb_negative.as_ptr(), |params| {
+                    &params.filter.comb_negative
+                });
+            }
+            _ => (),
+        },
+    );
+}
+
+// the svf's mode, or the ladder's slope. The sallen-key and the comb only have the one response
+fn make_mode_selector(cx: &mut Context, params: Arc<FilterParams>) {
+    Binding::new(
+        cx,
+        UiData::params.map(|params| params.filter.filter_type.value().to_index()),
+        move |cx, ft| match Circuits::from_index(ft.get(cx)) {
+            Circuits::SVF => {
+                make_enum_selector(cx, params.filter.mode.as_ptr(), |params| {
+                    &params.filter.mode
+                });
+            }
+            Circuits::Ladder => {
+                make_enum_selector(cx, params.filter.slope.as_ptr(), |params| {
+                    &params.filter.slope
+                });
+            }
+            Circuits::SallenKey | Circuits::Comb => (),
+        },
+    );
+}
+
+// the modulation matrix, a column for each slot
+fn make_mod_matrix(cx: &mut Context, params: Arc<FilterParams>) {
+    HStack::new(cx, |cx| {
+        for slot in 0..MOD_SLOTS {
+            let (source, destination, depth) = params.mod_slot(slot);
+            VStack::new(cx, |cx| {
+                make_cycle_button(cx, source.as_ptr(), move |params| params.mod_slot(slot).0);
+                make_cycle_button(cx, destination.as_ptr(), move |params| {
+                    params.mod_slot(slot).1
+                });
+                make_knob(cx, depth.as_ptr(), KNOB_TRAVEL, move |params| {
+                    params.mod_slot(slot).2
+                });
+            })
+            .class("mod_slot");
+        }
+    })
+    .class("mod_matrix");
+}
+
+/// A button showing the value of a discrete parameter, which steps to the next one on every click
+fn make_cycle_button<P, F>(
+    cx: &mut Context,
+    param_ptr: ParamPtr,
+    params_to_param: F,
+) -> Handle<Button>
+where
+    P: Param,
+    F: 'static + Fn(&Arc<FilterParams>) -> &P + Copy,
+{
+    Button::new(
+        cx,
+        move |cx| cx.emit(ParamChangeEvent::CycleParam(param_ptr)),
+        move |cx| {
+            Label::new(
+                cx,
+                UiData::params.map(move |params| params_to_param(params).to_string()),
+            )
+        },
+    )
+}
+
+/// A row of buttons, one for each variant of an enum parameter, with the active one highlighted.
+/// Clicking one sets the parameter to it as a single edit, and leaves the row focused for the arrow keys
+fn make_enum_selector<T, F>(
+    cx: &mut Context,
+    param_ptr: ParamPtr,
+    params_to_param: F,
+) -> Handle<EnumSelector>
+where
+    T: Enum + 'static,
+    F: 'static + Fn(&Arc<FilterParams>) -> &EnumParam<T> + Copy,
+{
+    EnumSelector::new(cx, param_ptr, move |cx| {
+        let variants = T::variants();
+        let last = (variants.len() - 1).max(1) as f32;
+        for (idx, name) in variants.iter().enumerate() {
+            Button::new(
+                cx,
+                move |cx| {
+                    cx.emit(ParamChangeEvent::BeginSet(param_ptr));
+                    cx.emit(ParamChangeEvent::SetParam(param_ptr, idx as f32 / last));
+                    cx.emit(ParamChangeEvent::EndSet(param_ptr));
+                },
+                move |cx| Label::new(cx, *name),
+            )
+            .checked(
+                UiData::params.map(move |params| params_to_param(params).value().to_index() == idx),
+            );
+        }
+    })
+    .layout_type(LayoutType::Row)
+    .class("enum_selector")
+}
+
+/// Holds the buttons of `make_enum_selector()`, and steps through the variants with the arrow keys while focused
+pub struct EnumSelector {
+    param_ptr: ParamPtr,
+}
+
+impl EnumSelector {
+    pub fn new(
+        cx: &mut Context,
+        param_ptr: ParamPtr,
+        content: impl FnOnce(&mut Context),
+    ) -> Handle<Self> {
+        Self { param_ptr }.build(cx, content)
+    }
+}
+
+impl View for EnumSelector {
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, meta| match window_event {
+            // the buttons are what's clicked, this only takes the focus
+            WindowEvent::MouseDown(MouseButton::Left) => cx.focus(),
+            WindowEvent::KeyDown(code, _) => {
+                if let Some(value) = key_value(self.param_ptr, *code, 1.) {
+                    cx.emit(ParamChangeEvent::BeginSet(self.param_ptr));
+                    cx.emit(ParamChangeEvent::SetParam(self.param_ptr, value));
+                    cx.emit(ParamChangeEvent::EndSet(self.param_ptr));
+                    meta.consume();
+                }
+            }
+            _ => (),
+        });
     }
 }
 
-pub fn plugin_gui(cx: &mut Context, params: Arc<FilterParams>, context: Arc<dyn GuiContext>) {
-    // let _shutup = crate::filter::NewSVF::new(params.clone());
-
-    UiData {
-        gui_context: context.clone(),
-        params: params.clone(),
-        // host: state.host,
-        filter_circuits: vec![
-            "SVF".to_string(),
-            "Transistor Ladder".to_string(),
-            "SallenKey".to_string(),
-        ],
-        // choice: if params.filter_type.value() == Circuits::SVF {
-        //     "SVF".to_string()
-        // } else {
-        //     "Transistor Ladder".to_string()
-        // },
-        show_phase: false,
-    }
-    .build(cx);
-
-    VStack::new(cx, |cx| {
-        // Filter circuit selection
-        HStack::new(cx, |cx| {
-            Label::new(cx, "Filter Circuit");
-            // Dropdown to select filter circuit
-            Dropdown::new(
+/// Where a key takes a focused control, as a normalized value, or `None` for keys that don't do anything.
+/// The arrows nudge, page up and down move further and home and end go to either end. Discrete parameters move
+/// a whole step for the arrows and the page keys alike
+fn key_value(param_ptr: ParamPtr, code: Code, fine: f32) -> Option<f32> {
+    let current = unsafe { param_ptr.normalized_value() };
+    let steps = unsafe { param_ptr.step_count() }.filter(|steps| *steps > 0);
+    let (nudge, coarse) = match steps {
+        Some(steps) => (1. / steps as f32, 1. / steps as f32),
+        None => (KEY_STEP * fine, KEY_COARSE_STEP),
+    };
+    let value = match code {
+        Code::ArrowUp | Code::ArrowRight => current + nudge,
+        Code::ArrowDown | Code::ArrowLeft => current - nudge,
+        Code::PageUp => current + coarse,
+        Code::PageDown => current - coarse,
+        Code::Home => 0.,
+        Code::End => 1.,
+        _ => return None,
+    }
+    .clamp(0., 1.);
+    Some(match steps {
+        Some(steps) => (value * steps as f32).round() / steps as f32,
+        None => value,
+    })
+}
+
+fn make_bode_plot(cx: &mut Context) {
+    BodePlot::new(cx).class("bode").on_press(|cx| {
+        cx.emit(ParamChangeEvent::ChangeBodeView());
+    });
+    HStack::new(cx, |cx| {
+        Button::new(
+            cx,
+            |cx| cx.emit(ParamChangeEvent::ExportPlot()),
+            |cx| Label::new(cx, "Export image"),
+        );
+        // pre/post filter analysis
+        Button::new(
+            cx,
+            |cx| cx.emit(ParamChangeEvent::ToggleAnalyzerSource()),
+            |cx| {
+                Label::new(
+                    cx,
+                    UiData::analyze_input.map(|input| {
+                        if *input {
+                            "Spectrum: input".to_string()
+                        } else {
+                            "Spectrum: output".to_string()
+                        }
+                    }),
+                )
+            },
+        );
+        // the spectrum, or how different the channels are for tuning the spread
+        Button::new(
+            cx,
+            |cx| cx.emit(ParamChangeEvent::CycleAnalyzerView()),
+            |cx| {
+                Label::new(
+                    cx,
+                    UiData::analyzer_view.map(|view| {
+                        match view {
+                            AnalyzerView::Spectrum => "Abs",
+                            AnalyzerView::LeftRight => "L - R",
+                            AnalyzerView::MidSide => "S - M",
+                        }
+                        .to_string()
+                    }),
+                )
+            },
+        );
+        // the cutoff in Hz only, or with the nearest note
+        Button::new(
+            cx,
+            |cx| cx.emit(ParamChangeEvent::ToggleNoteNames()),
+            |cx| {
+                Label::new(
+                    cx,
+                    UiData::note_names.map(|notes| {
+                        if *notes {
+                            "Cutoff: note".to_string()
+                        } else {
+                            "Cutoff: Hz".to_string()
+                        }
+                    }),
+                )
+            },
+        );
+    })
+    .class("plot_buttons");
+}
+
+/// The scope, switched between the transfer curve and the time domain by clicking it
+fn make_scope(cx: &mut Context) {
+    ScopeView::new(cx).class("scope").on_press(|cx| {
+        cx.emit(ParamChangeEvent::ToggleScopeView());
+    });
+}
+// makes a knob linked to a parameter
+/// The drive knob, with a bar under it showing how far the circuit goes into its saturation, and the auto
+/// oversampling switch with the factor the circuits are running at
+fn make_drive_knob(cx: &mut Context, params: &FilterParams) -> Handle<VStack> {
+    let param_ptr = params.drive.amount.as_ptr();
+    let auto_ptr = params.drive.auto_oversampling.as_ptr();
+    VStack::new(cx, move |cx| {
+        make_knob(cx, param_ptr, KNOB_TRAVEL, |params| &params.drive.amount);
+        Binding::new(cx, UiData::saturation_amount, |cx, amount| {
+            Element::new(cx)
+                .width(Pixels(SATURATION_METER_WIDTH * amount.get(cx)))
+                .class("saturation_meter");
+        });
+        HStack::new(cx, move |cx| {
+            Label::new(cx, "Auto OS");
+            make_cycle_button(cx, auto_ptr, |params| &params.drive.auto_oversampling);
+            Label::new(
                 cx,
-                move |cx|
-                // A Label and an Icon
-                HStack::new(cx, move |cx|{
-                    Label::new(cx, UiData::params.map(|p| p.filter_type.to_string())).left(Auto);
-                    Label::new(cx, ICON_DOWN_OPEN).class("arrow");
-                }).class("title"),
-                move |cx| {
-                    // List of options
-                    List::new(cx, UiData::filter_circuits, move |cx, idx, item| {
-                        VStack::new(cx, move |cx| {
-                            Binding::new(
-                                cx,
-                                UiData::params.map(|p| p.filter_type.to_string()),
-                                move |cx, choice| {
-                                    let selected = *item.get(cx) == *choice.get(cx);
-                                    Label::new(cx, &item.get(cx))
-                                        .width(Stretch(1.0))
-                                        .background_color(if selected {
-                                            Color::from("#c28919")
-                                        } else {
-                                            Color::transparent()
-                                        })
-                                        .on_press(move |cx| {
-                                            cx.emit(ParamChangeEvent::CircuitEvent(idx));
-                                            cx.emit(views::PopupEvent::Close);
-                                        });
-                                },
-                            );
-                        });
-                    });
-                },
+                UiData::oversampling_factor.map(|factor| format!("{}x", factor)),
             );
         })
-        .class("circuit_selector");
+        .class("oversampling");
+    })
+    .class("drive_knob")
+}
 
-        // The filter control knobs
-        HStack::new(cx, |cx| {
-            // UiData::params.filter_type;
-            // Cutoff
-            // make_knob(cx, 0);
-            make_knob(cx, params.cutoff.as_ptr(), |params| &params.cutoff);
-            // Resonance
-            // make_knob(cx, 1);
-            make_knob(cx, params.res.as_ptr(), |params| &params.res);
-            // // Drive
-            make_knob(cx, params.drive.as_ptr(), |params| &params.drive);
-            // Mode/ Slope
-            Binding::new(
-                cx,
-                UiData::params.map(|params| params.filter_type.value() as usize),
-                move |cx, ft| match ft.get(cx) {
-                    0 => {
-                        let steps = 5;
-                        make_steppy_knob(cx, steps, 270., params.mode.as_ptr(), |params| {
-                            &params.mode
-                        });
-                    }
-                    1 => {
-                        let steps = 4;
-                        make_steppy_knob(cx, steps, 270., params.slope.as_ptr(), |params| {
-                            &params.slope
-                        });
-                    }
-                    _ => (),
-                },
-            );
+/// The macro as a big knob, with its depths as small ones on either side
+fn make_macro(cx: &mut Context, params: &FilterParams) -> Handle<HStack> {
+    let modulation = &params.modulation;
+    let (cutoff, res, drive) = (
+        modulation.macro_cutoff.as_ptr(),
+        modulation.macro_res.as_ptr(),
+        modulation.macro_drive.as_ptr(),
+    );
+    let amount = modulation.macro_amount.as_ptr();
+    HStack::new(cx, move |cx| {
+        make_knob(cx, cutoff, KNOB_TRAVEL, |params| {
+            &params.modulation.macro_cutoff
         })
-        .class("knobs");
-
-        BodePlot::new(cx).class("bode").on_press(|cx| {
-            cx.emit(ParamChangeEvent::ChangeBodeView());
-        });
+        .class("macro_depth");
+        make_knob(cx, res, KNOB_TRAVEL, |params| &params.modulation.macro_res).class("macro_depth");
+        make_knob(cx, amount, 1.5 * KNOB_TRAVEL, |params| {
+            &params.modulation.macro_amount
+        })
+        .class("macro_knob");
+        make_knob(cx, drive, KNOB_TRAVEL, |params| {
+            &params.modulation.macro_drive
+        })
+        .class("macro_depth");
     })
-    .class("container");
+    .class("knobs")
 }
-// makes a knob linked to a parameter
+
 // fn make_knob<'a, P: Param>(cx: &mut Context, param: &'a P, setter: &'a ParamSetter<'a>) // -> Handle<VStack>
-fn make_knob<P, F>(cx: &mut Context, param_ptr: ParamPtr, params_to_param: F) -> Handle<VStack>
+/// `travel` is how many pixels of dragging it takes to go through the whole range
+fn make_knob<P, F>(
+    cx: &mut Context,
+    param_ptr: ParamPtr,
+    travel: f32,
+    params_to_param: F,
+) -> Handle<VStack>
 where
     P: Param,
     F: 'static + Fn(&Arc<FilterParams>) -> &P + Copy,
@@ -202,67 +1756,57 @@ where
             UiData::params.map(move |params| params_to_param(params).name().to_owned()),
         );
 
-        Knob::custom(
-            cx,
-            // UiData::params.get(cx).get_parameter_default(param_index),
-            0.5,
-            // params.get(cx).get_parameter(param_index),
-            UiData::params.map(move |params| params_to_param(params).normalized_value()),
-            move |cx, lens| {
-                TickKnob::new(
-                    cx,
-                    Percentage(80.0),
-                    // Percentage(20.0),
-                    Pixels(4.),
-                    Percentage(50.0),
-                    270.0,
-                    KnobMode::Continuous,
-                )
-                .value(lens.clone())
-                .class("tick");
-                ArcTrack::new(
-                    cx,
-                    false,
-                    Percentage(100.0),
-                    Percentage(10.),
-                    -135.,
-                    135.,
-                    KnobMode::Continuous,
-                )
-                .value(lens)
-                .class("track")
-            },
-        )
-        .on_changing(move |cx, val| {
-            cx.emit(
-                // setter.set_parameter_normalized(param, val);
-                // ParamChangeEvent::AllParams(param_index, val),
-                ParamChangeEvent::SetParam(param_ptr, val),
+        ParamKnob::new(cx, param_ptr, 0, travel, move |cx| {
+            let lens = UiData::params.map(move |params| params_to_param(params).normalized_value());
+            TickKnob::new(
+                cx,
+                Percentage(80.0),
+                // Percentage(20.0),
+                Pixels(4.),
+                Percentage(50.0),
+                270.0,
+                KnobMode::Continuous,
             )
-        })
-        .on_press(move |cx| {
-            cx.emit(
-                // setter.set_parameter_normalized(param, val);
-                ParamChangeEvent::BeginSet(param_ptr),
+            .value(lens.clone())
+            .class("tick");
+            ArcTrack::new(
+                cx,
+                false,
+                Percentage(100.0),
+                Percentage(10.),
+                -135.,
+                135.,
+                KnobMode::Continuous,
             )
-        })
-        .on_release(move |cx| {
-            cx.emit(
-                // setter.set_parameter_normalized(param, val);
-                ParamChangeEvent::EndSet(param_ptr),
+            .value(lens)
+            .class("track");
+            // a thin ring for where the modulation has it, on top of the track when nothing modulates it.
+            // Knobs the modulation can't move don't have one
+            ArcTrack::new(
+                cx,
+                false,
+                Percentage(100.0),
+                Percentage(4.),
+                -135.,
+                135.,
+                KnobMode::Continuous,
             )
+            .value(UiData::mod_rings.map(move |rings| {
+                rings
+                    .iter()
+                    .find(|(ptr, _)| *ptr == param_ptr)
+                    .map_or(0., |(_, position)| *position)
+            }))
+            .class("mod_ring");
         });
 
-        Label::new(
-            cx,
-            UiData::params.map(move |params| params_to_param(params).to_string()),
-        )
-        .width(Pixels(100.));
+        ValueLabel::new(cx, param_ptr, params_to_param);
+        make_midi_learn(cx, param_ptr);
     })
     .child_space(Stretch(1.0))
     .row_between(Pixels(10.0))
 }
-// using Knob::custom() to make a stepped knob with tickmarks indicating the steps
+// a stepped knob with tickmarks indicating the steps. Dragging snaps from one step to the next
 fn make_steppy_knob<P, F>(
     cx: &mut Context,
     steps: usize,
@@ -279,14 +1823,14 @@ fn make_steppy_knob<P, F>(
             UiData::params.map(move |params| params_to_param(params).name().to_owned()),
         );
 
-        Knob::custom(
+        ParamKnob::new(
             cx,
-            0.5,
-            // UiData::params.map(move |params| {
-            //     params.get_parameter(param_index)
-            // }),
-            UiData::params.map(move |params| params_to_param(params).normalized_value()),
-            move |cx, lens| {
+            param_ptr,
+            steps,
+            steps as f32 * STEP_TRAVEL,
+            move |cx| {
+                let lens =
+                    UiData::params.map(move |params| params_to_param(params).normalized_value());
                 let mode = KnobMode::Discrete(steps);
                 Ticks::new(
                     cx,
@@ -307,24 +1851,253 @@ fn make_steppy_knob<P, F>(
                     mode,
                 )
                 .value(lens)
-                .class("tick")
+                .class("tick");
             },
-        )
-        .on_changing(move |cx, val| cx.emit(ParamChangeEvent::SetParam(param_ptr, val)))
-        .on_press(move |cx| cx.emit(ParamChangeEvent::BeginSet(param_ptr)))
-        .on_release(move |cx| cx.emit(ParamChangeEvent::EndSet(param_ptr)));
+        );
 
-        Label::new(
-            cx,
-            // UiData::params.map(move |params| params.get_parameter_text(param_index)),
-            UiData::params.map(move |params| params_to_param(params).to_string()),
-        )
-        .width(Pixels(100.));
+        ValueLabel::new(cx, param_ptr, params_to_param);
+        make_midi_learn(cx, param_ptr);
     })
     .child_space(Stretch(1.0))
     .row_between(Pixels(10.0));
 }
 
+/// The learned cc under a knob, and the menu right clicking the knob opens
+fn make_midi_learn(cx: &mut Context, param_ptr: ParamPtr) {
+    Binding::new(
+        cx,
+        UiData::midi_learning.map(move |learning| *learning == Some(param_ptr)),
+        move |cx, learning| {
+            if learning.get(cx) {
+                Label::new(cx, "Learning...").class("midi_cc");
+                return;
+            }
+            // only takes up room under the knob once there's a cc to show
+            Binding::new(
+                cx,
+                UiData::midi_ccs.map(move |ccs| {
+                    ccs.iter()
+                        .find(|(ptr, _)| *ptr == param_ptr)
+                        .map(|(_, cc)| *cc)
+                }),
+                |cx, cc| {
+                    if let Some(cc) = cc.get(cx) {
+                        Label::new(cx, &format!("CC {}", cc)).class("midi_cc");
+                    }
+                },
+            );
+        },
+    );
+    Binding::new(
+        cx,
+        UiData::midi_menu.map(move |menu| *menu == Some(param_ptr)),
+        move |cx, open| {
+            if open.get(cx) {
+                VStack::new(cx, move |cx| {
+                    Button::new(
+                        cx,
+                        move |cx| cx.emit(ParamChangeEvent::MidiLearn(param_ptr)),
+                        |cx| Label::new(cx, "MIDI learn"),
+                    );
+                    Button::new(
+                        cx,
+                        move |cx| cx.emit(ParamChangeEvent::MidiForget(param_ptr)),
+                        |cx| Label::new(cx, "Forget"),
+                    );
+                })
+                .class("midi_menu");
+            }
+        },
+    );
+}
+
+/// Mouse handling for a knob. Dragging up or down moves the value, shift makes it 10 times finer,
+/// and the wheel nudges it. Double or ctrl/cmd clicking sets it back to the default, right clicking opens the
+/// midi learn menu.
+/// Every change goes through the same begin/set/end events as the other controls
+pub struct ParamKnob {
+    param_ptr: ParamPtr,
+    // number of positions for a stepped parameter, 0 if it's continuous
+    steps: usize,
+    // how many pixels of dragging cover the whole range
+    travel: f32,
+    dragging: bool,
+    prev_y: f32,
+    // the unsnapped value while dragging, so small moves add up to a step
+    continuous: f32,
+}
+
+impl ParamKnob {
+    pub fn new(
+        cx: &mut Context,
+        param_ptr: ParamPtr,
+        steps: usize,
+        travel: f32,
+        content: impl FnOnce(&mut Context),
+    ) -> Handle<Self> {
+        Self {
+            param_ptr,
+            steps,
+            travel,
+            dragging: false,
+            prev_y: 0.,
+            continuous: 0.,
+        }
+        .build(cx, content)
+    }
+
+    fn snap(&self, value: f32) -> f32 {
+        let value = value.clamp(0., 1.);
+        if self.steps > 1 {
+            let last = (self.steps - 1) as f32;
+            (value * last).round() / last
+        } else {
+            value
+        }
+    }
+}
+
+impl View for ParamKnob {
+    fn element(&self) -> Option<&'static str> {
+        Some("knob")
+    }
+
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        let fine = if cx.modifiers.contains(Modifiers::SHIFT) {
+            FINE_SCALE
+        } else {
+            1.
+        };
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::MouseDoubleClick(MouseButton::Left) => {
+                cx.emit(ParamChangeEvent::ResetParam(self.param_ptr));
+                meta.consume();
+            }
+            WindowEvent::MouseDown(MouseButton::Left)
+                if cx.modifiers.contains(Modifiers::CTRL)
+                    || cx.modifiers.contains(Modifiers::LOGO) =>
+            {
+                cx.emit(ParamChangeEvent::ResetParam(self.param_ptr));
+                meta.consume();
+            }
+            WindowEvent::MouseDown(MouseButton::Right) => {
+                cx.emit(ParamChangeEvent::ToggleMidiMenu(self.param_ptr));
+                meta.consume();
+            }
+            WindowEvent::MouseDown(MouseButton::Left) => {
+                self.dragging = true;
+                self.prev_y = cx.mouse.cursory;
+                self.continuous = unsafe { self.param_ptr.normalized_value() };
+                cx.focus();
+                cx.capture();
+                cx.emit(ParamChangeEvent::BeginSet(self.param_ptr));
+                meta.consume();
+            }
+            WindowEvent::MouseMove(_, y) if self.dragging => {
+                // up is more
+                self.continuous =
+                    (self.continuous + (self.prev_y - *y) / self.travel * fine).clamp(0., 1.);
+                self.prev_y = *y;
+                cx.emit(ParamChangeEvent::SetParam(
+                    self.param_ptr,
+                    self.snap(self.continuous),
+                ));
+            }
+            WindowEvent::MouseUp(MouseButton::Left) if self.dragging => {
+                self.dragging = false;
+                cx.release();
+                cx.emit(ParamChangeEvent::EndSet(self.param_ptr));
+            }
+            WindowEvent::MouseScroll(_, y) if !self.dragging => {
+                let current = unsafe { self.param_ptr.normalized_value() };
+                // a stepped knob moves a whole step per notch
+                let step = if self.steps > 1 {
+                    1. / (self.steps - 1) as f32
+                } else {
+                    WHEEL_STEP * fine
+                };
+                cx.emit(ParamChangeEvent::BeginSet(self.param_ptr));
+                cx.emit(ParamChangeEvent::SetParam(
+                    self.param_ptr,
+                    self.snap(current + y.signum() * step),
+                ));
+                cx.emit(ParamChangeEvent::EndSet(self.param_ptr));
+                meta.consume();
+            }
+            // every key press is an edit of its own, like a notch of the wheel
+            WindowEvent::KeyDown(code, _) if !self.dragging => {
+                if let Some(value) = key_value(self.param_ptr, *code, fine) {
+                    cx.emit(ParamChangeEvent::BeginSet(self.param_ptr));
+                    cx.emit(ParamChangeEvent::SetParam(self.param_ptr, value));
+                    cx.emit(ParamChangeEvent::EndSet(self.param_ptr));
+                    meta.consume();
+                }
+            }
+            // typing a number goes to the text entry, starting with what was typed
+            WindowEvent::CharInput(c) if c.is_ascii_digit() || *c == '-' || *c == '.' => {
+                let name = unsafe { self.param_ptr.name() }.to_owned();
+                cx.emit(ParamChangeEvent::TypeIntoEntry(name, c.to_string()));
+                meta.consume();
+            }
+            _ => (),
+        });
+    }
+}
+
+/// The value under a knob. Double or right clicking it swaps it for a textbox, so exact values can be typed in
+pub struct ValueLabel {
+    name: String,
+}
+
+impl ValueLabel {
+    pub fn new<P, F>(cx: &mut Context, param_ptr: ParamPtr, params_to_param: F) -> Handle<Self>
+    where
+        P: Param,
+        F: 'static + Fn(&Arc<FilterParams>) -> &P + Copy,
+    {
+        let name = params_to_param(&UiData::params.get(cx)).name().to_owned();
+        Self { name: name.clone() }.build(cx, move |cx| {
+            Binding::new(cx, UiData::text_entry, move |cx, entry| {
+                let value = UiData::params.map(move |params| params_to_param(params).to_string());
+                if entry.get(cx).as_deref() == Some(name.as_str()) {
+                    let typed = UiData::typed_entry.get(cx);
+                    Textbox::new(cx, value)
+                        .on_submit(move |cx, text, success| {
+                            if success {
+                                cx.emit(ParamChangeEvent::TextEntry(param_ptr, text));
+                            } else {
+                                cx.emit(ParamChangeEvent::CancelTextEntry());
+                            }
+                        })
+                        .on_build(move |cx| {
+                            cx.emit(TextEvent::StartEdit);
+                            cx.emit(TextEvent::SelectAll);
+                            if !typed.is_empty() {
+                                cx.emit(TextEvent::InsertText(typed));
+                            }
+                        })
+                        .width(Pixels(100.));
+                } else {
+                    Label::new(cx, value).width(Pixels(100.));
+                }
+            });
+        })
+    }
+}
+
+impl View for ValueLabel {
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::MouseDoubleClick(MouseButton::Left)
+            | WindowEvent::MouseDown(MouseButton::Right) => {
+                cx.emit(ParamChangeEvent::StartTextEntry(self.name.clone()));
+                meta.consume();
+            }
+            _ => (),
+        });
+    }
+}
+
 pub struct BodePlot {
     image: Rc<RefCell<Option<ImageId>>>,
 }
@@ -338,6 +2111,234 @@ impl BodePlot {
     }
 }
 
+/// The ladder's slope, or its pole mixed response, as a mode for the plot
+fn ladder_plot_mode(params: &FilterParams) -> usize {
+    match params.filter.ladder_response.value() {
+        LadderResponse::LP => params.filter.slope.value() as usize,
+        response => LADDER_MIX_MODES + response.to_index() - 1,
+    }
+}
+
+/// The damping of the svf stages cascaded after the first, if the mode has them
+fn svf_cascade_zeta(params: &FilterParams) -> &'static [f32] {
+    match params.filter.mode.value() {
+        SvfMode::LP | SvfMode::HP => &params.filter.svf_slope.value().butterworth_zeta()[1..],
+        _ => &[],
+    }
+}
+
+/// The plot's sallen-key has a damping of 2 - k, with the same q as the circuit's feedback gives it.
+/// Kept just short of self-oscillation, where the peak would be infinite
+fn sallen_key_plot_k(params: &FilterParams) -> f32 {
+    (2. - (0.8 - params.sk_feedback.get()) / 0.405).min(1.975)
+}
+
+/// The comb's feedback with its polarity, kept short of 1 where the peaks would go off the top of the plot
+fn comb_plot_feedback(params: &FilterParams) -> f32 {
+    let feedback = params.comb_feedback.get().min(0.99);
+    if params.filter.comb_negative.value() {
+        -feedback
+    } else {
+        feedback
+    }
+}
+
+/// The curve the bode plot shows for the current settings, with the range of values that fit in the plot
+fn plot_curve(params: &FilterParams, show_phase: bool, width: usize) -> (Vec<f32>, f32, f32) {
+    let amps: Vec<f32>;
+    let max;
+    let min;
+    if show_phase {
+        // FIXME: missing sallenkey
+        if params.filter.filter_type.value() == Circuits::SVF {
+            let mode = params.filter.mode.value() as usize;
+            let mut phases = get_phase_response(
+                params.filter.cutoff.value(),
+                params.zeta.get(),
+                mode,
+                params.filter.filter_type.value(),
+                width,
+            );
+            // the cascaded stages are in series, so their phases add up
+            for zeta in svf_cascade_zeta(params) {
+                let stage = get_phase_response(
+                    params.filter.cutoff.value(),
+                    *zeta,
+                    mode,
+                    Circuits::SVF,
+                    width,
+                );
+                for (phase, stage) in phases.iter_mut().zip(stage) {
+                    *phase = (*phase + stage + PI).rem_euclid(2. * PI) - PI;
+                }
+            }
+            amps = phases;
+            if !svf_cascade_zeta(params).is_empty() {
+                // past 180 degrees the phase wraps around
+                max = PI;
+                min = -PI;
+            } else if mode == 0 {
+                max = 0.;
+                // max phase shift of the state variable filter is Pi radians / 180 degrees
+                min = -PI;
+            } else if mode == 1 {
+                max = PI;
+                min = 0.;
+            } else {
+                max = PI / 2.;
+                min = -PI / 2.;
+            }
+        } else if params.filter.filter_type.value() == Circuits::Ladder {
+            amps = get_phase_response(
+                params.filter.cutoff.value(),
+                // 2.,
+                params.k_ladder(),
+                ladder_plot_mode(params),
+                params.filter.filter_type.value(),
+                width,
+            );
+            if ladder_plot_mode(params) > 1 {
+                max = PI;
+                min = -PI;
+            } else {
+                max = PI / 2.;
+                min = -PI;
+            }
+        } else if params.filter.filter_type.value() == Circuits::Comb {
+            amps = get_phase_response(
+                params.filter.cutoff.value(),
+                comb_plot_feedback(params),
+                0,
+                Circuits::Comb,
+                width,
+            );
+            max = PI / 2.;
+            min = -PI / 2.;
+        } else {
+            amps = get_phase_response(
+                params.filter.cutoff.value(),
+                // 2.,
+                sallen_key_plot_k(params),
+                params.filter.slope.value() as usize,
+                params.filter.filter_type.value(),
+                width,
+            );
+            max = PI / 2.;
+            min = -PI;
+        };
+    } else {
+        // min and max amplitude values that will be rendered
+        min = -60.0;
+        max = 40.0;
+        if params.filter.filter_type.value() == Circuits::Ladder {
+            amps = get_amplitude_response(
+                params.filter.cutoff.value(),
+                // 2.,
+                params.k_ladder(),
+                ladder_plot_mode(params),
+                params.filter.filter_type.value(),
+                width,
+            );
+        } else if params.filter.filter_type.value() == Circuits::SVF {
+            let mode = params.filter.mode.value() as usize;
+            let mut db = get_amplitude_response(
+                params.filter.cutoff.value(),
+                params.zeta.get(),
+                mode,
+                params.filter.filter_type.value(),
+                width,
+            );
+            for zeta in svf_cascade_zeta(params) {
+                let stage = get_amplitude_response(
+                    params.filter.cutoff.value(),
+                    *zeta,
+                    mode,
+                    Circuits::SVF,
+                    width,
+                );
+                for (db, stage) in db.iter_mut().zip(stage) {
+                    *db += stage;
+                }
+            }
+            amps = db;
+        } else if params.filter.filter_type.value() == Circuits::Comb {
+            amps = get_amplitude_response(
+                params.filter.cutoff.value(),
+                comb_plot_feedback(params),
+                0,
+                Circuits::Comb,
+                width,
+            );
+        } else {
+            amps = get_amplitude_response(
+                params.filter.cutoff.value(),
+                sallen_key_plot_k(params),
+                params.filter.mode.value() as usize,
+                params.filter.filter_type.value(),
+                width,
+            );
+        }
+    }
+
+    (amps, min, max)
+}
+
+/// The plot as currently shown, plus the phase if that's what's shown, and the settings that produced it
+fn plot_snapshot(params: &FilterParams, show_phase: bool) -> export::PlotSnapshot {
+    let curve = |phase| {
+        let (values, min, max) = plot_curve(params, phase, export::PLOT_WIDTH);
+        export::Curve { values, min, max }
+    };
+    let mut settings = vec![
+        ("Circuit".to_string(), params.filter.filter_type.to_string()),
+        (
+            params.filter.cutoff.name().to_string(),
+            params.filter.cutoff.to_string(),
+        ),
+        (
+            params.filter.res.name().to_string(),
+            params.filter.res.to_string(),
+        ),
+        (
+            params.drive.amount.name().to_string(),
+            params.drive.amount.to_string(),
+        ),
+    ];
+    match params.filter.filter_type.value() {
+        Circuits::SVF => {
+            settings.push((
+                params.filter.mode.name().to_string(),
+                params.filter.mode.to_string(),
+            ));
+            if !svf_cascade_zeta(params).is_empty() {
+                let slope = &params.filter.svf_slope;
+                settings.push((slope.name().to_string(), slope.to_string()));
+            }
+        }
+        Circuits::Ladder => {
+            let response = &params.filter.ladder_response;
+            settings.push((response.name().to_string(), response.to_string()));
+            if response.value() == LadderResponse::LP {
+                settings.push((
+                    params.filter.slope.name().to_string(),
+                    params.filter.slope.to_string(),
+                ))
+            }
+        }
+        // the sallen key doesn't have a mode
+        Circuits::SallenKey => (),
+        Circuits::Comb => {
+            let negative = &params.filter.comb_negative;
+            settings.push((negative.name().to_string(), negative.to_string()));
+        }
+    }
+    export::PlotSnapshot {
+        magnitude: curve(false),
+        phase: if show_phase { Some(curve(true)) } else { None },
+        settings,
+    }
+}
+
 impl View for BodePlot {
     fn draw(&self, cx: &mut DrawContext<'_>, canvas: &mut Canvas) {
         if let Some(ui_data) = cx.data::<UiData>() {
@@ -347,91 +2348,7 @@ impl View for BodePlot {
             let width = 360;
             let height = 200;
 
-            let amps: Vec<f32>;
-            let max;
-            let min;
-            //
-            if ui_data.show_phase {
-                // FIXME: missing sallenkey
-                if params.filter_type.value() == Circuits::SVF {
-                    let mode = params.mode.value() as usize;
-                    amps = get_phase_response(
-                        params.cutoff.value(),
-                        params.zeta.get(),
-                        mode,
-                        params.filter_type.value(),
-                        width,
-                    );
-                    if mode == 0 {
-                        max = 0.;
-                        // max phase shift of the state variable filter is Pi radians / 180 degrees
-                        min = -PI;
-                    } else if mode == 1 {
-                        max = PI;
-                        min = 0.;
-                    } else {
-                        max = PI / 2.;
-                        min = -PI / 2.;
-                    }
-                } else if params.filter_type.value() == Circuits::Ladder {
-                    amps = get_phase_response(
-                        params.cutoff.value(),
-                        // 2.,
-                        params.k_ladder.get(),
-                        params.slope.value() as usize,
-                        params.filter_type.value(),
-                        width,
-                    );
-                    if params.slope.value() as usize > 1 {
-                        max = PI;
-                        min = -PI;
-                    } else {
-                        max = PI / 2.;
-                        min = -PI;
-                    }
-                } else {
-                    amps = get_phase_response(
-                        params.cutoff.value(),
-                        // 2.,
-                        params.res.value().clamp(0.01, 0.9875) * 2.,
-                        params.slope.value() as usize,
-                        params.filter_type.value(),
-                        width,
-                    );
-                    max = PI / 2.;
-                    min = -PI;
-                };
-            } else {
-                // min and max amplitude values that will be rendered
-                min = -60.0;
-                max = 40.0;
-                if params.filter_type.value() == Circuits::Ladder {
-                    amps = get_amplitude_response(
-                        params.cutoff.value(),
-                        // 2.,
-                        params.k_ladder.get(),
-                        params.slope.value() as usize,
-                        params.filter_type.value(),
-                        width,
-                    );
-                } else if params.filter_type.value() == Circuits::SVF {
-                    amps = get_amplitude_response(
-                        params.cutoff.value(),
-                        params.zeta.get(),
-                        params.mode.value() as usize,
-                        params.filter_type.value(),
-                        width,
-                    );
-                } else {
-                    amps = get_amplitude_response(
-                        params.cutoff.value(),
-                        params.res.value().clamp(0.01, 0.9875) * 2.,
-                        params.mode.value() as usize,
-                        params.filter_type.value(),
-                        width,
-                    );
-                }
-            }
+            let (amps, min, max) = plot_curve(&params, ui_data.show_phase, width);
 
             let bounds = cx.bounds();
             let image_id = if let Some(image_id) = *self.image.borrow() {
@@ -458,6 +2375,27 @@ impl View for BodePlot {
             // Fill background
             canvas.clear_rect(0, 0, width as u32, height as u32, background_color);
 
+            // the analyzer's spectrum behind the curve, from FLOOR_DB at the bottom to 0 dB at the top. The channel
+            // deltas are filled from the middle, which is 0 dB, to DELTA_RANGE_DB either way
+            let view = ui_data.spectrum_view;
+            let base = if view.is_delta() { 0.5 } else { 0. };
+            let mut spectrum_path = Path::new();
+            spectrum_path.move_to(0., height as f32 * (1. - base));
+            for (i, db) in ui_data.spectrum.iter().enumerate() {
+                let y = height as f32 * (1. - view.height(*db));
+                spectrum_path.line_to(i as f32, y);
+            }
+            spectrum_path.line_to(width as f32, height as f32 * (1. - base));
+            let mut spectrum_color = color;
+            spectrum_color.set_alpha(48);
+            canvas.fill_path(&mut spectrum_path, Paint::color(spectrum_color));
+            if view.is_delta() {
+                let mut zero_line = Path::new();
+                zero_line.move_to(0., height as f32 * 0.5);
+                zero_line.line_to(width as f32, height as f32 * 0.5);
+                canvas.stroke_path(&mut zero_line, Paint::color(spectrum_color));
+            }
+
             let mut path = Path::new();
             let amp = amps[0].clamp(min, max);
             let y = height as f32 * ((amp - min) / (max - min));
@@ -510,3 +2448,227 @@ impl View for BodePlot {
         }
     }
 }
+
+/// Plots the output against the input, or both over time, from the latest snippet of the scope.
+/// While there's no signal it shows the static curve of the saturation instead
+pub struct ScopeView {}
+
+impl ScopeView {
+    pub fn new(cx: &mut Context) -> Handle<Self> {
+        Self {}.build(cx, |_| {})
+    }
+}
+
+impl View for ScopeView {
+    fn draw(&self, cx: &mut DrawContext<'_>, canvas: &mut Canvas) {
+        let ui_data = match cx.data::<UiData>() {
+            Some(ui_data) => ui_data,
+            None => return,
+        };
+        let bounds = cx.bounds();
+        let color: vizia::vg::Color = cx.font_color().cloned().unwrap_or_default().into();
+        let mut dim_color = color;
+        dim_color.set_alphaf(0.4);
+
+        let mut path = Path::new();
+        path.rect(bounds.x, bounds.y, bounds.w, bounds.h);
+        canvas.fill_path(
+            &mut path,
+            Paint::color(cx.background_color().cloned().unwrap_or_default().into()),
+        );
+
+        // -scale to scale on both axes, 0 in the middle
+        let (mid_x, mid_y) = (bounds.x + bounds.w / 2., bounds.y + bounds.h / 2.);
+        let mut axes = Path::new();
+        axes.move_to(bounds.x, mid_y);
+        axes.line_to(bounds.x + bounds.w, mid_y);
+        axes.move_to(mid_x, bounds.y);
+        axes.line_to(mid_x, bounds.y + bounds.h);
+        canvas.stroke_path(&mut axes, Paint::color(dim_color));
+        let y = |value: f32, scale: f32| mid_y - (value / scale).clamp(-1., 1.) * bounds.h / 2.;
+
+        let mut paint = Paint::color(color);
+        paint.set_line_width(1.5);
+        let (input, output) = match &ui_data.scope {
+            Some(scope) => scope,
+            None => {
+                let params = &ui_data.params;
+                let mut curve = Path::new();
+                for i in 0..=bounds.w as usize {
+                    let x = 2. * i as f32 / bounds.w - 1.;
+                    let value = static_curve(params, x);
+                    if i == 0 {
+                        curve.move_to(bounds.x, y(value, 1.));
+                    } else {
+                        curve.line_to(bounds.x + i as f32, y(value, 1.));
+                    }
+                }
+                canvas.stroke_path(&mut curve, paint);
+                return;
+            }
+        };
+        // the same scale on both axes, so a clean filter at low frequencies is the diagonal
+        let scale = input
+            .iter()
+            .chain(output.iter())
+            .fold(0., |max: f32, x| max.max(x.abs()));
+        if ui_data.scope_time {
+            let step = bounds.w / (SCOPE_LEN - 1) as f32;
+            for (samples, paint) in [(input, Paint::color(dim_color)), (output, paint)] {
+                let mut trace = Path::new();
+                trace.move_to(bounds.x, y(samples[0], scale));
+                for (i, value) in samples.iter().enumerate().skip(1) {
+                    trace.line_to(bounds.x + i as f32 * step, y(*value, scale));
+                }
+                canvas.stroke_path(&mut trace, paint);
+            }
+        } else {
+            let x = |value: f32| mid_x + (value / scale).clamp(-1., 1.) * bounds.w / 2.;
+            let mut curve = Path::new();
+            curve.move_to(x(input[0]), y(output[0], scale));
+            for (input, output) in input.iter().zip(output.iter()).skip(1) {
+                curve.line_to(x(*input), y(*output, scale));
+            }
+            canvas.stroke_path(&mut curve, paint);
+        }
+    }
+}
+
+/// A peak and rms bar per channel with a peak hold line, from `METER_FLOOR_DB` at the bottom to
+/// `METER_CEILING_DB` at the top
+pub struct LevelMeterView {
+    output: bool,
+}
+
+impl LevelMeterView {
+    pub fn new(cx: &mut Context, output: bool) -> Handle<Self> {
+        Self { output }.build(cx, |_| {})
+    }
+}
+
+impl View for LevelMeterView {
+    fn draw(&self, cx: &mut DrawContext<'_>, canvas: &mut Canvas) {
+        let levels = match cx.data::<UiData>() {
+            Some(ui_data) if self.output => ui_data.output_levels,
+            Some(ui_data) => ui_data.input_levels,
+            None => return,
+        };
+        let bounds = cx.bounds();
+        let color: vizia::vg::Color = cx.font_color().cloned().unwrap_or_default().into();
+        let mut peak_color = color;
+        peak_color.set_alphaf(0.4);
+
+        let mut path = Path::new();
+        path.rect(bounds.x, bounds.y, bounds.w, bounds.h);
+        canvas.fill_path(
+            &mut path,
+            Paint::color(cx.background_color().cloned().unwrap_or_default().into()),
+        );
+
+        // one column per channel with a 1 pixel gap between them
+        let column_width = bounds.w / 2.;
+        let bottom = bounds.y + bounds.h;
+        let mut peaks = Path::new();
+        let mut rms = Path::new();
+        let mut holds = Path::new();
+        for (channel, levels) in levels.iter().enumerate() {
+            let x = bounds.x + channel as f32 * column_width;
+            let peak_height = bounds.h * meter_fraction(levels.peak);
+            let rms_height = bounds.h * meter_fraction(levels.rms);
+            peaks.rect(x, bottom - peak_height, column_width - 1., peak_height);
+            rms.rect(x, bottom - rms_height, column_width - 1., rms_height);
+            if levels.hold > 0. {
+                let hold_y = bottom - bounds.h * meter_fraction(levels.hold);
+                holds.rect(x, hold_y, column_width - 1., 2.);
+            }
+        }
+        canvas.fill_path(&mut peaks, Paint::color(peak_color));
+        canvas.fill_path(&mut rms, Paint::color(color));
+        canvas.fill_path(&mut holds, Paint::color(color));
+    }
+}
+
+/// Bars for the values of the step sequencer, set by clicking or dragging across them
+pub struct StepEditor {
+    params: Arc<FilterParams>,
+    dragging: bool,
+}
+
+impl StepEditor {
+    pub fn new(cx: &mut Context, params: Arc<FilterParams>) -> Handle<Self> {
+        Self {
+            params,
+            dragging: false,
+        }
+        .build(cx, |_| {})
+    }
+
+    // sets the step under the cursor to the value at the cursor's height
+    fn set_step_at_cursor(&self, cx: &mut EventContext) {
+        let bounds = cx.bounds();
+        let x = (cx.mouse.cursorx - bounds.x) / bounds.w;
+        let y = (cx.mouse.cursory - bounds.y) / bounds.h;
+        if !(0. ..1.).contains(&x) {
+            return;
+        }
+        let step = (x * N_STEPS as f32) as usize;
+        // the middle of the view is 0
+        let value = (1. - 2. * y).clamp(-1., 1.);
+        if let Ok(mut steps) = self.params.seq_steps.write() {
+            steps[step] = value;
+        }
+        cx.need_redraw();
+    }
+}
+
+impl View for StepEditor {
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::MouseDown(MouseButton::Left) => {
+                self.dragging = true;
+                cx.capture();
+                self.set_step_at_cursor(cx);
+                meta.consume();
+            }
+            WindowEvent::MouseMove(_, _) if self.dragging => {
+                self.set_step_at_cursor(cx);
+            }
+            WindowEvent::MouseUp(MouseButton::Left) => {
+                self.dragging = false;
+                cx.release();
+            }
+            _ => (),
+        });
+    }
+
+    fn draw(&self, cx: &mut DrawContext<'_>, canvas: &mut Canvas) {
+        let bounds = cx.bounds();
+        let color: vizia::vg::Color = cx.font_color().cloned().unwrap_or_default().into();
+        let steps = match self.params.seq_steps.read() {
+            Ok(steps) => *steps,
+            Err(_) => return,
+        };
+
+        let mut path = Path::new();
+        path.rect(bounds.x, bounds.y, bounds.w, bounds.h);
+        canvas.fill_path(
+            &mut path,
+            Paint::color(cx.background_color().cloned().unwrap_or_default().into()),
+        );
+
+        let bar_width = bounds.w / N_STEPS as f32;
+        let mid = bounds.y + bounds.h / 2.;
+        let mut path = Path::new();
+        for (i, value) in steps.iter().enumerate() {
+            let height = -value * bounds.h / 2.;
+            // a bar going up or down from the middle, with a small gap between bars
+            path.rect(
+                bounds.x + i as f32 * bar_width + 1.,
+                mid.min(mid + height),
+                bar_width - 2.,
+                height.abs().max(1.),
+            );
+        }
+        canvas.fill_path(&mut path, Paint::color(color));
+    }
+}
@@ -0,0 +1,147 @@
+// renders the bode plot and a summary of the settings to an svg, without going through vizia
+use std::fmt::Write;
+
+pub const PLOT_WIDTH: usize = 360;
+pub const PLOT_HEIGHT: usize = 200;
+// room below the plot for the settings summary
+const SUMMARY_HEIGHT: usize = 110;
+
+/// A curve as the bode plot draws it: one value per horizontal pixel, and the values at the bottom and top edge
+pub struct Curve {
+    pub values: Vec<f32>,
+    pub min: f32,
+    pub max: f32,
+}
+
+pub struct PlotSnapshot {
+    pub magnitude: Curve,
+    pub phase: Option<Curve>,
+    /// (label, value) pairs listed below the plot, like ("Cutoff", "1000 Hz")
+    pub settings: Vec<(String, String)>,
+}
+
+/// Renders the snapshot as a standalone svg document
+pub fn render_svg(snapshot: &PlotSnapshot) -> String {
+    let height = PLOT_HEIGHT + SUMMARY_HEIGHT;
+    let mut svg = String::new();
+    // writing to a string can't fail
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = PLOT_WIDTH,
+        h = height
+    );
+    let _ = writeln!(
+        svg,
+        r##"<rect width="{}" height="{}" fill="#191919"/>"##,
+        PLOT_WIDTH, height
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="{}" height="{}" fill="none" stroke="white"/>"#,
+        PLOT_WIDTH, PLOT_HEIGHT
+    );
+    write_curve(&mut svg, "magnitude", "#1eafaf", &snapshot.magnitude);
+    if let Some(phase) = &snapshot.phase {
+        write_curve(&mut svg, "phase", "#ffb74d", phase);
+    }
+    for (i, (label, value)) in snapshot.settings.iter().enumerate() {
+        let _ = writeln!(
+            svg,
+            r##"<text x="10" y="{}" fill="#C2C2C2" font-family="sans-serif" font-size="14">{}: {}</text>"##,
+            PLOT_HEIGHT + 25 + 20 * i,
+            escape(label),
+            escape(value)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn write_curve(svg: &mut String, class: &str, color: &str, curve: &Curve) {
+    let _ = write!(
+        svg,
+        r#"<polyline class="{}" fill="none" stroke="{}" stroke-width="2" points=""#,
+        class, color
+    );
+    let x_scale = PLOT_WIDTH as f32 / curve.values.len().max(1) as f32;
+    for (i, value) in curve.values.iter().enumerate() {
+        // same mapping as the plot view, clamped so the resonance peak doesn't leave the plot
+        let value = value.clamp(curve.min, curve.max);
+        let y = PLOT_HEIGHT as f32 * (1. - (value - curve.min) / (curve.max - curve.min));
+        if i > 0 {
+            svg.push(' ');
+        }
+        let _ = write!(svg, "{:.1},{:.1}", i as f32 * x_scale, y);
+    }
+    svg.push_str("\"/>\n");
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+fn test_snapshot(phase: bool) -> PlotSnapshot {
+    let curve = |min, max| Curve {
+        values: (0..PLOT_WIDTH).map(|i| i as f32 / 3. - 60.).collect(),
+        min,
+        max,
+    };
+    PlotSnapshot {
+        magnitude: curve(-60., 40.),
+        phase: if phase {
+            Some(curve(-3.14, 3.14))
+        } else {
+            None
+        },
+        settings: vec![
+            ("Circuit".to_string(), "SVF".to_string()),
+            ("Cutoff".to_string(), "1000 Hz".to_string()),
+            ("Res".to_string(), "0.50".to_string()),
+        ],
+    }
+}
+
+#[test]
+fn test_svg_structure() {
+    let svg = render_svg(&test_snapshot(false));
+    assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(svg.trim_end().ends_with("</svg>"));
+    assert_eq!(svg.matches("<polyline").count(), 1);
+    assert!(svg.contains(r#"class="magnitude""#));
+    assert!(!svg.contains(r#"class="phase""#));
+    // one point per pixel, and every value stays inside the plot
+    let points = svg
+        .split("points=\"")
+        .nth(1)
+        .unwrap()
+        .split('"')
+        .next()
+        .unwrap();
+    assert_eq!(points.split(' ').count(), PLOT_WIDTH);
+    for point in points.split(' ') {
+        let y: f32 = point.split(',').nth(1).unwrap().parse().unwrap();
+        assert!((0. ..=PLOT_HEIGHT as f32).contains(&y));
+    }
+    // one line per setting
+    assert_eq!(svg.matches("<text").count(), 3);
+    assert!(svg.contains(">Cutoff: 1000 Hz</text>"));
+}
+
+#[test]
+fn test_svg_with_phase() {
+    let svg = render_svg(&test_snapshot(true));
+    assert_eq!(svg.matches("<polyline").count(), 2);
+    assert!(svg.contains(r#"class="phase""#));
+}
+
+#[test]
+fn test_svg_escapes_text() {
+    let mut snapshot = test_snapshot(false);
+    snapshot.settings = vec![("Mode".to_string(), "<LP & HP>".to_string())];
+    let svg = render_svg(&snapshot);
+    assert!(svg.contains("Mode: &lt;LP &amp; HP&gt;"));
+}
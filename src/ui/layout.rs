@@ -0,0 +1,173 @@
+// declarative description of the knob layout, so it can be reloaded from disk while working on the gui
+use super::ViewerGuard;
+use crate::FilterParams;
+use serde::Deserialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+use vizia::prelude::*;
+
+/// ids of the parameters that can be placed in the knob row
+pub const KNOB_IDS: [&str; 5] = ["cutoff", "res", "drive", "antisat", "spread"];
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Data)]
+#[serde(deny_unknown_fields)]
+pub struct KnobLayout {
+    /// parameter ids of the knobs in the knob row, from left to right.
+    /// The svf slope / ladder response knob is always placed after these
+    pub knobs: Vec<String>,
+    /// whether to show the bode plot below the knobs
+    #[serde(default = "default_true")]
+    pub show_plot: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for KnobLayout {
+    fn default() -> Self {
+        Self {
+            knobs: KNOB_IDS.iter().map(|id| id.to_string()).collect(),
+            show_plot: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutError {
+    Io(String),
+    Parse(String),
+    UnknownParam(String),
+    DuplicateParam(String),
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::Io(e) => write!(f, "couldn't read layout: {}", e),
+            LayoutError::Parse(e) => write!(f, "couldn't parse layout: {}", e),
+            LayoutError::UnknownParam(id) => {
+                write!(f, "layout refers to unknown parameter \"{}\"", id)
+            }
+            LayoutError::DuplicateParam(id) => {
+                write!(f, "parameter \"{}\" is in the layout twice", id)
+            }
+        }
+    }
+}
+
+pub fn parse_layout(src: &str) -> Result<KnobLayout, LayoutError> {
+    let layout: KnobLayout =
+        serde_json::from_str(src).map_err(|e| LayoutError::Parse(e.to_string()))?;
+    validate_layout(&layout)?;
+    Ok(layout)
+}
+
+/// checks that every knob in the layout refers to an existing parameter, and only once
+pub fn validate_layout(layout: &KnobLayout) -> Result<(), LayoutError> {
+    for (i, id) in layout.knobs.iter().enumerate() {
+        if !KNOB_IDS.contains(&id.as_str()) {
+            return Err(LayoutError::UnknownParam(id.clone()));
+        }
+        if layout.knobs[..i].contains(id) {
+            return Err(LayoutError::DuplicateParam(id.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// the theme and layout files that get watched. Can be pointed elsewhere with `VA_FILTER_UI_DIR`
+pub fn theme_path() -> PathBuf {
+    ui_dir().join("style.css")
+}
+pub fn layout_path() -> PathBuf {
+    ui_dir().join("ui").join("layout.json")
+}
+fn ui_dir() -> PathBuf {
+    std::env::var_os("VA_FILTER_UI_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src")))
+}
+
+pub fn load_layout() -> Result<KnobLayout, LayoutError> {
+    let src = std::fs::read_to_string(layout_path()).map_err(|e| LayoutError::Io(e.to_string()))?;
+    parse_layout(&src)
+}
+
+#[derive(Debug)]
+pub enum DevUiEvent {
+    ThemeChanged,
+    LayoutChanged,
+}
+
+/// Polls the theme and layout files and tells the gui when they change.
+/// The thread stops once the returned guard is dropped with the editor, or the events can't be delivered anymore
+pub fn spawn_watcher(cx: &mut Context, params: Arc<FilterParams>) -> ViewerGuard {
+    let mut proxy = cx.get_proxy();
+    // like the meter watcher's, only for the open flag
+    let guard = ViewerGuard::new(params, |_| {}, |_| {});
+    let open = guard.open();
+    std::thread::spawn(move || {
+        let modified = |path: PathBuf| -> Option<SystemTime> {
+            std::fs::metadata(path).and_then(|m| m.modified()).ok()
+        };
+        let mut theme_time = modified(theme_path());
+        let mut layout_time = modified(layout_path());
+        loop {
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            if !open.load(std::sync::atomic::Ordering::Relaxed) {
+                return;
+            }
+            let new_theme_time = modified(theme_path());
+            if new_theme_time != theme_time {
+                theme_time = new_theme_time;
+                if proxy.emit(DevUiEvent::ThemeChanged).is_err() {
+                    return;
+                }
+            }
+            let new_layout_time = modified(layout_path());
+            if new_layout_time != layout_time {
+                layout_time = new_layout_time;
+                if proxy.emit(DevUiEvent::LayoutChanged).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    guard
+}
+
+#[test]
+fn test_layout_file_matches_default() {
+    let src = include_str!("layout.json");
+    assert_eq!(parse_layout(src), Ok(KnobLayout::default()));
+}
+
+#[test]
+fn test_layout_show_plot_defaults_to_true() {
+    let layout = parse_layout(r#"{ "knobs": ["res"] }"#).unwrap();
+    assert_eq!(layout.knobs, vec!["res".to_string()]);
+    assert!(layout.show_plot);
+}
+
+#[test]
+fn test_layout_errors() {
+    assert_eq!(
+        parse_layout(r#"{ "knobs": ["cutoff", "feedback"] }"#),
+        Err(LayoutError::UnknownParam("feedback".to_string()))
+    );
+    assert_eq!(
+        parse_layout(r#"{ "knobs": ["drive", "drive"] }"#),
+        Err(LayoutError::DuplicateParam("drive".to_string()))
+    );
+    assert!(matches!(
+        parse_layout(r#"{ "knobs": "cutoff" }"#),
+        Err(LayoutError::Parse(_))
+    ));
+    assert!(matches!(
+        parse_layout(r#"{ "knobs": [], "colour": "red" }"#),
+        Err(LayoutError::Parse(_))
+    ));
+}
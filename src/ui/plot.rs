@@ -3,7 +3,11 @@ use num::complex::Complex;
 
 use std::f32::consts::PI;
 
-use crate::filter_params::Circuits;
+use crate::filter_params::{Circuits, LadderResponse};
+use nih_plug::prelude::Enum;
+
+/// The ladder's modes in the plot are the four lowpass slopes, and then the pole mixed responses
+pub const LADDER_MIX_MODES: usize = 4;
 
 pub fn lin_to_db(gain: f32) -> f32 {
     gain.log10() * 20.0
@@ -36,7 +40,22 @@ fn get_filter_bode(
     let j = Complex::new(0., 1.);
     let mut curr_s: Complex<f32>;
     match filter_type {
-        // transistor ladder filter
+        // transistor ladder filter. Modes past the four lowpass slopes are the pole mixed responses
+        Circuits::Ladder if mode >= LADDER_MIX_MODES => {
+            let mix = LadderResponse::from_index(mode - LADDER_MIX_MODES + 1)
+                .pole_mix()
+                .unwrap_or([0., 0., 0., 0., 1.]);
+            for i in 0..len {
+                curr_s = frequencies[i] * j;
+                let stage = 1. / (1. + curr_s / g);
+                // the weighted sum of the powers of one stage, by horner's method
+                let mut out = Complex::new(mix[4], 0.);
+                for weight in mix[..4].iter().rev() {
+                    out = out * stage + *weight;
+                }
+                array[i] = out / (1. + k * stage.powi(4));
+            }
+        }
         Circuits::Ladder => {
             for i in 0..len {
                 curr_s = frequencies[i] * j;
@@ -98,6 +117,14 @@ fn get_filter_bode(
                 array[i] = g.powi(2) / ((curr_s).powi(2) - (k - 2.) * g * curr_s + g.powi(2));
             }
         }
+        // feedback comb tuned to the cutoff, with k as the feedback. Negative feedback runs at half the delay
+        Circuits::Comb => {
+            let period = if k < 0. { 0.5 / g } else { 1. / g };
+            for i in 0..len {
+                let delay = Complex::from_polar(1., -2. * PI * frequencies[i] * period);
+                array[i] = 1. / (1. - k * delay);
+            }
+        }
     }
     array
 }
@@ -134,7 +161,19 @@ pub fn get_amplitude_response(
             .0;
         amplitudes[max] = lin_to_db(1.0 / k);
     }
-    // TODO: I'd like to do this for the ladder filter too, but I couldn't find a formula for its resonance
+    // same for the ladder close to self-oscillation. There the peak sits at s = j * g, where (1 + s / g)^4 = -4,
+    // so the denominator is just 4 - k and the numerator is (1 + k) * |1 + j|^(3 - mode)
+    else if filter_type == Circuits::Ladder && mode < LADDER_MIX_MODES && k > 3. {
+        let max = amplitudes
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).expect("NaN in the filter response"))
+            .unwrap()
+            .0;
+        let numerator = (1. + k) * 2f32.sqrt().powi(3 - mode as i32);
+        // past k = 4 it self-oscillates, so the peak is as high as the plot goes
+        amplitudes[max] = lin_to_db(numerator / (4. - k).max(1e-6));
+    }
     amplitudes
 }
 
@@ -221,3 +260,19 @@ fn test_ladder_value() {
     println!("current lowest: {}", frequencies[0]);
     println!("current highest: {}", frequencies[999]);
 }
+
+#[test]
+fn test_ladder_peak_near_self_oscillation() {
+    for mode in 0..4 {
+        let k = 3.99;
+        let amplitudes = get_amplitude_response(1000., k, mode, Circuits::Ladder, 360);
+        let peak = amplitudes.into_iter().reduce(f32::max).unwrap();
+        let expected = lin_to_db((1. + k) * 2f32.sqrt().powi(3 - mode as i32) / (4. - k));
+        assert!(
+            (peak - expected).abs() < 1e-3,
+            "{} dB at mode {}",
+            peak,
+            mode
+        );
+    }
+}
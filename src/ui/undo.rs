@@ -0,0 +1,152 @@
+// the editor's history of parameter edits
+use std::collections::VecDeque;
+
+/// How many edits the history keeps, the oldest go first
+const MAX_EDITS: usize = 100;
+
+/// Finished gestures on the parameters, as the normalized values of every parameter they changed from before and
+/// after. Gestures that overlap, or a group around several of them, make up a single edit.
+/// `P` is whatever tells the parameters apart, a `ParamPtr` in the editor
+pub struct UndoStack<P> {
+    undo: VecDeque<Vec<(P, f32, f32)>>,
+    redo: Vec<Vec<(P, f32, f32)>>,
+    // the values from before the edit that's going on
+    pending: Vec<(P, f32)>,
+    // gestures and groups that haven't ended yet
+    depth: usize,
+}
+
+impl<P> Default for UndoStack<P> {
+    fn default() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            pending: Vec::new(),
+            depth: 0,
+        }
+    }
+}
+
+impl<P: Copy + PartialEq> UndoStack<P> {
+    /// A gesture on `param` starts, with the value it has before it
+    pub fn begin(&mut self, param: P, value: f32) {
+        if !self.pending.iter().any(|(pending, _)| *pending == param) {
+            self.pending.push((param, value));
+        }
+        self.depth += 1;
+    }
+    /// Everything up to the matching `end()` becomes one edit
+    pub fn begin_group(&mut self) {
+        self.depth += 1;
+    }
+    /// Ends a gesture or group. Once nothing's going on anymore, the parameters whose value now differs make up a new
+    /// edit, with `value` giving their current values
+    pub fn end(&mut self, value: impl Fn(P) -> f32) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth > 0 {
+            return;
+        }
+        let changes: Vec<_> = self
+            .pending
+            .drain(..)
+            .filter_map(|(param, before)| {
+                let after = value(param);
+                (after != before).then(|| (param, before, after))
+            })
+            .collect();
+        if changes.is_empty() {
+            return;
+        }
+        if self.undo.len() == MAX_EDITS {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(changes);
+        self.redo.clear();
+    }
+    /// The values that take the last edit back, or nothing while a gesture is still going on
+    pub fn undo(&mut self) -> Option<Vec<(P, f32)>> {
+        if self.depth > 0 {
+            return None;
+        }
+        let edit = self.undo.pop_back()?;
+        let values = edit
+            .iter()
+            .map(|(param, before, _)| (*param, *before))
+            .collect();
+        self.redo.push(edit);
+        Some(values)
+    }
+    /// The values that make the last undone edit again
+    pub fn redo(&mut self) -> Option<Vec<(P, f32)>> {
+        if self.depth > 0 {
+            return None;
+        }
+        let edit = self.redo.pop()?;
+        let values = edit
+            .iter()
+            .map(|(param, _, after)| (*param, *after))
+            .collect();
+        self.undo.push_back(edit);
+        Some(values)
+    }
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.pending.clear();
+        self.depth = 0;
+    }
+}
+
+#[test]
+fn test_undo_stack() {
+    let mut stack = UndoStack::default();
+    let values = std::cell::RefCell::new([0.; 3]);
+    let value = |param: usize| values.borrow()[param];
+    assert_eq!(stack.undo(), None);
+
+    // a drag is one edit, however many values it went through
+    stack.begin(0, value(0));
+    values.borrow_mut()[0] = 0.3;
+    values.borrow_mut()[0] = 0.5;
+    stack.end(value);
+    // touching a knob without moving it isn't one
+    stack.begin(1, value(1));
+    stack.end(value);
+    assert_eq!(stack.undo(), Some(vec![(0, 0.)]));
+    assert_eq!(stack.undo(), None);
+    assert_eq!(stack.redo(), Some(vec![(0, 0.5)]));
+    assert_eq!(stack.redo(), None);
+
+    // a group is one edit too, and nothing gets undone halfway through it
+    stack.begin_group();
+    for param in 1..3 {
+        stack.begin(param, value(param));
+        values.borrow_mut()[param] = 1.;
+        stack.end(value);
+        assert_eq!(stack.depth, 1);
+    }
+    assert_eq!(stack.undo(), None);
+    stack.end(value);
+    assert_eq!(stack.undo(), Some(vec![(1, 0.), (2, 0.)]));
+    // a new edit drops what could be redone
+    stack.begin(0, value(0));
+    values.borrow_mut()[0] = 0.;
+    stack.end(value);
+    assert_eq!(stack.redo(), None);
+}
+
+#[test]
+fn test_undo_stack_limit() {
+    let mut stack = UndoStack::default();
+    for i in 0..MAX_EDITS + 10 {
+        stack.begin(0, i as f32);
+        stack.end(|_| (i + 1) as f32);
+    }
+    let mut oldest = None;
+    while let Some(values) = stack.undo() {
+        oldest = Some(values[0].1);
+    }
+    assert_eq!(oldest, Some(10.));
+    stack.clear();
+    assert_eq!((stack.undo(), stack.redo()), (None, None));
+}
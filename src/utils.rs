@@ -1,4 +1,5 @@
 #![allow(dead_code)]
+use core_simd::simd::*;
 use std::sync::atomic::{self, Ordering};
 // use std::ops::{Sub, Add, Mul, Div};
 pub trait AtomicOps {
@@ -91,24 +92,386 @@ impl AtomicI8 {
         self.0.store(v, Ordering::Relaxed)
     }
 }
-/// Simple 64-bit floating point wrapper over `AtomicU32` with relaxed ordering.
+/// Simple 64-bit floating point wrapper over `AtomicU64` with relaxed ordering.
 pub struct AtomicF64(atomic::AtomicU64);
-#[allow(dead_code)]
-impl AtomicF64 {
-    /// Create a new atomic 32-bit float with initial value `v`.
-    pub fn new(v: f64) -> Self {
+impl AtomicOps for AtomicF64 {
+    type Item = f64;
+    /// Create a new atomic 64-bit float with initial value `v`.
+    fn new(v: f64) -> Self {
         AtomicF64(atomic::AtomicU64::new(v.to_bits()))
     }
-
     /// Loads a value from the atomic float with relaxed ordering.
     #[inline]
-    pub fn get(&self) -> f64 {
+    fn get(&self) -> f64 {
         f64::from_bits(self.0.load(Ordering::Relaxed))
     }
-
     /// Stores a value into the atomic float with relaxed ordering.
     #[inline]
-    pub fn set(&self, v: f64) {
+    fn set(&self, v: f64) {
         self.0.store(v.to_bits(), Ordering::Relaxed)
     }
 }
+
+/// `N` floats that are only ever published together, so a reader never gets some of them from before a store and
+/// some from after. A seqlock: the sequence is odd while a store is under way, and a load that overlapped one
+/// tries again. Loads never hold up a store, and stores only wait on each other
+pub struct CoefficientCell<const N: usize> {
+    sequence: atomic::AtomicU32,
+    values: [atomic::AtomicU32; N],
+}
+
+impl<const N: usize> CoefficientCell<N> {
+    pub fn new(values: [f32; N]) -> Self {
+        Self {
+            sequence: atomic::AtomicU32::new(0),
+            values: values.map(|v| atomic::AtomicU32::new(v.to_bits())),
+        }
+    }
+    /// All the values from the same store
+    #[inline]
+    pub fn load(&self) -> [f32; N] {
+        loop {
+            let before = self.sequence.load(Ordering::Acquire);
+            if before & 1 == 0 {
+                let values =
+                    std::array::from_fn(|i| f32::from_bits(self.values[i].load(Ordering::Relaxed)));
+                // the values have to be read before the sequence is checked again
+                atomic::fence(Ordering::Acquire);
+                if self.sequence.load(Ordering::Relaxed) == before {
+                    return values;
+                }
+            }
+            std::hint::spin_loop();
+        }
+    }
+    /// Publishes all of `values` at once
+    #[inline]
+    pub fn store(&self, values: [f32; N]) {
+        let sequence = self.begin_store();
+        for (cell, v) in self.values.iter().zip(values) {
+            cell.store(v.to_bits(), Ordering::Relaxed);
+        }
+        self.sequence
+            .store(sequence.wrapping_add(2), Ordering::Release);
+    }
+    /// Changes some of the values and publishes them with the rest, without another store getting in between
+    #[inline]
+    pub fn update(&self, f: impl FnOnce(&mut [f32; N])) {
+        let sequence = self.begin_store();
+        let mut values =
+            std::array::from_fn(|i| f32::from_bits(self.values[i].load(Ordering::Relaxed)));
+        f(&mut values);
+        for (cell, v) in self.values.iter().zip(values) {
+            cell.store(v.to_bits(), Ordering::Relaxed);
+        }
+        self.sequence
+            .store(sequence.wrapping_add(2), Ordering::Release);
+    }
+    /// Makes the sequence odd, once no other store is under way, and returns where it was
+    fn begin_store(&self) -> u32 {
+        let mut sequence = self.sequence.load(Ordering::Relaxed);
+        loop {
+            if sequence & 1 == 0 {
+                match self.sequence.compare_exchange_weak(
+                    sequence,
+                    sequence.wrapping_add(1),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => sequence = current,
+                }
+            } else {
+                std::hint::spin_loop();
+                sequence = self.sequence.load(Ordering::Relaxed);
+            }
+        }
+        // readers that see any of the new values also see the odd sequence
+        atomic::fence(Ordering::Release);
+        sequence
+    }
+}
+
+/// Bits of work left for the audio thread, like the `DIRTY_*` flags in `filter_params`.
+/// Marked with release and taken with acquire, so whatever was written before `mark()` is seen after `take()`
+pub struct DirtyFlags(atomic::AtomicU32);
+
+impl DirtyFlags {
+    pub fn new(flags: u32) -> Self {
+        DirtyFlags(atomic::AtomicU32::new(flags))
+    }
+    /// Adds `flags` to the ones waiting
+    #[inline]
+    pub fn mark(&self, flags: u32) {
+        self.0.fetch_or(flags, Ordering::Release);
+    }
+    /// Clears the waiting flags and returns them
+    #[inline]
+    pub fn take(&self) -> u32 {
+        self.0.swap(0, Ordering::Acquire)
+    }
+    /// The waiting flags, left in place
+    #[inline]
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Steps the splitmix64 generator at `state`. Small and good enough for anything that isn't cryptography
+pub fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// A different seed every time, from the same randomness std seeds its hash maps with
+pub fn random_seed() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    RandomState::new().build_hasher().finish()
+}
+
+/// Uniform in -1 to 1 from the next value of `splitmix64()`
+pub fn random_bipolar(state: &mut u64) -> f32 {
+    // the top 24 bits, which is all an f32 can hold
+    (splitmix64(state) >> 40) as f32 / (1 << 23) as f32 - 1.
+}
+
+/// Inputs get clamped to this before the rational approximation of tanh, since it overshoots 1 not far past here
+const TANH_CLAMP: f32 = 6.25;
+
+/// [9/8] Padé approximant of tanh. Max error is about 6e-7 in -5 to 5, and 1e-5 outside that.
+/// Only one division and no square root, so it's cheaper than `tanh_levien` as well as more accurate
+#[inline(always)]
+pub fn tanh_pade(x: f32x4) -> f32x4 {
+    let x = x.simd_clamp(f32x4::splat(-TANH_CLAMP), f32x4::splat(TANH_CLAMP));
+    let x2 = x * x;
+    let num = f32x4::splat(34459425.)
+        + x2 * (f32x4::splat(4729725.)
+            + x2 * (f32x4::splat(135135.) + x2 * (f32x4::splat(990.) + x2)));
+    let den = f32x4::splat(34459425.)
+        + x2 * (f32x4::splat(16216200.)
+            + x2 * (f32x4::splat(945945.) + x2 * (f32x4::splat(13860.) + x2 * f32x4::splat(45.))));
+    x * num / den
+}
+
+/// Double precision version of `tanh_pade()` for the DK-method solvers
+#[inline(always)]
+pub fn tanh_pade_f64(x: f64) -> f64 {
+    let x = x.clamp(-TANH_CLAMP as f64, TANH_CLAMP as f64);
+    let x2 = x * x;
+    let num = 34459425. + x2 * (4729725. + x2 * (135135. + x2 * (990. + x2)));
+    let den = 34459425. + x2 * (16216200. + x2 * (945945. + x2 * (13860. + x2 * 45.)));
+    x * num / den
+}
+
+/// The tanh the solvers use. The `precise-tanh` feature swaps the approximation out for the std one,
+/// to compare against
+#[cfg(not(feature = "precise-tanh"))]
+#[inline(always)]
+pub fn solver_tanh(x: f32x4) -> f32x4 {
+    tanh_pade(x)
+}
+#[cfg(feature = "precise-tanh")]
+#[inline(always)]
+pub fn solver_tanh(x: f32x4) -> f32x4 {
+    f32x4::from_array(x.to_array().map(f32::tanh))
+}
+#[cfg(not(feature = "precise-tanh"))]
+#[inline(always)]
+pub fn solver_tanh_f64(x: f64) -> f64 {
+    tanh_pade_f64(x)
+}
+#[cfg(feature = "precise-tanh")]
+#[inline(always)]
+pub fn solver_tanh_f64(x: f64) -> f64 {
+    x.tanh()
+}
+
+/// [5/4] Padé approximant of tan, for when the cutoff changes every sample.
+/// Relative error is below 3e-5 up to 0.45 pi, which is a fraction of a cent in the cutoff.
+/// 25 kHz at 88.2 kHz is only about 0.28 pi
+#[inline(always)]
+pub fn tan_pade(x: f32) -> f32 {
+    let x2 = x * x;
+    x * (945. + x2 * (-105. + x2)) / (945. + x2 * (-420. + x2 * 15.))
+}
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// The nearest note to `hz`, and how many cents `hz` is above it. A4 is 440 Hz, and middle C is C4
+pub fn hz_to_note(hz: f32) -> (String, f32) {
+    let midi = 69. + 12. * (hz / 440.).log2();
+    let nearest = midi.round();
+    let note = nearest as i32;
+    (
+        format!(
+            "{}{}",
+            NOTE_NAMES[note.rem_euclid(12) as usize],
+            note.div_euclid(12) - 1
+        ),
+        100. * (midi - nearest),
+    )
+}
+
+/// The frequency of a note name like "A4", "C#3", "Eb2" or "C-1"
+pub fn note_to_hz(name: &str) -> Option<f32> {
+    let mut chars = name.trim().chars();
+    let semitone = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (semitone, octave) = if let Some(octave) = rest.strip_prefix('#') {
+        (semitone + 1, octave)
+    } else if let Some(octave) = rest.strip_prefix('b') {
+        (semitone - 1, octave)
+    } else {
+        (semitone, rest)
+    };
+    let octave: i32 = octave.trim().parse().ok()?;
+    let midi = (octave + 1) * 12 + semitone;
+    Some(440. * ((midi - 69) as f32 / 12.).exp2())
+}
+
+#[test]
+fn test_tanh_pade_accuracy() {
+    for i in -20000..=20000 {
+        let x = i as f32 / 1000.;
+        let max_err = if x.abs() <= 5. { 1e-6 } else { 2e-5 };
+        let approx = tanh_pade(f32x4::splat(x))[0];
+        assert!((approx - x.tanh()).abs() < max_err, "{}: {}", x, approx);
+        let approx = tanh_pade_f64(x as f64);
+        assert!(
+            (approx - (x as f64).tanh()).abs() < max_err as f64,
+            "{}: {}",
+            x,
+            approx
+        );
+        assert!(approx.abs() <= 1.);
+    }
+}
+
+#[test]
+fn test_tan_pade_accuracy() {
+    for i in 1..=1000 {
+        let x = i as f32 / 1000. * 0.45 * std::f32::consts::PI;
+        let approx = tan_pade(x);
+        assert!((approx / x.tan() - 1.).abs() < 3e-5, "{}: {}", x, approx);
+    }
+}
+
+#[test]
+fn test_note_names() {
+    assert_eq!(hz_to_note(440.), ("A4".to_string(), 0.));
+    let (name, cents) = hz_to_note(523.);
+    assert_eq!(name, "C5");
+    assert!((cents + 0.83).abs() < 0.01, "{}", cents);
+    assert_eq!(hz_to_note(8.1758).0, "C-1");
+    assert_eq!(note_to_hz("A4"), Some(440.));
+    assert_eq!(note_to_hz("C#3"), note_to_hz("Db3"));
+    assert_eq!(note_to_hz("H2"), None);
+    assert_eq!(note_to_hz("C"), None);
+    // every note reads back as itself
+    for midi in 0..128 {
+        let hz = 440. * ((midi - 69) as f32 / 12.).exp2();
+        let (name, cents) = hz_to_note(hz);
+        assert!(cents.abs() < 0.01);
+        let parsed = note_to_hz(&name).unwrap();
+        assert!((parsed / hz - 1.).abs() < 1e-5, "{}", name);
+    }
+}
+
+#[test]
+fn test_dirty_flags() {
+    let flags = std::sync::Arc::new(DirtyFlags::new(0));
+    // marks from other threads pile up until they're taken, and nothing gets taken twice
+    let threads: Vec<_> = (0..4)
+        .map(|n| {
+            let flags = flags.clone();
+            std::thread::spawn(move || flags.mark(1 << n))
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(flags.get(), 0b1111);
+    assert_eq!(flags.take(), 0b1111);
+    assert_eq!(flags.take(), 0);
+}
+
+#[test]
+fn test_atomic_f64() {
+    let x = AtomicF64::new(0.1);
+    assert_eq!(x.get(), 0.1);
+    // every bit of it, where an f32 would round
+    x.set(1. + f64::EPSILON);
+    assert_eq!(x.get(), 1. + f64::EPSILON);
+    x.set(-0.);
+    assert!(x.get().is_sign_negative());
+}
+
+#[test]
+fn test_coefficient_cell_stress() {
+    use std::sync::Arc;
+    const STORES: usize = 200_000;
+    let cell = Arc::new(CoefficientCell::new([0.; 4]));
+    let done = Arc::new(atomic::AtomicBool::new(false));
+    // readers only ever see a whole store, and never one from before the last they saw
+    let readers: Vec<_> = (0..3)
+        .map(|_| {
+            let (cell, done) = (cell.clone(), done.clone());
+            std::thread::spawn(move || {
+                let (mut last, mut loads) = (0., 0);
+                while !done.load(Ordering::Relaxed) {
+                    let values = cell.load();
+                    assert!(values.iter().all(|v| *v == values[0]), "{:?}", values);
+                    assert!(values[0] >= last, "{} after {}", values[0], last);
+                    last = values[0];
+                    loads += 1;
+                }
+                loads
+            })
+        })
+        .collect();
+    let writer = {
+        let cell = cell.clone();
+        std::thread::spawn(move || {
+            for i in 1..=STORES {
+                cell.store([i as f32; 4]);
+            }
+        })
+    };
+    writer.join().unwrap();
+    done.store(true, Ordering::Relaxed);
+    for reader in readers {
+        assert!(reader.join().unwrap() > 0);
+    }
+    assert_eq!(cell.load(), [STORES as f32; 4]);
+
+    // stores from several threads at once don't lose each other's updates
+    let writers: Vec<_> = (0..4)
+        .map(|n| {
+            let cell = cell.clone();
+            std::thread::spawn(move || {
+                for _ in 0..STORES / 4 {
+                    cell.update(|values| values[n] += 1.);
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert_eq!(cell.load(), [(STORES + STORES / 4) as f32; 4]);
+}
@@ -0,0 +1,481 @@
+#![feature(portable_simd)]
+// checks the measured frequency responses of the circuits against what they're supposed to be
+use core_simd::simd::f32x4;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use va_filter::filter::sallen_key::SallenKey;
+use va_filter::filter::svf::Svf;
+use va_filter::filter::LadderFilter;
+use va_filter::filter_params::{
+    Circuits, FilterParams, LadderResponse, SvfMode, SvfSlope, Q_BUTTERWORTH, Q_MIN, Q_SELF_OSC,
+};
+use va_filter::utils::AtomicOps;
+
+const FS: f32 = 48000.;
+const LEN: usize = 1 << 15;
+// small enough that the dk solvers stay in their linear region. The ladder's newton solver stops at an
+// absolute error, so it gets measured with sines instead
+const IMPULSE: f32 = 1e-3;
+
+#[derive(Clone, Copy, Debug)]
+enum Tick {
+    LadderLinear,
+    Svf,
+    SallenKey,
+}
+
+fn make_params(circuit: Circuits, cutoff: f32, q: f32) -> Arc<FilterParams> {
+    let mut params = FilterParams::new();
+    params.filter.filter_type = nih_plug::prelude::EnumParam::new("circuit", circuit);
+    params.sample_rate.set(FS);
+    params.update_g(cutoff);
+    params.set_resonances(q);
+    Arc::new(params)
+}
+
+/// magnitude response in dB of the impulse response, from bin 0 to nyquist
+fn measure(tick: Tick, cutoff: f32, res: f32) -> Vec<f32> {
+    let circuit = match tick {
+        Tick::LadderLinear => Circuits::Ladder,
+        Tick::Svf => Circuits::SVF,
+        Tick::SallenKey => Circuits::SallenKey,
+    };
+    let params = make_params(circuit, cutoff, res);
+    let mut ladder = LadderFilter::new(params.clone());
+    let mut svf = Svf::new(params.clone());
+    svf.update();
+    let mut sallen_key = SallenKey::new(params);
+    sallen_key.update();
+
+    impulse_response_db(|input| match tick {
+        Tick::LadderLinear => ladder.tick_linear(input),
+        Tick::Svf => svf.process(input),
+        Tick::SallenKey => sallen_key.process(input),
+    })
+}
+
+/// magnitude response in dB of the linear ladder at no resonance with one of the pole mixed responses
+fn measure_ladder_response(response: LadderResponse, cutoff: f32) -> Vec<f32> {
+    let mut params = FilterParams::new();
+    params.filter.ladder_response = nih_plug::prelude::EnumParam::new("response", response);
+    params.sample_rate.set(FS);
+    params.update_g(cutoff);
+    params.set_resonances(Q_MIN);
+    let mut ladder = LadderFilter::new(Arc::new(params));
+    impulse_response_db(|input| ladder.tick_linear(input))
+}
+
+/// magnitude response in dB of the svf with its stages cascaded for `slope`, at no resonance
+fn measure_svf_slope(slope: SvfSlope, mode: SvfMode, cutoff: f32) -> Vec<f32> {
+    let mut params = FilterParams::new();
+    params.filter.filter_type = nih_plug::prelude::EnumParam::new("circuit", Circuits::SVF);
+    params.filter.svf_slope = nih_plug::prelude::EnumParam::new("slope", slope);
+    params.filter.mode = nih_plug::prelude::EnumParam::new("mode", mode);
+    params.sample_rate.set(FS);
+    params.update_g(cutoff);
+    params.set_resonances(Q_BUTTERWORTH);
+    let mut svf = Svf::new(Arc::new(params));
+    svf.update();
+    impulse_response_db(|input| svf.process(input))
+}
+
+fn impulse_response_db(mut tick: impl FnMut(f32x4) -> f32x4) -> Vec<f32> {
+    let mut re = vec![0.; LEN];
+    for (i, sample) in re.iter_mut().enumerate() {
+        let input = f32x4::splat(if i == 0 { IMPULSE } else { 0. });
+        *sample = tick(input)[0] / IMPULSE;
+    }
+    let mut im = vec![0.; LEN];
+    fft(&mut re, &mut im);
+    (0..=LEN / 2)
+        .map(|i| 10. * (re[i] * re[i] + im[i] * im[i]).log10())
+        .collect()
+}
+
+/// in-place radix-2 fft
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2. * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+fn bin(freq: f32) -> usize {
+    (freq / FS * LEN as f32).round() as usize
+}
+
+/// the first frequency where the response has dropped 3 dB below dc
+fn minus_3db(response: &[f32]) -> f32 {
+    let dc = response[1];
+    let i = response
+        .iter()
+        .position(|db| *db < dc - 3.)
+        .expect("response never drops 3 dB");
+    // interpolate between the bins
+    let t = (dc - 3. - response[i - 1]) / (response[i] - response[i - 1]);
+    (i as f32 - 1. + t) * FS / LEN as f32
+}
+
+/// steady state gain in dB of a sine at `freq` through the ladder
+fn ladder_sine_gain(newton: bool, cutoff: f32, res: f32, freq: f32) -> f32 {
+    let mut ladder = LadderFilter::new(make_params(Circuits::Ladder, cutoff, res));
+    let amp = 0.1;
+    let settle = 1 << 14;
+    let (mut re, mut im) = (0., 0.);
+    for i in 0..settle + LEN {
+        let phase = 2. * PI * freq * i as f32 / FS;
+        let input = f32x4::splat(amp * phase.sin());
+        let out = if newton {
+            ladder.tick_newton(input)
+        } else {
+            ladder.tick_linear(input)
+        };
+        if i >= settle {
+            re += out[0] * phase.cos();
+            im += out[0] * phase.sin();
+        }
+    }
+    20. * (2. * (re * re + im * im).sqrt() / (LEN as f32 * amp)).log10()
+}
+
+// the q where each circuit's passband is about flat, so the corner should sit on the cutoff
+const FLAT_RES: [(Tick, f32); 3] = [
+    (Tick::LadderLinear, 1.4),
+    (Tick::Svf, Q_BUTTERWORTH),
+    (Tick::SallenKey, 0.75),
+];
+
+#[test]
+fn test_cutoff_tracking() {
+    for (tick, res) in FLAT_RES {
+        for cutoff in [250., 1000., 4000.] {
+            let corner = minus_3db(&measure(tick, cutoff, res));
+            assert!(
+                (corner / cutoff - 1.).abs() < 0.15,
+                "{:?} at {} Hz has its -3 dB point at {} Hz",
+                tick,
+                cutoff,
+                corner
+            );
+        }
+    }
+}
+
+#[test]
+fn test_slopes() {
+    // measured an octave above 4x the cutoff, low enough that the bilinear warping doesn't steepen it
+    let cutoff = 250.;
+    for (tick, res, expected) in [
+        (Tick::LadderLinear, 1.4, -24.),
+        (Tick::Svf, Q_BUTTERWORTH, -12.),
+        (Tick::SallenKey, 0.75, -12.),
+    ] {
+        let response = measure(tick, cutoff, res);
+        let slope = response[bin(8. * cutoff)] - response[bin(4. * cutoff)];
+        assert!(
+            (slope - expected).abs() < 1.5,
+            "{:?} falls {} dB/oct, expected {}",
+            tick,
+            slope,
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_ladder_pole_mixing() {
+    let cutoff = 1000.;
+    // slopes in dB/oct an octave below cutoff / 4 and an octave above 4x the cutoff
+    for (response, below, above) in [
+        (LadderResponse::HP12, 12., 0.),
+        (LadderResponse::HP24, 24., 0.),
+        (LadderResponse::BP12, 6., -6.),
+        (LadderResponse::BP24, 12., -12.),
+    ] {
+        let db = measure_ladder_response(response, cutoff);
+        for (slope, expected) in [
+            (db[bin(cutoff / 4.)] - db[bin(cutoff / 8.)], below),
+            (db[bin(8. * cutoff)] - db[bin(4. * cutoff)], above),
+        ] {
+            assert!(
+                (slope - expected).abs() < 1.5,
+                "{:?} has a slope of {} dB/oct, expected {}",
+                response,
+                slope,
+                expected
+            );
+        }
+        let peak = db[1..].iter().cloned().fold(f32::MIN, f32::max);
+        assert!(peak.abs() < 0.5, "{:?} peaks at {} dB", response, peak);
+    }
+
+    let notch = measure_ladder_response(LadderResponse::Notch, cutoff);
+    assert!(notch[bin(cutoff)] < -20., "{} dB", notch[bin(cutoff)]);
+    // k is slightly negative at no resonance, which lifts the side below the notch by 1 / (1 + k)
+    for db in [notch[bin(cutoff / 16.)], notch[bin(16. * cutoff)]] {
+        assert!(db.abs() < 2.5, "{} dB", db);
+    }
+}
+
+#[test]
+fn test_svf_cascade() {
+    // low enough that the bilinear warping doesn't steepen the lowpass by 8x the cutoff
+    let cutoff = 250.;
+    let single_lp = measure_svf_slope(SvfSlope::Db12, SvfMode::LP, cutoff);
+    let single_hp = measure_svf_slope(SvfSlope::Db12, SvfMode::HP, cutoff);
+    for (slope, expected) in [(SvfSlope::Db24, 24.), (SvfSlope::Db36, 36.)] {
+        let lowpass = measure_svf_slope(slope, SvfMode::LP, cutoff);
+        let highpass = measure_svf_slope(slope, SvfMode::HP, cutoff);
+        for (slope_db, expected) in [
+            (
+                lowpass[bin(8. * cutoff)] - lowpass[bin(4. * cutoff)],
+                -expected,
+            ),
+            (
+                highpass[bin(cutoff / 4.)] - highpass[bin(cutoff / 8.)],
+                expected,
+            ),
+        ] {
+            assert!(
+                (slope_db - expected).abs() < 1.5,
+                "{:?} has a slope of {} dB/oct, expected {}",
+                slope,
+                slope_db,
+                expected
+            );
+        }
+        // butterworth, so flat up to the corner on the cutoff, at the same level as a single stage
+        let corner = minus_3db(&lowpass);
+        assert!(
+            (corner / cutoff - 1.).abs() < 0.1,
+            "{:?} has its -3 dB point at {} Hz",
+            slope,
+            corner
+        );
+        let peak = lowpass[1..].iter().cloned().fold(f32::MIN, f32::max);
+        assert!(peak - lowpass[1] < 0.5, "{:?} peaks at {} dB", slope, peak);
+        let passband = bin(16. * cutoff);
+        for (db, single) in [
+            (lowpass[1], single_lp[1]),
+            (highpass[passband], single_hp[passband]),
+        ] {
+            assert!(
+                (db - single).abs() < 0.5,
+                "{:?} passband is at {} dB, a single stage at {} dB",
+                slope,
+                db,
+                single
+            );
+        }
+    }
+}
+
+#[test]
+fn test_resonance_peak_monotonic() {
+    for tick in [Tick::LadderLinear, Tick::Svf, Tick::SallenKey] {
+        let mut last_peak = f32::MIN;
+        for i in 0..10 {
+            let res = Q_MIN * 1.45f32.powi(i);
+            let response = measure(tick, 1000., res);
+            let peak = response[1..].iter().cloned().fold(f32::MIN, f32::max) - response[1];
+            assert!(
+                peak >= last_peak - 0.01,
+                "{:?} peak dropped to {} dB at res {}",
+                tick,
+                peak,
+                res
+            );
+            last_peak = peak;
+        }
+        assert!(last_peak > 10., "{:?} only peaks {} dB", tick, last_peak);
+    }
+}
+
+#[test]
+fn test_ladder_newton_matches_linear() {
+    // the newton version has extra output gain, so compare relative to the passband
+    let (cutoff, res) = (1000., 1.4);
+    let linear_dc = ladder_sine_gain(false, cutoff, res, 62.5);
+    let newton_dc = ladder_sine_gain(true, cutoff, res, 62.5);
+    for freq in [500., 1000., 2000., 4000.] {
+        let linear = ladder_sine_gain(false, cutoff, res, freq) - linear_dc;
+        let newton = ladder_sine_gain(true, cutoff, res, freq) - newton_dc;
+        assert!(
+            (linear - newton).abs() < 0.5,
+            "at {} Hz newton gives {} dB, linear {} dB",
+            freq,
+            newton,
+            linear
+        );
+    }
+}
+
+/// magnitude response in dB of the linear ladder with a 1 kHz cutoff, with fat on or off
+fn measure_ladder_fat(fat: bool, q: f32) -> Vec<f32> {
+    let mut params = FilterParams::new();
+    params.filter.fat = nih_plug::prelude::BoolParam::new("fat", fat);
+    params.sample_rate.set(FS);
+    params.update_g(1000.);
+    params.set_resonances(q);
+    let mut ladder = LadderFilter::new(Arc::new(params));
+    impulse_response_db(|input| ladder.tick_linear(input))
+}
+
+#[test]
+fn test_ladder_fat_passband() {
+    // at 100 Hz, well in the passband, up to just short of self-oscillation
+    let passband = |fat, q| measure_ladder_fat(fat, q)[bin(100.)];
+    for q in (0..10)
+        .map(|i| Q_MIN * 1.45f32.powi(i))
+        .chain([0.95 * Q_SELF_OSC])
+    {
+        let db = passband(true, q);
+        assert!(db.abs() < 1., "fat passband is at {} dB at q {}", db, q);
+    }
+    // without it the feedback takes the passband down by 1 + k, about 14 dB at the top
+    let (bottom, top) = (passband(false, Q_MIN), passband(false, 0.95 * Q_SELF_OSC));
+    assert!(
+        bottom - top > 12.,
+        "passband only drops from {} to {} dB",
+        bottom,
+        top
+    );
+    // while the peak over the passband grows the same either way
+    let peak = |fat| {
+        let db = measure_ladder_fat(fat, 10.);
+        db[1..].iter().cloned().fold(f32::MIN, f32::max) - db[bin(100.)]
+    };
+    assert!(
+        (peak(true) - peak(false)).abs() < 0.1,
+        "the peak is {} dB with fat and {} dB without",
+        peak(true),
+        peak(false)
+    );
+}
+
+/// magnitude response in dB of the svf's bandpass with a 1 kHz cutoff, with bp norm on or off
+fn measure_svf_bp(bp_norm: bool, q: f32) -> Vec<f32> {
+    let mut params = FilterParams::new();
+    params.filter.filter_type = nih_plug::prelude::EnumParam::new("circuit", Circuits::SVF);
+    params.filter.mode = nih_plug::prelude::EnumParam::new("mode", SvfMode::BP1);
+    params.filter.bp_norm = nih_plug::prelude::BoolParam::new("bp norm", bp_norm);
+    params.sample_rate.set(FS);
+    params.update_g(1000.);
+    params.set_resonances(q);
+    let mut svf = Svf::new(Arc::new(params));
+    svf.update();
+    svf.reset();
+    impulse_response_db(|input| svf.process(input))
+}
+
+#[test]
+fn test_svf_bp_norm_peak() {
+    let peak = |bp_norm, q| {
+        measure_svf_bp(bp_norm, q)[1..]
+            .iter()
+            .cloned()
+            .fold(f32::MIN, f32::max)
+    };
+    let qs = (0..10)
+        .map(|i| Q_MIN * 1.45f32.powi(i))
+        .chain([0.95 * Q_SELF_OSC]);
+    let peaks: Vec<f32> = qs.clone().map(|q| peak(true, q)).collect();
+    let (min, max) = peaks.iter().fold((f32::MAX, f32::MIN), |(min, max), db| {
+        (min.min(*db), max.max(*db))
+    });
+    assert!(
+        max - min < 0.5,
+        "normalized peaks go from {} to {} dB: {:?}",
+        min,
+        max,
+        peaks
+    );
+    // without it the peak grows with the res
+    assert!(
+        peak(false, 0.95 * Q_SELF_OSC) - peak(false, Q_MIN) > 12.,
+        "the peak only grows from {} to {} dB",
+        peak(false, Q_MIN),
+        peak(false, 0.95 * Q_SELF_OSC)
+    );
+}
+
+/// steady state gain in dB of a sine at `freq` through the nonlinear solvers, with a 1 kHz cutoff
+fn sine_gain(circuit: Circuits, res: f32, res_character: f32, amp: f32, freq: f32) -> f32 {
+    let params = make_params(circuit, 1000., res);
+    params.set_res_squash(res_character);
+    let mut ladder = LadderFilter::new(params.clone());
+    let mut svf = Svf::new(params);
+    svf.update();
+    let settle = 1 << 14;
+    let (mut re, mut im) = (0., 0.);
+    for i in 0..settle + LEN {
+        let phase = 2. * PI * freq * i as f32 / FS;
+        let input = f32x4::splat(amp * phase.sin());
+        let out = match circuit {
+            Circuits::SVF => svf.process(input),
+            _ => ladder.tick_newton(input),
+        };
+        if i >= settle {
+            re += out[0] * phase.cos();
+            im += out[0] * phase.sin();
+        }
+    }
+    20. * (2. * (re * re + im * im).sqrt() / (LEN as f32 * amp)).log10()
+}
+
+#[test]
+fn test_res_character_level_dependence() {
+    // the resonance peak over the passband, so the saturation of the input doesn't count
+    let peak = |circuit, res_character, amp| {
+        sine_gain(circuit, 5., res_character, amp, 1000.)
+            - sine_gain(circuit, 5., res_character, amp, 125.)
+    };
+    // the svf's opamps only start squashing the resonance when they're driven much harder than the ladder
+    for (circuit, loud) in [(Circuits::Ladder, 1.), (Circuits::SVF, 3.)] {
+        let quiet = peak(circuit, 0., 0.01);
+        assert!(quiet > 6., "{:?} only peaks {} dB", circuit, quiet);
+        let clean = peak(circuit, 0., loud);
+        assert!(
+            (clean - quiet).abs() < 0.2,
+            "{:?} peak goes from {} to {} dB at clean res character",
+            circuit,
+            quiet,
+            clean
+        );
+        let squashed = peak(circuit, 1., loud);
+        assert!(
+            quiet - squashed > 6.,
+            "{:?} peak only goes from {} to {} dB at full res character",
+            circuit,
+            quiet,
+            squashed
+        );
+    }
+}
@@ -0,0 +1,103 @@
+#![feature(portable_simd)]
+// how far the cheaper solvers get from newton on a driven sawtooth, and where they stop being good enough
+use core_simd::simd::f32x4;
+use std::sync::Arc;
+use va_filter::filter::svf::Svf;
+use va_filter::filter::LadderFilter;
+use va_filter::filter_params::{Circuits, FilterParams, Solver};
+use va_filter::utils::AtomicOps;
+
+const FS: f32 = 96000.;
+const LEN: usize = 1 << 14;
+// a 110 Hz sawtooth at half scale, before the drive
+const SAW_HZ: f32 = 110.;
+const SAW_LEVEL: f32 = 0.5;
+/// about +6 dB and +24 dB
+const MODERATE_DRIVE: f32 = 2.;
+const EXTREME_DRIVE: f32 = 15.8;
+// the cutoffs and qs every solver gets compared at
+const SETTINGS: [(f32, f32); 4] = [(300., 0.7), (1000., 2.), (3000., 5.), (8000., 10.)];
+
+fn render(circuit: Circuits, solver: Solver, cutoff: f32, q: f32, drive: f32) -> Vec<f32> {
+    let mut params = FilterParams::new();
+    params.filter.filter_type = nih_plug::prelude::EnumParam::new("", circuit);
+    params.drive.solver = nih_plug::prelude::EnumParam::new("", solver);
+    params.sample_rate.set(FS);
+    params.update_g(cutoff);
+    params.set_resonances(q);
+    params.drive_mod.set(drive);
+    let params = Arc::new(params);
+    let mut ladder = LadderFilter::new(params.clone());
+    let mut svf = Svf::new(params);
+    svf.update();
+    (0..LEN)
+        .map(|i| {
+            let phase = (i as f32 * SAW_HZ / FS).fract();
+            let input = f32x4::splat(SAW_LEVEL * (2. * phase - 1.));
+            match circuit {
+                Circuits::SVF => svf.process(input)[0],
+                _ => ladder.tick(input)[0],
+            }
+        })
+        .collect()
+}
+
+/// RMS of the difference to `reference`, relative to the RMS of `reference`
+fn relative_error(output: &[f32], reference: &[f32]) -> f32 {
+    let power = |x: &mut dyn Iterator<Item = f32>| x.map(|x| x * x).sum::<f32>().sqrt();
+    let diff = power(&mut output.iter().zip(reference).map(|(a, b)| a - b));
+    diff / power(&mut reference.iter().copied())
+}
+
+/// The relative error of the linear and fixed pivot solvers against newton, at each of `SETTINGS`
+fn solver_errors(circuit: Circuits, drive: f32) -> Vec<(f32, f32)> {
+    SETTINGS
+        .iter()
+        .map(|&(cutoff, q)| {
+            let newton = render(circuit, Solver::Newton, cutoff, q, drive);
+            let linear = render(circuit, Solver::Linear, cutoff, q, drive);
+            let pivot = render(circuit, Solver::FixedPivot, cutoff, q, drive);
+            let errors = (
+                relative_error(&linear, &newton),
+                relative_error(&pivot, &newton),
+            );
+            println!(
+                "{:?} at {} Hz, q {}, drive {}: linear {:.4}, fixed pivot {:.4}",
+                circuit, cutoff, q, drive, errors.0, errors.1
+            );
+            errors
+        })
+        .collect()
+}
+
+#[test]
+fn test_solver_accuracy() {
+    // the ladder's fixed pivot is only ever linearized around the last sample and goes about 10% off on the saw's
+    // edges, the svf's takes a newton step from there and falls back to the full solve when that's not enough
+    for (circuit, bound) in [(Circuits::Ladder, 0.15), (Circuits::SVF, 0.01)] {
+        let moderate = solver_errors(circuit, MODERATE_DRIVE);
+        let extreme = solver_errors(circuit, EXTREME_DRIVE);
+        for (&(linear, pivot), (cutoff, q)) in moderate.iter().zip(SETTINGS) {
+            // and either way much closer than leaving out the nonlinearities
+            assert!(
+                pivot < bound && pivot < linear,
+                "{:?} fixed pivot is {} off at {} Hz, q {}, linear {}",
+                circuit,
+                pivot,
+                cutoff,
+                q,
+                linear
+            );
+        }
+        // once the drive gets far into the saturation it drifts away, at the worst setting at least.
+        // At low cutoffs the svf's stays right on newton
+        let worst = |errors: &[(f32, f32)]| errors.iter().map(|e| e.1).fold(0., f32::max);
+        assert!(
+            worst(&extreme) > 2. * worst(&moderate),
+            "{:?} fixed pivot is only {} off at extreme drive, {} at moderate",
+            circuit,
+            worst(&extreme),
+            worst(&moderate)
+        );
+    }
+}
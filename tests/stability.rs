@@ -0,0 +1,333 @@
+#![feature(portable_simd)]
+// throws random parameter changes and nasty input at every circuit and solver, and checks nothing blows up
+use core_simd::simd::f32x4;
+use std::fmt;
+use std::sync::Arc;
+use va_filter::filter::sallen_key::SallenKey;
+use va_filter::filter::svf::Svf;
+use va_filter::filter::{Comb, LadderFilter};
+use va_filter::filter_params::{
+    Circuits, FilterParams, LadderSolver, Solver, FEEDBACK_DRIVE_MAX, Q_MAX, Q_MIN,
+};
+use va_filter::utils::{random_bipolar, AtomicOps};
+
+// what the circuits run at with the oversampling
+const FS: f32 = 96000.;
+/// how long each trace runs its steps over and over, half a second
+const TRACE_LEN: usize = 48000;
+/// random traces per circuit and solver
+const CASES: usize = 6;
+/// more than any circuit should put out at full scale input, +40 dB. Linear at full res, the peak alone is +28 dB
+const BOUND: f32 = 100.;
+/// the most the drive goes to, +24 dB like the parameter
+const DRIVE_MAX: f32 = 15.849;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Input {
+    Silence,
+    Dc(f32),
+    /// full scale, flipping every `half_period` samples
+    Square(usize),
+    Noise,
+}
+
+/// The parameters and input for the next `len` samples
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Step {
+    len: usize,
+    cutoff: f32,
+    q: f32,
+    drive: f32,
+    /// the ladder's feedback drive
+    feedback: f32,
+    input: Input,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Trace {
+    circuit: Circuits,
+    solver: Solver,
+    ladder_solver: LadderSolver,
+    steps: Vec<Step>,
+}
+
+// as code, so a failing trace can be pasted straight into a regression test
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Silence => write!(f, "Input::Silence"),
+            Input::Dc(level) => write!(f, "Input::Dc({:?})", level),
+            Input::Square(half_period) => write!(f, "Input::Square({})", half_period),
+            Input::Noise => write!(f, "Input::Noise"),
+        }
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Trace {{")?;
+        writeln!(f, "    circuit: Circuits::{:?},", self.circuit)?;
+        writeln!(f, "    solver: Solver::{:?},", self.solver)?;
+        writeln!(
+            f,
+            "    ladder_solver: LadderSolver::{:?},",
+            self.ladder_solver
+        )?;
+        writeln!(f, "    steps: vec![")?;
+        for step in &self.steps {
+            writeln!(
+                f,
+                "        Step {{ len: {}, cutoff: {:?}, q: {:?}, drive: {:?}, feedback: {:?}, input: {} }},",
+                step.len, step.cutoff, step.q, step.drive, step.feedback, step.input
+            )?;
+        }
+        write!(f, "    ],\n}}")
+    }
+}
+
+/// from 0 to 1
+fn uniform(rng: &mut u64) -> f32 {
+    0.5 * (random_bipolar(rng) + 1.)
+}
+
+/// Spread evenly in ratios between `min` and `max`, and right on one of them a quarter of the time
+fn log_uniform(rng: &mut u64, min: f32, max: f32) -> f32 {
+    match uniform(rng) {
+        x if x < 0.125 => min,
+        x if x < 0.25 => max,
+        _ => min * (max / min).powf(uniform(rng)),
+    }
+}
+
+fn random_step(rng: &mut u64) -> Step {
+    Step {
+        len: 1 + (uniform(rng) * 63.) as usize,
+        cutoff: log_uniform(rng, 20., 20000.),
+        q: log_uniform(rng, Q_MIN, Q_MAX),
+        drive: log_uniform(rng, 1., DRIVE_MAX),
+        // it starts at 0, so it's spread evenly instead
+        feedback: match uniform(rng) {
+            x if x < 0.125 => 0.,
+            x if x < 0.25 => FEEDBACK_DRIVE_MAX,
+            _ => FEEDBACK_DRIVE_MAX * uniform(rng),
+        },
+        input: match (uniform(rng) * 4.) as usize {
+            0 => Input::Silence,
+            1 => Input::Dc(random_bipolar(rng).signum()),
+            2 => Input::Square(1 + (uniform(rng) * 200.) as usize),
+            _ => Input::Noise,
+        },
+    }
+}
+
+fn random_trace(
+    rng: &mut u64,
+    circuit: Circuits,
+    solver: Solver,
+    ladder_solver: LadderSolver,
+) -> Trace {
+    let len = 1 + (uniform(rng) * 31.) as usize;
+    Trace {
+        circuit,
+        solver,
+        ladder_solver,
+        steps: (0..len).map(|_| random_step(rng)).collect(),
+    }
+}
+
+/// Runs the trace's steps over and over for `TRACE_LEN` samples, through the same `update_g()`,
+/// `set_resonances()` and `update()` the plugin uses. Stops at the first sample that isn't finite or is out of bounds
+fn run(trace: &Trace) -> Result<(), String> {
+    let mut params = FilterParams::new();
+    params.filter.filter_type = nih_plug::prelude::EnumParam::new("", trace.circuit);
+    params.drive.solver = nih_plug::prelude::EnumParam::new("", trace.solver);
+    params.filter.ladder_solver = nih_plug::prelude::EnumParam::new("", trace.ladder_solver);
+    params.sample_rate.set(FS);
+    let params = Arc::new(params);
+    let mut ladder = LadderFilter::new(params.clone());
+    let mut svf = Svf::new(params.clone());
+    let mut sallen_key = SallenKey::new(params.clone());
+    let mut comb = Comb::new(params.clone());
+
+    let mut rng = 1;
+    let mut steps = trace.steps.iter().cycle();
+    let (mut step, mut left) = (trace.steps[0], 0);
+    for i in 0..TRACE_LEN {
+        if left == 0 {
+            step = *steps.next().unwrap();
+            left = step.len;
+            params.update_g(step.cutoff);
+            params.set_resonances(step.q);
+            params.drive_mod.set(step.drive);
+            params.feedback_drive.set(step.feedback);
+            svf.update();
+            sallen_key.update();
+            comb.update();
+        }
+        left -= 1;
+        let input = match step.input {
+            Input::Silence => 0.,
+            Input::Dc(level) => level,
+            Input::Square(half_period) => {
+                if (i / half_period) % 2 == 0 {
+                    1.
+                } else {
+                    -1.
+                }
+            }
+            Input::Noise => random_bipolar(&mut rng),
+        };
+        let out = match trace.circuit {
+            Circuits::SVF => svf.process(f32x4::splat(input)),
+            Circuits::SallenKey => sallen_key.process(f32x4::splat(input)),
+            Circuits::Ladder => ladder.tick(f32x4::splat(input)),
+            Circuits::Comb => comb.process(f32x4::splat(input)),
+        };
+        for &sample in &out.to_array()[..2] {
+            if !sample.is_finite() || sample.abs() > BOUND {
+                return Err(format!("{} at sample {}", sample, i));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Simpler versions of a trace: with a step less, or with a step's parameters or input set back to something tame
+fn simplifications(trace: &Trace) -> Vec<Trace> {
+    let mut simpler = Vec::new();
+    let mut with_step = |i: usize, step: Step| {
+        if step != trace.steps[i] {
+            let mut trace = trace.clone();
+            trace.steps[i] = step;
+            simpler.push(trace);
+        }
+    };
+    for (i, step) in trace.steps.iter().enumerate() {
+        with_step(i, Step { len: 1, ..*step });
+        with_step(
+            i,
+            Step {
+                cutoff: 1000.,
+                ..*step
+            },
+        );
+        with_step(i, Step { q: Q_MIN, ..*step });
+        with_step(i, Step { drive: 1., ..*step });
+        with_step(
+            i,
+            Step {
+                feedback: 0.,
+                ..*step
+            },
+        );
+        with_step(
+            i,
+            Step {
+                input: Input::Silence,
+                ..*step
+            },
+        );
+    }
+    if trace.steps.len() > 1 {
+        for i in 0..trace.steps.len() {
+            let mut trace = trace.clone();
+            trace.steps.remove(i);
+            simpler.insert(i, trace);
+        }
+    }
+    simpler
+}
+
+/// Keeps making the trace simpler for as long as it still fails
+fn shrink(mut trace: Trace) -> Trace {
+    while let Some(simpler) = simplifications(&trace)
+        .into_iter()
+        .find(|simpler| run(simpler).is_err())
+    {
+        trace = simpler;
+    }
+    trace
+}
+
+#[test]
+fn test_random_parameter_torture() {
+    let configs = [
+        (Circuits::Ladder, Solver::Linear, LadderSolver::Newton),
+        (Circuits::Ladder, Solver::FixedPivot, LadderSolver::Newton),
+        (Circuits::Ladder, Solver::Newton, LadderSolver::Newton),
+        (Circuits::Ladder, Solver::Newton, LadderSolver::Dk),
+        (Circuits::SVF, Solver::Linear, LadderSolver::Newton),
+        (Circuits::SVF, Solver::FixedPivot, LadderSolver::Newton),
+        (Circuits::SVF, Solver::Newton, LadderSolver::Newton),
+        (Circuits::SallenKey, Solver::Linear, LadderSolver::Newton),
+        (
+            Circuits::SallenKey,
+            Solver::FixedPivot,
+            LadderSolver::Newton,
+        ),
+        (Circuits::SallenKey, Solver::Newton, LadderSolver::Newton),
+        // the comb doesn't have a solver
+        (Circuits::Comb, Solver::Newton, LadderSolver::Newton),
+    ];
+    for (n, (circuit, solver, ladder_solver)) in configs.iter().enumerate() {
+        for case in 0..CASES {
+            // fixed, so a failure shows up on every run
+            let seed = (n * CASES + case) as u64;
+            let mut rng = seed;
+            let trace = random_trace(&mut rng, *circuit, *solver, *ladder_solver);
+            if let Err(e) = run(&trace) {
+                let minimal = shrink(trace);
+                panic!(
+                    "seed {} failed with {}, smallest failing trace ({}):\n{}",
+                    seed,
+                    e,
+                    run(&minimal).unwrap_err(),
+                    minimal
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_shrinking() {
+    // what `shrink()` gets to pick from
+    let trace = Trace {
+        circuit: Circuits::SVF,
+        solver: Solver::Newton,
+        ladder_solver: LadderSolver::Newton,
+        steps: vec![
+            Step {
+                len: 10,
+                cutoff: 500.,
+                q: 3.,
+                drive: 4.,
+                feedback: 1.,
+                input: Input::Noise,
+            },
+            Step {
+                len: 20,
+                cutoff: 5000.,
+                q: 1.,
+                drive: 2.,
+                feedback: 1.,
+                input: Input::Dc(1.),
+            },
+        ],
+    };
+    let simpler = simplifications(&trace);
+    // every step on its own, and each of its values tamed
+    assert_eq!(simpler.len(), 2 + 2 * 6);
+    assert!(simpler.iter().all(|simpler| *simpler != trace));
+    assert_eq!(simpler[0].steps, trace.steps[1..]);
+    // and what gets printed reads back as the same trace
+    let printed = trace.to_string();
+    assert!(printed.contains("circuit: Circuits::SVF,"), "{}", printed);
+    assert!(
+        printed.contains(
+            "Step { len: 20, cutoff: 5000.0, q: 1.0, drive: 2.0, feedback: 1.0, input: Input::Dc(1.0) },"
+        ),
+        "{}",
+        printed
+    );
+}